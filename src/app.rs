@@ -52,7 +52,7 @@ impl App {
 
 pub(crate) trait StorageObject {
     fn pretty_json(&self) -> String;
-    fn save_to<P: AsRef<Path>>(&self, path: P)
+    fn save_to<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()>
     where
         Self: Serialize;
     fn read_from<T: Default + DeserializeOwned + Serialize, P: AsRef<Path>>(path: P) -> T;
@@ -68,10 +68,11 @@ impl<T: Serialize> StorageObject for T {
             }
         }
     }
-    fn save_to<P: AsRef<Path>>(&self, path: P) {
-        if let Err(e) = std::fs::write(path, self.pretty_json()) {
-            eprintln!("⚠️ 写入文件失败: {}", e);
-        }
+    fn save_to<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        use anyhow::Context as _;
+        let path = path.as_ref();
+        std::fs::write(path, self.pretty_json())
+            .with_context(|| format!("写入文件失败: {}", path.display()))
     }
     fn read_from<R: Default + DeserializeOwned + Serialize, P: AsRef<Path>>(path: P) -> R {
         let v = match std::fs::read_to_string(path) {
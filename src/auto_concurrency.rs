@@ -1,3 +1,10 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use rusqlite::{Connection, params};
+
 /// 选择自动并发度的启发式实现
 /// 简单的启发式：基于文件数和总字节计算一个合理的 worker 数。
 pub fn choose_auto_concurrency(total_files: usize, total_bytes: u64) -> usize {
@@ -21,6 +28,305 @@ pub fn choose_auto_concurrency(total_files: usize, total_bytes: u64) -> usize {
     workers.clamp(1, 32)
 }
 
+// 自适应并发（AIMD）：未指定 -c 时，worker 线程按上限全部启动，但同一时刻只放行
+// `limit` 个处理条目；采样线程按吞吐量增减 limit，并把观测到的最佳值按别名记入 server.db。
+// Adaptive concurrency (AIMD) for runs without -c.
+
+/// 没有历史最佳值时的起始并发上限（从小开始） — initial limit cap without a learned value
+pub const INITIAL_MAX: usize = 4;
+// 采样间隔：需覆盖新 worker 的握手时间 — long enough to cover the handshake of new workers
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(3);
+// 吞吐量提升超过该比例才继续加并发 — keep adding workers while throughput grows this much
+const GAIN: f64 = 0.05;
+// 吞吐量下降超过该比例则乘性减少 — back off when throughput drops this much
+const LOSS: f64 = 0.15;
+// 少于该样本数的运行不更新已记住的值 — shorter runs do not replace the stored value
+const MIN_SAMPLES: usize = 3;
+
+/// AIMD 控制器：每个吞吐量样本之后给出新的并发上限。
+/// Additive increase while throughput improves, multiplicative decrease (×3/4) when it drops.
+#[derive(Debug, Clone)]
+pub struct Aimd {
+    limit: usize,
+    max: usize,
+    last_rate: Option<f64>,
+    best: (usize, f64),
+    samples: usize,
+}
+
+impl Aimd {
+    pub fn new(start: usize, max: usize) -> Self {
+        let max = max.max(1);
+        let limit = start.clamp(1, max);
+        Self { limit, max, last_rate: None, best: (limit, 0.0), samples: 0 }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Record the throughput (bytes/s) measured at the current limit and return the next limit.
+    pub fn observe(&mut self, rate: f64) -> usize {
+        // nothing moved (handshakes, stalls, tail of the run): no information
+        if rate <= 0.0 {
+            return self.limit;
+        }
+        self.samples += 1;
+        if rate > self.best.1 {
+            self.best = (self.limit, rate);
+        }
+        let next = match self.last_rate {
+            None => self.limit + 1,
+            Some(prev) if rate >= prev * (1.0 + GAIN) => self.limit + 1,
+            Some(prev) if rate < prev * (1.0 - LOSS) => self.limit - (self.limit / 4).max(1),
+            Some(_) => self.limit,
+        };
+        self.last_rate = Some(rate);
+        self.limit = next.clamp(1, self.max);
+        self.limit
+    }
+}
+
+/// 可调整上限的计数信号量：worker 处理每个条目前取得一个名额。
+/// Counting semaphore whose capacity can change while workers wait.
+#[derive(Debug)]
+pub struct ConcurrencyGate {
+    // (active, limit)
+    state: Mutex<(usize, usize)>,
+    cv: Condvar,
+}
+
+/// 名额在 drop 时归还 — released on drop
+pub struct GateTurn<'a>(&'a ConcurrencyGate);
+
+impl ConcurrencyGate {
+    pub fn new(limit: usize) -> Self {
+        Self { state: Mutex::new((0, limit.max(1))), cv: Condvar::new() }
+    }
+
+    pub fn acquire(&self) -> GateTurn<'_> {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while st.0 >= st.1 {
+            st = self.cv.wait(st).unwrap_or_else(|e| e.into_inner());
+        }
+        st.0 += 1;
+        GateTurn(self)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.1 = limit.max(1);
+        self.cv.notify_all();
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).1
+    }
+}
+
+impl Drop for GateTurn<'_> {
+    fn drop(&mut self) {
+        let mut st = self.0.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.0 = st.0.saturating_sub(1);
+        self.0.cv.notify_one();
+    }
+}
+
+/// 一次运行的自适应结果 — outcome of one adaptive run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TuneReport {
+    pub start: usize,
+    pub last: usize,
+    pub best: usize,
+    pub best_bytes_per_sec: f64,
+    pub samples: usize,
+}
+
+impl TuneReport {
+    /// Enough samples for the best value to be worth remembering.
+    pub fn is_conclusive(&self) -> bool {
+        self.samples >= MIN_SAMPLES
+    }
+
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "start": self.start,
+            "final": self.last,
+            "best": self.best,
+            "best_bytes_per_sec": self.best_bytes_per_sec,
+        })
+    }
+}
+
+/// 运行期间的自适应并发：门控 + 字节计量 + 采样线程。
+/// The byte meter is fed by the workers' `Throttler`s; a sampler thread turns it into
+/// throughput samples for the `Aimd` controller and applies the result to the gate.
+pub struct AutoTuner {
+    gate: ConcurrencyGate,
+    meter: Arc<AtomicU64>,
+    aimd: Mutex<Aimd>,
+    start: usize,
+    stop: (Mutex<bool>, Condvar),
+    sampler: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl AutoTuner {
+    /// Start with `start` of `max` workers admitted and begin sampling.
+    pub fn spawn(start: usize, max: usize) -> Arc<Self> {
+        let aimd = Aimd::new(start, max);
+        let tuner = Arc::new(Self {
+            gate: ConcurrencyGate::new(aimd.limit()),
+            meter: Arc::new(AtomicU64::new(0)),
+            start: aimd.limit(),
+            aimd: Mutex::new(aimd),
+            stop: (Mutex::new(false), Condvar::new()),
+            sampler: Mutex::new(None),
+        });
+        // the sampler only holds a weak reference and ends once the run drops the tuner
+        let weak = Arc::downgrade(&tuner);
+        let handle = std::thread::spawn(move || sample_loop(weak));
+        if let Ok(mut s) = tuner.sampler.lock() {
+            *s = Some(handle);
+        }
+        tuner
+    }
+
+    /// Limit the run started with.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn meter(&self) -> Arc<AtomicU64> {
+        self.meter.clone()
+    }
+
+    pub fn acquire(&self) -> GateTurn<'_> {
+        self.gate.acquire()
+    }
+
+    /// Number of workers currently admitted at a time.
+    pub fn limit(&self) -> usize {
+        self.gate.limit()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_limit_for_test(&self, limit: usize) {
+        self.gate.set_limit(limit);
+    }
+
+    /// Stop sampling and report the limits seen during the run.
+    pub fn finish(&self) -> TuneReport {
+        if let Ok(mut stopped) = self.stop.0.lock() {
+            *stopped = true;
+            self.stop.1.notify_all();
+        }
+        if let Some(handle) = self.sampler.lock().ok().and_then(|mut s| s.take()) {
+            let _ = handle.join();
+        }
+        let aimd = self.aimd.lock().unwrap_or_else(|e| e.into_inner());
+        TuneReport {
+            start: self.start,
+            last: aimd.limit,
+            best: aimd.best.0,
+            best_bytes_per_sec: aimd.best.1,
+            samples: aimd.samples,
+        }
+    }
+
+    // wait one interval; false once finish() was called
+    fn wait_interval(&self) -> bool {
+        let stopped = self.stop.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (stopped, _) = self
+            .stop
+            .1
+            .wait_timeout_while(stopped, SAMPLE_INTERVAL, |s| !*s)
+            .unwrap_or_else(|e| e.into_inner());
+        !*stopped
+    }
+}
+
+fn sample_loop(tuner: Weak<AutoTuner>) {
+    let mut last = (Instant::now(), 0u64);
+    loop {
+        let Some(t) = tuner.upgrade() else {
+            return;
+        };
+        if !t.wait_interval() {
+            return;
+        }
+        let (now, bytes) = (Instant::now(), t.meter.load(Ordering::SeqCst));
+        let secs = now.duration_since(last.0).as_secs_f64();
+        let rate = if secs > 0.0 { bytes.saturating_sub(last.1) as f64 / secs } else { 0.0 };
+        last = (now, bytes);
+        let mut aimd = t.aimd.lock().unwrap_or_else(|e| e.into_inner());
+        let before = aimd.limit();
+        let next = aimd.observe(rate);
+        drop(aimd);
+        if next != before {
+            tracing::info!("[ts][auto] throughput={:.0}B/s workers {} -> {}", rate, before, next);
+            t.gate.set_limit(next);
+        }
+    }
+}
+
+/// Create the table of learned concurrency values if needed.
+fn ensure_tuning_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS concurrency_tuning (
+            alias TEXT PRIMARY KEY,
+            workers INTEGER NOT NULL,
+            bytes_per_sec REAL NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn open_tuning<P: AsRef<Path>>(path: P) -> anyhow::Result<Connection> {
+    use anyhow::Context as _;
+    let conn = Connection::open(path).with_context(|| "Failed to open SQLite database")?;
+    ensure_tuning_schema(&conn).with_context(|| "Failed to create concurrency_tuning table")?;
+    Ok(conn)
+}
+
+/// 读取别名记住的最佳并发 — best worker count remembered for an alias
+pub fn learned_concurrency<P: AsRef<Path>>(path: P, alias: &str) -> Option<usize> {
+    let conn = open_tuning(path).ok()?;
+    conn.query_row("SELECT workers FROM concurrency_tuning WHERE alias = ?1", [alias], |r| {
+        r.get::<_, i64>(0)
+    })
+    .ok()
+    .map(|w| w.max(1) as usize)
+}
+
+/// 记住别名本次观测到的最佳并发 — store the best worker count observed for an alias
+pub fn remember_concurrency<P: AsRef<Path>>(
+    path: P,
+    alias: &str,
+    report: &TuneReport,
+) -> anyhow::Result<()> {
+    let conn = open_tuning(path)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO concurrency_tuning (alias, workers, bytes_per_sec, updated_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            alias,
+            report.best as i64,
+            report.best_bytes_per_sec,
+            chrono::Local::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Move the learned value along with `hp mv`.
+pub fn rename_alias<P: AsRef<Path>>(path: P, old: &str, new: &str) -> anyhow::Result<()> {
+    let conn = open_tuning(path)?;
+    conn.execute("UPDATE concurrency_tuning SET alias = ?2 WHERE alias = ?1", params![old, new])?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +347,46 @@ mod tests {
         let w = choose_auto_concurrency(9, 9 * 20 * 1024 * 1024);
         assert!(w <= 4);
     }
+
+    #[test]
+    fn aimd_ramps_up_while_throughput_grows_and_backs_off_on_drop() {
+        let mut a = Aimd::new(2, 8);
+        assert_eq!(a.observe(0.0), 2);
+        assert_eq!(a.observe(100.0), 3);
+        assert_eq!(a.observe(150.0), 4);
+        assert_eq!(a.observe(200.0), 5);
+        // plateau: hold
+        assert_eq!(a.observe(199.0), 5);
+        // drop: multiplicative decrease
+        assert_eq!(a.observe(120.0), 4);
+        assert_eq!(a.best, (4, 200.0));
+        let mut capped = Aimd::new(7, 8);
+        capped.observe(10.0);
+        assert_eq!(capped.observe(20.0), 8);
+    }
+
+    #[test]
+    fn gate_admits_up_to_limit() {
+        let gate = ConcurrencyGate::new(1);
+        let first = gate.acquire();
+        gate.set_limit(2);
+        let second = gate.acquire();
+        assert_eq!(gate.state.lock().unwrap().0, 2);
+        drop((first, second));
+        assert_eq!(gate.state.lock().unwrap().0, 0);
+    }
+
+    #[test]
+    fn learned_concurrency_round_trip() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("hp_tuning_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(learned_concurrency(&path, "web"), None);
+        let report =
+            TuneReport { best: 6, best_bytes_per_sec: 1e6, samples: 5, ..Default::default() };
+        remember_concurrency(&path, "web", &report).unwrap();
+        rename_alias(&path, "web", "web2").unwrap();
+        assert_eq!(learned_concurrency(&path, "web2"), Some(6));
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -18,6 +18,8 @@ pub struct Cli {
     pub command: Option<Commands>,
 }
 
+// parsed once per process; boxing the large `ts` variant buys nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     #[clap(about = "Create alias for a remote SSH server", name = "new", display_order = 3)]
@@ -30,17 +32,49 @@ pub enum Commands {
     List {},
     #[clap(about = "Copy RSA public key to remote server", name = "ln")]
     Link { alias: String },
+    #[clap(about = "Show or set the default ts bandwidth limit of an alias", name = "limit")]
+    Limit {
+        alias: String,
+        #[clap(help = "Rate such as 10M, a schedule like 09:00-18:00=2M,20M, or off to clear")]
+        rate: Option<String>,
+    },
+    #[clap(
+        about = "Show or set how many SSH handshakes ts may run at once to an alias",
+        name = "handshakes"
+    )]
+    Handshakes {
+        alias: String,
+        #[clap(
+            help = "Upper bound such as 2 (lower it for sshd MaxStartups), or off for the default"
+        )]
+        limit: Option<String>,
+    },
+    #[clap(
+        about = "Show, add or remove the tags of an alias (ts targets @tag:/path)",
+        name = "tag"
+    )]
+    Tag {
+        alias: String,
+        #[clap(help = "Tags to add (comma or space separated)")]
+        tags: Vec<String>,
+        #[clap(long = "rm", help = "Remove the given tags instead of adding them")]
+        remove: bool,
+    },
 
     #[clap(about = "Transfer files using builtin ssh2 SFTP (no password support)", name = "ts")]
     Ts {
-        #[clap(num_args = 1.., required = true, help = "Source paths (local or remote alias:/path)")]
-        sources: Vec<String>,
-        #[clap(required = true, help = "Target path (local or remote alias:/path)")]
-        target: String,
+        // sources followed by the target; one positional so that `--resume` can stand alone
+        #[clap(
+            num_args = 2..,
+            value_name = "PATH",
+            required_unless_present_any = ["resume", "retry_failed"],
+            help = "Source paths followed by the target path (local or remote alias:/path)"
+        )]
+        paths: Vec<String>,
         #[clap(
             short = 'c',
             long = "concurrency",
-            help = "Number of concurrent workers or 'auto' (default auto, max 32)"
+            help = "Number of concurrent workers or 'auto' (default auto: adapts to throughput, max 32)"
         )]
         concurrency: Option<String>,
         #[clap(
@@ -73,6 +107,20 @@ pub enum Commands {
             help = "Emit a single-line JSON summary at end (machine-readable)"
         )]
         json: bool,
+        #[clap(
+            long = "events",
+            value_name = "FORMAT",
+            value_parser = ["ndjson"],
+            help = "Stream machine-readable progress events (queued/started/progress/completed/failed/retrying/summary)"
+        )]
+        events: Option<String>,
+        #[clap(
+            long = "events-to",
+            value_name = "DEST",
+            requires = "events",
+            help = "Where --events goes: - for stdout (default, implies --quiet), fd:N, or a file path"
+        )]
+        events_to: Option<String>,
         #[clap(
             short = 'f',
             long = "buf-mib",
@@ -80,6 +128,200 @@ pub enum Commands {
             value_parser
         )]
         buf_mib: Option<usize>,
+        #[clap(
+            long = "delete",
+            help = "Mirror mode: delete destination entries that do not exist at the source"
+        )]
+        delete: bool,
+        #[clap(
+            long = "delete-dry-run",
+            help = "Show what --delete would remove without deleting (implies --delete)"
+        )]
+        delete_dry_run: bool,
+        #[clap(
+            long = "max-delete",
+            value_name = "N",
+            help = "Abort mirror deletion when more than N entries would be removed"
+        )]
+        max_delete: Option<usize>,
+        #[clap(
+            long = "dry-run",
+            help = "Validate and enumerate, then print planned mkdir/create/overwrite/skip operations without transferring"
+        )]
+        dry_run: bool,
+        #[clap(
+            long = "exclude",
+            value_name = "PATTERN",
+            help = "Skip paths matching a gitignore-style pattern (repeatable; .hpignore is honored automatically)"
+        )]
+        exclude: Vec<String>,
+        #[clap(
+            long = "include",
+            value_name = "PATTERN",
+            help = "Re-include paths that an exclude pattern would skip (repeatable)"
+        )]
+        include: Vec<String>,
+        #[clap(
+            long = "exclude-from",
+            value_name = "FILE",
+            help = "Read exclude patterns from a file (gitignore syntax)"
+        )]
+        exclude_from: Option<PathBuf>,
+        #[clap(
+            short = 'p',
+            long = "preserve",
+            help = "Preserve permission bits and modification/access times"
+        )]
+        preserve: bool,
+        #[clap(
+            long = "chmod",
+            value_name = "MODE",
+            help = "Set destination modes, octal for all or per kind (e.g. 644 or D755,F644)"
+        )]
+        chmod: Option<String>,
+        #[clap(
+            long = "umask",
+            value_name = "OCTAL",
+            help = "Mask applied to destination modes (e.g. 022)"
+        )]
+        umask: Option<String>,
+        #[clap(
+            long = "links",
+            value_name = "MODE",
+            value_parser = ["copy", "follow", "skip"],
+            help = "Symlinks inside source directories: copy the link, follow it, or skip it (default)"
+        )]
+        links: Option<String>,
+        #[clap(
+            short = 'n',
+            long = "no-clobber",
+            help = "Never overwrite existing destination files"
+        )]
+        no_clobber: bool,
+        #[clap(
+            short = 'u',
+            long = "update",
+            help = "Overwrite only when the source is newer than the destination"
+        )]
+        update: bool,
+        #[clap(
+            long = "backup",
+            value_name = "SUFFIX",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "~",
+            help = "Rename an existing destination file aside before writing (default suffix ~)"
+        )]
+        backup: Option<String>,
+        #[clap(
+            long = "limit-rate",
+            value_name = "RATE",
+            help = "Total bandwidth limit across workers, e.g. 10M or 09:00-18:00=2M,20M (off disables the alias default)"
+        )]
+        limit_rate: Option<String>,
+        #[clap(
+            long = "direct",
+            help = "Remote-to-remote: run the copy on the source host when it can reach the destination"
+        )]
+        direct: bool,
+        #[clap(
+            long = "parallel-hosts",
+            value_name = "N",
+            help = "Tag fan-out (@tag:/path): hosts uploaded to at the same time (default 4)"
+        )]
+        parallel_hosts: Option<usize>,
+        #[clap(
+            long = "fail-fast",
+            conflicts_with = "keep_going",
+            help = "Tag fan-out: do not start further hosts after one fails"
+        )]
+        fail_fast: bool,
+        #[clap(
+            long = "continue",
+            conflicts_with = "fail_fast",
+            help = "Tag fan-out: keep uploading to the remaining hosts after a failure (default)"
+        )]
+        keep_going: bool,
+        #[clap(
+            long = "host-dir",
+            value_name = "TEMPLATE",
+            help = "Tag fan-in (@tag:/path source): per-host directory under the target; {alias}, {host}, {date} (default {alias})"
+        )]
+        host_dir: Option<String>,
+        #[clap(
+            long = "tar",
+            help = "Directory sources only: stream the tree through one tar exec channel instead of per-file SFTP"
+        )]
+        tar: bool,
+        #[clap(
+            long = "chunk-threshold",
+            value_name = "SIZE",
+            help = "Split files of at least SIZE (e.g. 256M, min 16M) across several sessions; off by default"
+        )]
+        chunk_threshold: Option<String>,
+        #[clap(
+            long = "prescan",
+            help = "Uploads: walk all local sources before transferring, for an exact total and ETA"
+        )]
+        prescan: bool,
+        #[clap(
+            long = "resume",
+            value_name = "JOB_ID",
+            conflicts_with = "paths",
+            help = "Rerun a journaled job (see hp jobs), skipping the files it already finished"
+        )]
+        resume: Option<i64>,
+        #[clap(
+            long = "retry-failed",
+            value_name = "RUN_ID",
+            num_args = 0..=1,
+            conflicts_with_all = ["paths", "resume"],
+            help = "Re-transfer only the files a run failed on (default the latest run), with its options"
+        )]
+        retry_failed: Option<Option<i64>>,
+    },
+    #[clap(about = "Show the summaries of past ts runs", name = "history")]
+    History {
+        #[clap(help = "Only runs of this alias")]
+        alias: Option<String>,
+        #[clap(
+            long = "since",
+            value_name = "WHEN",
+            help = "Only runs finished since a span (30m, 12h, 7d, 2w) or local date (2026-10-01[ 08:00])"
+        )]
+        since: Option<String>,
+        #[clap(short = 'j', long = "json", help = "Print the runs as a JSON array")]
+        json: bool,
+        #[clap(short = 'n', long = "limit", default_value_t = 50, help = "Show at most N runs")]
+        limit: usize,
+    },
+    #[clap(about = "List, show or remove journaled ts jobs", name = "jobs")]
+    Jobs {
+        #[clap(subcommand)]
+        action: Option<JobsAction>,
+    },
+    #[clap(
+        about = "Run a background agent that keeps SSH sessions open for ts and exec",
+        name = "agent"
+    )]
+    Agent {
+        #[clap(subcommand)]
+        action: Option<AgentAction>,
+    },
+    #[clap(
+        about = "Run a command on a remote alias (through hp agent when running)",
+        name = "exec"
+    )]
+    Exec {
+        alias: String,
+        #[clap(
+            num_args = 1..,
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "Command and arguments, run through the remote shell"
+        )]
+        command: Vec<String>,
     },
     #[clap(about = "Configure HostPilot")]
     Set {
@@ -93,3 +335,40 @@ pub enum Commands {
         scp_path: Option<PathBuf>,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub enum AgentAction {
+    #[clap(about = "Start the agent in the background (default)")]
+    Start {
+        #[clap(long = "foreground", help = "Serve in this process instead of detaching")]
+        foreground: bool,
+        #[clap(
+            long = "idle-timeout",
+            value_name = "SECS",
+            help = "Close sessions idle for longer than this (default 600)"
+        )]
+        idle_timeout: Option<u64>,
+    },
+    #[clap(about = "Show the agent and its idle sessions")]
+    Status,
+    #[clap(about = "Stop the agent")]
+    Stop,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JobsAction {
+    #[clap(about = "List jobs with their progress (default)")]
+    Ls,
+    #[clap(about = "Show a job, its options and unfinished entries")]
+    Show {
+        id: i64,
+        #[clap(long = "all", help = "List finished entries too")]
+        all: bool,
+    },
+    #[clap(about = "Remove jobs from the journal")]
+    Rm {
+        ids: Vec<i64>,
+        #[clap(long = "done", help = "Remove every finished job")]
+        done: bool,
+    },
+}
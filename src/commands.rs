@@ -1,5 +1,6 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
+use crate::TransferError;
 use crate::config::Config;
 use crate::server::Server;
 use crate::server::ServerCollection;
@@ -14,33 +15,27 @@ fn save_server_collection(collection: &ServerCollection, config: &Config) -> Res
     collection.save_to_storage(&config.server_file_path)
 }
 
-/// 检查别名是否存在，返回 true 表示存在
-fn check_alias_exists(collection: &ServerCollection, alias: &str, should_exist: bool) -> bool {
-    let exists = collection.get(alias).is_some();
-    if should_exist && !exists {
-        eprintln!("❌ 别名 '{}' 不存在", alias);
-        return false;
-    }
-    if !should_exist && exists {
-        eprintln!("⚠️ 别名 '{}' 已存在", alias);
-        return false;
+/// 要求别名存在，否则返回 `AliasNotFound`
+fn require_alias<'a>(collection: &'a ServerCollection, alias: &str) -> Result<&'a Server> {
+    collection.get(alias).ok_or_else(|| TransferError::AliasNotFound(alias.to_string()).into())
+}
+
+/// 要求别名尚未被占用，否则返回 `AliasExists`
+fn require_alias_free(collection: &ServerCollection, alias: &str) -> Result<()> {
+    if collection.get(alias).is_some() {
+        return Err(TransferError::AliasExists(alias.to_string()).into());
     }
-    true
+    Ok(())
 }
 
 pub fn handle_create(config: &Config, alias: String, remote_host: String) -> Result<()> {
-    let (username, address, port) = match crate::parse::parse_remote_host(&remote_host) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("new 命令参数错误: {}\n格式示例: hp new <alias> user@host[:port]", e);
-            return Ok(());
-        }
-    };
+    let (username, address, port) =
+        crate::parse::parse_remote_host(&remote_host).with_context(|| {
+            format!("无效的远端主机 '{}'（格式示例: hp new <alias> user@host[:port]）", remote_host)
+        })?;
 
     let mut collection = load_server_collection(config)?;
-    if !check_alias_exists(&collection, &alias, false) {
-        return Ok(());
-    }
+    require_alias_free(&collection, &alias)?;
 
     let server = Server {
         id: None,
@@ -49,6 +44,9 @@ pub fn handle_create(config: &Config, alias: String, remote_host: String) -> Res
         address,
         port,
         last_connect: None,
+        limit_rate: None,
+        tags: Vec::new(),
+        max_handshakes: None,
     };
     collection.insert(&alias, server);
     save_server_collection(&collection, config)?;
@@ -58,21 +56,17 @@ pub fn handle_create(config: &Config, alias: String, remote_host: String) -> Res
 
 pub fn handle_rename(config: &Config, alias: String, new_alias: String) -> Result<()> {
     let mut collection = load_server_collection(config)?;
-    if !check_alias_exists(&collection, &alias, true) {
-        return Ok(());
-    }
-    if !check_alias_exists(&collection, &new_alias, false) {
-        return Ok(());
-    }
+    let old = require_alias(&collection, &alias)?.clone();
+    require_alias_free(&collection, &new_alias)?;
 
-    if let Some(old) = collection.hosts().get(&alias).cloned() {
-        collection.remove(&alias);
-        let mut new_server = old.clone();
-        new_server.alias = Some(new_alias.clone());
-        collection.insert(&new_alias, new_server);
-        save_server_collection(&collection, config)?;
-        println!("已将别名 '{}' 重命名为 '{}'", alias, new_alias);
-    }
+    collection.remove(&alias);
+    let mut new_server = old;
+    new_server.alias = Some(new_alias.clone());
+    collection.insert(&new_alias, new_server);
+    save_server_collection(&collection, config)?;
+    crate::history::rename_alias(&config.server_file_path, &alias, &new_alias)?;
+    crate::auto_concurrency::rename_alias(&config.server_file_path, &alias, &new_alias)?;
+    println!("已将别名 '{}' 重命名为 '{}'", alias, new_alias);
     Ok(())
 }
 
@@ -84,44 +78,137 @@ pub fn handle_list(config: &Config) -> Result<()> {
 
 pub fn handle_remove(config: &Config, alias: String) -> Result<()> {
     let mut collection = load_server_collection(config)?;
-    if !check_alias_exists(&collection, &alias, true) {
-        return Ok(());
-    }
+    require_alias(&collection, &alias)?;
     collection.remove(alias.as_str());
     save_server_collection(&collection, config)?;
     println!("✅ 已删除别名 '{}'", alias);
     Ok(())
 }
 
-pub fn handle_link(config: &Config, alias: String) -> Result<()> {
-    let collection = load_server_collection(config)?;
-    let Some(server) = collection.get(&alias as &str) else {
-        check_alias_exists(&collection, &alias, true);
+/// Show, set or clear (`off`) the default `ts` bandwidth limit of an alias.
+pub fn handle_limit(config: &Config, alias: String, rate: Option<String>) -> Result<()> {
+    let mut collection = load_server_collection(config)?;
+    let server = require_alias(&collection, &alias)?.clone();
+    let Some(rate) = rate else {
+        match server.limit_rate {
+            Some(ref r) => println!("{}: {}", alias, r),
+            None => println!("{}: 未设置限速", alias),
+        }
         return Ok(());
     };
+    let schedule = crate::transfer::RateSchedule::parse(&rate)?;
+    let mut updated = server;
+    updated.limit_rate = (!schedule.is_unlimited()).then(|| rate.trim().to_string());
+    collection.insert(&alias, updated);
+    save_server_collection(&collection, config)?;
+    if schedule.is_unlimited() {
+        println!("✅ 已清除别名 '{}' 的默认限速", alias);
+    } else {
+        println!("✅ 已设置别名 '{}' 的默认限速: {}", alias, rate.trim());
+    }
+    Ok(())
+}
+
+/// Show or set the concurrent SSH handshake limit of an alias; `off` restores the default.
+pub fn handle_handshakes(config: &Config, alias: String, limit: Option<String>) -> Result<()> {
+    let mut collection = load_server_collection(config)?;
+    let server = require_alias(&collection, &alias)?.clone();
+    let default = crate::transfer::DEFAULT_MAX_HANDSHAKES;
+    let Some(limit) = limit else {
+        match server.max_handshakes {
+            Some(n) => println!("{}: {}", alias, n),
+            None => println!("{}: 未设置（默认 {}）", alias, default),
+        }
+        return Ok(());
+    };
+    let limit = limit.trim();
+    let value = if limit.eq_ignore_ascii_case("off") {
+        None
+    } else {
+        match limit.parse::<u32>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                return Err(TransferError::InvalidArgument(format!(
+                    "无效的握手并发上限 '{}'：需为正整数或 off",
+                    limit
+                ))
+                .into());
+            }
+        }
+    };
+    let mut updated = server;
+    updated.max_handshakes = value;
+    collection.insert(&alias, updated);
+    save_server_collection(&collection, config)?;
+    match value {
+        Some(n) => println!("✅ 已设置别名 '{}' 的握手并发上限: {}", alias, n),
+        None => println!("✅ 已清除别名 '{}' 的握手并发上限（恢复默认 {}）", alias, default),
+    }
+    Ok(())
+}
+
+/// Show the tags of an alias, or add/remove (`--rm`) the given ones.
+pub fn handle_tag(config: &Config, alias: String, tags: Vec<String>, remove: bool) -> Result<()> {
+    let mut collection = load_server_collection(config)?;
+    let server = require_alias(&collection, &alias)?.clone();
+    let given = crate::server::split_tags(&tags.join(","));
+    if given.is_empty() {
+        if server.tags.is_empty() {
+            println!("{}: 无标签", alias);
+        } else {
+            println!("{}: {}", alias, server.tags.join(","));
+        }
+        return Ok(());
+    }
+    if let Some(bad) =
+        given.iter().find(|t| !t.chars().all(|c| c.is_alphanumeric() || "-_.".contains(c)))
+    {
+        return Err(TransferError::InvalidArgument(format!(
+            "无效的标签 '{}'：仅允许字母、数字及 - _ .",
+            bad
+        ))
+        .into());
+    }
+    let mut updated = server;
+    if remove {
+        updated.tags.retain(|t| !given.contains(t));
+    } else {
+        for t in given {
+            if !updated.tags.contains(&t) {
+                updated.tags.push(t);
+            }
+        }
+    }
+    let shown = updated.tags.join(",");
+    collection.insert(&alias, updated);
+    save_server_collection(&collection, config)?;
+    println!("✅ 别名 '{}' 的标签: {}", alias, if shown.is_empty() { "无" } else { &shown });
+    Ok(())
+}
+
+pub fn handle_link(config: &Config, alias: String) -> Result<()> {
+    let collection = load_server_collection(config)?;
+    let server = require_alias(&collection, &alias)?;
 
     // 检查本地公钥
     let pub_key = &config.pub_key_path;
     if !pub_key.exists() {
-        eprintln!(
-            "🔑 本地公钥不存在: {}\n请先使用 ssh-keygen 生成公钥，或在 config.json 配置 pub_key_path",
+        return Err(TransferError::OperationFailed(format!(
+            "本地公钥不存在: {}\n请先使用 ssh-keygen 生成公钥，或在 config.json 配置 pub_key_path",
             pub_key.display()
-        );
-        return Ok(());
+        ))
+        .into());
     }
 
     // 读取并验证本地公钥 — Read and validate local public key
-    let key_content = match std::fs::read_to_string(pub_key) {
-        Ok(s) => s.replace('\r', ""),
-        Err(e) => {
-            eprintln!("❌ 读取本地公钥失败: {}", e);
-            return Ok(());
-        }
-    };
+    let key_content = std::fs::read_to_string(pub_key)
+        .with_context(|| format!("读取本地公钥失败: {}", pub_key.display()))?
+        .replace('\r', "");
     let key_line = key_content.lines().next().unwrap_or("").trim().to_string();
     if key_line.is_empty() {
-        eprintln!("⚠️ 公钥文件为空: {}", pub_key.display());
-        return Ok(());
+        return Err(
+            TransferError::OperationFailed(format!("公钥文件为空: {}", pub_key.display())).into()
+        );
     }
 
     use base64::engine::general_purpose::STANDARD;
@@ -165,7 +252,7 @@ printf '%s\n' "$KEY" >> ~/.ssh/authorized_keys
 echo "added"
 "#;
 
-    let mut child = match Command::new(&config.ssh_client_app_path)
+    let mut child = Command::new(&config.ssh_client_app_path)
         .args([
             format!("-p{}", server.port),
             format!("{}@{}", server.username, server.address),
@@ -176,13 +263,9 @@ echo "added"
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
-    {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("⚠️ 无法执行 ssh: {} (路径: {})", e, config.ssh_client_app_path.display());
-            return Ok(());
-        }
-    };
+        .with_context(|| {
+            format!("无法执行 ssh (路径: {})", config.ssh_client_app_path.display())
+        })?;
 
     if let Some(mut stdin) = child.stdin.take() {
         use std::io::Write as _;
@@ -192,31 +275,23 @@ echo "added"
         }
     }
 
-    match child.wait_with_output() {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if output.status.success() {
-                if stdout.contains("already-present") {
-                    println!("公钥已存在于 {}@{}", server.username, server.address);
-                } else if stdout.contains("added") {
-                    println!(
-                        "已将本地公钥添加到 {}@{} 的 authorized_keys",
-                        server.username, server.address
-                    );
-                } else {
-                    println!("已完成公钥安装（远端输出: {}）", stdout.trim());
-                }
-            } else {
-                eprintln!(
-                    "❌ 远端处理失败，退出码: {}，输出: {}",
-                    output.status.code().unwrap_or(-1),
-                    stdout.trim()
-                );
-            }
-        }
-        Err(e) => eprintln!("❌ 等待 ssh 进程失败: {}", e),
+    let output = child.wait_with_output().context("等待 ssh 进程失败")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        return Err(TransferError::OperationFailed(format!(
+            "远端处理失败，退出码: {}，输出: {}",
+            output.status.code().unwrap_or(-1),
+            stdout.trim()
+        ))
+        .into());
+    }
+    if stdout.contains("already-present") {
+        println!("公钥已存在于 {}@{}", server.username, server.address);
+    } else if stdout.contains("added") {
+        println!("已将本地公钥添加到 {}@{} 的 authorized_keys", server.username, server.address);
+    } else {
+        println!("已完成公钥安装（远端输出: {}）", stdout.trim());
     }
-
     Ok(())
 }
 
@@ -245,7 +320,7 @@ pub fn handle_set(
         cfg.scp_app_path = scp;
     }
     // 写回配置文件（使用默认位置） — Write back to config file (use default location)
-    cfg.save_to_storage();
+    cfg.save_to_storage()?;
     println!("✅ 配置已更新");
     Ok(())
 }
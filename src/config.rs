@@ -51,7 +51,10 @@ impl Config {
                         version: Some(2),
                         mode,
                     };
-                    config.save_to(&config_file_path);
+                    if let Err(e) = config.save_to(&config_file_path) {
+                        eprintln!("⚠️ 无法写入初始配置: {:#}", e);
+                        std::process::exit(1);
+                    }
                 }
                 let mut conf: Config = Config::read_from(chosen_config);
                 conf.mode = mode;
@@ -65,24 +68,19 @@ impl Config {
     }
 
     /// 将配置保存回 $HOME/.{pkgname}/config.json — Save config back to expected config.json under $HOME/.{pkgname}/config.json
-    pub fn save_to_storage(&self) {
-        if let Some(home_dir) = dirs::home_dir() {
-            let config_storage_dir = match crate::ops::ensure_hostpilot_dir(&home_dir) {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("⚠️ 无法准备配置目录: {}", e);
-                    return;
-                }
-            };
-            // 根据 mode 决定写回到哪一个配置文件；mode==1 时写回 config_test.json
-            let config_path = if self.mode == 1 {
-                config_storage_dir.join("config_test.json")
-            } else {
-                config_storage_dir.join("config.json")
-            };
-            self.save_to(&config_path);
+    pub fn save_to_storage(&self) -> anyhow::Result<()> {
+        let home_dir = dirs::home_dir().ok_or_else(|| {
+            crate::TransferError::OperationFailed("无法找到 home 目录，无法保存配置".to_string())
+        })?;
+        let config_storage_dir = crate::ops::ensure_hostpilot_dir(&home_dir).map_err(|e| {
+            crate::TransferError::OperationFailed(format!("无法准备配置目录: {}", e))
+        })?;
+        // 根据 mode 决定写回到哪一个配置文件；mode==1 时写回 config_test.json
+        let config_path = if self.mode == 1 {
+            config_storage_dir.join("config_test.json")
         } else {
-            eprintln!("⚠️ 无法找到 home 目录，无法保存配置");
-        }
+            config_storage_dir.join("config.json")
+        };
+        self.save_to(&config_path)
     }
 }
@@ -28,6 +28,36 @@ fn display_path(p: &std::path::Path) -> String {
     crate::transfer::normalize_path(&s, true)
 }
 
+/// 单个文件经重试后仍失败时的源与目标，用于 `hp ts --retry-failed` 重放。
+/// `source`/`destination` 使用 ts 的参数形式（本地路径或 alias:/path）。
+#[derive(Debug, Clone)]
+pub struct FailedEntry {
+    pub alias: String,
+    pub source: String,
+    pub destination: String,
+}
+
+/// Process exit codes of `hp`; every `TransferError` maps onto one of them
+/// (`TransferError::exit_code`). 0 is success; clap reports its own usage errors with 2.
+pub mod exit_code {
+    /// 其他错误（IO、数据库、配置等未归类的失败）
+    pub const FAILURE: i32 = 1;
+    /// 参数无效或选项组合不支持
+    pub const USAGE: i32 = 2;
+    pub const ALIAS_NOT_FOUND: i32 = 3;
+    pub const ALIAS_EXISTS: i32 = 4;
+    /// SSH 认证失败
+    pub const AUTH: i32 = 5;
+    /// 地址解析、建连、握手或 SFTP 通道失败
+    pub const CONNECT: i32 = 6;
+    /// 源或目标路径不可用（不存在、不是目录、无法创建、glob 无匹配）
+    pub const PATH: i32 = 7;
+    /// 部分文件或主机失败，其余已完成
+    pub const PARTIAL: i32 = 8;
+    /// 所有文件或主机均失败
+    pub const ALL_FAILED: i32 = 9;
+}
+
 /// Higher-level transfer command errors that are useful to represent
 /// programmatically instead of ad-hoc formatted strings.
 #[derive(Debug, Clone)]
@@ -52,10 +82,24 @@ pub enum TransferError {
     SshAuthFailed(String),
     WorkerBuildSessionFailed(String),
     // command validation / generic
+    InvalidArgument(String),
+    AliasExists(String),
     MissingLocalSource(String),
     DownloadMultipleRemoteSources(String),
     OperationFailed(String),
     WorkerIo(String),
+    // mirror (--delete) related
+    MirrorUnsupported(String),
+    SourceIncomplete(String),
+    DeleteLimitExceeded(usize, usize),
+    DeleteFailed(String, String),
+    // attribute preservation (-p/--chmod/--umask); the file itself was transferred
+    PreserveFailed(String, String),
+    // final failure of one file, carrying what is needed to replay it
+    EntryFailed(Box<FailedEntry>, Box<TransferError>),
+    // outcome of a run whose failures were already reported (some / all units failed)
+    PartialFailure(String),
+    AllFailed(String),
 }
 
 impl std::fmt::Display for TransferError {
@@ -63,9 +107,9 @@ impl std::fmt::Display for TransferError {
         use TransferError::*;
         match self {
             InvalidDirection => {
-                write!(f, "命令必须且只有一端为远端（alias:/path），请检查源和目标")
+                write!(f, "源和目标至少一端须为远端（alias:/path），请检查源和目标")
             }
-            UnsupportedGlobUsage(s) => write!(f, "不支持的通配符用法（仅允许最后一段）：{}", s),
+            UnsupportedGlobUsage(s) => write!(f, "不支持的通配符用法（语法错误）：{}", s),
             AliasNotFound(a) => write!(f, "别名 '{}' 不存在", a),
             RemoteTargetMustBeDir(b) => write!(f, "目标必须存在且为目录: {} (远端)", b),
             RemoteTargetParentMissing(p) => {
@@ -92,10 +136,26 @@ impl std::fmt::Display for TransferError {
             SshHandshakeFailed(addr) => write!(f, "SSH 握手失败: {}", addr),
             SshAuthFailed(addr) => write!(f, "SSH 认证失败: {}", addr),
             WorkerBuildSessionFailed(addr) => write!(f, "工作线程构建会话失败: {}", addr),
+            InvalidArgument(s) => write!(f, "参数错误: {}", s),
+            AliasExists(a) => write!(f, "别名 '{}' 已存在", a),
             MissingLocalSource(s) => write!(f, "缺少本地源: {}", s),
-            DownloadMultipleRemoteSources(s) => write!(f, "下载仅支持单个远端源: {}", s),
+            DownloadMultipleRemoteSources(s) => write!(f, "仅支持单个远端源: {}", s),
             OperationFailed(s) => write!(f, "操作失败: {}", s),
             WorkerIo(s) => write!(f, "传输/IO 错误: {}", s),
+            MirrorUnsupported(s) => {
+                write!(f, "--delete 仅支持目录同步（源为目录且不含通配符，目标为目录）: {}", s)
+            }
+            SourceIncomplete(p) => {
+                write!(f, "源端枚举不完整（无法读取: {}），已跳过镜像删除", p)
+            }
+            DeleteLimitExceeded(planned, max) => {
+                write!(f, "待删除条目 {} 超过上限 {}，已放弃镜像删除", planned, max)
+            }
+            DeleteFailed(path, msg) => write!(f, "删除失败: {} — {}", path, msg),
+            PreserveFailed(path, msg) => write!(f, "保留文件属性失败: {} — {}", path, msg),
+            EntryFailed(_, cause) => write!(f, "{}", cause),
+            PartialFailure(s) => write!(f, "部分失败: {}", s),
+            AllFailed(s) => write!(f, "全部失败: {}", s),
         }
     }
 }
@@ -103,6 +163,21 @@ impl std::fmt::Display for TransferError {
 impl std::error::Error for TransferError {}
 
 impl TransferError {
+    /// Attach the source and destination of the file this error ended.
+    pub fn for_entry(
+        self,
+        alias: impl Into<String>,
+        source: impl Into<String>,
+        destination: impl Into<String>,
+    ) -> Self {
+        let entry = FailedEntry {
+            alias: alias.into(),
+            source: source.into(),
+            destination: destination.into(),
+        };
+        TransferError::EntryFailed(Box::new(entry), Box::new(self))
+    }
+
     /// Whether this error is considered retriable when it occurs before an
     /// actual data transfer starts (session/SFTP establishment, pre-checks,
     /// mkdir checks, etc.). Conservative defaults: network/handshake related
@@ -110,6 +185,7 @@ impl TransferError {
     pub fn is_retriable_pre_transfer(&self) -> bool {
         use TransferError::*;
         match self {
+            EntryFailed(_, cause) => cause.is_retriable_pre_transfer(),
             // retriable: transient connection/session issues
             SshSessionCreateFailed(_)
             | SshHandshakeFailed(_)
@@ -129,7 +205,13 @@ impl TransferError {
             | LocalTargetMustBeDir(_)
             | GlobNoMatches(_)
             | CreateLocalDirFailed(_, _)
-            | CreateRemoteDirFailed(_, _) => false,
+            | CreateRemoteDirFailed(_, _)
+            | MirrorUnsupported(_)
+            | DeleteLimitExceeded(_, _)
+            | InvalidArgument(_)
+            | AliasExists(_)
+            | PartialFailure(_)
+            | AllFailed(_) => false,
             // fallback: treat unknown/generic as non-retriable by default
             _ => false,
         }
@@ -142,6 +224,7 @@ impl TransferError {
     pub fn is_retriable_during_transfer(&self) -> bool {
         use TransferError::*;
         match self {
+            EntryFailed(_, cause) => cause.is_retriable_during_transfer(),
             // transient IO/network errors -> retriable
             WorkerIo(_) | SftpCreateFailed(_) | WorkerNoSftp(_) | WorkerNoSession(_) => true,
             // non-retriable: permission/validation style errors
@@ -158,9 +241,141 @@ impl TransferError {
             | CreateLocalDirFailed(_, _)
             | CreateRemoteDirFailed(_, _)
             | DownloadMultipleRemoteSources(_)
-            | OperationFailed(_) => false,
+            | OperationFailed(_)
+            | MirrorUnsupported(_)
+            | SourceIncomplete(_)
+            | DeleteLimitExceeded(_, _)
+            | DeleteFailed(_, _)
+            | PreserveFailed(_, _)
+            | InvalidArgument(_)
+            | AliasExists(_)
+            | PartialFailure(_)
+            | AllFailed(_) => false,
             // conservative default
             _ => false,
         }
     }
+
+    /// Process exit code of a command that ended with this error (see `exit_code`).
+    pub fn exit_code(&self) -> i32 {
+        use TransferError::*;
+        match self {
+            EntryFailed(_, cause) => cause.exit_code(),
+            InvalidDirection
+            | UnsupportedGlobUsage(_)
+            | DownloadMultipleRemoteSources(_)
+            | MirrorUnsupported(_)
+            | InvalidArgument(_) => exit_code::USAGE,
+            AliasNotFound(_) => exit_code::ALIAS_NOT_FOUND,
+            AliasExists(_) => exit_code::ALIAS_EXISTS,
+            SshAuthFailed(_) => exit_code::AUTH,
+            SshNoAddress(_)
+            | SshSessionCreateFailed(_)
+            | SshHandshakeFailed(_)
+            | WorkerBuildSessionFailed(_)
+            | WorkerNoSession(_)
+            | WorkerNoSftp(_)
+            | SftpCreateFailed(_) => exit_code::CONNECT,
+            MissingLocalSource(_)
+            | RemoteTargetMustBeDir(_)
+            | RemoteTargetParentMissing(_)
+            | CreateRemoteDirFailed(_, _)
+            | LocalTargetMustBeDir(_)
+            | LocalTargetParentMissing(_)
+            | CreateLocalDirFailed(_, _)
+            | GlobNoMatches(_) => exit_code::PATH,
+            // the files themselves were transferred; deletion/attributes did not complete
+            SourceIncomplete(_)
+            | DeleteLimitExceeded(_, _)
+            | DeleteFailed(_, _)
+            | PreserveFailed(_, _)
+            | PartialFailure(_) => exit_code::PARTIAL,
+            AllFailed(_) => exit_code::ALL_FAILED,
+            OperationFailed(_) | WorkerIo(_) => exit_code::FAILURE,
+        }
+    }
+
+    /// Number of files among `failures`: entries that stayed failed after their retries.
+    pub fn failed_files(failures: &[TransferError]) -> u64 {
+        failures.iter().filter(|e| matches!(e, TransferError::EntryFailed(..))).count() as u64
+    }
+
+    /// Outcome of a run over `files` entries that reported `failures`: `None` when nothing
+    /// failed, `AllFailed` when every failure ended a file and no file is left over.
+    pub fn for_run(failures: &[TransferError], files: u64) -> Option<Self> {
+        if failures.is_empty() {
+            return None;
+        }
+        let failed_files = Self::failed_files(failures);
+        let summary = format!("{} 项失败（共 {} 个文件）", failures.len(), files);
+        if failed_files >= files {
+            Some(TransferError::AllFailed(summary))
+        } else {
+            Some(TransferError::PartialFailure(summary))
+        }
+    }
+}
+
+/// Exit code of a failed command: the first `TransferError` (or `MkdirError`) in the
+/// error chain decides; TCP connect errors count as connection failures, anything else
+/// is a generic failure.
+pub fn exit_code_of(err: &anyhow::Error) -> i32 {
+    if let Some(e) = err.downcast_ref::<TransferError>() {
+        return e.exit_code();
+    }
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<TransferError>() {
+            return e.exit_code();
+        }
+        if cause.downcast_ref::<MkdirError>().is_some() {
+            return exit_code::PATH;
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind::*;
+            if matches!(
+                e.kind(),
+                ConnectionRefused
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | TimedOut
+                    | HostUnreachable
+                    | NetworkUnreachable
+            ) {
+                return exit_code::CONNECT;
+            }
+        }
+    }
+    exit_code::FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context as _;
+
+    #[test]
+    fn exit_codes_follow_the_cause_through_entries_and_context() {
+        let auth = TransferError::SshAuthFailed("h:22".into()).for_entry("web", "a", "web:/a");
+        assert_eq!(auth.exit_code(), exit_code::AUTH);
+        let err: anyhow::Error = TransferError::AliasNotFound("web".into()).into();
+        assert_eq!(exit_code_of(&err.context("解析目标失败")), exit_code::ALIAS_NOT_FOUND);
+        let io: Result<(), std::io::Error> = Err(std::io::Error::other("disk"));
+        assert_eq!(exit_code_of(&io.context("写入失败").unwrap_err()), exit_code::FAILURE);
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(exit_code_of(&refused.into()), exit_code::CONNECT);
+    }
+
+    #[test]
+    fn run_outcome_distinguishes_partial_and_total_failure() {
+        let entry = || TransferError::WorkerIo("x".into()).for_entry("web", "a", "web:/a");
+        assert!(TransferError::for_run(&[], 3).is_none());
+        let partial = TransferError::for_run(&[entry()], 3).unwrap();
+        assert_eq!(partial.exit_code(), exit_code::PARTIAL);
+        let all = TransferError::for_run(&[entry(), entry()], 2).unwrap();
+        assert_eq!(all.exit_code(), exit_code::ALL_FAILED);
+        // a failed deletion after every file arrived is not a total failure
+        let mirror =
+            TransferError::for_run(&[TransferError::DeleteFailed("p".into(), "e".into())], 2);
+        assert_eq!(mirror.unwrap().exit_code(), exit_code::PARTIAL);
+    }
 }
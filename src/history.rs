@@ -0,0 +1,344 @@
+// Transfer history kept in server.db: one row per `hp ts` run (one per host for tag
+// fan-out) with the summary `finalize_transfer` prints. Read by `hp history` and by the
+// host detail popup of the TUI.
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use cli_table::{Cell, CellStruct, Style, Table, format::Justify, print_stdout};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Summary of one transfer run.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TransferRecord {
+    pub id: i64,
+    // unix seconds when the run finished
+    pub finished_at: i64,
+    // upload / download / relay / direct
+    pub direction: String,
+    pub alias: Option<String>,
+    pub sources: Vec<String>,
+    pub target: String,
+    pub bytes: u64,
+    pub files: u64,
+    pub elapsed_secs: f64,
+    pub session_rebuilds: u64,
+    pub sftp_rebuilds: u64,
+    pub failures: u64,
+    // journaled job of the run (`hp jobs`)
+    pub job_id: Option<i64>,
+}
+
+/// `direction` of a `--direct` copy: the source host moved the data itself, so bytes and
+/// files are unknown and the run is left out of the throughput averages.
+pub const DIRECT: &str = "direct";
+
+impl TransferRecord {
+    /// Whether bytes and files were measured (every run but `--direct` copies).
+    pub fn measured(&self) -> bool {
+        self.direction != DIRECT
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        if self.elapsed_secs > 0.0 { self.bytes as f64 / self.elapsed_secs } else { 0.0 }
+    }
+}
+
+/// Recent runs of one alias and its average throughput over every recorded run.
+#[derive(Debug, Default)]
+pub struct HostStats {
+    pub recent: Vec<TransferRecord>,
+    pub runs: u64,
+    pub total_bytes: u64,
+    pub avg_bytes_per_sec: f64,
+}
+
+/// Create the `transfers` table if needed.
+pub fn ensure_history_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            finished_at INTEGER NOT NULL,
+            direction TEXT NOT NULL,
+            alias TEXT,
+            sources TEXT NOT NULL,
+            target TEXT NOT NULL,
+            bytes INTEGER NOT NULL,
+            files INTEGER NOT NULL,
+            elapsed_secs REAL NOT NULL,
+            session_rebuilds INTEGER NOT NULL,
+            sftp_rebuilds INTEGER NOT NULL,
+            failures INTEGER NOT NULL,
+            job_id INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS transfers_alias ON transfers (alias, finished_at);",
+    )
+}
+
+fn open_history<P: AsRef<Path>>(path: P) -> Result<Connection> {
+    let conn = Connection::open(path).with_context(|| "Failed to open SQLite database")?;
+    ensure_history_schema(&conn).with_context(|| "Failed to create transfers table")?;
+    Ok(conn)
+}
+
+/// Append one run to the history.
+pub fn record_transfer<P: AsRef<Path>>(path: P, rec: &TransferRecord) -> Result<()> {
+    let conn = open_history(path)?;
+    conn.execute(
+        "INSERT INTO transfers (finished_at, direction, alias, sources, target, bytes, files,
+            elapsed_secs, session_rebuilds, sftp_rebuilds, failures, job_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            rec.finished_at,
+            rec.direction,
+            rec.alias,
+            serde_json::to_string(&rec.sources)?,
+            rec.target,
+            rec.bytes as i64,
+            rec.files as i64,
+            rec.elapsed_secs,
+            rec.session_rebuilds as i64,
+            rec.sftp_rebuilds as i64,
+            rec.failures as i64,
+            rec.job_id,
+        ],
+    )
+    .with_context(|| "Failed to insert transfer record")?;
+    Ok(())
+}
+
+/// Move the history of an alias along with `hp mv`.
+pub fn rename_alias<P: AsRef<Path>>(path: P, old: &str, new: &str) -> Result<()> {
+    let conn = open_history(path)?;
+    conn.execute("UPDATE transfers SET alias = ?2 WHERE alias = ?1", params![old, new])?;
+    Ok(())
+}
+
+fn record_row(r: &rusqlite::Row) -> rusqlite::Result<TransferRecord> {
+    let sources: String = r.get(4)?;
+    Ok(TransferRecord {
+        id: r.get(0)?,
+        finished_at: r.get(1)?,
+        direction: r.get(2)?,
+        alias: r.get(3)?,
+        sources: serde_json::from_str(&sources).unwrap_or_default(),
+        target: r.get(5)?,
+        bytes: r.get::<_, i64>(6)? as u64,
+        files: r.get::<_, i64>(7)? as u64,
+        elapsed_secs: r.get(8)?,
+        session_rebuilds: r.get::<_, i64>(9)? as u64,
+        sftp_rebuilds: r.get::<_, i64>(10)? as u64,
+        failures: r.get::<_, i64>(11)? as u64,
+        job_id: r.get(12)?,
+    })
+}
+
+/// Runs newest first, optionally of one alias and finished at or after `since`.
+pub fn load_history<P: AsRef<Path>>(
+    path: P,
+    alias: Option<&str>,
+    since: Option<i64>,
+    limit: usize,
+) -> Result<Vec<TransferRecord>> {
+    let conn = open_history(path)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, finished_at, direction, alias, sources, target, bytes, files, elapsed_secs,
+            session_rebuilds, sftp_rebuilds, failures, job_id
+         FROM transfers
+         WHERE (?1 IS NULL OR alias = ?1) AND (?2 IS NULL OR finished_at >= ?2)
+         ORDER BY finished_at DESC, id DESC LIMIT ?3",
+    )?;
+    let rows = stmt
+        .query_map(params![alias, since, limit as i64], record_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// The `recent` latest runs of an alias plus totals over all of its runs; the average
+/// covers measured runs only.
+pub fn host_stats<P: AsRef<Path>>(path: P, alias: &str, recent: usize) -> Result<HostStats> {
+    let conn = open_history(&path)?;
+    let (runs, total_bytes, total_secs): (i64, i64, f64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(bytes), 0),
+            COALESCE(SUM(CASE WHEN direction = ?2 THEN 0 ELSE elapsed_secs END), 0)
+         FROM transfers WHERE alias = ?1",
+        params![alias, DIRECT],
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+    )?;
+    Ok(HostStats {
+        recent: load_history(&path, Some(alias), None, recent)?,
+        runs: runs as u64,
+        total_bytes: total_bytes as u64,
+        avg_bytes_per_sec: if total_secs > 0.0 { total_bytes as f64 / total_secs } else { 0.0 },
+    })
+}
+
+/// `--since`: a relative span (`30m`, `12h`, `7d`, `2w`) or a local date/time
+/// (`2026-10-01`, `2026-10-01 08:00`). Returns unix seconds.
+pub fn parse_since(s: &str, now: i64) -> Result<i64> {
+    let s = s.trim();
+    let invalid = || -> anyhow::Error {
+        crate::TransferError::InvalidArgument(format!(
+            "无效的 --since: {}（例如 7d、12h、2026-10-01）",
+            s
+        ))
+        .into()
+    };
+    if let Some(unit) = s.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let n: i64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        let secs = match unit {
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => return Err(invalid()),
+        };
+        return Ok(now - n * secs);
+    }
+    let naive = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| invalid())?;
+    naive.and_local_timezone(chrono::Local).earliest().map(|t| t.timestamp()).ok_or_else(invalid)
+}
+
+pub fn fmt_time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+pub fn fmt_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", crate::util::human_bytes(bytes_per_sec as u64))
+}
+
+// `-` in place of the bytes/files/rate columns of a `--direct` copy.
+fn measured(r: &TransferRecord, value: String) -> String {
+    if r.measured() { value } else { "-".to_string() }
+}
+
+/// `hp history [alias] [--since] [--json]`
+pub fn handle_history(
+    config: &Config,
+    alias: Option<String>,
+    since: Option<String>,
+    limit: usize,
+    json: bool,
+) -> Result<()> {
+    let since = since.map(|s| parse_since(&s, chrono::Local::now().timestamp())).transpose()?;
+    let records = load_history(&config.server_file_path, alias.as_deref(), since, limit)?;
+    if json {
+        println!("{}", serde_json::to_string(&records)?);
+        return Ok(());
+    }
+    if records.is_empty() {
+        println!("暂无传输记录");
+        return Ok(());
+    }
+    let title = vec![
+        "Finished".cell().bold(true),
+        "Dir".cell().bold(true),
+        "Alias".cell().bold(true),
+        "Files".cell().bold(true),
+        "Bytes".cell().bold(true),
+        "Elapsed".cell().bold(true),
+        "Rate".cell().bold(true),
+        "Failed".cell().bold(true),
+        "Transfer".cell().bold(true),
+    ];
+    let rows: Vec<Vec<CellStruct>> = records
+        .iter()
+        .map(|r| {
+            vec![
+                fmt_time(r.finished_at).cell(),
+                r.direction.clone().cell(),
+                r.alias.clone().unwrap_or_else(|| "-".to_string()).cell(),
+                measured(r, r.files.to_string()).cell().justify(Justify::Right),
+                measured(r, crate::util::human_bytes(r.bytes)).cell().justify(Justify::Right),
+                format!("{:.1}s", r.elapsed_secs).cell().justify(Justify::Right),
+                measured(r, fmt_rate(r.bytes_per_sec())).cell().justify(Justify::Right),
+                r.failures.cell().justify(Justify::Right),
+                format!("{} -> {}", r.sources.join(" "), r.target).cell(),
+            ]
+        })
+        .collect();
+    if let Err(e) = print_stdout(rows.table().title(title)) {
+        eprintln!("⚠️ 无法渲染表格: {}", e);
+    }
+    if let Some(a) = alias.as_deref() {
+        let stats = host_stats(&config.server_file_path, a, 0)?;
+        println!(
+            "{}: 共 {} 次传输, {}, 平均速率 {}",
+            a,
+            stats.runs,
+            crate::util::human_bytes(stats.total_bytes),
+            fmt_rate(stats.avg_bytes_per_sec)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(alias: &str, finished_at: i64, bytes: u64, elapsed_secs: f64) -> TransferRecord {
+        TransferRecord {
+            finished_at,
+            direction: "upload".to_string(),
+            alias: Some(alias.to_string()),
+            sources: vec!["./dist/".to_string()],
+            target: format!("{}:/srv/app/", alias),
+            bytes,
+            files: 3,
+            elapsed_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn history_filters_by_alias_and_time_and_averages_throughput() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("hp_history_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        record_transfer(&path, &run("web", 100, 1000, 1.0)).unwrap();
+        record_transfer(&path, &run("web", 200, 3000, 1.0)).unwrap();
+        record_transfer(&path, &run("db", 300, 10, 1.0)).unwrap();
+
+        let all = load_history(&path, None, None, 10).unwrap();
+        assert_eq!(all.iter().map(|r| r.finished_at).collect::<Vec<_>>(), [300, 200, 100]);
+        let web = load_history(&path, Some("web"), Some(150), 10).unwrap();
+        assert_eq!(web.len(), 1);
+        assert_eq!(web[0].sources, ["./dist/"]);
+
+        let stats = host_stats(&path, "web", 1).unwrap();
+        assert_eq!((stats.runs, stats.total_bytes, stats.recent.len()), (2, 4000, 1));
+        assert_eq!(stats.avg_bytes_per_sec, 2000.0);
+
+        // a --direct copy counts as a run but not towards the average
+        let direct = TransferRecord { direction: DIRECT.to_string(), ..run("web", 400, 0, 5.0) };
+        record_transfer(&path, &direct).unwrap();
+        let stats = host_stats(&path, "web", 1).unwrap();
+        assert_eq!((stats.runs, stats.total_bytes), (3, 4000));
+        assert_eq!(stats.avg_bytes_per_sec, 2000.0);
+        assert!(!stats.recent[0].measured());
+
+        rename_alias(&path, "web", "web2").unwrap();
+        assert_eq!(host_stats(&path, "web2", 0).unwrap().runs, 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn since_accepts_spans_and_dates() {
+        assert_eq!(parse_since("30m", 10_000).unwrap(), 10_000 - 1800);
+        assert_eq!(parse_since("2d", 1_000_000).unwrap(), 1_000_000 - 2 * 86400);
+        let day = parse_since("2026-10-01", 0).unwrap();
+        assert_eq!(parse_since("2026-10-01 08:00", 0).unwrap(), day + 8 * 3600);
+        assert!(parse_since("7x", 0).is_err());
+        assert!(parse_since("yesterday", 0).is_err());
+    }
+}
@@ -13,6 +13,7 @@ mod cli;
 mod commands;
 mod config;
 mod error;
+mod history;
 mod ops;
 mod parse;
 mod server;
@@ -44,16 +45,50 @@ fn main() -> Result<()> {
         Some(cli::Commands::List {}) => commands::handle_list(&config),
         Some(cli::Commands::Remove { alias }) => commands::handle_remove(&config, alias),
         Some(cli::Commands::Link { alias }) => commands::handle_link(&config, alias),
+        Some(cli::Commands::Limit { alias, rate }) => commands::handle_limit(&config, alias, rate),
+        Some(cli::Commands::Handshakes { alias, limit }) => {
+            commands::handle_handshakes(&config, alias, limit)
+        }
+        Some(cli::Commands::Tag { alias, tags, remove }) => {
+            commands::handle_tag(&config, alias, tags, remove)
+        }
         Some(cli::Commands::Ts {
-            sources,
-            target,
+            mut paths,
             concurrency,
             verbose,
             json,
             quiet,
+            events,
+            events_to,
             retry,
             retry_backoff_ms,
             buf_mib,
+            delete,
+            delete_dry_run,
+            max_delete,
+            dry_run,
+            exclude,
+            include,
+            exclude_from,
+            preserve,
+            chmod,
+            umask,
+            links,
+            no_clobber,
+            update,
+            backup,
+            limit_rate,
+            direct,
+            parallel_hosts,
+            fail_fast,
+            // the default policy; accepted so scripts can state it explicitly
+            keep_going: _,
+            host_dir,
+            tar,
+            chunk_threshold,
+            prescan,
+            resume,
+            retry_failed,
         }) => {
             // 默认并发改为 auto（由 transfer 根据文件数/大小选择），上限提高到 32
             // concurrency 可以为 numeric 或 "auto"；当未提供或为 "auto" 时传 None
@@ -70,8 +105,12 @@ fn main() -> Result<()> {
             if let Some(ms) = retry_backoff_ms {
                 util::set_backoff_ms(ms);
             }
+            if events.is_some() {
+                transfer::init_events(events_to.as_deref())?;
+            }
+            let target = paths.pop().unwrap_or_default();
             let args = transfer::HandleTsArgs {
-                sources,
+                sources: paths,
                 target,
                 verbose,
                 json,
@@ -79,9 +118,64 @@ fn main() -> Result<()> {
                 concurrency: conc_opt,
                 max_retries,
                 buf_size: buf_mib.map(|m| m.clamp(1, 8) * 1024 * 1024).unwrap_or(1024 * 1024),
+                delete,
+                delete_dry_run,
+                max_delete,
+                dry_run,
+                excludes: exclude,
+                includes: include,
+                exclude_from,
+                preserve,
+                chmod,
+                umask,
+                links,
+                no_clobber,
+                update,
+                backup,
+                limit_rate,
+                direct,
+                parallel_hosts,
+                fail_fast,
+                host_dir,
+                tar,
+                chunk_threshold,
+                prescan,
+                resume,
+                retry_failed,
             };
             transfer::handle_ts(&config, args)
         }
+        Some(cli::Commands::History { alias, since, json, limit }) => {
+            history::handle_history(&config, alias, since, limit, json)
+        }
+        Some(cli::Commands::Jobs { action }) => match action.unwrap_or(cli::JobsAction::Ls) {
+            cli::JobsAction::Ls => transfer::handle_jobs_ls(&config),
+            cli::JobsAction::Show { id, all } => transfer::handle_jobs_show(&config, id, all),
+            cli::JobsAction::Rm { ids, done } => transfer::handle_jobs_rm(&config, &ids, done),
+        },
+        #[cfg(unix)]
+        Some(cli::Commands::Agent { action }) => {
+            match action
+                .unwrap_or(cli::AgentAction::Start { foreground: false, idle_timeout: None })
+            {
+                cli::AgentAction::Start { foreground, idle_timeout } => {
+                    transfer::handle_agent_start(&config, foreground, idle_timeout)
+                }
+                cli::AgentAction::Status => transfer::handle_agent_status(),
+                cli::AgentAction::Stop => transfer::handle_agent_stop(),
+            }
+        }
+        #[cfg(not(unix))]
+        Some(cli::Commands::Agent { .. }) => {
+            Err(TransferError::OperationFailed("hp agent 仅支持 Unix 平台".to_string()).into())
+        }
+        Some(cli::Commands::Exec { alias, command }) => {
+            let code = transfer::handle_exec(&config, &alias, &command)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+            Ok(())
+        }
         Some(cli::Commands::Set { pub_key_path, server_path, client_path, scp_path }) => {
             commands::handle_set(&config, pub_key_path, server_path, client_path, scp_path)
         }
@@ -90,24 +184,24 @@ fn main() -> Result<()> {
             if cli.alias != "-" {
                 // 连接到提供的别名 — Connect to the provided alias
                 let mut collection = ServerCollection::read_from_storage(&config.server_file_path)?;
-                if let Some(server) = collection.get(&cli.alias) {
-                    let host = format!("{}@{}", server.username, server.address);
-                    let port = format!("-p{}", server.port);
-                    let args = vec![host, port];
-                    let status = std::process::Command::new(&config.ssh_client_app_path)
-                        .args(args)
-                        .status()?;
+                let Some(server) = collection.get(&cli.alias) else {
+                    exit_with(TransferError::AliasNotFound(cli.alias).into());
+                };
+                let host = format!("{}@{}", server.username, server.address);
+                let port = format!("-p{}", server.port);
+                let args = vec![host, port];
+                let status =
+                    std::process::Command::new(&config.ssh_client_app_path).args(args).status()?;
 
-                    // 在连接成功后更新 last_connect 时间戳 — Update last_connect timestamp after successful connection
-                    if status.success() {
-                        let mut updated_server = server.clone();
-                        updated_server.set_last_connect_now();
-                        collection.insert(cli.alias.as_str(), updated_server);
-                        collection.save_to_storage(&config.server_file_path)?;
-                    }
-                } else {
-                    eprintln!("❌ 别名 '{}' 未找到", cli.alias);
+                // 在连接成功后更新 last_connect 时间戳 — Update last_connect timestamp after successful connection
+                if !status.success() {
+                    // 与 hp exec 一致，透传 ssh 的退出码 — pass ssh's exit code through, like hp exec
+                    std::process::exit(status.code().unwrap_or(error::exit_code::FAILURE));
                 }
+                let mut updated_server = server.clone();
+                updated_server.set_last_connect_now();
+                collection.insert(cli.alias.as_str(), updated_server);
+                collection.save_to_storage(&config.server_file_path)?;
                 Ok(())
             } else {
                 // 未指定命令，运行 TUI — No command, run TUI
@@ -120,8 +214,17 @@ fn main() -> Result<()> {
         }
     };
 
-    res?;
-    Ok(())
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) => exit_with(e),
+    }
+}
+
+/// 打印错误并以文档约定的退出码结束进程（见 README “退出码”）
+/// — print the error and exit with the documented code of its cause.
+fn exit_with(err: anyhow::Error) -> ! {
+    eprintln!("Error: {:?}", err);
+    std::process::exit(error::exit_code_of(&err));
 }
 
 fn init_tracing_if_requested(cfg: &config::Config, debug: bool) {
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossterm::cursor::{Hide, Show};
 use crossterm::execute;
 use crossterm::terminal::{
@@ -267,6 +267,9 @@ pub fn upgrade_config_and_data(_config: &crate::config::Config) -> Result<()> {
                             address: address.to_string(),
                             port: port as u16,
                             last_connect: None, // Initialize as None for migrated servers
+                            limit_rate: None,
+                            tags: Vec::new(),
+                            max_handshakes: None,
                         };
                         collection.insert(alias, server);
                     }
@@ -288,9 +291,8 @@ pub fn upgrade_config_and_data(_config: &crate::config::Config) -> Result<()> {
 
     // 创建数据库并保存数据 — Create database and save data
     create_sqlite_database(&db_path)?;
-    if let Err(e) = collection.save_to_storage(&db_path) {
-        eprintln!("⚠️ 保存迁移后的集合到 SQLite 失败: {}", e);
-    }
+    // config.json must not point at a database that lost the migrated hosts
+    collection.save_to_storage(&db_path).context("保存迁移后的集合到 SQLite 失败")?;
 
     // 第 3 步：更新 config.json — Step 3: Update config.json
     println!("📝 Updating config.json...");
@@ -317,21 +319,11 @@ pub fn create_sqlite_database(db_path: &std::path::Path) -> Result<()> {
     let conn = Connection::open(db_path)?;
 
     // 使用新 schema 创建 servers 表（id + alias 唯一） — Create servers table with new schema (id + alias unique)
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS servers (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            alias TEXT UNIQUE NOT NULL,
-            username TEXT NOT NULL,
-            address TEXT NOT NULL,
-            port INTEGER NOT NULL,
-            last_connect TEXT
-        )",
-        [],
-    )?;
+    crate::server::ensure_servers_schema(&conn)?;
 
     println!("   🗄️  SQLite database ensured with servers table");
     println!(
-        "   📋  Table structure: id (PK AUTOINCREMENT), alias (UNIQUE), username, address, port, last_connect"
+        "   📋  Table structure: id (PK AUTOINCREMENT), alias (UNIQUE), username, address, port, last_connect, limit_rate, tags, max_handshakes"
     );
 
     Ok(())
@@ -2,14 +2,14 @@ use anyhow::Result;
 
 pub fn parse_remote_host(input: &str) -> Result<(String, String, u16)> {
     let at_pos = input.find('@').ok_or_else(|| -> anyhow::Error {
-        crate::TransferError::OperationFailed("缺少用户名，例如 user@host[:port]".to_string())
+        crate::TransferError::InvalidArgument("缺少用户名，例如 user@host[:port]".to_string())
             .into()
     })?;
     let (user_part, host_part) = input.split_at(at_pos);
     let user = user_part.trim();
     let host_port = &host_part[1..]; // skip '@'
     if user.is_empty() || host_port.is_empty() {
-        return Err(crate::TransferError::OperationFailed("用户名或主机为空".to_string()).into());
+        return Err(crate::TransferError::InvalidArgument("用户名或主机为空".to_string()).into());
     }
 
     // 支持 host:port，否则默认 22 — Support host:port, default to 22 if not provided
@@ -17,7 +17,7 @@ pub fn parse_remote_host(input: &str) -> Result<(String, String, u16)> {
         let (h, p_str) = host_port.split_at(colon);
         let p_str = &p_str[1..]; // skip ':'
         let p: u16 = p_str.parse().map_err(|_| -> anyhow::Error {
-            crate::TransferError::OperationFailed(format!("端口无效: {}", p_str)).into()
+            crate::TransferError::InvalidArgument(format!("端口无效: {}", p_str)).into()
         })?;
         (h.to_string(), p)
     } else {
@@ -32,10 +32,10 @@ pub fn parse_alias_and_path(input: &str) -> Result<(String, String)> {
         let a = alias.trim();
         let p = rest.trim();
         if a.is_empty() || p.is_empty() {
-            return Err(crate::TransferError::OperationFailed("别名或路径为空".to_string()).into());
+            return Err(crate::TransferError::InvalidArgument("别名或路径为空".to_string()).into());
         }
         Ok((a.to_string(), p.to_string()))
     } else {
-        Err(crate::TransferError::OperationFailed("未找到分隔符 ':'".to_string()).into())
+        Err(crate::TransferError::InvalidArgument("未找到分隔符 ':'".to_string()).into())
     }
 }
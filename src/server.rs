@@ -13,6 +13,38 @@ pub const fn get_protocol_version() -> u32 {
     PROTOCOL_VERSION
 }
 
+/// Create the `servers` table if needed and add columns introduced after the first schema.
+pub fn ensure_servers_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS servers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alias TEXT UNIQUE NOT NULL,
+            username TEXT NOT NULL,
+            address TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            last_connect TEXT,
+            limit_rate TEXT,
+            tags TEXT,
+            max_handshakes INTEGER
+        )",
+        [],
+    )?;
+    let mut stmt = conn.prepare("PRAGMA table_info(servers)")?;
+    let columns: Vec<String> =
+        stmt.query_map([], |row| row.get::<_, String>(1))?.collect::<Result<_, _>>()?;
+    // 旧数据库补充新列 — add columns missing from older databases
+    for (name, ddl) in [
+        ("limit_rate", "ALTER TABLE servers ADD COLUMN limit_rate TEXT"),
+        ("tags", "ALTER TABLE servers ADD COLUMN tags TEXT"),
+        ("max_handshakes", "ALTER TABLE servers ADD COLUMN max_handshakes INTEGER"),
+    ] {
+        if !columns.iter().any(|c| c == name) {
+            conn.execute(ddl, [])?;
+        }
+    }
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ServerCollection {
     hosts: BTreeMap<String, Server>,
@@ -28,21 +60,12 @@ impl ServerCollection {
         let conn = Connection::open(path).with_context(|| "Failed to open SQLite database")?;
 
         // 如果尚不存在则创建表（使用新 schema：id + alias 唯一） — Create table if not exists (new schema with id + alias unique)
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS servers (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                alias TEXT UNIQUE NOT NULL,
-                username TEXT NOT NULL,
-                address TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                last_connect TEXT
-            )",
-            [],
-        )
-        .with_context(|| "Failed to create table")?;
+        ensure_servers_schema(&conn).with_context(|| "Failed to create table")?;
 
         let mut stmt = conn
-            .prepare("SELECT id, alias, username, address, port, last_connect FROM servers")
+            .prepare(
+                "SELECT id, alias, username, address, port, last_connect, limit_rate, tags, max_handshakes FROM servers",
+            )
             .with_context(|| "Failed to prepare statement")?;
         let server_iter = stmt
             .query_map([], |row| {
@@ -55,6 +78,9 @@ impl ServerCollection {
                     address: row.get(3)?,
                     port: row.get(4)?,
                     last_connect: row.get(5)?,
+                    limit_rate: row.get(6)?,
+                    tags: split_tags(row.get::<_, Option<String>>(7)?.as_deref().unwrap_or("")),
+                    max_handshakes: row.get::<_, Option<i64>>(8)?.map(|n| n.max(1) as u32),
                 };
                 Ok((alias, s))
             })
@@ -78,18 +104,7 @@ impl ServerCollection {
         let conn = Connection::open(path).with_context(|| "Failed to open SQLite database")?;
 
         // 如果尚不存在则创建表（使用新 schema：id + alias 唯一） — Create table if not exists (new schema with id + alias unique)
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS servers (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                alias TEXT UNIQUE NOT NULL,
-                username TEXT NOT NULL,
-                address TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                last_connect TEXT
-            )",
-            [],
-        )
-        .with_context(|| "Failed to create table")?;
+        ensure_servers_schema(&conn).with_context(|| "Failed to create table")?;
 
         // 清空现有数据 — Clear existing data
         conn.execute("DELETE FROM servers", []).with_context(|| "Failed to clear table")?;
@@ -97,7 +112,7 @@ impl ServerCollection {
         // 插入服务器（让数据库分配 id） — Insert servers (let DB assign id)
         let mut stmt = conn
             .prepare(
-                "INSERT OR REPLACE INTO servers (alias, username, address, port, last_connect) VALUES (?1, ?2, ?3, ?4, ?5)",
+                "INSERT OR REPLACE INTO servers (alias, username, address, port, last_connect, limit_rate, tags, max_handshakes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             )
             .with_context(|| "Failed to prepare insert statement")?;
 
@@ -108,6 +123,9 @@ impl ServerCollection {
                 server.address,
                 server.port as i64,
                 server.last_connect,
+                server.limit_rate,
+                (!server.tags.is_empty()).then(|| server.tags.join(",")),
+                server.max_handshakes.map(i64::from),
             ])
             .with_context(|| "Failed to insert server")?;
         }
@@ -140,6 +158,15 @@ impl ServerCollection {
         &self.hosts
     }
 
+    /// Aliases carrying `tag`, in alias order.
+    pub fn members_of(&self, tag: &str) -> Vec<String> {
+        self.hosts
+            .iter()
+            .filter(|(_, s)| s.tags.iter().any(|t| t == tag))
+            .map(|(alias, _)| alias.clone())
+            .collect()
+    }
+
     pub fn show_table(&self) {
         if !self.is_empty() {
             let title = vec![
@@ -148,6 +175,7 @@ impl ServerCollection {
                 "Address".cell().bold(true),
                 "Port".cell().bold(true),
                 "Last Connect".cell().bold(true),
+                "Tags".cell().bold(true),
             ];
             let mut table: Vec<Vec<CellStruct>> = Vec::new();
             for (alias, server) in &self.hosts {
@@ -159,6 +187,7 @@ impl ServerCollection {
                     server.address.to_string().cell().justify(Justify::Right),
                     port.cell().justify(Justify::Right),
                     last_connect.cell().justify(Justify::Right),
+                    server.tags.join(",").cell(),
                 ];
                 table.push(col);
             }
@@ -180,6 +209,27 @@ pub struct Server {
     pub port: u16,
     #[serde(default)]
     pub last_connect: Option<String>,
+    /// Default `ts` bandwidth limit for this host (`--limit-rate` syntax), set via `hp limit`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub limit_rate: Option<String>,
+    /// Group names used as `@tag` host selectors (`hp tag`).
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tags: Vec<String>,
+    /// Upper bound of concurrent SSH handshakes `ts` opens to this host (`hp handshakes`);
+    /// the default applies when unset.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_handshakes: Option<u32>,
+}
+
+/// Split a comma-separated tag list, dropping blanks and duplicates.
+pub fn split_tags(s: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for t in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if !tags.iter().any(|x| x == t) {
+            tags.push(t.to_string());
+        }
+    }
+    tags
 }
 
 impl Server {
@@ -1,20 +1,56 @@
 // transfer module: file transfer orchestration and helpers
+#[cfg(unix)]
+mod agent;
+mod attrs;
+mod direct;
 mod enumeration;
+pub(crate) mod events;
+mod fanout;
+mod filter;
 mod helpers;
+mod journal;
+mod links;
+mod mirror;
+mod overwrite;
+mod plan;
+mod ratelimit;
+mod replay;
 mod session;
 mod sftp_like;
+mod tarmode;
 mod workers;
+use crate::auto_concurrency::{AutoTuner, TuneReport};
 use crate::config::Config;
 use crate::server::ServerCollection;
+#[cfg(unix)]
+pub use agent::{handle_agent_start, handle_agent_status, handle_agent_stop};
 use anyhow::{Context, Result};
+pub use events::init_events;
 pub use helpers::normalize_path;
+pub use journal::{handle_jobs_ls, handle_jobs_rm, handle_jobs_show};
+use serde::{Deserialize, Serialize};
+// public for integration tests; unused by the binary itself
+#[allow(unused_imports)]
 pub use helpers::wildcard_match;
+// used by `hp limit` to validate per-alias defaults
+pub(crate) use ratelimit::RateSchedule;
+pub(crate) use session::DEFAULT_MAX_HANDSHAKES;
 // Transfer errors are re-exported at crate root (see src/lib.rs)
 
-use self::enumeration::{enumerate_local_sources, enumerate_remote_and_push};
-use self::helpers::{is_disallowed_glob, is_remote_spec};
-use self::session::{connect_session, expand_remote_tilde};
-use self::workers::download::{DownloadWorkersCtx, run_download_workers};
+use self::attrs::{AttrPolicy, FileAttrs};
+use self::enumeration::{
+    RemoteListers, enumerate_local_and_push, enumerate_local_sources, enumerate_remote_and_push,
+};
+use self::filter::PathFilter;
+use self::helpers::{is_disallowed_glob, is_local_glob, is_remote_glob, is_remote_spec};
+use self::journal::Journal;
+use self::links::LinkPolicy;
+use self::mirror::{MirrorOptions, MirrorReport, MirrorTarget};
+use self::overwrite::{OverwritePolicy, OverwriteReport};
+use self::session::{PoolStats, SessionPool, expand_remote_tilde};
+use self::workers::chunked::ChunkPolicy;
+use self::workers::download::{DownloadWorkersCtx, OriginStats, run_download_workers};
+use self::workers::relay::{RelayWorkersCtx, run_relay_workers};
 use self::workers::upload::{UploadWorkersCtx, run_upload_workers};
 use self::workers::{WorkerCommonCtx, WorkerMetrics, WorkerRuntimeHandles};
 use crossbeam_channel::bounded;
@@ -33,6 +69,69 @@ struct FinalizeCtx {
     total_pb: indicatif::ProgressBar,
     json_mode: bool,
     quiet_mode: bool,
+    mirror: Option<MirrorReport>,
+    // symlinks left out of the transfer (`--links`)
+    skipped_links: Vec<String>,
+    // present when --no-clobber/--update/--backup is in effect
+    overwrite: Option<Arc<OverwriteReport>>,
+    // per-alias breakdown of a fan-in download; empty otherwise
+    hosts: Vec<HostBreakdown>,
+    sessions: PoolStats,
+    journal: Option<Arc<Journal>>,
+    // stamped on the failure records of the run (upload / download / relay)
+    direction: &'static str,
+    alias: Option<String>,
+    history: HistoryTarget,
+    // adaptive concurrency outcome (no -c)
+    tuning: Option<TuneReport>,
+}
+
+// Where a finished run is recorded for `hp history`: server.db plus the ts arguments.
+#[derive(Clone)]
+struct HistoryTarget {
+    db: std::path::PathBuf,
+    sources: Vec<String>,
+    target: String,
+}
+
+impl HistoryTarget {
+    // the history is informational: a write failure never fails the transfer
+    fn record(&self, rec: crate::history::TransferRecord) {
+        let rec = crate::history::TransferRecord {
+            finished_at: chrono::Local::now().timestamp(),
+            sources: self.sources.clone(),
+            target: self.target.clone(),
+            ..rec
+        };
+        if let Err(e) = crate::history::record_transfer(&self.db, &rec) {
+            tracing::warn!("[ts] failed to record transfer history: {:#}", e);
+        }
+    }
+}
+
+// One alias of a fan-in download (`@tag:/path` source).
+struct HostBreakdown {
+    alias: String,
+    // local subdirectory below the target; None when the host could not be connected
+    dir: Option<String>,
+    // non-directory entries enumerated on the host
+    discovered: u64,
+    files: u64,
+    bytes: u64,
+    error: Option<String>,
+}
+
+impl HostBreakdown {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "alias": self.alias,
+            "dir": self.dir,
+            "discovered": self.discovered,
+            "files": self.files,
+            "bytes": self.bytes,
+            "error": self.error,
+        })
+    }
 }
 // write_failures is available via crate::util; no local re-export needed here.
 // JSONL failure writer available at crate::util::write_failures_jsonl
@@ -44,6 +143,8 @@ struct FinalizeCtx {
 enum EntryKind {
     File,
     Dir,
+    // recreated as a link at the destination (`--links copy`)
+    Symlink,
 }
 
 #[derive(Clone)]
@@ -53,10 +154,38 @@ struct FileEntry {
     size: Option<u64>,
     kind: EntryKind,
     local_full: Option<String>,
+    // index of the remote alias the entry comes from (multi-source downloads)
+    origin: usize,
 }
 
-/// Arguments for `handle_ts` grouped to avoid too-many-arguments lint.
-#[derive(Clone)]
+// `queued` event of an entry handed to the workers; directories are not reported
+fn queue_event(alias: Option<&str>, entry: &FileEntry) {
+    if entry.kind != EntryKind::Dir {
+        events::queued(alias, &entry.rel, entry.size);
+    }
+}
+
+// Destination of an upload: one alias, or every member of a tag (`@tag:/path`).
+enum UploadDest {
+    Host { server: Arc<crate::server::Server>, addr: String, expanded_remote_base: String },
+    // aliases resolved from the tag; each host expands `remote_path` itself
+    Tag { tag: String, aliases: Vec<String>, remote_path: String },
+}
+
+// One alias taking part in a download, with the probe session used for tilde expansion
+// and enumeration.
+struct RemoteHost {
+    alias: String,
+    server: Arc<crate::server::Server>,
+    addr: String,
+    sess: ssh2::Session,
+}
+
+/// Arguments for `handle_ts` grouped to avoid too-many-arguments lint. They are stored
+/// with each job of the transfer journal so `--resume` can rerun it; fields missing from
+/// a job stored by an older version take their `Default` value.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct HandleTsArgs {
     pub sources: Vec<String>,
     pub target: String,
@@ -66,12 +195,115 @@ pub struct HandleTsArgs {
     pub concurrency: Option<usize>,
     pub max_retries: usize,
     pub buf_size: usize,
+    /// Mirror mode: delete destination entries that are not present at the source.
+    pub delete: bool,
+    /// Report what `--delete` would remove without deleting anything (implies `delete`).
+    pub delete_dry_run: bool,
+    /// Abort mirror deletion when more than this many entries would be removed.
+    pub max_delete: Option<usize>,
+    /// Validate and enumerate, then print the planned operations without transferring.
+    pub dry_run: bool,
+    /// gitignore-style patterns to skip (repeatable `--exclude`).
+    pub excludes: Vec<String>,
+    /// Patterns re-included even when excluded (repeatable `--include`).
+    pub includes: Vec<String>,
+    /// File with one exclude pattern per line (`--exclude-from`).
+    pub exclude_from: Option<std::path::PathBuf>,
+    /// Carry mode bits and mtime/atime to the destination (`-p/--preserve`).
+    pub preserve: bool,
+    /// Explicit destination mode, e.g. `644` or `D755,F644` (`--chmod`).
+    pub chmod: Option<String>,
+    /// Octal mask applied to destination modes (`--umask`).
+    pub umask: Option<String>,
+    /// Symlink policy inside source directories: `copy`, `follow` or `skip` (default).
+    pub links: Option<String>,
+    /// Never overwrite an existing destination file (`--no-clobber`).
+    pub no_clobber: bool,
+    /// Overwrite only when the source is newer than the destination (`--update`).
+    pub update: bool,
+    /// Rename an existing destination to `<name><suffix>` before writing (`--backup`).
+    pub backup: Option<String>,
+    /// Total bandwidth limit or time-of-day schedule; overrides the alias default.
+    pub limit_rate: Option<String>,
+    /// Remote-to-remote only: let the source host push to the destination itself
+    /// (`--direct`), falling back to the local relay when it cannot reach it.
+    pub direct: bool,
+    /// Tag fan-out: number of hosts uploaded to at the same time (`--parallel-hosts`).
+    pub parallel_hosts: Option<usize>,
+    /// Tag fan-out: stop starting new hosts after the first failed one (`--fail-fast`).
+    pub fail_fast: bool,
+    /// Tag fan-in: per-alias directory below the local target, with `{alias}`, `{host}`
+    /// and `{date}` placeholders (`--host-dir`, default `{alias}`).
+    pub host_dir: Option<String>,
+    /// Directory sources only: move the whole tree through one `tar` stream over an
+    /// exec channel instead of one SFTP transfer per file (`--tar`).
+    pub tar: bool,
+    /// Files at or above this size are split into ranges moved by several sessions
+    /// (`--chunk-threshold`; unset or `off` keeps one stream per file).
+    pub chunk_threshold: Option<String>,
+    /// Walk all local sources before the upload starts, for an exact total and ETA
+    /// (`--prescan`); by default entries stream to the workers as they are found.
+    pub prescan: bool,
+    /// Rerun a journaled job with its stored arguments, skipping the entries it already
+    /// finished (`--resume <job-id>`); the other fields are ignored.
+    #[serde(skip)]
+    pub resume: Option<i64>,
+    /// Re-transfer only the files a run failed on, as recorded in failures.jsonl
+    /// (`--retry-failed [run-id]`, default the latest run); the other fields are ignored.
+    #[serde(skip)]
+    pub retry_failed: Option<Option<i64>>,
+}
+
+impl Default for HandleTsArgs {
+    // the `hp ts` defaults without any option given
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            target: String::new(),
+            verbose: false,
+            json: false,
+            quiet: false,
+            concurrency: None,
+            max_retries: 3,
+            buf_size: 1024 * 1024,
+            delete: false,
+            delete_dry_run: false,
+            max_delete: None,
+            dry_run: false,
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            exclude_from: None,
+            preserve: false,
+            chmod: None,
+            umask: None,
+            links: None,
+            no_clobber: false,
+            update: false,
+            backup: None,
+            limit_rate: None,
+            direct: false,
+            parallel_hosts: None,
+            fail_fast: false,
+            host_dir: None,
+            tar: false,
+            chunk_threshold: None,
+            prescan: false,
+            resume: None,
+            retry_failed: None,
+        }
+    }
 }
 
 // helper and session functions moved into submodules
 
-// remote target pre-checks and single-level mkdir; returns whether target is dir
-fn prepare_remote_target(sftp: &ssh2::Sftp, base: &str, ends_slash: bool) -> anyhow::Result<bool> {
+// remote target pre-checks and single-level mkdir; returns whether target is dir.
+// With `create == false` (dry-run) the mkdir is skipped but all checks still apply.
+fn prepare_remote_target(
+    sftp: &ssh2::Sftp,
+    base: &str,
+    ends_slash: bool,
+    create: bool,
+) -> anyhow::Result<bool> {
     let target_exists_meta = sftp.stat(std::path::Path::new(base));
     let target_is_dir_final = match (ends_slash, target_exists_meta) {
         (true, Ok(st)) => {
@@ -89,13 +321,15 @@ fn prepare_remote_target(sftp: &ssh2::Sftp, base: &str, ends_slash: bool) -> any
             let tpath = std::path::Path::new(base);
             if let Some(parent) = tpath.parent() {
                 if sftp.stat(parent).is_ok() {
-                    sftp.mkdir(tpath, 0o755).map_err(|e| -> anyhow::Error {
-                        crate::TransferError::CreateRemoteDirFailed(
-                            base.to_string(),
-                            format!("{}", e),
-                        )
-                        .into()
-                    })?;
+                    if create {
+                        sftp.mkdir(tpath, 0o755).map_err(|e| -> anyhow::Error {
+                            crate::TransferError::CreateRemoteDirFailed(
+                                base.to_string(),
+                                format!("{}", e),
+                            )
+                            .into()
+                        })?;
+                    }
                     true
                 } else {
                     return Err(crate::TransferError::RemoteTargetParentMissing(
@@ -104,7 +338,7 @@ fn prepare_remote_target(sftp: &ssh2::Sftp, base: &str, ends_slash: bool) -> any
                     .into());
                 }
             } else {
-                return Err(crate::TransferError::OperationFailed(format!(
+                return Err(crate::TransferError::InvalidArgument(format!(
                     "无效远端路径: {}",
                     base
                 ))
@@ -115,8 +349,13 @@ fn prepare_remote_target(sftp: &ssh2::Sftp, base: &str, ends_slash: bool) -> any
     Ok(target_is_dir_final)
 }
 
-// local target pre-checks and single-level mkdir; returns whether target is dir
-fn prepare_local_target(tpath: &std::path::Path, ends_slash: bool) -> anyhow::Result<bool> {
+// local target pre-checks and single-level mkdir; returns whether target is dir.
+// With `create == false` (dry-run) the mkdir is skipped but all checks still apply.
+fn prepare_local_target(
+    tpath: &std::path::Path,
+    ends_slash: bool,
+    create: bool,
+) -> anyhow::Result<bool> {
     if ends_slash {
         if !(tpath.exists() && tpath.is_dir()) {
             return Err(
@@ -128,18 +367,21 @@ fn prepare_local_target(tpath: &std::path::Path, ends_slash: bool) -> anyhow::Re
     if tpath.exists() {
         return Ok(tpath.is_dir());
     }
+    let mkdir = |p: &std::path::Path| -> anyhow::Result<()> {
+        if !create {
+            return Ok(());
+        }
+        std::fs::create_dir(p).map_err(|e| -> anyhow::Error {
+            crate::TransferError::CreateLocalDirFailed(p.display().to_string(), format!("{}", e))
+                .into()
+        })
+    };
     // create target dir (single level). If target is a single-segment path (no parent),
     // treat parent as current directory and allow creation.
     match tpath.parent() {
         Some(parent) if !parent.as_os_str().is_empty() => {
             if parent.exists() && parent.is_dir() {
-                std::fs::create_dir(tpath).map_err(|e| -> anyhow::Error {
-                    crate::TransferError::CreateLocalDirFailed(
-                        tpath.display().to_string(),
-                        format!("{}", e),
-                    )
-                    .into()
-                })?;
+                mkdir(tpath)?;
                 Ok(true)
             } else {
                 let pdisp = if parent.as_os_str().is_empty() {
@@ -152,13 +394,7 @@ fn prepare_local_target(tpath: &std::path::Path, ends_slash: bool) -> anyhow::Re
         }
         // No parent (single-segment like "dist") or empty parent -> use current directory
         _ => {
-            std::fs::create_dir(tpath).map_err(|e| -> anyhow::Error {
-                crate::TransferError::CreateLocalDirFailed(
-                    tpath.display().to_string(),
-                    format!("{}", e),
-                )
-                .into()
-            })?;
+            mkdir(tpath)?;
             Ok(true)
         }
     }
@@ -179,17 +415,54 @@ fn load_server_with_addr(
     Ok((server, addr))
 }
 
+// The session that expands `~` goes back to `pool` for the probe and the first worker.
 fn resolve_remote_endpoint(
     config: &Config,
+    pool: &SessionPool,
     alias: &str,
     remote_path: &str,
 ) -> anyhow::Result<(Arc<crate::server::Server>, String, String)> {
     let (server, addr) = load_server_with_addr(config, alias)?;
-    let sess = connect_session(&server)?;
+    #[cfg(unix)]
+    if let Some(agent) = pool.agent() {
+        match agent.expand(alias, remote_path) {
+            Ok(expanded) => return Ok((server, addr, expanded)),
+            Err(e) => tracing::debug!("[ts][agent] 展开路径失败，改用本地会话: {:#}", e),
+        }
+    }
+    let sess = pool.checkout(&server)?;
     let expanded = expand_remote_tilde(&sess, remote_path)?;
+    pool.checkin(&server, sess);
     Ok((server, addr, expanded))
 }
 
+/// `hp exec <alias> <command…>`: run a command through the remote shell, on a session
+/// of `hp agent` when one is running. Prints the merged output and returns the remote
+/// exit status.
+pub fn handle_exec(config: &Config, alias: &str, command: &[String]) -> Result<i32> {
+    use std::io::Write as _;
+    let cmd = command.join(" ");
+    #[cfg(unix)]
+    let delegated = match agent::AgentHandle::probe() {
+        Some(agent) => Some(agent.exec(alias, &cmd)?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let delegated = None;
+    let (code, out) = match delegated {
+        Some(res) => res,
+        None => {
+            let (server, _) = load_server_with_addr(config, alias)?;
+            // through the host's handshake limiter, so a refused handshake backs off
+            let sess = session::SessionPool::default().checkout(&server)?;
+            session::exec_remote(&sess, &cmd)?
+        }
+    };
+    print!("{}", out);
+    std::io::stdout().flush()?;
+    Ok(code)
+}
+
 // ensure a worker has an SSH session established and authenticated
 // ensure_worker_session moved into session module
 
@@ -205,6 +478,30 @@ fn calc_upload_workers(
     workers
 }
 
+// 自适应并发（未指定 -c）：起始值取别名记住的最佳值，否则取 `initial`（不超过 INITIAL_MAX）。
+// Adaptive concurrency: start from the alias's remembered best, else small.
+fn spawn_tuner(
+    db: &std::path::Path,
+    alias: Option<&str>,
+    initial: usize,
+    workers: usize,
+) -> Arc<AutoTuner> {
+    let start = alias
+        .and_then(|a| crate::auto_concurrency::learned_concurrency(db, a))
+        .unwrap_or(initial.min(crate::auto_concurrency::INITIAL_MAX));
+    tracing::info!("[ts][auto] start workers={} of {} (alias={:?})", start, workers, alias);
+    AutoTuner::spawn(start, workers)
+}
+
+// Remember the best concurrency of a conclusive adaptive run for the alias.
+fn remember_tuning(db: &std::path::Path, alias: Option<&str>, tuning: Option<&TuneReport>) {
+    if let (Some(alias), Some(t)) = (alias, tuning.filter(|t| t.is_conclusive()))
+        && let Err(e) = crate::auto_concurrency::remember_concurrency(db, alias, t)
+    {
+        tracing::warn!("[ts][auto] failed to remember concurrency of {}: {:#}", alias, e);
+    }
+}
+
 // Helper: calculate download workers bounded by max
 fn calc_download_workers(concurrency: usize, max_allowed_workers: usize) -> usize {
     let mut workers = if concurrency == 0 { 8usize } else { concurrency };
@@ -216,7 +513,7 @@ fn calc_download_workers(concurrency: usize, max_allowed_workers: usize) -> usiz
 // 行为说明:
 // - 从 `ctx.rx` 消费待传条目（文件/目录）。目录在远端作存在性与必要的单级 mkdir 处理，文件则执行读写传输。
 // - 使用 `retry_operation_with_ctx(max_retries, op, phase, ctx)` 包裹单文件传输，减少瞬时错误的影响，并输出上下文日志。
-// - 通过 `conn_token_rx/tx` 作为令牌桶限制并发建连次数，避免过多 SSH 同时握手。
+// - 建连经由 `SessionPool`，其按主机共享的握手限流器约束同时握手数（`hp handshakes`）。
 // - 使用 `MultiProgress` 与每文件 `ProgressBar` 更新总进度与单文件进度。
 // - 对失败条目通过 `failure_tx` 上报，函数最后会 join 所有线程。
 // run_upload_workers moved to workers module
@@ -232,26 +529,182 @@ fn calc_download_workers(concurrency: usize, max_allowed_workers: usize) -> usiz
 /// 传输子命令主入口：根据源/目标判定方向，完成上传或下载。
 ///
 /// 概览:
-/// - 方向判定：源或目标必须且只有一端是远端（`alias:/path`）。
+/// - 方向判定：至少一端为远端（`alias:/path`）；两端均为远端时经本机中转（或 `--direct` 直连）。
 /// - 认证与路径展开：复用 `resolve_remote_endpoint` 统一加载别名、建连并展开远端路径。
 /// - 失败输出：失败清单会写入到配置目录下的 `logs/`（不可配置）。
 pub fn handle_ts(config: &Config, args: HandleTsArgs) -> Result<()> {
-    let HandleTsArgs { sources, target, verbose, json, quiet, concurrency, max_retries, buf_size } =
-        args;
+    if let Some(run_id) = args.retry_failed {
+        return replay::retry_failed(config, run_id);
+    }
+    let (args, mut journal) = match args.resume {
+        Some(id) => {
+            let (journal, stored, cwd) = Journal::resume(&journal::journal_path(config), id)?;
+            // relative local paths of the job refer to the directory it was started from
+            std::env::set_current_dir(&cwd)
+                .with_context(|| format!("无法进入任务 #{} 的工作目录 {}", id, cwd.display()))?;
+            (stored, Some(Arc::new(journal)))
+        }
+        None => (args, None),
+    };
+    // new jobs are recorded by `run_ts` once the arguments and endpoints check out
+    let res = run_ts(config, args, &mut journal);
+    events::emit(
+        "job_end",
+        serde_json::json!({
+            "job_id": journal.as_ref().map(|j| j.id()),
+            "ok": res.is_ok(),
+            "error": res.as_ref().err().map(|e| format!("{:#}", e)),
+            "exit_code": res.as_ref().err().map_or(0, crate::error::exit_code_of),
+        }),
+    );
+    if let Some(journal) = journal
+        && let Err(e) = journal.finish(res.is_ok())
+    {
+        eprintln!("⚠️ 无法更新任务 #{} 的状态: {:#}", journal.id(), e);
+    }
+    res
+}
+
+/// `journal` holds the job being resumed; otherwise the new job is recorded into it
+/// right before the first transfer, so runs rejected earlier leave nothing to resume.
+fn run_ts(config: &Config, args: HandleTsArgs, journal: &mut Option<Arc<Journal>>) -> Result<()> {
+    let record = (journal.is_none() && !args.dry_run).then(|| args.clone());
+    let HandleTsArgs {
+        sources,
+        target,
+        verbose,
+        json,
+        quiet,
+        concurrency,
+        max_retries,
+        buf_size,
+        delete,
+        delete_dry_run,
+        max_delete,
+        dry_run,
+        excludes,
+        includes,
+        exclude_from,
+        preserve,
+        chmod,
+        umask,
+        links,
+        no_clobber,
+        update,
+        backup,
+        limit_rate,
+        direct,
+        parallel_hosts,
+        fail_fast,
+        host_dir,
+        tar,
+        chunk_threshold,
+        prescan,
+        resume: _,
+        retry_failed: _,
+    } = args;
+    // --events on stdout: the stream replaces the human output and the --json line;
+    // progress bars stay on stderr
+    let (verbose, quiet, json) =
+        if events::on_stdout() { (false, true, false) } else { (verbose, quiet, json) };
+    let history = HistoryTarget {
+        db: config.server_file_path.clone(),
+        sources: sources.clone(),
+        target: target.clone(),
+    };
+    let filter = PathFilter::from_cli(&excludes, &includes, exclude_from.as_deref())?;
+    let attr_policy = AttrPolicy::from_cli(preserve, chmod.as_deref(), umask.as_deref())?;
+    let links = LinkPolicy::from_cli(links.as_deref())?;
+    let overwrite = OverwritePolicy::from_cli(no_clobber, update, backup)?;
+    let chunking = ChunkPolicy::from_cli(chunk_threshold.as_deref())?;
+    // --delete keeps earlier backups at the destination
+    let mirror_filter = overwrite.protect_backups(&filter);
+    let mirror_opts =
+        (delete || delete_dry_run).then_some(MirrorOptions { dry_run: delete_dry_run, max_delete });
     // Early validations enforcing repository transfer rules (R1-R10)
-    // R1: Exactly one side must be remote (target or first source)
+    // R1: At least one side must be remote; both remote means a relay through this host
     let target_is_remote = is_remote_spec(&target);
     let source0_is_remote = sources.first().map(|s| is_remote_spec(s)).unwrap_or(false);
-    if target_is_remote == source0_is_remote {
+    if !target_is_remote && !source0_is_remote {
         return Err(crate::TransferError::InvalidDirection.into());
     }
+    let is_relay = target_is_remote && source0_is_remote;
+    if direct && !is_relay {
+        return Err(crate::TransferError::InvalidArgument(
+            "--direct 仅适用于远端到远端传输".to_string(),
+        )
+        .into());
+    }
+    let is_fanout = target_is_remote && target.starts_with('@');
+    if is_fanout && is_relay {
+        return Err(crate::TransferError::InvalidArgument(
+            "标签目标（@tag:/path）仅支持从本地上传".to_string(),
+        )
+        .into());
+    }
+    let is_fan_in = !target_is_remote && sources.iter().any(|s| s.starts_with('@'));
+    if is_relay && sources.iter().any(|s| s.starts_with('@')) {
+        return Err(crate::TransferError::InvalidArgument(
+            "标签源（@tag:/path）仅支持下载到本地".to_string(),
+        )
+        .into());
+    }
+    if host_dir.is_some() && !is_fan_in {
+        return Err(crate::TransferError::InvalidArgument(
+            "--host-dir 仅适用于标签源（@tag:/path）".to_string(),
+        )
+        .into());
+    }
+    if !is_fanout && (parallel_hosts.is_some() || fail_fast) {
+        return Err(crate::TransferError::InvalidArgument(
+            "--parallel-hosts/--fail-fast 仅适用于标签目标（@tag:/path）".to_string(),
+        )
+        .into());
+    }
 
-    // R3: allow dir/*.txt but forbid recursive ** and wildcard in non-last segments
+    if tar {
+        if is_relay {
+            return Err(crate::TransferError::InvalidArgument(
+                "--tar 不支持远端到远端传输".to_string(),
+            )
+            .into());
+        }
+        if chmod.is_some() || umask.is_some() || overwrite.is_active() {
+            return Err(crate::TransferError::InvalidArgument(
+                "--tar 不能与 --chmod/--umask/--no-clobber/--update/--backup 同时使用".to_string(),
+            )
+            .into());
+        }
+        // uploads: local sources are checked here; download roots after connecting
+        for s in sources.iter() {
+            let local_non_dir = target_is_remote && !std::path::Path::new(s).is_dir();
+            if (target_is_remote && is_local_glob(s)) || local_non_dir {
+                return Err(crate::TransferError::InvalidArgument(format!(
+                    "--tar 仅适用于目录源: {}",
+                    s
+                ))
+                .into());
+            }
+        }
+    }
+
+    // R3: globs may span segments and recurse via `**`; reject only malformed syntax
     for s in sources.iter() {
-        if is_disallowed_glob(s) {
+        if is_disallowed_glob(s) && !helpers::local_literal(s) {
             return Err(crate::TransferError::UnsupportedGlobUsage(s.clone()).into());
         }
     }
+    // --delete only makes sense for directory-content syncs (no globs)
+    if mirror_opts.is_some() {
+        // download and relay roots must be remote directories, checked after connecting
+        for s in sources.iter() {
+            let is_upload = target_is_remote && !is_relay;
+            let local_non_dir = !std::path::Path::new(s).is_dir();
+            if is_upload && (is_local_glob(s) || local_non_dir) {
+                return Err(crate::TransferError::MirrorUnsupported(s.clone()).into());
+            }
+        }
+    }
     // 确定传输方向 — Determine transfer direction
     // target/source detection already performed above
 
@@ -288,24 +741,36 @@ pub fn handle_ts(config: &Config, args: HandleTsArgs) -> Result<()> {
 
     enum TransferKind {
         Upload {
-            server: Arc<crate::server::Server>,
-            addr: String,
-            expanded_remote_base: String,
-            entries: Vec<FileEntry>,
-            total_size: u64,
-        },
-        Download {
-            server: Arc<crate::server::Server>,
-            addr: String,
-            remote_root: String,
+            dest: UploadDest,
+            // None: streamed while the workers run (see `LocalSources`)
+            enumeration: Option<enumeration::LocalEnumeration>,
         },
+        Download(DownloadSources),
+        // remote -> remote through this host
+        Relay(RelayEnds),
         Unknown,
     }
 
     // Build a TransferKind instance by performing the minimal parsing/auth required
-    let transfer_kind = if target_is_remote {
-        // Prepare upload-side instance. We'll parse alias and enumerate sources now so
-        // common setup can be moved into the Upload variant.
+    // sessions of this run: handshakes are counted, idle sessions are handed on
+    let pool = Arc::new(SessionPool::with_agent());
+    let transfer_kind = if is_relay {
+        if sources.len() != 1 {
+            return Err(crate::TransferError::DownloadMultipleRemoteSources(
+                "ts 远端到远端仅支持单个远端源".to_string(),
+            )
+            .into());
+        }
+        let (src_alias, src_path) = crate::parse::parse_alias_and_path(&sources[0])?;
+        let (src_server, src_addr, src_root) =
+            resolve_remote_endpoint(config, &pool, &src_alias, &src_path)?;
+        let (alias, remote_path) = crate::parse::parse_alias_and_path(&target)?;
+        let (server, addr, dest_base) =
+            resolve_remote_endpoint(config, &pool, &alias, &remote_path)?;
+        TransferKind::Relay(RelayEnds { src_server, src_addr, src_root, server, addr, dest_base })
+    } else if target_is_remote {
+        // Prepare upload-side instance: the target is `alias:/path` or a tag selector
+        // `@tag:/path` (fan-out); local sources are enumerated once either way.
         if sources.is_empty() {
             return Err(crate::TransferError::MissingLocalSource(
                 "ts 上传需要至少一个本地源".to_string(),
@@ -314,339 +779,1672 @@ pub fn handle_ts(config: &Config, args: HandleTsArgs) -> Result<()> {
         }
         // parse alias:path
         let (alias, remote_path) = crate::parse::parse_alias_and_path(&target)?;
-        let (server, addr, expanded_remote_base) =
-            resolve_remote_endpoint(config, &alias, &remote_path)?;
-        // enumerate local sources
-        let (entries, total_size) = enumerate_local_sources(&sources)?;
-        TransferKind::Upload { server, addr, expanded_remote_base, entries, total_size }
+        let dest = match alias.strip_prefix('@') {
+            Some(tag) => {
+                let aliases = fanout::resolve_tag(config, tag)?;
+                UploadDest::Tag { tag: tag.to_string(), aliases, remote_path }
+            }
+            None => {
+                let (server, addr, expanded_remote_base) =
+                    resolve_remote_endpoint(config, &pool, &alias, &remote_path)?;
+                UploadDest::Host { server, addr, expanded_remote_base }
+            }
+        };
+        // enumerate local sources up front only when the full list is needed before the
+        // first byte moves: --prescan (exact ETA), plans, tar streams and tag fan-out
+        let listed = prescan || dry_run || tar || matches!(dest, UploadDest::Tag { .. });
+        let enumeration = if listed {
+            Some(enumerate_local_sources(&sources, &filter, links)?)
+        } else {
+            for s in sources.iter() {
+                enumeration::check_local_source(s, &filter, links)?;
+            }
+            None
+        };
+        TransferKind::Upload { dest, enumeration }
     } else if source0_is_remote {
-        // Prepare download-side instance
-        if sources.len() != 1 {
-            return Err(crate::TransferError::DownloadMultipleRemoteSources(
-                "ts 下载仅支持单个远端源".to_string(),
-            )
-            .into());
-        }
-        let (alias, remote_path) = crate::parse::parse_alias_and_path(&sources[0])?;
-        let (server, addr, remote_root) =
-            match resolve_remote_endpoint(config, &alias, &remote_path) {
-                Ok(t) => t,
-                Err(e) => {
-                    tracing::debug!("SSH 认证失败: {}", e);
-                    return Err(e);
-                }
-            };
-        TransferKind::Download { server, addr, remote_root }
+        TransferKind::Download(connect_download_sources(config, &pool, &sources)?)
     } else {
         TransferKind::Unknown
     };
 
+    if let Some(args) = record {
+        match Journal::create(&journal::journal_path(config), &args) {
+            Ok(created) => *journal = Some(Arc::new(created)),
+            Err(e) => eprintln!("⚠️ 无法写入任务日志，本次传输不可续传: {:#}", e),
+        }
+    }
+    events::emit(
+        "job_start",
+        serde_json::json!({
+            "job_id": journal.as_ref().map(|j| j.id()),
+            "direction": if is_relay { "relay" } else if target_is_remote { "upload" } else { "download" },
+            "sources": sources,
+            "target": history.target,
+            "dry_run": dry_run,
+        }),
+    );
+
+    let run = PullRun {
+        verbose,
+        json,
+        quiet,
+        dry_run,
+        concurrency,
+        max_workers: max_allowed_workers,
+        max_retries,
+        buf_size,
+        filter: &filter,
+        filtered: !excludes.is_empty() || !includes.is_empty() || exclude_from.is_some(),
+        links,
+        attr_policy,
+        overwrite: &overwrite,
+        mirror_opts,
+        mirror_filter: &mirror_filter,
+        limit_rate: limit_rate.as_deref(),
+        tar,
+        chunking,
+        pool: &pool,
+        journal: journal.as_ref(),
+        history: &history,
+        total_style: &total_style,
+        file_style: &file_style,
+    };
     match transfer_kind {
-        TransferKind::Upload { server, addr, expanded_remote_base, mut entries, total_size } => {
-            // R2 flags per source and target
-            let tgt_ends_slash = expanded_remote_base.ends_with('/');
-
-            // sftp for probing/creating remote dirs
-            let sess = connect_session(&server)?;
-            let sftp = sess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", addr))?;
-
-            // 预判目标目录策略（R5–R7）
-            let target_is_dir_final =
-                prepare_remote_target(&sftp, &expanded_remote_base, tgt_ends_slash)?;
-
-            // 多源/单源一致性（R8）
-            let total_entries = entries.len();
-            if !target_is_dir_final && total_entries > 1 {
-                return Err(crate::TransferError::RemoteTargetMustBeDir(
-                    expanded_remote_base.clone(),
-                )
-                .into());
-            }
-            if !target_is_dir_final && total_entries == 1 {
-                // 唯一条目必须为文件
-                if entries[0].kind != EntryKind::File {
-                    return Err(crate::TransferError::RemoteTargetMustBeDir(
-                        expanded_remote_base.clone(),
-                    )
-                    .into());
-                }
-            }
+        TransferKind::Upload { dest, enumeration } => {
+            let stream = enumeration.is_none().then_some(LocalSources {
+                sources: &sources,
+                filter: &filter,
+                links,
+            });
+            let enumeration = enumeration.unwrap_or_default();
+            let upload = UploadRun {
+                entries: &enumeration.entries,
+                total_size: enumeration.total_size,
+                unreadable: &enumeration.unreadable,
+                skipped_links: &enumeration.skipped_links,
+                verbose,
+                json,
+                quiet,
+                dry_run,
+                concurrency,
+                max_workers: max_allowed_workers,
+                max_retries,
+                buf_size,
+                attr_policy,
+                overwrite: &overwrite,
+                mirror_opts,
+                mirror_filter: &mirror_filter,
+                limit_rate: limit_rate.as_deref(),
+                tar,
+                chunking,
+                stream,
+                pool: &pool,
+                journal: journal.as_ref(),
+                history: &history,
+                total_style: &total_style,
+                file_style: &file_style,
+            };
+            upload_to_dest(config, &upload, dest, parallel_hosts, fail_fast)
+        }
+        TransferKind::Download(src) => download_from_hosts(&run, src, &target, host_dir.as_deref()),
+        TransferKind::Relay(ends) => relay_between_hosts(&run, ends, direct),
+        TransferKind::Unknown => Err(crate::TransferError::InvalidDirection.into()),
+    }
+}
 
-            // 进度与工作线程
-            // Determine effective concurrency: if CLI passed None, choose auto based on totals
-            let effective_conc = match concurrency {
-                Some(c) => c,
+// Download sources: every one must be remote; they may span several aliases (or a tag
+// selector `@tag:/path`), each connected once.
+fn connect_download_sources(
+    config: &Config,
+    pool: &SessionPool,
+    sources: &[String],
+) -> Result<DownloadSources> {
+    let mut hosts: Vec<RemoteHost> = Vec::new();
+    let mut roots: Vec<(usize, String)> = Vec::new();
+    let mut unreachable: Vec<(String, String)> = Vec::new();
+    let mut fan_in = false;
+    for s in sources.iter() {
+        if !is_remote_spec(s) {
+            return Err(crate::TransferError::InvalidDirection.into());
+        }
+        let (alias, remote_path) = crate::parse::parse_alias_and_path(s)?;
+        let (members, is_tag) = match alias.strip_prefix('@') {
+            Some(tag) => (fanout::resolve_tag(config, tag)?, true),
+            None => (vec![alias], false),
+        };
+        fan_in |= is_tag;
+        for alias in members {
+            let origin = match hosts.iter().position(|h| h.alias == alias) {
+                Some(i) => i,
+                None if unreachable.iter().any(|(a, _)| *a == alias) => continue,
                 None => {
-                    // import heuristic
-                    let auto =
-                        crate::auto_concurrency::choose_auto_concurrency(total_entries, total_size);
-                    if verbose {
-                        tracing::info!(
-                            "hp: auto-concurrency chosen workers={} (total_entries={}, total_size={})",
-                            auto,
-                            total_entries,
-                            total_size
-                        );
-                    }
-                    auto
+                    let (server, addr) = load_server_with_addr(config, &alias)?;
+                    let sess = match pool.checkout(&server) {
+                        Ok(sess) => sess,
+                        // one unreachable tag member does not stop the others
+                        Err(e) if is_tag => {
+                            unreachable.push((alias, format!("{:#}", e)));
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::debug!("SSH 认证失败: {}", e);
+                            return Err(e);
+                        }
+                    };
+                    hosts.push(RemoteHost { alias, server, addr, sess });
+                    hosts.len() - 1
                 }
             };
-            let (mp, total_pb, header) = init_progress_and_mp(verbose, total_size, &total_style);
-            // Display compact startup header above total progress (one line)
-            let backoff_ms = crate::util::get_backoff_ms();
-            set_startup_header(&header, "Upload", effective_conc, backoff_ms, buf_size);
-            // ensure header stays visible until we clear the MultiProgress later
-            let workers = calc_upload_workers(effective_conc, max_allowed_workers, total_entries);
-            // 使生产者队列容量严格大于总条目数（若基础容量足够），避免在“先生产后开工人”的流程里刚好填满导致边界卡住
-            // 示例：workers=8 时基础为 32；当 total_entries=32 时将 cap 调整为 33。
-            let cap = {
-                let base_plus = std::cmp::max(4, workers * 4 + 1);
-                // 将上限与 total_entries+1 对齐，既控制内存，又保证有一个额外槽位
-                std::cmp::min(base_plus, std::cmp::max(1, total_entries + 1))
-            };
-            let (tx, rx) = bounded::<FileEntry>(cap);
-            let runtime_handles = workers::setup_worker_runtime(workers);
-            let WorkerRuntimeHandles {
+            roots.push((origin, expand_remote_tilde(&hosts[origin].sess, &remote_path)?));
+        }
+    }
+    if hosts.is_empty()
+        && let Some((alias, err)) = unreachable.first()
+    {
+        return Err(crate::TransferError::AllFailed(format!(
+            "标签内主机均无法连接（{}: {}）",
+            alias, err
+        ))
+        .into());
+    }
+    Ok(DownloadSources { hosts, roots, fan_in, unreachable })
+}
+
+// Upload to one alias, or to every host of a tag (fan-out).
+fn upload_to_dest(
+    config: &Config,
+    run: &UploadRun,
+    dest: UploadDest,
+    parallel_hosts: Option<usize>,
+    fail_fast: bool,
+) -> Result<()> {
+    let (server, addr, expanded_remote_base) = match dest {
+        UploadDest::Host { server, addr, expanded_remote_base } => {
+            (server, addr, expanded_remote_base)
+        }
+        UploadDest::Tag { tag, aliases, remote_path } => {
+            let opts =
+                fanout::FanoutOptions { tag, aliases, remote_path, parallel_hosts, fail_fast };
+            return fanout::fanout_upload(config, run, opts);
+        }
+    };
+    let limiter = ratelimit::limiter_for(run.limit_rate, &server)?;
+    let Some(out) = upload_to_host(run, &server, &addr, &expanded_remote_base, limiter, None)?
+    else {
+        // dry-run: the plan has been emitted
+        return Ok(());
+    };
+    // finalize_transfer performs UI cleanup, summary printing and failure file writes.
+    let finalize_ctx = FinalizeCtx {
+        mp: out.mp,
+        header: out.header,
+        total_pb: out.total_pb,
+        json_mode: run.json,
+        quiet_mode: run.quiet,
+        mirror: out.mirror,
+        skipped_links: out.skipped_links,
+        overwrite: run.overwrite.is_active().then_some(out.overwrite),
+        hosts: Vec::new(),
+        sessions: run.pool.stats(),
+        journal: run.journal.cloned(),
+        direction: "upload",
+        alias: server.alias.clone(),
+        history: run.history.clone(),
+        tuning: out.tuning,
+    };
+    finalize_transfer(finalize_ctx, out.start, out.metrics, out.failures, out.total_size, out.files)
+}
+
+// Settings of a run that reads from remote sources (download or relay).
+struct PullRun<'a> {
+    verbose: bool,
+    json: bool,
+    quiet: bool,
+    dry_run: bool,
+    concurrency: Option<usize>,
+    max_workers: usize,
+    max_retries: usize,
+    buf_size: usize,
+    filter: &'a PathFilter,
+    // --exclude/--include/--exclude-from were given (`--direct` cannot apply them)
+    filtered: bool,
+    links: LinkPolicy,
+    attr_policy: AttrPolicy,
+    overwrite: &'a OverwritePolicy,
+    mirror_opts: Option<MirrorOptions>,
+    mirror_filter: &'a PathFilter,
+    limit_rate: Option<&'a str>,
+    // `--tar`: one tar stream per alias replaces the worker pool
+    tar: bool,
+    chunking: Option<ChunkPolicy>,
+    pool: &'a Arc<SessionPool>,
+    journal: Option<&'a Arc<Journal>>,
+    history: &'a HistoryTarget,
+    total_style: &'a ProgressStyle,
+    file_style: &'a ProgressStyle,
+}
+
+// Remote sources of a download; every alias is connected once.
+struct DownloadSources {
+    hosts: Vec<RemoteHost>,
+    // (index into `hosts`, expanded remote path) per source, in CLI order
+    roots: Vec<(usize, String)>,
+    // a source was a tag selector: every alias lands in its own subdirectory
+    fan_in: bool,
+    // tag members that could not be connected: (alias, error)
+    unreachable: Vec<(String, String)>,
+}
+
+// Both ends of a remote -> remote transfer; `server`/`addr` are the destination.
+struct RelayEnds {
+    src_server: Arc<crate::server::Server>,
+    src_addr: String,
+    src_root: String,
+    server: Arc<crate::server::Server>,
+    addr: String,
+    dest_base: String,
+}
+
+// A download past the target pre-checks, shared by the plan, tar and worker paths.
+struct DownloadJob<'a> {
+    src: &'a DownloadSources,
+    // one SFTP channel per alias on the probe session, reused for enumeration
+    sftps: Vec<ssh2::Sftp>,
+    target: &'a str,
+    tpath: &'a std::path::Path,
+    target_is_dir_final: bool,
+    // subdirectory per alias when fanning in; empty otherwise
+    host_dirs: Vec<String>,
+}
+
+impl DownloadJob<'_> {
+    // relative path below the target, inside the alias' directory when fanning in
+    fn host_rel(&self, origin: usize, rel: String) -> String {
+        match self.host_dirs.get(origin) {
+            Some(dir) => format!("{}/{}", dir, rel),
+            None => rel,
+        }
+    }
+
+    // failure records name the alias when the run reads from a single host
+    fn run_alias(&self) -> Option<String> {
+        (self.src.hosts.len() == 1).then(|| self.src.hosts[0].alias.clone())
+    }
+
+    // With several roots two of them may map to the same local path: the first one wins,
+    // later ones are reported and skipped.
+    fn claim(
+        &self,
+        claimed: &std::cell::RefCell<std::collections::HashSet<String>>,
+        origin: usize,
+        full: &str,
+        rel: &str,
+        kind: EntryKind,
+        failure_tx: &crossbeam_channel::Sender<crate::TransferError>,
+    ) -> bool {
+        if self.src.roots.len() < 2
+            || kind == EntryKind::Dir
+            || claimed.borrow_mut().insert(rel.to_string())
+        {
+            return true;
+        }
+        workers::report_failure(
+            failure_tx,
+            crate::TransferError::OperationFailed(format!(
+                "多个远端源映射到同一本地路径 {}，已跳过 {}:{}",
+                rel, self.src.hosts[origin].alias, full
+            )),
+        );
+        false
+    }
+
+    // first root that is not a remote directory (--delete and --tar need directories)
+    fn non_dir_root(&self) -> Option<&(usize, String)> {
+        self.src.roots.iter().find(|(origin, root)| {
+            !self.sftps[*origin]
+                .stat(std::path::Path::new(root))
+                .map(|st| st.is_dir())
+                .unwrap_or(false)
+        })
+    }
+
+    fn enumerate(
+        &self,
+        run: &PullRun,
+        push: &dyn Fn(usize, String, String, Option<u64>, EntryKind),
+    ) -> (enumeration::RemoteWalk, Vec<(usize, String)>) {
+        let src = self.src;
+        enumerate_download_roots(
+            run.pool,
+            &src.hosts,
+            &self.sftps,
+            &src.roots,
+            run.filter,
+            run.links,
+            push,
+        )
+    }
+
+    fn report_unreachable(&self, failure_tx: &crossbeam_channel::Sender<crate::TransferError>) {
+        for (alias, err) in self.src.unreachable.iter() {
+            workers::report_failure(
                 failure_tx,
-                failure_rx,
-                metrics_tx,
-                metrics_rx,
-                pb_slot_tx,
-                pb_slot_rx,
-            } = runtime_handles;
-            // connection token bucket
-            // 握手并发与工作线程数解耦，避免过高并发导致服务端限流/认证失败；此值可后续抽为配置项
-            let handshake_limit = std::cmp::min(workers, 4);
-            let (conn_token_tx, conn_token_rx) = bounded::<()>(handshake_limit);
-            for _ in 0..handshake_limit {
-                let _ = conn_token_tx.send(());
-            }
+                crate::TransferError::OperationFailed(format!("{}: {}", alias, err)),
+            );
+        }
+    }
 
-            // 先启动 worker 再生产，避免生产者在有界队列上阻塞
-            let ctx_for_workers = UploadWorkersCtx {
-                common: WorkerCommonCtx {
-                    workers,
-                    mp: mp.clone(),
-                    total_pb: total_pb.clone(),
-                    file_style: file_style.clone(),
-                    server: server.clone(),
-                    addr: addr.clone(),
-                    max_retries,
-                    target_is_dir_final,
-                    failure_tx: failure_tx.clone(),
-                    buf_size,
-                },
-                rx,
-                expanded_remote_base: expanded_remote_base.clone(),
-                conn_token_rx: conn_token_rx.clone(),
-                conn_token_tx: conn_token_tx.clone(),
-                metrics_tx: metrics_tx.clone(),
-                pb_slot_rx: pb_slot_rx.clone(),
-                pb_slot_tx: pb_slot_tx.clone(),
-            };
-            let worker_thread = std::thread::spawn(move || {
-                run_upload_workers(ctx_for_workers);
-            });
+    // Per-alias rows of a fan-in summary: the connected aliases, then the unreachable ones.
+    fn host_breakdown(&self, reached: impl Iterator<Item = HostBreakdown>) -> Vec<HostBreakdown> {
+        if !self.src.fan_in {
+            return Vec::new();
+        }
+        let failed = self.src.unreachable.iter().map(|(alias, err)| HostBreakdown {
+            alias: alias.clone(),
+            dir: None,
+            discovered: 0,
+            files: 0,
+            bytes: 0,
+            error: Some(err.clone()),
+        });
+        reached.chain(failed).collect()
+    }
+}
+
+// 下载：远端 -> 本地。目标预检（R5–R8）后按预演、tar 流或 worker 池执行。
+// Every root (possibly from several aliases) fans into one worker queue; fan-in (tag
+// sources) places each alias under `<target>/<host dir>/`.
+fn download_from_hosts(
+    run: &PullRun,
+    src: DownloadSources,
+    target: &str,
+    host_dir: Option<&str>,
+) -> Result<()> {
+    // Flags per R2
+    let tgt_ends_slash = target.ends_with('/');
+
+    // Pre-check and normalize local target per R5–R7
+    let tpath = std::path::Path::new(target);
+    let base_state = plan::local_state(tpath);
+    let target_is_dir_final: bool = prepare_local_target(tpath, tgt_ends_slash, !run.dry_run)?;
+    if run.mirror_opts.is_some() && !target_is_dir_final {
+        return Err(crate::TransferError::MirrorUnsupported(target.to_string()).into());
+    }
+
+    let host_dirs: Vec<String> = if src.fan_in {
+        if !target_is_dir_final {
+            return Err(crate::TransferError::LocalTargetMustBeDir(target.to_string()).into());
+        }
+        if run.mirror_opts.is_some() {
+            return Err(crate::TransferError::MirrorUnsupported(target.to_string()).into());
+        }
+        let template = host_dir.unwrap_or("{alias}");
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        src.hosts
+            .iter()
+            .map(|h| helpers::render_host_dir(template, &h.alias, &h.server.address, &date))
+            .collect::<Result<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    // Additional multi-entry constraint (R8): if target is a file path, forbid glob or recursive
+    if !target_is_dir_final && src.roots.len() > 1 {
+        return Err(crate::TransferError::LocalTargetMustBeDir(target.to_string()).into());
+    }
+
+    // 枚举远端文件（支持目录、通配或单文件） — Streamed producer (support dir, glob, or single)
+    // The probe sessions opened for tilde expansion are reused for enumeration
+    let sftps = src
+        .hosts
+        .iter()
+        .map(|h| h.sess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", h.addr)))
+        .collect::<Result<Vec<_>>>()?;
+    let job = DownloadJob { src: &src, sftps, target, tpath, target_is_dir_final, host_dirs };
+    if !target_is_dir_final
+        && src.roots.iter().any(|(o, r)| is_remote_glob(&job.sftps[*o], r) || r.ends_with('/'))
+    {
+        return Err(crate::TransferError::InvalidArgument(
+            "目标为文件路径，但源为 glob 或目录递归；请将目标设为目录或去除 glob/尾部/".to_string(),
+        )
+        .into());
+    }
+    if run.mirror_opts.is_some()
+        && let Some((_, root)) = job.non_dir_root()
+    {
+        return Err(crate::TransferError::MirrorUnsupported(root.clone()).into());
+    }
+    if run.tar {
+        if !target_is_dir_final {
+            return Err(crate::TransferError::LocalTargetMustBeDir(target.to_string()).into());
+        }
+        if let Some((origin, root)) = job.non_dir_root() {
+            return Err(crate::TransferError::InvalidArgument(format!(
+                "--tar 仅适用于目录源: {}:{}",
+                src.hosts[*origin].alias, root
+            ))
+            .into());
+        }
+    }
+    if run.dry_run {
+        return plan_download(run, &job, base_state);
+    }
+    for dir in job.host_dirs.iter() {
+        let dest = tpath.join(dir);
+        std::fs::create_dir_all(&dest).map_err(|e| -> anyhow::Error {
+            crate::TransferError::CreateLocalDirFailed(dest.display().to_string(), e.to_string())
+                .into()
+        })?;
+    }
+    if run.tar {
+        return download_tar(run, &job);
+    }
+    download_streamed(run, &job)
+}
+
+// `--dry-run` of a download: walk the roots and emit the plan.
+fn plan_download(run: &PullRun, job: &DownloadJob, base_state: plan::DestState) -> Result<()> {
+    let found: std::cell::RefCell<Vec<(String, Option<u64>, EntryKind)>> =
+        std::cell::RefCell::new(Vec::new());
+    let push = |origin: usize, _full: String, rel: String, size: Option<u64>, kind: EntryKind| {
+        found.borrow_mut().push((job.host_rel(origin, rel), size, kind));
+    };
+    let (walk, unmatched) = job.enumerate(run, &push);
+    if job.src.roots.len() == 1
+        && let Some((_, root)) = unmatched.into_iter().next()
+    {
+        return Err(crate::TransferError::GlobNoMatches(root).into());
+    }
+    let found = found.into_inner();
+    let mut plan = plan::TransferPlan::new("download", job.target);
+    plan.skipped_links = walk.skipped_links;
+    plan.no_clobber = run.overwrite.no_clobber;
+    plan.push_target(base_state, job.target_is_dir_final);
+    for dir in job.host_dirs.iter() {
+        let dest = job.tpath.join(dir);
+        let state = plan::local_state(&dest);
+        plan.push(EntryKind::Dir, dest.to_string_lossy().to_string(), None, state);
+    }
+    for (rel, size, kind) in found.iter() {
+        let dest =
+            if job.target_is_dir_final { job.tpath.join(rel) } else { job.tpath.to_path_buf() };
+        let state = plan::local_state(&dest);
+        plan.push(*kind, dest.to_string_lossy().to_string(), *size, state);
+    }
+    if let Some(opts) = run.mirror_opts {
+        plan.mirror = Some(mirror::preview_after_walk(
+            MirrorTarget::Local(job.tpath),
+            found.iter().map(|(r, _, _)| r.as_str()),
+            &plan.skipped_links,
+            &walk.unreadable,
+            run.mirror_filter,
+            opts,
+        ));
+    }
+    plan.emit(run.json, run.quiet);
+    Ok(())
+}
+
+// `--tar` download: one `tar -c` stream per alias; the walk only decides what is requested.
+fn download_tar(run: &PullRun, job: &DownloadJob) -> Result<()> {
+    let hosts = &job.src.hosts;
+    let items: std::cell::RefCell<Vec<Vec<tarmode::TarItem>>> =
+        std::cell::RefCell::new(hosts.iter().map(|_| Vec::new()).collect());
+    let claimed: std::cell::RefCell<std::collections::HashSet<String>> =
+        std::cell::RefCell::new(std::collections::HashSet::new());
+    let total_size = std::cell::Cell::new(0u64);
+    let (failure_tx, failure_rx) = crossbeam_channel::unbounded();
+    let push = |origin: usize, full: String, rel: String, size: Option<u64>, kind: EntryKind| {
+        let rel = job.host_rel(origin, rel);
+        if !job.claim(&claimed, origin, &full, &rel, kind, &failure_tx) {
+            return;
+        }
+        total_size.set(total_size.get() + size.unwrap_or(0));
+        items.borrow_mut()[origin].push(tarmode::TarItem { full, rel, kind });
+    };
+    // roots were checked to be directories, so nothing is unmatched
+    let (walk, _) = job.enumerate(run, &push);
+    let items = items.take();
+    let start = Instant::now();
+    let (mp, total_pb, header) =
+        init_progress_and_mp(run.verbose, total_size.get(), run.total_style);
+    let backoff_ms = crate::util::get_backoff_ms();
+    set_startup_header(&header, "Download", 1, backoff_ms, run.buf_size);
+    job.report_unreachable(&failure_tx);
+    let servers: Vec<&crate::server::Server> = hosts.iter().map(|h| h.server.as_ref()).collect();
+    let limiters = ratelimit::limiters_for(run.limit_rate, &servers)?;
+    let follow = run.links == LinkPolicy::Follow;
+    let mut per_host: Vec<(u64, u64)> = Vec::with_capacity(hosts.len());
+    for (origin, host) in hosts.iter().enumerate() {
+        let stream = tarmode::TarStream {
+            sess: &host.sess,
+            preserve: run.attr_policy.preserve,
+            total_pb: &total_pb,
+            limiter: limiters[origin].clone(),
+            failure_tx: &failure_tx,
+        };
+        per_host.push(if items[origin].is_empty() {
+            (0, 0)
+        } else {
+            tarmode::download(&stream, &job.sftps[origin], &items[origin], job.tpath, follow)
+        });
+    }
+    let mirror_report = run.mirror_opts.map(|opts| {
+        mirror::mirror_after_walk(
+            MirrorTarget::Local(job.tpath),
+            items.iter().flatten().map(|i| i.rel.as_str()),
+            &walk.skipped_links,
+            &walk.unreadable,
+            run.mirror_filter,
+            opts,
+            &failure_tx,
+        )
+    });
+    drop(failure_tx);
+    let host_breakdown = job.host_breakdown(hosts.iter().enumerate().map(|(i, h)| HostBreakdown {
+        alias: h.alias.clone(),
+        dir: job.host_dirs.get(i).cloned(),
+        discovered: items[i].iter().filter(|t| t.kind != EntryKind::Dir).count() as u64,
+        files: per_host[i].0,
+        bytes: per_host[i].1,
+        error: None,
+    }));
+    let agg = WorkerMetrics { bytes: per_host.iter().map(|(_, b)| b).sum(), ..Default::default() };
+    // files requested, as the other paths count them: the run outcome compares failed
+    // files against this, whether or not they were extracted
+    let files = items.iter().flatten().filter(|t| t.kind != EntryKind::Dir).count() as u64;
+    let finalize_ctx = FinalizeCtx {
+        mp,
+        header,
+        total_pb,
+        json_mode: run.json,
+        quiet_mode: run.quiet,
+        mirror: mirror_report,
+        skipped_links: walk.skipped_links,
+        overwrite: None,
+        hosts: host_breakdown,
+        sessions: run.pool.stats(),
+        journal: run.journal.cloned(),
+        direction: "download",
+        alias: job.run_alias(),
+        history: run.history.clone(),
+        tuning: None,
+    };
+    let failures: Vec<crate::TransferError> = failure_rx.into_iter().collect();
+    finalize_transfer(finalize_ctx, start, agg, failures, total_size.get(), files)
+}
+
+// Download through the worker pool while the roots are walked on this thread.
+fn download_streamed(run: &PullRun, job: &DownloadJob) -> Result<()> {
+    let (hosts, roots) = (&job.src.hosts, &job.src.roots);
+    // without -c the cap bounds the workers; they start as the tuner's limit grows
+    let producer_workers = run.concurrency.unwrap_or(run.max_workers);
+    let cap = std::cmp::max(4, producer_workers * 4);
+    let (file_tx, file_rx) = bounded::<FileEntry>(cap);
+    let bytes_transferred = Arc::new(AtomicU64::new(0));
+    let files_discovered = Arc::new(AtomicU64::new(0));
+    let estimated_total_bytes = Arc::new(AtomicU64::new(0));
+    let enumeration_done = Arc::new(AtomicBool::new(false));
+    // Prepare progress and spawn worker threads BEFORE enumeration so
+    // the producer won't block when the bounded channel is full.
+    let start = Instant::now();
+    let initial_total = estimated_total_bytes.load(Ordering::SeqCst);
+    let workers = calc_download_workers(producer_workers, run.max_workers);
+    let run_alias = job.run_alias();
+    let tuner = run.concurrency.is_none().then(|| {
+        let initial = crate::auto_concurrency::INITIAL_MAX;
+        spawn_tuner(&run.history.db, run_alias.as_deref(), initial, workers)
+    });
+    let (mp, total_pb, header) = init_progress_and_mp(run.verbose, initial_total, run.total_style);
+    total_pb.set_style(run.total_style.clone());
+    if initial_total == 0 {
+        // unknown total — show spinner so user sees activity
+        total_pb.enable_steady_tick(Duration::from_millis(100));
+    }
+    // Display compact startup header above total progress for download
+    let backoff_ms = crate::util::get_backoff_ms();
+    let header_workers = tuner.as_ref().map(|t| t.start()).unwrap_or(workers);
+    set_startup_header(&header, "Download", header_workers, backoff_ms, run.buf_size);
 
-            for e in entries.drain(..) {
-                // Blocking send to apply backpressure on producer
-                let _ = tx.send(e);
+    let runtime_handles = workers::setup_worker_runtime(workers);
+    let WorkerRuntimeHandles {
+        failure_tx,
+        failure_rx,
+        metrics_tx,
+        metrics_rx,
+        pb_slot_tx,
+        pb_slot_rx,
+    } = runtime_handles;
+    let (server, addr) = (hosts[0].server.clone(), hosts[0].addr.clone());
+    // each alias' default limits its own bytes; --limit-rate caps the whole run
+    let servers: Vec<&crate::server::Server> = hosts.iter().map(|h| h.server.as_ref()).collect();
+    let origin_limiters = Arc::new(ratelimit::limiters_for(run.limit_rate, &servers)?);
+    let origins =
+        Arc::new(hosts.iter().map(|h| (h.server.clone(), h.addr.clone())).collect::<Vec<_>>());
+    let origin_stats: Arc<Vec<OriginStats>> =
+        Arc::new(hosts.iter().map(|_| OriginStats::default()).collect());
+    let overwrite_report = Arc::new(OverwriteReport::default());
+    let handles = run_download_workers(DownloadWorkersCtx {
+        common: WorkerCommonCtx {
+            workers,
+            mp: mp.clone(),
+            total_pb: total_pb.clone(),
+            file_style: run.file_style.clone(),
+            server: server.clone(),
+            addr: addr.clone(),
+            pool: run.pool.clone(),
+            max_retries: run.max_retries,
+            target_is_dir_final: job.target_is_dir_final,
+            failure_tx: failure_tx.clone(),
+            buf_size: run.buf_size,
+            attr_policy: run.attr_policy,
+            overwrite: run.overwrite.clone(),
+            overwrite_report: overwrite_report.clone(),
+            rate_limiter: None,
+            chunking: run.chunking.map(|c| c.for_workers(workers)),
+            journal: run.journal.cloned(),
+            tuner: tuner.clone(),
+        },
+        origins,
+        origin_limiters,
+        origin_stats: origin_stats.clone(),
+        file_rx: file_rx.clone(),
+        target: job.target.to_string(),
+        bytes_transferred: bytes_transferred.clone(),
+        verbose: run.verbose,
+        metrics_tx: metrics_tx.clone(),
+        pb_slot_rx: pb_slot_rx.clone(),
+        pb_slot_tx: pb_slot_tx.clone(),
+    });
+    // Enumerate in current thread (streaming) to avoid cloning SFTP/session
+    // local helper to push an entry
+    let file_tx_clone = file_tx.clone();
+    let files_discovered_ref = files_discovered.clone();
+    let estimated_total_bytes_ref = estimated_total_bytes.clone();
+    let total_pb_clone = total_pb.clone();
+    // relative paths seen at the source; only collected for mirror mode
+    let source_rels: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+    // remote directories whose attributes are applied after the run (--preserve etc.)
+    let remote_dirs: std::cell::RefCell<Vec<(usize, String, String)>> =
+        std::cell::RefCell::new(Vec::new());
+    // local paths already claimed by another source; only tracked with several roots
+    let claimed: std::cell::RefCell<std::collections::HashSet<String>> =
+        std::cell::RefCell::new(std::collections::HashSet::new());
+    // non-directory entries found per alias (fan-in breakdown)
+    let discovered: std::cell::RefCell<Vec<u64>> = std::cell::RefCell::new(vec![0; hosts.len()]);
+    let push = |origin: usize, full: String, rel: String, size: Option<u64>, kind: EntryKind| {
+        let rel = job.host_rel(origin, rel);
+        if !job.claim(&claimed, origin, &full, &rel, kind, &failure_tx) {
+            return;
+        }
+        if run.mirror_opts.is_some() {
+            source_rels.borrow_mut().push(rel.clone());
+        }
+        if kind != EntryKind::Dir {
+            discovered.borrow_mut()[origin] += 1;
+            // the summary and the run outcome count files, not directories
+            files_discovered_ref.fetch_add(1, Ordering::SeqCst);
+        }
+        if run.attr_policy.is_active() && kind == EntryKind::Dir && job.target_is_dir_final {
+            remote_dirs.borrow_mut().push((origin, full.clone(), rel.clone()));
+        }
+        let entry = FileEntry { remote_full: full, rel, size, kind, local_full: None, origin };
+        queue_event(Some(&hosts[origin].alias), &entry);
+        // Blocking send with bounded queue applies natural backpressure
+        let _ = file_tx_clone.send(entry);
+        if let Some(s) = size {
+            estimated_total_bytes_ref.fetch_add(s, Ordering::SeqCst);
+            let new_total = estimated_total_bytes_ref.load(Ordering::SeqCst);
+            total_pb_clone.set_length(new_total);
+            if new_total > 0 {
+                total_pb_clone.disable_steady_tick();
             }
-            drop(tx);
-            let start = Instant::now();
-            // 等待 worker 完成
-            let _ = worker_thread.join();
-            drop(failure_tx);
-            drop(metrics_tx);
-            // finalize_transfer consumes the receivers and performs aggregation,
-            // UI cleanup, summary printing and failure file writes.
-            let finalize_ctx = FinalizeCtx {
-                mp: mp.clone(),
-                header: header.clone(),
-                total_pb: total_pb.clone(),
-                json_mode: json,
-                quiet_mode: quiet,
+        }
+    };
+
+    // 复用提炼后的远端枚举推送逻辑
+    let (walk, unmatched) = job.enumerate(run, &push);
+    // the probe sessions are free now (directory attributes only stat on them)
+    for h in hosts.iter() {
+        run.pool.checkin(&h.server, h.sess.clone());
+    }
+
+    enumeration_done.store(true, Ordering::SeqCst);
+    drop(file_tx_clone);
+    drop(file_tx);
+
+    // R3: glob with no match is an error; with several sources it is one failure
+    // among the others so the remaining roots still complete
+    if roots.len() == 1
+        && let Some((_, root)) = unmatched.first()
+    {
+        // Join workers first to avoid leaving threads running
+        for h in handles {
+            let _ = h.join();
+        }
+        return Err(crate::TransferError::GlobNoMatches(root.clone()).into());
+    }
+    for (_, root) in unmatched.iter() {
+        workers::report_failure(&failure_tx, crate::TransferError::GlobNoMatches(root.clone()));
+    }
+    job.report_unreachable(&failure_tx);
+
+    for h in handles {
+        let _ = h.join();
+    }
+    let tuning = tuner.map(|t| t.finish());
+    let dir_attrs: Vec<(std::path::PathBuf, FileAttrs)> = remote_dirs
+        .take()
+        .into_iter()
+        .filter_map(|(origin, full, rel)| {
+            let src = if run.attr_policy.preserve {
+                let st = job.sftps[origin].stat(std::path::Path::new(&full)).ok()?;
+                FileAttrs::from_stat(&st)
+            } else {
+                FileAttrs::default()
             };
-            finalize_transfer(
-                finalize_ctx,
-                start,
-                metrics_rx,
-                failure_rx,
-                total_size,
-                total_entries as u64,
-            );
+            let attrs = run.attr_policy.resolve(src, true);
+            (!attrs.is_empty()).then_some((job.tpath.join(rel), attrs))
+        })
+        .collect();
+    attrs::apply_dir_attrs_local(dir_attrs, &failure_tx);
+    let mirror_report = run.mirror_opts.map(|opts| {
+        mirror::mirror_after_walk(
+            MirrorTarget::Local(job.tpath),
+            source_rels.borrow().iter().map(String::as_str),
+            &walk.skipped_links,
+            &walk.unreadable,
+            run.mirror_filter,
+            opts,
+            &failure_tx,
+        )
+    });
+    drop(failure_tx);
+    drop(metrics_tx);
+    let total_done = bytes_transferred.load(Ordering::SeqCst);
+    let files_done = files_discovered.load(Ordering::SeqCst);
+    let discovered = discovered.take();
+    let host_breakdown = job.host_breakdown(hosts.iter().enumerate().map(|(i, h)| {
+        HostBreakdown {
+            alias: h.alias.clone(),
+            dir: job.host_dirs.get(i).cloned(),
+            discovered: discovered[i],
+            files: origin_stats[i].files.load(Ordering::SeqCst),
+            bytes: origin_stats[i].bytes.load(Ordering::SeqCst),
+            error: unmatched
+                .iter()
+                .find(|(o, _)| *o == i)
+                .map(|(_, root)| crate::TransferError::GlobNoMatches(root.clone()).to_string()),
+        }
+    }));
+    // finalize_transfer will consume receivers and perform the rest
+    let finalize_ctx = FinalizeCtx {
+        mp: mp.clone(),
+        header: header.clone(),
+        total_pb: total_pb.clone(),
+        json_mode: run.json,
+        quiet_mode: run.quiet,
+        mirror: mirror_report,
+        skipped_links: walk.skipped_links,
+        overwrite: run.overwrite.is_active().then(|| overwrite_report.clone()),
+        hosts: host_breakdown,
+        sessions: run.pool.stats(),
+        journal: run.journal.cloned(),
+        direction: "download",
+        alias: run_alias,
+        history: run.history.clone(),
+        tuning,
+    };
+    let (agg, failures) = collect_run(metrics_rx, failure_rx);
+    finalize_transfer(finalize_ctx, start, agg, failures, total_done, files_done)
+}
 
-            Ok(())
-        }
-        TransferKind::Download { server, addr, remote_root } => {
-            // 下载：远端 -> 本地 — Download remote -> local
-            // Use the pre-parsed/expanded fields from TransferKind to avoid
-            // repeating parsing/lookup.
-            // Flags per R2
-            let src_has_glob = remote_root.chars().any(|c| c == '*' || c == '?');
-            let explicit_dir_suffix = remote_root.ends_with('/');
-            let tgt_ends_slash = target.ends_with('/');
-
-            // Pre-check and normalize local target per R5–R7
-            let tpath = std::path::Path::new(&target);
-            let target_is_dir_final: bool = prepare_local_target(tpath, tgt_ends_slash)?;
-
-            // Additional multi-entry constraint (R8): if target is a file path, forbid glob or recursive
-            if !target_is_dir_final && (src_has_glob || explicit_dir_suffix) {
-                return Err(crate::TransferError::OperationFailed(
-                    "目标为文件路径，但源为 glob 或目录递归；请将目标设为目录或去除 glob/尾部/"
-                        .to_string(),
-                )
-                .into());
+// 远端 -> 远端：源端 SFTP 读取经本机内存直接写入目标端 SFTP，不落本地磁盘。
+// `--direct` first tries to have the source host push straight to the destination.
+fn relay_between_hosts(run: &PullRun, ends: RelayEnds, direct: bool) -> Result<()> {
+    let RelayEnds { src_server, src_addr, src_root, server, addr, dest_base } = &ends;
+    let ssess = run.pool.checkout(src_server)?;
+    let ssftp = ssess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", src_addr))?;
+    let src_has_glob = is_remote_glob(&ssftp, src_root);
+    let explicit_dir_suffix = src_root.ends_with('/');
+    let tgt_ends_slash = dest_base.ends_with('/');
+
+    // destination pre-checks per R5–R7
+    let dsess = run.pool.checkout(server)?;
+    let dsftp = dsess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", addr))?;
+    let base_state = plan::remote_state(&dsftp, dest_base);
+    let target_is_dir_final =
+        prepare_remote_target(&dsftp, dest_base, tgt_ends_slash, !run.dry_run)?;
+    if run.mirror_opts.is_some() && !target_is_dir_final {
+        return Err(crate::TransferError::MirrorUnsupported(dest_base.clone()).into());
+    }
+    if !target_is_dir_final && (src_has_glob || explicit_dir_suffix) {
+        return Err(crate::TransferError::RemoteTargetMustBeDir(dest_base.clone()).into());
+    }
+
+    let src_is_dir =
+        ssftp.stat(std::path::Path::new(src_root)).map(|st| st.is_dir()).unwrap_or(false);
+    if run.mirror_opts.is_some() && !src_is_dir {
+        return Err(crate::TransferError::MirrorUnsupported(src_root.clone()).into());
+    }
+    if !target_is_dir_final && src_is_dir {
+        return Err(crate::TransferError::RemoteTargetMustBeDir(dest_base.clone()).into());
+    }
+
+    if direct {
+        let pushed =
+            relay_direct(run, &ends, &ssess, src_has_glob, src_is_dir, target_is_dir_final);
+        if let Some(res) = pushed {
+            return res;
+        }
+    }
+    if run.dry_run {
+        let dest = (&dsftp, base_state, target_is_dir_final);
+        return plan_relay(run, &ends, &ssftp, dest, src_has_glob);
+    }
+
+    let producer_workers = run.concurrency.unwrap_or(run.max_workers);
+    let cap = std::cmp::max(4, producer_workers * 4);
+    let (file_tx, file_rx) = bounded::<FileEntry>(cap);
+    let bytes_transferred = Arc::new(AtomicU64::new(0));
+    let files_discovered = Arc::new(AtomicU64::new(0));
+    let estimated_total_bytes = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let workers = calc_download_workers(producer_workers, run.max_workers);
+    let tuner = run.concurrency.is_none().then(|| {
+        let initial = crate::auto_concurrency::INITIAL_MAX;
+        spawn_tuner(&run.history.db, src_server.alias.as_deref(), initial, workers)
+    });
+    let (mp, total_pb, header) = init_progress_and_mp(run.verbose, 0, run.total_style);
+    total_pb.set_style(run.total_style.clone());
+    total_pb.enable_steady_tick(Duration::from_millis(100));
+    let backoff_ms = crate::util::get_backoff_ms();
+    let header_workers = tuner.as_ref().map(|t| t.start()).unwrap_or(workers);
+    set_startup_header(&header, "Relay", header_workers, backoff_ms, run.buf_size);
+
+    let WorkerRuntimeHandles {
+        failure_tx,
+        failure_rx,
+        metrics_tx,
+        metrics_rx,
+        pb_slot_tx,
+        pb_slot_rx,
+    } = workers::setup_worker_runtime(workers);
+    // CLI value first, then the destination's alias default, then the source's
+    let rate_limiter = match ratelimit::limiter_for(run.limit_rate, server)? {
+        Some(l) => Some(l),
+        None => ratelimit::limiter_for(run.limit_rate, src_server)?,
+    };
+    let overwrite_report = Arc::new(OverwriteReport::default());
+    let handles = run_relay_workers(RelayWorkersCtx {
+        common: WorkerCommonCtx {
+            workers,
+            mp: mp.clone(),
+            total_pb: total_pb.clone(),
+            file_style: run.file_style.clone(),
+            server: server.clone(),
+            addr: addr.clone(),
+            pool: run.pool.clone(),
+            max_retries: run.max_retries,
+            target_is_dir_final,
+            failure_tx: failure_tx.clone(),
+            buf_size: run.buf_size,
+            attr_policy: run.attr_policy,
+            overwrite: run.overwrite.clone(),
+            overwrite_report: overwrite_report.clone(),
+            rate_limiter,
+            // relays stream through this host; no positional IO to split on
+            chunking: None,
+            journal: run.journal.cloned(),
+            tuner: tuner.clone(),
+        },
+        src_server: src_server.clone(),
+        src_addr: src_addr.clone(),
+        file_rx,
+        dest_base: dest_base.clone(),
+        bytes_transferred: bytes_transferred.clone(),
+        metrics_tx: metrics_tx.clone(),
+        pb_slot_rx,
+        pb_slot_tx,
+    });
+    let source_rels: std::cell::RefCell<Vec<String>> = std::cell::RefCell::new(Vec::new());
+    let src_dirs: std::cell::RefCell<Vec<(String, String)>> = std::cell::RefCell::new(Vec::new());
+    // non-directory entries: the run outcome is judged per file
+    let files_found = std::cell::Cell::new(0u64);
+    let push = |full: String, rel: String, size: Option<u64>, kind: EntryKind| {
+        if run.mirror_opts.is_some() {
+            source_rels.borrow_mut().push(rel.clone());
+        }
+        if run.attr_policy.is_active() && kind == EntryKind::Dir && target_is_dir_final {
+            src_dirs.borrow_mut().push((full.clone(), rel.clone()));
+        }
+        let entry = FileEntry { remote_full: full, rel, size, kind, local_full: None, origin: 0 };
+        queue_event(server.alias.as_deref(), &entry);
+        let _ = file_tx.send(entry);
+        files_discovered.fetch_add(1, Ordering::SeqCst);
+        if kind != EntryKind::Dir {
+            files_found.set(files_found.get() + 1);
+        }
+        if let Some(s) = size {
+            let new_total = estimated_total_bytes.fetch_add(s, Ordering::SeqCst) + s;
+            total_pb.set_length(new_total);
+            if new_total > 0 {
+                total_pb.disable_steady_tick();
             }
+        }
+    };
+    let walk = enumerate_remote_and_push(
+        &ssftp,
+        src_root,
+        run.filter,
+        run.links,
+        RemoteListers::new(run.pool, src_server),
+        &push,
+    );
+    drop(file_tx);
+    for h in handles {
+        let _ = h.join();
+    }
+    let tuning = tuner.map(|t| t.finish());
+    if src_has_glob && files_discovered.load(Ordering::SeqCst) == 0 {
+        return Err(crate::TransferError::GlobNoMatches(src_root.clone()).into());
+    }
 
-            // 枚举远端文件（支持目录、通配或单文件） — Streamed producer (support dir, glob, or single)
-            // Need an authenticated session for enumeration
-            let sess = match connect_session(&server) {
-                Ok(s) => s,
-                Err(e) => {
-                    tracing::debug!("SSH 认证失败: {}", e);
-                    return Err(e);
-                }
+    // probe sessions may have idled out during a long transfer; use fresh ones
+    let dir_attrs: Vec<(String, FileAttrs)> = src_dirs
+        .take()
+        .into_iter()
+        .filter_map(|(full, rel)| {
+            let src = if run.attr_policy.preserve {
+                FileAttrs::from_stat(&ssftp.stat(std::path::Path::new(&full)).ok()?)
+            } else {
+                FileAttrs::default()
             };
-            let sftp = sess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", addr))?;
-            let producer_workers = concurrency.unwrap_or(8usize);
-            let cap = std::cmp::max(4, producer_workers * 4);
-            let (file_tx, file_rx) = bounded::<FileEntry>(cap);
-            let bytes_transferred = Arc::new(AtomicU64::new(0));
-            let files_discovered = Arc::new(AtomicU64::new(0));
-            let estimated_total_bytes = Arc::new(AtomicU64::new(0));
-            let enumeration_done = Arc::new(AtomicBool::new(false));
-            // Prepare progress and spawn worker threads BEFORE enumeration so
-            // the producer won't block when the bounded channel is full.
-            let start = Instant::now();
-            let initial_total = estimated_total_bytes.load(Ordering::SeqCst);
-            let workers = calc_download_workers(producer_workers, max_allowed_workers);
-            let (mp, total_pb, header) = init_progress_and_mp(verbose, initial_total, &total_style);
-            total_pb.set_style(total_style.clone());
-            if initial_total == 0 {
-                // unknown total — show spinner so user sees activity
-                total_pb.enable_steady_tick(Duration::from_millis(100));
+            let attrs = run.attr_policy.resolve(src, true);
+            let dest =
+                format!("{}/{}", dest_base.trim_end_matches('/'), normalize_path(&rel, true));
+            (!attrs.is_empty()).then_some((dest, attrs))
+        })
+        .collect();
+    drop(ssftp);
+    run.pool.checkin(src_server, ssess);
+    let post_sftp = if !dir_attrs.is_empty() || run.mirror_opts.is_some() {
+        match run.pool.checkout_sftp(server) {
+            Ok((_, sftp)) => Some(sftp),
+            Err(e) => {
+                workers::report_failure(
+                    &failure_tx,
+                    crate::TransferError::WorkerIo(format!("目标端收尾无法建立会话: {}", e)),
+                );
+                None
             }
-            // Display compact startup header above total progress for download
-            let backoff_ms = crate::util::get_backoff_ms();
-            set_startup_header(&header, "Download", workers, backoff_ms, buf_size);
+        }
+    } else {
+        None
+    };
+    if let Some(ref psftp) = post_sftp {
+        attrs::apply_dir_attrs_remote(psftp, dir_attrs, &failure_tx);
+    }
+    let mirror_report = run.mirror_opts.map(|opts| match post_sftp {
+        Some(ref psftp) => mirror::mirror_after_walk(
+            MirrorTarget::Remote(psftp, dest_base),
+            source_rels.borrow().iter().map(String::as_str),
+            &walk.skipped_links,
+            &walk.unreadable,
+            run.mirror_filter,
+            opts,
+            &failure_tx,
+        ),
+        None => MirrorReport { dry_run: opts.dry_run, aborted: true, ..Default::default() },
+    });
+    drop(failure_tx);
+    drop(metrics_tx);
+    let finalize_ctx = FinalizeCtx {
+        mp: mp.clone(),
+        header: header.clone(),
+        total_pb: total_pb.clone(),
+        json_mode: run.json,
+        quiet_mode: run.quiet,
+        mirror: mirror_report,
+        skipped_links: walk.skipped_links,
+        overwrite: run.overwrite.is_active().then(|| overwrite_report.clone()),
+        hosts: Vec::new(),
+        sessions: run.pool.stats(),
+        journal: run.journal.cloned(),
+        direction: "relay",
+        alias: src_server.alias.clone(),
+        history: run.history.clone(),
+        tuning,
+    };
+    let (agg, failures) = collect_run(metrics_rx, failure_rx);
+    finalize_transfer(
+        finalize_ctx,
+        start,
+        agg,
+        failures,
+        bytes_transferred.load(Ordering::SeqCst),
+        files_found.get(),
+    )
+}
 
-            let runtime_handles = workers::setup_worker_runtime(workers);
-            let WorkerRuntimeHandles {
-                failure_tx,
-                failure_rx,
-                metrics_tx,
-                metrics_rx,
-                pb_slot_tx,
-                pb_slot_rx,
-            } = runtime_handles;
-            let handles = run_download_workers(DownloadWorkersCtx {
-                common: WorkerCommonCtx {
-                    workers,
-                    mp: mp.clone(),
-                    total_pb: total_pb.clone(),
-                    file_style: file_style.clone(),
-                    server: server.clone(),
-                    addr: addr.clone(),
-                    max_retries,
-                    target_is_dir_final,
-                    failure_tx: failure_tx.clone(),
-                    buf_size,
-                },
-                file_rx: file_rx.clone(),
-                target: target.clone(),
-                bytes_transferred: bytes_transferred.clone(),
-                verbose,
-                metrics_tx: metrics_tx.clone(),
-                pb_slot_rx: pb_slot_rx.clone(),
-                pb_slot_tx: pb_slot_tx.clone(),
-            });
-            // Enumerate in current thread (streaming) to avoid cloning SFTP/session
-            // local helper to push an entry
-            let file_tx_clone = file_tx.clone();
-            let files_discovered_ref = files_discovered.clone();
-            let estimated_total_bytes_ref = estimated_total_bytes.clone();
-            let total_pb_clone = total_pb.clone();
-            let push = |full: String, rel: String, size: Option<u64>, kind: EntryKind| {
-                let entry = FileEntry { remote_full: full, rel, size, kind, local_full: None };
-                // Blocking send with bounded queue applies natural backpressure
-                let _ = file_tx_clone.send(entry);
-                files_discovered_ref.fetch_add(1, Ordering::SeqCst);
-                if let Some(s) = size {
-                    estimated_total_bytes_ref.fetch_add(s, Ordering::SeqCst);
-                    let new_total = estimated_total_bytes_ref.load(Ordering::SeqCst);
-                    total_pb_clone.set_length(new_total);
-                    if new_total > 0 {
-                        total_pb_clone.disable_steady_tick();
-                    }
-                }
+// `--direct`: the source host pushes straight to the destination over its own SSH.
+// None when the source cannot reach the destination and the relay goes through this host.
+fn relay_direct(
+    run: &PullRun,
+    ends: &RelayEnds,
+    ssess: &ssh2::Session,
+    src_has_glob: bool,
+    src_is_dir: bool,
+    target_is_dir_final: bool,
+) -> Option<Result<()>> {
+    // options that need per-file control cannot be honoured by the source host
+    let per_file = run.filtered
+        || run.mirror_opts.is_some()
+        || run.overwrite.is_active()
+        || run.links != LinkPolicy::Skip
+        || run.attr_policy.is_active()
+        || run.limit_rate.is_some()
+        || run.dry_run
+        || src_has_glob;
+    if per_file {
+        return Some(Err(crate::TransferError::InvalidArgument(
+            "--direct 不支持通配符源及 --exclude/--include/--delete/--no-clobber/--update/--backup/--links/-p/--chmod/--umask/--limit-rate/--dry-run".to_string(),
+        )
+        .into()));
+    }
+    let command = direct::direct_command(
+        &ends.server,
+        &ends.src_root,
+        src_is_dir,
+        &ends.dest_base,
+        target_is_dir_final,
+    );
+    let start = Instant::now();
+    let outcome = direct::run_direct(ssess, &ends.server, &command);
+    if let Ok(direct::DirectOutcome::Unreachable(reason)) = &outcome {
+        tracing::debug!("[ts][direct] probe failed: {}", reason);
+        if !run.quiet {
+            eprintln!("源主机无法直连目标主机，改为经本机中转");
+        }
+        return None;
+    }
+    // the copy is one remote command: it either moved everything or failed
+    let elapsed = start.elapsed().as_secs_f64();
+    let failures = u64::from(outcome.is_err());
+    if !run.quiet && failures == 0 {
+        println!(
+            "直连传输完成: {} -> {} (耗时 {:.2} 秒，数据未经本机)",
+            ends.src_root, ends.dest_base, elapsed
+        );
+    }
+    // bytes and files stay unknown: the row is marked and not averaged
+    run.history.record(crate::history::TransferRecord {
+        direction: crate::history::DIRECT.to_string(),
+        alias: ends.src_server.alias.clone(),
+        elapsed_secs: elapsed,
+        failures,
+        ..Default::default()
+    });
+    if run.json {
+        let line = serde_json::json!({
+            "direct": true,
+            "elapsed_secs": elapsed,
+            "failures": failures,
+            "error": outcome.as_ref().err().map(|e| format!("{:#}", e)),
+        });
+        println!("{}", line);
+    }
+    Some(outcome.map(|_| ()))
+}
+
+// `--dry-run` of a relay: walk the source and emit the plan against the destination
+// (`dest`: SFTP channel, state of the target, whether it is a directory).
+fn plan_relay(
+    run: &PullRun,
+    ends: &RelayEnds,
+    ssftp: &ssh2::Sftp,
+    dest: (&ssh2::Sftp, plan::DestState, bool),
+    src_has_glob: bool,
+) -> Result<()> {
+    let (dsftp, base_state, target_is_dir_final) = dest;
+    let found: std::cell::RefCell<Vec<(String, Option<u64>, EntryKind)>> =
+        std::cell::RefCell::new(Vec::new());
+    let push = |_full: String, rel: String, size: Option<u64>, kind: EntryKind| {
+        found.borrow_mut().push((rel, size, kind));
+    };
+    let walk = enumerate_remote_and_push(
+        ssftp,
+        &ends.src_root,
+        run.filter,
+        run.links,
+        RemoteListers::new(run.pool, &ends.src_server),
+        &push,
+    );
+    let found = found.into_inner();
+    if src_has_glob && found.is_empty() {
+        return Err(crate::TransferError::GlobNoMatches(ends.src_root.clone()).into());
+    }
+    let dest_base = &ends.dest_base;
+    let mut plan = plan::TransferPlan::new("relay", dest_base);
+    plan.skipped_links = walk.skipped_links;
+    plan.no_clobber = run.overwrite.no_clobber;
+    plan.push_target(base_state, target_is_dir_final);
+    let base = dest_base.trim_end_matches('/');
+    for (rel, size, kind) in found.iter() {
+        let dest = if target_is_dir_final {
+            format!("{}/{}", base, normalize_path(rel, true))
+        } else {
+            dest_base.clone()
+        };
+        let state = if base_state == plan::DestState::Missing {
+            plan::DestState::Missing
+        } else {
+            plan::remote_state(dsftp, &dest)
+        };
+        plan.push(*kind, dest, *size, state);
+    }
+    if let Some(opts) = run.mirror_opts {
+        plan.mirror = Some(mirror::preview_after_walk(
+            MirrorTarget::Remote(dsftp, dest_base),
+            found.iter().map(|(r, _, _)| r.as_str()),
+            &plan.skipped_links,
+            &walk.unreadable,
+            run.mirror_filter,
+            opts,
+        ));
+    }
+    plan.emit(run.json, run.quiet);
+    Ok(())
+}
+
+// Walk every download root into `push`, tagging entries with the root's alias index.
+// Returns the combined walk and the glob roots that matched nothing, with their alias index.
+fn enumerate_download_roots(
+    pool: &SessionPool,
+    hosts: &[RemoteHost],
+    sftps: &[ssh2::Sftp],
+    roots: &[(usize, String)],
+    filter: &PathFilter,
+    links: LinkPolicy,
+    push: &dyn Fn(usize, String, String, Option<u64>, EntryKind),
+) -> (enumeration::RemoteWalk, Vec<(usize, String)>) {
+    let mut combined = enumeration::RemoteWalk::default();
+    let mut unmatched = Vec::new();
+    for (origin, root) in roots {
+        let src_has_glob = is_remote_glob(&sftps[*origin], root);
+        let pushed = std::cell::Cell::new(0usize);
+        let walk = enumerate_remote_and_push(
+            &sftps[*origin],
+            root,
+            filter,
+            links,
+            RemoteListers::new(pool, &hosts[*origin].server),
+            &|full, rel, size, kind| {
+                pushed.set(pushed.get() + 1);
+                push(*origin, full, rel, size, kind);
+            },
+        );
+        if src_has_glob && pushed.get() == 0 {
+            unmatched.push((*origin, root.clone()));
+        }
+        combined.unreadable.extend(walk.unreadable);
+        combined.skipped_links.extend(walk.skipped_links);
+    }
+    (combined, unmatched)
+}
+
+// Settings shared by every destination host of one upload (single alias or tag fan-out).
+// Local sources are enumerated once; each host gets its own copy of the entries.
+struct UploadRun<'a> {
+    entries: &'a [FileEntry],
+    total_size: u64,
+    unreadable: &'a [String],
+    skipped_links: &'a [String],
+    verbose: bool,
+    json: bool,
+    quiet: bool,
+    dry_run: bool,
+    concurrency: Option<usize>,
+    max_workers: usize,
+    max_retries: usize,
+    buf_size: usize,
+    attr_policy: AttrPolicy,
+    overwrite: &'a OverwritePolicy,
+    mirror_opts: Option<MirrorOptions>,
+    mirror_filter: &'a PathFilter,
+    limit_rate: Option<&'a str>,
+    // `--tar`: one tar stream replaces the worker pool
+    tar: bool,
+    chunking: Option<ChunkPolicy>,
+    // set when `entries` is empty and the sources are walked during the upload
+    stream: Option<LocalSources<'a>>,
+    pool: &'a Arc<SessionPool>,
+    journal: Option<&'a Arc<Journal>>,
+    history: &'a HistoryTarget,
+    total_style: &'a ProgressStyle,
+    file_style: &'a ProgressStyle,
+}
+
+// Local sources walked by a producer thread while the workers upload (no pre-scan).
+#[derive(Clone, Copy)]
+struct LocalSources<'a> {
+    sources: &'a [String],
+    filter: &'a PathFilter,
+    links: LinkPolicy,
+}
+
+// Tag fan-out draws one labelled total bar per host in a shared MultiProgress.
+struct SharedProgress<'a> {
+    mp: &'a Arc<indicatif::MultiProgress>,
+    style: &'a ProgressStyle,
+    alias: &'a str,
+}
+
+// Outcome of uploading to one host; the caller prints the summary.
+struct HostUpload {
+    mp: Arc<indicatif::MultiProgress>,
+    header: indicatif::ProgressBar,
+    total_pb: indicatif::ProgressBar,
+    start: Instant,
+    metrics: WorkerMetrics,
+    failures: Vec<crate::TransferError>,
+    mirror: Option<MirrorReport>,
+    overwrite: Arc<OverwriteReport>,
+    // entries and bytes found at the source (known only at the end when streamed)
+    files: u64,
+    total_size: u64,
+    skipped_links: Vec<String>,
+    // adaptive concurrency outcome (no -c)
+    tuning: Option<TuneReport>,
+}
+
+// 上传到单个主机：目标预检（R5–R8）、预演或启动 worker，并收集失败与指标。
+// 预演时输出计划并返回 None。`rate_limiter` comes from the caller so that a fan-out
+// shares one `--limit-rate` bucket across its hosts.
+fn upload_to_host(
+    run: &UploadRun,
+    server: &Arc<crate::server::Server>,
+    addr: &str,
+    expanded_remote_base: &str,
+    rate_limiter: Option<Arc<ratelimit::RateLimiter>>,
+    shared: Option<SharedProgress>,
+) -> Result<Option<HostUpload>> {
+    // R2 flags per source and target
+    let tgt_ends_slash = expanded_remote_base.ends_with('/');
+
+    // sftp for probing/creating remote dirs
+    let sess = run.pool.checkout(server)?;
+    let sftp = sess.sftp().with_context(|| format!("创建 SFTP 会话失败: {}", addr))?;
+
+    // 预判目标目录策略（R5–R7）
+    let base_state = plan::remote_state(&sftp, expanded_remote_base);
+    let target_is_dir_final =
+        prepare_remote_target(&sftp, expanded_remote_base, tgt_ends_slash, !run.dry_run)?;
+
+    if run.mirror_opts.is_some() && !target_is_dir_final {
+        return Err(
+            crate::TransferError::MirrorUnsupported(expanded_remote_base.to_string()).into()
+        );
+    }
+
+    // streamed sources into a file target: list them so R8 can check the single entry
+    let listed;
+    let listed_run;
+    let run = match run.stream {
+        Some(src) if !target_is_dir_final => {
+            listed = enumerate_local_sources(src.sources, src.filter, src.links)?;
+            listed_run = UploadRun {
+                entries: &listed.entries,
+                total_size: listed.total_size,
+                unreadable: &listed.unreadable,
+                skipped_links: &listed.skipped_links,
+                stream: None,
+                ..*run
             };
+            &listed_run
+        }
+        _ => run,
+    };
 
-            // 复用提炼后的远端枚举推送逻辑
-            enumerate_remote_and_push(
-                &sftp,
-                &remote_root,
-                explicit_dir_suffix,
-                src_has_glob,
-                &push,
-            );
+    // 多源/单源一致性（R8）
+    let total_entries = run.entries.len();
+    if !target_is_dir_final && total_entries > 1 {
+        return Err(
+            crate::TransferError::RemoteTargetMustBeDir(expanded_remote_base.to_string()).into()
+        );
+    }
+    if !target_is_dir_final && total_entries == 1 {
+        // 唯一条目必须为文件
+        if run.entries[0].kind != EntryKind::File {
+            return Err(crate::TransferError::RemoteTargetMustBeDir(
+                expanded_remote_base.to_string(),
+            )
+            .into());
+        }
+    }
 
-            enumeration_done.store(true, Ordering::SeqCst);
-            drop(file_tx_clone);
-            drop(file_tx);
+    if run.tar && !target_is_dir_final {
+        return Err(
+            crate::TransferError::RemoteTargetMustBeDir(expanded_remote_base.to_string()).into()
+        );
+    }
 
-            // R3: glob with no match is an error
-            if src_has_glob && files_discovered.load(Ordering::SeqCst) == 0 {
-                // Join workers first to avoid leaving threads running
-                for h in handles {
-                    let _ = h.join();
-                }
-                return Err(crate::TransferError::GlobNoMatches(remote_root.clone()).into());
-            }
+    if run.dry_run {
+        let mut plan = plan::TransferPlan::new("upload", expanded_remote_base);
+        plan.skipped_links = run.skipped_links.to_vec();
+        plan.no_clobber = run.overwrite.no_clobber;
+        plan.host = shared.map(|p| p.alias.to_string());
+        plan.push_target(base_state, target_is_dir_final);
+        let base = expanded_remote_base.trim_end_matches('/');
+        for e in run.entries.iter() {
+            let dest = if target_is_dir_final {
+                format!("{}/{}", base, normalize_path(&e.rel, true))
+            } else {
+                expanded_remote_base.to_string()
+            };
+            let state = if base_state == plan::DestState::Missing {
+                plan::DestState::Missing
+            } else {
+                plan::remote_state(&sftp, &dest)
+            };
+            plan.push(e.kind, dest, e.size, state);
+        }
+        if let Some(opts) = run.mirror_opts {
+            plan.mirror = Some(mirror::preview_after_walk(
+                MirrorTarget::Remote(&sftp, expanded_remote_base),
+                run.entries.iter().map(|e| e.rel.as_str()),
+                &plan.skipped_links,
+                run.unreadable,
+                run.mirror_filter,
+                opts,
+            ));
+        }
+        plan.emit(run.json, run.quiet);
+        return Ok(None);
+    }
+
+    // 进度与工作线程
+    // Determine effective concurrency: if CLI passed None, choose auto based on totals
+    let effective_conc = match run.concurrency {
+        // the tar stream is a single channel
+        _ if run.tar => 1,
+        Some(c) => c,
+        // adaptive: the cap bounds the workers, started as the tuner's limit grows
+        None => run.max_workers,
+    };
+    let known_entries = if run.stream.is_some() { usize::MAX } else { total_entries };
+    let workers = calc_upload_workers(effective_conc, run.max_workers, known_entries);
+    let tuner = (run.concurrency.is_none() && !run.tar).then(|| {
+        // streamed: totals are unknown until the walk ends
+        let initial = match run.stream {
+            Some(_) => crate::auto_concurrency::INITIAL_MAX,
+            None => crate::auto_concurrency::choose_auto_concurrency(total_entries, run.total_size),
+        };
+        spawn_tuner(&run.history.db, server.alias.as_deref(), initial, workers)
+    });
+    let header_workers = tuner.as_ref().map(|t| t.start()).unwrap_or(effective_conc);
+    let (mp, total_pb, header) = match shared {
+        Some(p) => {
+            let total_pb = p.mp.add(indicatif::ProgressBar::new(run.total_size));
+            total_pb.set_style(p.style.clone());
+            total_pb.set_prefix(p.alias.to_string());
+            (p.mp.clone(), total_pb, indicatif::ProgressBar::hidden())
+        }
+        None => {
+            let (mp, total_pb, header) =
+                init_progress_and_mp(run.verbose, run.total_size, run.total_style);
+            // Display compact startup header above total progress (one line)
+            let backoff_ms = crate::util::get_backoff_ms();
+            set_startup_header(&header, "Upload", header_workers, backoff_ms, run.buf_size);
+            (mp, total_pb, header)
+        }
+    };
+    // 使生产者队列容量严格大于总条目数（若基础容量足够），避免在“先生产后开工人”的流程里刚好填满导致边界卡住
+    // 示例：workers=8 时基础为 32；当 total_entries=32 时将 cap 调整为 33。
+    let cap = if run.stream.is_some() {
+        std::cmp::max(4, workers * 4)
+    } else {
+        let base_plus = std::cmp::max(4, workers * 4 + 1);
+        // 将上限与 total_entries+1 对齐，既控制内存，又保证有一个额外槽位
+        std::cmp::min(base_plus, std::cmp::max(1, total_entries + 1))
+    };
+    let (tx, rx) = bounded::<FileEntry>(cap);
+    let runtime_handles = workers::setup_worker_runtime(workers);
+    let WorkerRuntimeHandles {
+        failure_tx,
+        failure_rx,
+        metrics_tx,
+        metrics_rx,
+        pb_slot_tx,
+        pb_slot_rx,
+    } = runtime_handles;
+    // 每个主机独立统计覆盖策略结果
+    let overwrite_report = Arc::new(OverwriteReport::default());
+    // 先启动 worker 再生产，避免生产者在有界队列上阻塞
+    let ctx_for_workers = UploadWorkersCtx {
+        common: WorkerCommonCtx {
+            workers,
+            mp: mp.clone(),
+            total_pb: total_pb.clone(),
+            file_style: run.file_style.clone(),
+            server: server.clone(),
+            addr: addr.to_string(),
+            pool: run.pool.clone(),
+            max_retries: run.max_retries,
+            target_is_dir_final,
+            failure_tx: failure_tx.clone(),
+            buf_size: run.buf_size,
+            attr_policy: run.attr_policy,
+            overwrite: run.overwrite.clone(),
+            overwrite_report: overwrite_report.clone(),
+            rate_limiter: rate_limiter.clone(),
+            chunking: run.chunking.map(|c| c.for_workers(workers)),
+            journal: run.journal.cloned(),
+            tuner: tuner.clone(),
+        },
+        rx,
+        expanded_remote_base: expanded_remote_base.to_string(),
+        metrics_tx: metrics_tx.clone(),
+        pb_slot_rx: pb_slot_rx.clone(),
+        pb_slot_tx: pb_slot_tx.clone(),
+    };
+    // --tar: no worker pool, the probe session carries the stream below;
+    // otherwise it is handed to the first worker
+    if !run.tar {
+        drop(sftp);
+        run.pool.checkin(server, sess.clone());
+    }
+    let worker_thread =
+        (!run.tar).then(|| std::thread::spawn(move || run_upload_workers(ctx_for_workers)));
+    // skipped links are left alone at the destination as well
+    let mut keep = run.mirror_opts.map(|_| {
+        mirror::build_keep_set(
+            run.entries
+                .iter()
+                .map(|e| e.rel.as_str())
+                .chain(run.skipped_links.iter().map(String::as_str)),
+        )
+    });
+    let base = expanded_remote_base.trim_end_matches('/');
+    let dir_attr = |e: &FileEntry| -> Option<(String, FileAttrs)> {
+        if !run.attr_policy.is_active() || e.kind != EntryKind::Dir {
+            return None;
+        }
+        let md = std::fs::metadata(e.local_full.as_deref()?).ok()?;
+        let attrs = run.attr_policy.resolve(FileAttrs::from_metadata(&md), true);
+        let remote = format!("{}/{}", base, normalize_path(&e.rel, true));
+        (!attrs.is_empty()).then_some((remote, attrs))
+    };
+    let mut dir_attrs: Vec<(String, FileAttrs)> = run.entries.iter().filter_map(dir_attr).collect();
 
-            for h in handles {
-                let _ = h.join();
+    let start = Instant::now();
+    let mut walked: Option<enumeration::LocalStream> = None;
+    match worker_thread {
+        Some(handle) => {
+            if let Some(src) = run.stream {
+                // producer thread: walk and push while the workers drain the bounded queue;
+                // the total bar grows with every sized entry
+                total_pb.enable_steady_tick(Duration::from_millis(100));
+                let mut rels: Vec<String> = Vec::new();
+                let walk = std::thread::scope(|scope| {
+                    let producer = scope.spawn(|| {
+                        enumerate_local_and_push(src.sources, src.filter, src.links, &mut |e| {
+                            if run.mirror_opts.is_some() {
+                                rels.push(e.rel.clone());
+                            }
+                            dir_attrs.extend(dir_attr(&e));
+                            if let Some(size) = e.size {
+                                total_pb.inc_length(size);
+                                total_pb.disable_steady_tick();
+                            }
+                            queue_event(server.alias.as_deref(), &e);
+                            // Blocking send to apply backpressure on producer
+                            let _ = tx.send(e);
+                        })
+                    });
+                    producer.join()
+                });
+                drop(tx);
+                let _ = handle.join();
+                let walk = match walk {
+                    Ok(res) => res.inspect_err(|_| {
+                        let _ = mp.clear();
+                    })?,
+                    Err(_) => {
+                        return Err(crate::TransferError::WorkerIo(
+                            "本地枚举线程异常退出".to_string(),
+                        )
+                        .into());
+                    }
+                };
+                keep = run.mirror_opts.map(|_| {
+                    mirror::build_keep_set(
+                        rels.iter()
+                            .map(String::as_str)
+                            .chain(walk.skipped_links.iter().map(String::as_str)),
+                    )
+                });
+                walked = Some(walk);
+            } else {
+                for e in run.entries.iter() {
+                    queue_event(server.alias.as_deref(), e);
+                    // Blocking send to apply backpressure on producer
+                    let _ = tx.send(e.clone());
+                }
+                drop(tx);
+                // 等待 worker 完成
+                let _ = handle.join();
             }
-            drop(failure_tx);
-            drop(metrics_tx);
-            let total_done = bytes_transferred.load(Ordering::SeqCst);
-            let files_done = files_discovered.load(Ordering::SeqCst);
-            // finalize_transfer will consume receivers and perform the rest
-            let finalize_ctx = FinalizeCtx {
-                mp: mp.clone(),
-                header: header.clone(),
-                total_pb: total_pb.clone(),
-                json_mode: json,
-                quiet_mode: quiet,
+        }
+        None => {
+            drop(tx);
+            let stream = tarmode::TarStream {
+                sess: &sess,
+                preserve: run.attr_policy.preserve,
+                total_pb: &total_pb,
+                limiter: rate_limiter,
+                failure_tx: &failure_tx,
             };
-            finalize_transfer(finalize_ctx, start, metrics_rx, failure_rx, total_done, files_done);
-
-            Ok(())
+            let _ = metrics_tx.send(tarmode::upload(&stream, run.entries, expanded_remote_base));
         }
-        TransferKind::Unknown => Err(crate::TransferError::InvalidDirection.into()),
     }
+    let walked = walked.unwrap_or_else(|| enumeration::LocalStream {
+        entries: run.entries.len() as u64,
+        files: run.entries.iter().filter(|e| e.kind != EntryKind::Dir).count() as u64,
+        total_size: run.total_size,
+        unreadable: run.unreadable.to_vec(),
+        skipped_links: run.skipped_links.to_vec(),
+    });
+    if !dir_attrs.is_empty() {
+        match run.pool.checkout_sftp(server) {
+            Ok((_, asftp)) => attrs::apply_dir_attrs_remote(&asftp, dir_attrs, &failure_tx),
+            Err(e) => {
+                workers::report_failure(
+                    &failure_tx,
+                    crate::TransferError::PreserveFailed(
+                        expanded_remote_base.to_string(),
+                        e.to_string(),
+                    ),
+                );
+            }
+        }
+    }
+    let mirror_report = match (run.mirror_opts, keep) {
+        (Some(opts), Some(keep)) if walked.unreadable.is_empty() => {
+            // pooled sessions that idled out during a long transfer are skipped on checkout
+            match run.pool.checkout_sftp(server) {
+                Ok((_, msftp)) => Some(mirror::mirror_remote(
+                    &msftp,
+                    expanded_remote_base,
+                    &keep,
+                    run.mirror_filter,
+                    opts,
+                    &failure_tx,
+                )),
+                Err(e) => {
+                    workers::report_failure(
+                        &failure_tx,
+                        crate::TransferError::WorkerIo(format!("镜像删除无法建立会话: {}", e)),
+                    );
+                    Some(MirrorReport {
+                        dry_run: opts.dry_run,
+                        aborted: true,
+                        ..Default::default()
+                    })
+                }
+            }
+        }
+        (Some(opts), _) => Some(mirror::skipped_report(opts, &walked.unreadable, &failure_tx)),
+        _ => None,
+    };
+    drop(failure_tx);
+    drop(metrics_tx);
+    let (metrics, failures) = collect_run(metrics_rx, failure_rx);
+    let tuning = tuner.map(|t| t.finish());
+    Ok(Some(HostUpload {
+        mp,
+        header,
+        total_pb,
+        start,
+        metrics,
+        failures,
+        mirror: mirror_report,
+        overwrite: overwrite_report,
+        files: walked.files,
+        total_size: walked.total_size,
+        skipped_links: walked.skipped_links,
+        tuning,
+    }))
 }
 
-// Module-private helper: finalize transfer. Consumes the metrics and failure
-// receivers, clears progress UI and prints/writes summary and failures.
-fn finalize_transfer(
-    ctx: FinalizeCtx,
-    start: std::time::Instant,
+// Drain the worker channels once every sender is gone.
+fn collect_run(
     metrics_rx: crossbeam_channel::Receiver<WorkerMetrics>,
     failure_rx: crossbeam_channel::Receiver<crate::TransferError>,
-    total_bytes: u64,
-    files: u64,
-) {
-    // Collect structured failures and also produce the legacy string vector
-    let failures_struct: Vec<crate::TransferError> = failure_rx.into_iter().collect();
-    let failures_vec: Vec<String> = failures_struct.iter().map(|e| e.to_string()).collect();
+) -> (WorkerMetrics, Vec<crate::TransferError>) {
+    let failures: Vec<crate::TransferError> = failure_rx.into_iter().collect();
     let mut agg = WorkerMetrics::default();
     for m in metrics_rx.into_iter() {
         agg.bytes += m.bytes;
         agg.session_rebuilds += m.session_rebuilds;
         agg.sftp_rebuilds += m.sftp_rebuilds;
     }
+    (agg, failures)
+}
+
+// Module-private helper: finalize transfer. Takes the collected metrics and
+// failures, clears progress UI and prints/writes summary and failures.
+fn finalize_transfer(
+    ctx: FinalizeCtx,
+    start: std::time::Instant,
+    agg: WorkerMetrics,
+    failures_struct: Vec<crate::TransferError>,
+    total_bytes: u64,
+    files: u64,
+) -> Result<()> {
+    // Also produce the legacy string vector
+    let failures_vec: Vec<String> = failures_struct.iter().map(|e| e.to_string()).collect();
 
     let _ = ctx.mp.clear();
     ctx.header.finish_and_clear();
@@ -660,7 +2458,43 @@ fn finalize_transfer(
             files,
             agg.session_rebuilds as u64,
             agg.sftp_rebuilds as u64,
+            ctx.sessions.handshakes,
+            ctx.sessions.reuses,
         );
+        if ctx.sessions.handshake_backoffs > 0 {
+            println!("握手被服务端拒绝后退避重试: {} 次", ctx.sessions.handshake_backoffs);
+        }
+        if let Some(ref m) = ctx.mirror {
+            print_mirror_summary(m);
+        }
+        print_skipped_links(&ctx.skipped_links);
+        if let Some(ref r) = ctx.overwrite {
+            let (skipped, backed_up) = r.counts();
+            println!("未覆盖的已存在文件: {} | 已备份: {}", skipped, backed_up);
+        }
+        if let Some(ref t) = ctx.tuning {
+            println!(
+                "自动并发: 起始 {} -> 结束 {} 个 worker（最佳 {}，{}）",
+                t.start,
+                t.last,
+                t.best,
+                crate::history::fmt_rate(t.best_bytes_per_sec)
+            );
+        }
+        for h in ctx.hosts.iter() {
+            match h.error {
+                Some(ref e) if h.dir.is_none() => println!("  {}: 未连接 — {}", h.alias, e),
+                _ => println!(
+                    "  {}: {}/{} 个文件, {} -> {}{}",
+                    h.alias,
+                    h.files,
+                    h.discovered,
+                    crate::util::human_bytes(h.bytes),
+                    h.dir.as_deref().unwrap_or("-"),
+                    h.error.as_ref().map(|e| format!(" ({})", e)).unwrap_or_default()
+                ),
+            }
+        }
     }
 
     // If JSON mode requested, emit a single-line JSON summary for machine
@@ -668,27 +2502,122 @@ fn finalize_transfer(
     let mut failures_path: Option<std::path::PathBuf> = None;
     if !failures_vec.is_empty() {
         // Always write failures to the canonical logs directory; no CLI path accepted.
-        failures_path = crate::util::write_failures_jsonl(None, &failures_struct);
+        let run = crate::util::FailureRun {
+            run_id: ctx.journal.as_ref().map(|j| j.id()),
+            direction: Some(ctx.direction.to_string()),
+            alias: ctx.alias.clone(),
+        };
+        failures_path = crate::util::write_failures_jsonl(None, &run, &failures_struct);
         if !ctx.quiet_mode
             && let Some(ref p) = failures_path
         {
             println!("失败清单已写入: {}", p.display());
         }
     }
+    if !ctx.quiet_mode
+        && let Some(ref j) = ctx.journal
+    {
+        if j.skipped() > 0 {
+            println!("续传任务 #{}: 跳过已完成的 {} 个文件", j.id(), j.skipped());
+        }
+        if !failures_vec.is_empty() {
+            println!("可用 hp ts --resume {} 重试未完成的条目", j.id());
+        }
+        if failures_struct.iter().any(|e| matches!(e, crate::TransferError::EntryFailed(..))) {
+            println!("可用 hp ts --retry-failed {} 仅重传失败的文件", j.id());
+        }
+    }
 
-    if ctx.json_mode {
-        let summary_obj = serde_json::json!({
+    if ctx.json_mode || events::enabled() {
+        let mut summary_obj = serde_json::json!({
             "total_bytes": total_bytes,
             "elapsed_secs": elapsed,
             "files": files,
             "session_rebuilds": agg.session_rebuilds as u64,
             "sftp_rebuilds": agg.sftp_rebuilds as u64,
+            "session_handshakes": ctx.sessions.handshakes,
+            "session_reuses": ctx.sessions.reuses,
+            "session_handshake_backoffs": ctx.sessions.handshake_backoffs,
             "failures": failures_vec.len(),
             "failures_path": failures_path.as_ref().map(|p| p.to_string_lossy().to_string()),
+            "skipped_links": ctx.skipped_links,
         });
-        if let Ok(line) = serde_json::to_string(&summary_obj) {
+        if let Some(ref j) = ctx.journal {
+            summary_obj["job_id"] = serde_json::json!(j.id());
+            summary_obj["resumed_skipped"] = serde_json::json!(j.skipped());
+        }
+        if let Some(ref r) = ctx.overwrite {
+            let (skipped, backed_up) = r.counts();
+            summary_obj["skipped_existing"] = serde_json::json!(skipped);
+            summary_obj["backed_up"] = serde_json::json!(backed_up);
+            summary_obj["overwrite"] = r.to_json();
+        }
+        if let Some(ref t) = ctx.tuning {
+            summary_obj["auto_concurrency"] = t.to_json();
+        }
+        if !ctx.hosts.is_empty() {
+            summary_obj["hosts"] =
+                serde_json::json!(ctx.hosts.iter().map(HostBreakdown::to_json).collect::<Vec<_>>());
+        }
+        if let Some(ref m) = ctx.mirror {
+            summary_obj["deleted"] = serde_json::json!(m.deleted);
+            summary_obj["deletions"] = serde_json::json!(m.planned);
+            summary_obj["delete_dry_run"] = serde_json::json!(m.dry_run);
+            summary_obj["delete_aborted"] = serde_json::json!(m.aborted);
+        }
+        if ctx.json_mode
+            && let Ok(line) = serde_json::to_string(&summary_obj)
+        {
             println!("{}", line);
         }
+        events::emit("summary", summary_obj);
+    }
+    remember_tuning(&ctx.history.db, ctx.alias.as_deref(), ctx.tuning.as_ref());
+    ctx.history.record(crate::history::TransferRecord {
+        direction: ctx.direction.to_string(),
+        alias: ctx.alias,
+        bytes: total_bytes,
+        files,
+        elapsed_secs: elapsed,
+        session_rebuilds: agg.session_rebuilds as u64,
+        sftp_rebuilds: agg.sftp_rebuilds as u64,
+        failures: failures_vec.len() as u64,
+        job_id: ctx.journal.as_ref().map(|j| j.id()),
+        ..Default::default()
+    });
+    // failures were reported above; the run still ends with an error so the exit code tells
+    match crate::TransferError::for_run(&failures_struct, files) {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// Human-readable mirror summary; dry-run lists every path that would be removed.
+fn print_mirror_summary(m: &MirrorReport) {
+    if m.aborted {
+        println!("镜像删除已跳过（共 {} 项待删除），详见失败清单", m.planned.len());
+    } else if m.dry_run {
+        println!("镜像删除（预演）: 将删除 {} 项", m.planned.len());
+        for p in &m.planned {
+            println!("  - {}", p);
+        }
+    } else {
+        println!("镜像删除: 已删除 {}/{} 项", m.deleted, m.planned.len());
+    }
+}
+
+// Symlinks left out by `--links skip` (or broken/looping under `follow`); long lists are cut.
+fn print_skipped_links(links: &[String]) {
+    const SHOWN: usize = 20;
+    if links.is_empty() {
+        return;
+    }
+    println!("已跳过符号链接 {} 个（可用 --links copy|follow 处理）", links.len());
+    for p in links.iter().take(SHOWN) {
+        println!("  - {}", p);
+    }
+    if links.len() > SHOWN {
+        println!("  ... 其余 {} 个略", links.len() - SHOWN);
     }
 }
 
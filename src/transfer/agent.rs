@@ -0,0 +1,870 @@
+// Background agent (`hp agent`): keeps authenticated sessions per user@host:port open
+// between CLI invocations and serves SFTP and exec work for them over a Unix socket in
+// the HostPilot home, similar to an OpenSSH ControlMaster for the built-in engine.
+//
+// Protocol: every connection starts with one JSON request line and gets one JSON reply
+// line. An `sftp` request turns the connection into an SFTP channel of the alias that
+// then carries `SftpOp` lines; file contents of `read`/`write` follow as frames of a
+// big-endian u32 length plus bytes, an empty frame ends the stream.
+use super::attrs::FileAttrs;
+use super::session::{SessionPool, exec_remote, expand_remote_tilde};
+use super::sftp_like::{SftpLike, Ssh2Adapter};
+use crate::config::Config;
+use crate::server::ServerCollection;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// the socket lives in a directory only the owner can enter, so it is never reachable
+// by others between `bind` and `set_permissions`
+const SOCKET_DIR: &str = "agent";
+const SOCKET_NAME: &str = "agent.sock";
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+const MAINTAIN_EVERY: Duration = Duration::from_secs(30);
+const START_WAIT: Duration = Duration::from_secs(5);
+const READ_FRAME_SIZE: usize = 256 * 1024;
+// frame header announcing an error message frame instead of data
+const ERR_FRAME: u32 = u32::MAX;
+
+fn socket_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("无法获取用户主目录")?;
+    Ok(crate::ops::ensure_hostpilot_dir(&home)?.join(SOCKET_DIR).join(SOCKET_NAME))
+}
+
+// Create the socket directory with mode 0700, tightening an existing one.
+fn prepare_socket_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("无法创建目录 {}", dir.display()))?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("无法设置目录权限 {}", dir.display()))?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Status,
+    Stop,
+    Exec { alias: String, cmd: String },
+    Expand { alias: String, path: String },
+    Sftp { alias: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum SftpOp {
+    StatIsFile { path: PathBuf },
+    Mkdir { path: PathBuf, mode: i32 },
+    StatAttrs { path: PathBuf },
+    SetAttrs { path: PathBuf, mode: Option<u32>, atime: Option<u64>, mtime: Option<u64> },
+    ReadLink { path: PathBuf },
+    Symlink { target: String, link: PathBuf },
+    Rename { from: PathBuf, to: PathBuf },
+    // reply, then data frames from the agent
+    Read { path: PathBuf },
+    // reply, then data frames from the client, then a reply once the file is closed
+    Write { path: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    data: serde_json::Value,
+}
+
+impl Reply {
+    fn from_result<T: Serialize>(res: std::result::Result<T, String>) -> Self {
+        match res {
+            Ok(v) => Reply { error: None, data: serde_json::to_value(v).unwrap_or_default() },
+            Err(e) => Reply { error: Some(e), data: serde_json::Value::Null },
+        }
+    }
+
+    fn into_result(self) -> std::result::Result<serde_json::Value, String> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.data),
+        }
+    }
+}
+
+fn send_line<T: Serialize>(w: &mut impl Write, msg: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    w.write_all(&line)?;
+    w.flush()
+}
+
+// `None` once the peer closed the connection
+fn recv_line<T: DeserializeOwned>(r: &mut impl BufRead) -> std::io::Result<Option<T>> {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line).map(Some).map_err(std::io::Error::other)
+}
+
+fn write_frame(w: &mut impl Write, data: &[u8]) -> std::io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(data)
+}
+
+fn write_err_frame(w: &mut impl Write, msg: &str) -> std::io::Result<()> {
+    w.write_all(&ERR_FRAME.to_be_bytes())?;
+    write_frame(w, msg.as_bytes())
+}
+
+fn read_header(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    Ok(u32::from_be_bytes(len))
+}
+
+fn read_frame(r: &mut impl Read, len: u32) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// ---- client side ----
+
+struct Conn {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Conn {
+    fn open(socket: &Path) -> std::io::Result<Self> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Conn { reader: BufReader::new(writer.try_clone()?), writer })
+    }
+
+    fn call<T: Serialize>(&mut self, msg: &T) -> std::result::Result<serde_json::Value, String> {
+        send_line(&mut self.writer, msg).map_err(|e| format!("hp agent 连接中断: {}", e))?;
+        match recv_line::<Reply>(&mut self.reader) {
+            Ok(Some(reply)) => reply.into_result(),
+            Ok(None) => Err("hp agent 连接已关闭".to_string()),
+            Err(e) => Err(format!("hp agent 连接中断: {}", e)),
+        }
+    }
+}
+
+/// A running agent, found through its socket.
+#[derive(Clone, Debug)]
+pub struct AgentHandle {
+    socket: PathBuf,
+}
+
+impl AgentHandle {
+    /// The agent of this user when one is listening; `HP_NO_AGENT=1` disables delegation.
+    pub fn probe() -> Option<Self> {
+        if std::env::var_os("HP_NO_AGENT").is_some_and(|v| !v.is_empty() && v != "0") {
+            return None;
+        }
+        let socket = socket_path().ok()?;
+        UnixStream::connect(&socket).ok()?;
+        Some(AgentHandle { socket })
+    }
+
+    fn call(&self, req: &Request) -> Result<serde_json::Value> {
+        let mut conn = Conn::open(&self.socket)
+            .with_context(|| format!("无法连接 hp agent: {}", self.socket.display()))?;
+        conn.call(req).map_err(|e| crate::TransferError::OperationFailed(e).into())
+    }
+
+    /// Run `cmd` on `alias` over an agent session; returns the exit status and output.
+    pub fn exec(&self, alias: &str, cmd: &str) -> Result<(i32, String)> {
+        let data = self.call(&Request::Exec { alias: alias.to_string(), cmd: cmd.to_string() })?;
+        Ok(serde_json::from_value(data)?)
+    }
+
+    pub fn expand(&self, alias: &str, path: &str) -> Result<String> {
+        let req = Request::Expand { alias: alias.to_string(), path: path.to_string() };
+        Ok(serde_json::from_value(self.call(&req)?)?)
+    }
+
+    /// An SFTP channel of `alias` kept open inside the agent.
+    pub fn sftp(&self, alias: &str) -> Result<AgentSftp> {
+        let conn = self.open_sftp(alias)?;
+        Ok(AgentSftp { agent: self.clone(), alias: alias.to_string(), conn: Mutex::new(conn) })
+    }
+
+    fn open_sftp(&self, alias: &str) -> Result<Conn> {
+        let mut conn = Conn::open(&self.socket)
+            .with_context(|| format!("无法连接 hp agent: {}", self.socket.display()))?;
+        conn.call(&Request::Sftp { alias: alias.to_string() })
+            .map_err(crate::TransferError::OperationFailed)?;
+        Ok(conn)
+    }
+}
+
+/// `SftpLike` served by the agent. Small requests share one connection; every file
+/// stream gets a connection (and an SFTP channel in the agent) of its own.
+pub struct AgentSftp {
+    agent: AgentHandle,
+    alias: String,
+    conn: Mutex<Conn>,
+}
+
+impl AgentSftp {
+    fn call<T: DeserializeOwned>(&self, op: &SftpOp) -> std::result::Result<T, String> {
+        let mut conn = self.conn.lock().map_err(|_| "hp agent 连接不可用".to_string())?;
+        let data = conn.call(op)?;
+        serde_json::from_value(data).map_err(|e| e.to_string())
+    }
+
+    fn stream(&self, op: &SftpOp) -> std::result::Result<Conn, String> {
+        let mut conn = self.agent.open_sftp(&self.alias).map_err(|e| format!("{:#}", e))?;
+        conn.call(op)?;
+        Ok(conn)
+    }
+}
+
+impl SftpLike for AgentSftp {
+    fn stat_is_file(&self, p: &Path) -> std::result::Result<bool, String> {
+        self.call(&SftpOp::StatIsFile { path: p.to_path_buf() })
+    }
+
+    fn mkdir(&self, p: &Path, mode: i32) -> std::result::Result<(), String> {
+        self.call(&SftpOp::Mkdir { path: p.to_path_buf(), mode })
+    }
+
+    fn open_read(&self, p: &Path) -> std::result::Result<Box<dyn Read + Send>, String> {
+        let conn = self.stream(&SftpOp::Read { path: p.to_path_buf() })?;
+        Ok(Box::new(FrameReader { conn, remaining: 0, done: false }))
+    }
+
+    fn create_write(&self, p: &Path) -> std::result::Result<Box<dyn Write + Send>, String> {
+        let conn = self.stream(&SftpOp::Write { path: p.to_path_buf() })?;
+        Ok(Box::new(FrameWriter { conn, finished: false }))
+    }
+
+    fn stat_attrs(&self, p: &Path) -> std::result::Result<FileAttrs, String> {
+        let (mode, atime, mtime) = self.call(&SftpOp::StatAttrs { path: p.to_path_buf() })?;
+        Ok(FileAttrs { mode, atime, mtime })
+    }
+
+    fn set_attrs(&self, p: &Path, attrs: FileAttrs) -> std::result::Result<(), String> {
+        let FileAttrs { mode, atime, mtime } = attrs;
+        self.call(&SftpOp::SetAttrs { path: p.to_path_buf(), mode, atime, mtime })
+    }
+
+    fn read_link(&self, p: &Path) -> std::result::Result<PathBuf, String> {
+        self.call(&SftpOp::ReadLink { path: p.to_path_buf() })
+    }
+
+    fn symlink(&self, target: &str, link: &Path) -> std::result::Result<(), String> {
+        self.call(&SftpOp::Symlink { target: target.to_string(), link: link.to_path_buf() })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::result::Result<(), String> {
+        self.call(&SftpOp::Rename { from: from.to_path_buf(), to: to.to_path_buf() })
+    }
+}
+
+struct FrameReader {
+    conn: Conn,
+    remaining: u32,
+    done: bool,
+}
+
+impl Read for FrameReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            match read_header(&mut self.conn.reader)? {
+                0 => {
+                    self.done = true;
+                    return Ok(0);
+                }
+                ERR_FRAME => {
+                    let len = read_header(&mut self.conn.reader)?;
+                    let msg = read_frame(&mut self.conn.reader, len)?;
+                    self.done = true;
+                    return Err(std::io::Error::other(String::from_utf8_lossy(&msg).to_string()));
+                }
+                n => self.remaining = n,
+            }
+        }
+        let want = buf.len().min(self.remaining as usize);
+        let n = self.conn.reader.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u32;
+        Ok(n)
+    }
+}
+
+// Data goes out as frames; `flush` ends the stream and waits until the agent closed
+// the remote file, so write errors surface there.
+struct FrameWriter {
+    conn: Conn,
+    finished: bool,
+}
+
+impl FrameWriter {
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        write_frame(&mut self.conn.writer, &[])?;
+        match recv_line::<Reply>(&mut self.conn.reader)? {
+            Some(reply) => reply.into_result().map(|_| ()).map_err(std::io::Error::other),
+            None => Err(std::io::Error::other("hp agent 连接已关闭")),
+        }
+    }
+}
+
+impl Write for FrameWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.finished {
+            return Err(std::io::Error::other("远端文件已关闭"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = buf.len().min(u32::MAX as usize - 1);
+        write_frame(&mut self.conn.writer, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.finish()
+    }
+}
+
+impl Drop for FrameWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// ---- agent side ----
+
+struct Agent {
+    server_file: PathBuf,
+    socket: PathBuf,
+    pool: SessionPool,
+    started: Instant,
+    idle_timeout: Duration,
+    requests: AtomicU64,
+    // requests currently holding a pooled session
+    in_use: AtomicUsize,
+    stopping: AtomicBool,
+}
+
+// Marks a request as holding a session until dropped.
+struct InUse<'a>(&'a AtomicUsize);
+
+impl Drop for InUse<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Agent {
+    // Admit a request that uses a session; none are admitted once a stop is under way.
+    fn enter(&self) -> std::result::Result<InUse<'_>, String> {
+        self.in_use.fetch_add(1, Ordering::SeqCst);
+        let guard = InUse(&self.in_use);
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err("hp agent 正在停止".to_string());
+        }
+        Ok(guard)
+    }
+
+    // `stop`: refused while sessions are in use, so running transfers keep theirs.
+    fn try_stop(&self) -> std::result::Result<(), String> {
+        self.stopping.store(true, Ordering::SeqCst);
+        let busy = self.in_use.load(Ordering::SeqCst);
+        if busy > 0 {
+            self.stopping.store(false, Ordering::SeqCst);
+            return Err(format!("hp agent 仍有 {} 个会话正在使用，请待传输结束后再停止", busy));
+        }
+        Ok(())
+    }
+
+    fn server(&self, alias: &str) -> Result<crate::server::Server> {
+        let collection = ServerCollection::read_from_storage(&self.server_file)?;
+        collection
+            .get(alias)
+            .cloned()
+            .ok_or_else(|| crate::TransferError::AliasNotFound(alias.to_string()).into())
+    }
+
+    // Run `f` on a pooled session; sessions that failed are not handed back.
+    fn with_session<T>(
+        &self,
+        alias: &str,
+        f: impl FnOnce(&ssh2::Session) -> Result<T>,
+    ) -> std::result::Result<T, String> {
+        let _in_use = self.enter()?;
+        let run = || -> Result<T> {
+            let server = self.server(alias)?;
+            let sess = self.pool.checkout(&server)?;
+            let out = f(&sess)?;
+            self.pool.checkin(&server, sess);
+            Ok(out)
+        };
+        run().map_err(|e| format!("{:#}", e))
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let stats = self.pool.stats();
+        let idle: Vec<serde_json::Value> = self
+            .pool
+            .idle_summary()
+            .into_iter()
+            .map(|(key, sessions, oldest)| {
+                serde_json::json!({ "key": key, "sessions": sessions, "idle_secs": oldest.as_secs() })
+            })
+            .collect();
+        serde_json::json!({
+            "pid": std::process::id(),
+            "socket": self.socket.to_string_lossy(),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "idle_timeout_secs": self.idle_timeout.as_secs(),
+            "requests": self.requests.load(Ordering::Relaxed),
+            "in_use": self.in_use.load(Ordering::SeqCst),
+            "handshakes": stats.handshakes,
+            "reuses": stats.reuses,
+            "idle": idle,
+        })
+    }
+
+    fn handle(&self, stream: UnixStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let Some(req) = recv_line::<Request>(&mut reader)? else {
+            return Ok(());
+        };
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!("[agent] {:?}", req);
+        match req {
+            Request::Status => send_line(&mut writer, &Reply::from_result(Ok(self.status()))),
+            Request::Stop => {
+                if let Err(e) = self.try_stop() {
+                    return send_line(&mut writer, &Reply::from_result::<()>(Err(e)));
+                }
+                send_line(&mut writer, &Reply::default())?;
+                let _ = std::fs::remove_file(&self.socket);
+                std::process::exit(0);
+            }
+            Request::Exec { alias, cmd } => {
+                let res = self.with_session(&alias, |sess| exec_remote(sess, &cmd));
+                send_line(&mut writer, &Reply::from_result(res))
+            }
+            Request::Expand { alias, path } => {
+                let res = self.with_session(&alias, |sess| expand_remote_tilde(sess, &path));
+                send_line(&mut writer, &Reply::from_result(res))
+            }
+            Request::Sftp { alias } => self.serve_sftp(&alias, reader, writer),
+        }
+    }
+
+    fn serve_sftp(
+        &self,
+        alias: &str,
+        mut reader: BufReader<UnixStream>,
+        mut writer: UnixStream,
+    ) -> std::io::Result<()> {
+        let _in_use = match self.enter() {
+            Ok(guard) => guard,
+            Err(e) => return send_line(&mut writer, &Reply::from_result::<()>(Err(e))),
+        };
+        let opened = self
+            .server(alias)
+            .and_then(|server| self.pool.checkout_sftp(&server).map(|pair| (server, pair)));
+        let (server, (sess, sftp)) = match opened {
+            Ok(v) => v,
+            Err(e) => {
+                return send_line(&mut writer, &Reply::from_result::<()>(Err(format!("{:#}", e))));
+            }
+        };
+        send_line(&mut writer, &Reply::default())?;
+        let sftp = Ssh2Adapter(sftp);
+        let served = serve_sftp_ops(&sftp, &mut reader, &mut writer);
+        drop(sftp);
+        // a broken client connection says nothing about the SSH session
+        self.pool.checkin(&server, sess);
+        served
+    }
+}
+
+fn serve_sftp_ops(
+    sftp: &dyn SftpLike,
+    reader: &mut BufReader<UnixStream>,
+    writer: &mut UnixStream,
+) -> std::io::Result<()> {
+    while let Some(op) = recv_line::<SftpOp>(reader)? {
+        let reply = match op {
+            SftpOp::StatIsFile { path } => Reply::from_result(sftp.stat_is_file(&path)),
+            SftpOp::Mkdir { path, mode } => Reply::from_result(sftp.mkdir(&path, mode)),
+            SftpOp::StatAttrs { path } => {
+                Reply::from_result(sftp.stat_attrs(&path).map(|a| (a.mode, a.atime, a.mtime)))
+            }
+            SftpOp::SetAttrs { path, mode, atime, mtime } => {
+                Reply::from_result(sftp.set_attrs(&path, FileAttrs { mode, atime, mtime }))
+            }
+            SftpOp::ReadLink { path } => Reply::from_result(sftp.read_link(&path)),
+            SftpOp::Symlink { target, link } => Reply::from_result(sftp.symlink(&target, &link)),
+            SftpOp::Rename { from, to } => Reply::from_result(sftp.rename(&from, &to)),
+            SftpOp::Read { path } => {
+                match sftp.open_read(&path) {
+                    Ok(f) => {
+                        send_line(writer, &Reply::default())?;
+                        stream_file(f, writer)?;
+                    }
+                    Err(e) => send_line(writer, &Reply::from_result::<()>(Err(e)))?,
+                }
+                continue;
+            }
+            SftpOp::Write { path } => match sftp.create_write(&path) {
+                Ok(f) => {
+                    send_line(writer, &Reply::default())?;
+                    Reply::from_result(receive_file(f, reader)?)
+                }
+                Err(e) => Reply::from_result::<()>(Err(e)),
+            },
+        };
+        send_line(writer, &reply)?;
+    }
+    Ok(())
+}
+
+fn stream_file(mut f: Box<dyn Read + Send>, writer: &mut UnixStream) -> std::io::Result<()> {
+    let mut buf = vec![0u8; READ_FRAME_SIZE];
+    loop {
+        match f.read(&mut buf) {
+            Ok(0) => return write_frame(writer, &[]),
+            Ok(n) => write_frame(writer, &buf[..n])?,
+            Err(e) => return write_err_frame(writer, &e.to_string()),
+        }
+    }
+}
+
+// Frames are drained up to the end marker even after a failed write so the connection
+// stays in step; the first error is reported once the client ends the stream.
+fn receive_file(
+    mut f: Box<dyn Write + Send>,
+    reader: &mut BufReader<UnixStream>,
+) -> std::io::Result<std::result::Result<(), String>> {
+    let mut failed: Option<String> = None;
+    loop {
+        let len = read_header(reader)?;
+        if len == 0 {
+            break;
+        }
+        let data = read_frame(reader, len)?;
+        if failed.is_none()
+            && let Err(e) = f.write_all(&data)
+        {
+            failed = Some(e.to_string());
+        }
+    }
+    if failed.is_none()
+        && let Err(e) = f.flush()
+    {
+        failed = Some(e.to_string());
+    }
+    drop(f);
+    Ok(failed.map_or(Ok(()), Err))
+}
+
+fn serve(config: &Config, socket: PathBuf, idle_timeout: Duration) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(dir) = socket.parent() {
+        prepare_socket_dir(dir)?;
+    }
+    let _ = std::fs::remove_file(&socket);
+    let listener =
+        UnixListener::bind(&socket).with_context(|| format!("无法监听 {}", socket.display()))?;
+    std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o600))?;
+    let agent = Arc::new(Agent {
+        server_file: config.server_file_path.clone(),
+        socket,
+        pool: SessionPool::default(),
+        started: Instant::now(),
+        idle_timeout,
+        requests: AtomicU64::new(0),
+        in_use: AtomicUsize::new(0),
+        stopping: AtomicBool::new(false),
+    });
+    {
+        let agent = agent.clone();
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(
+                    MAINTAIN_EVERY.min(agent.idle_timeout).max(Duration::from_secs(1)),
+                );
+                let closed = agent.pool.maintain(agent.idle_timeout);
+                if closed > 0 {
+                    tracing::debug!("[agent] 关闭 {} 个空闲会话", closed);
+                }
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let agent = agent.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = agent.handle(stream) {
+                tracing::debug!("[agent] 连接中断: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn running_status(socket: &Path) -> Option<serde_json::Value> {
+    Conn::open(socket).ok()?.call(&Request::Status).ok()
+}
+
+/// `hp agent start`: detach a `--foreground` copy of this binary unless one is running.
+pub fn handle_agent_start(
+    config: &Config,
+    foreground: bool,
+    idle_timeout: Option<u64>,
+) -> Result<()> {
+    let socket = socket_path()?;
+    if let Some(status) = running_status(&socket) {
+        println!("hp agent 已在运行 (pid {})", status["pid"]);
+        return Ok(());
+    }
+    let secs = idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS);
+    if foreground {
+        return serve(config, socket, Duration::from_secs(secs));
+    }
+    use std::os::unix::process::CommandExt;
+    let exe = std::env::current_exe().context("无法定位 hp 可执行文件")?;
+    std::process::Command::new(exe)
+        .args(["agent", "start", "--foreground", "--idle-timeout", &secs.to_string()])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("无法启动 hp agent")?;
+    let deadline = Instant::now() + START_WAIT;
+    while Instant::now() < deadline {
+        if let Some(status) = running_status(&socket) {
+            println!("hp agent 已启动 (pid {}, socket {})", status["pid"], socket.display());
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(crate::TransferError::OperationFailed("hp agent 启动超时".to_string()).into())
+}
+
+/// `hp agent status`: uptime, counters and the idle sessions per host.
+pub fn handle_agent_status() -> Result<()> {
+    let socket = socket_path()?;
+    let Some(st) = running_status(&socket) else {
+        return Err(crate::TransferError::OperationFailed("hp agent 未运行".to_string()).into());
+    };
+    let secs = st["uptime_secs"].as_u64().unwrap_or(0);
+    println!(
+        "hp agent 运行中 (pid {}, 已运行 {}h{:02}m{:02}s)",
+        st["pid"],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    println!("  socket: {}", socket.display());
+    println!(
+        "  空闲超时: {}s | 请求: {} | 握手: {} | 会话复用: {} | 使用中: {}",
+        st["idle_timeout_secs"], st["requests"], st["handshakes"], st["reuses"], st["in_use"]
+    );
+    let idle = st["idle"].as_array().cloned().unwrap_or_default();
+    if idle.is_empty() {
+        println!("  无空闲会话");
+    }
+    for entry in idle {
+        println!(
+            "  {}  空闲会话 {} (最久 {}s)",
+            entry["key"].as_str().unwrap_or("?"),
+            entry["sessions"],
+            entry["idle_secs"]
+        );
+    }
+    Ok(())
+}
+
+/// `hp agent stop`
+pub fn handle_agent_stop() -> Result<()> {
+    let socket = socket_path()?;
+    let mut conn = Conn::open(&socket)
+        .map_err(|_| crate::TransferError::OperationFailed("hp agent 未运行".to_string()))?;
+    conn.call(&Request::Stop).map_err(crate::TransferError::OperationFailed)?;
+    println!("hp agent 已停止");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_with_end_and_error_markers() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"hello").unwrap();
+        write_frame(&mut wire, &[]).unwrap();
+        write_err_frame(&mut wire, "boom").unwrap();
+        let mut r = std::io::Cursor::new(wire);
+        let len = read_header(&mut r).unwrap();
+        assert_eq!(read_frame(&mut r, len).unwrap(), b"hello");
+        assert_eq!(read_header(&mut r).unwrap(), 0);
+        assert_eq!(read_header(&mut r).unwrap(), ERR_FRAME);
+        let len = read_header(&mut r).unwrap();
+        assert_eq!(read_frame(&mut r, len).unwrap(), b"boom");
+    }
+
+    // in-memory remote for the agent side of the protocol
+    #[derive(Default)]
+    struct MemSftp {
+        files: Arc<Mutex<std::collections::HashMap<PathBuf, Vec<u8>>>>,
+    }
+
+    struct MemFile {
+        path: PathBuf,
+        buf: Vec<u8>,
+        files: Arc<Mutex<std::collections::HashMap<PathBuf, Vec<u8>>>>,
+    }
+
+    impl Write for MemFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.files.lock().unwrap().insert(self.path.clone(), self.buf.clone());
+            Ok(())
+        }
+    }
+
+    impl SftpLike for MemSftp {
+        fn stat_is_file(&self, p: &Path) -> std::result::Result<bool, String> {
+            Ok(self.files.lock().unwrap().contains_key(p))
+        }
+
+        fn mkdir(&self, _p: &Path, _mode: i32) -> std::result::Result<(), String> {
+            Err("permission denied".to_string())
+        }
+
+        fn open_read(&self, p: &Path) -> std::result::Result<Box<dyn Read + Send>, String> {
+            let data = self.files.lock().unwrap().get(p).cloned().ok_or("no such file")?;
+            Ok(Box::new(std::io::Cursor::new(data)))
+        }
+
+        fn create_write(&self, p: &Path) -> std::result::Result<Box<dyn Write + Send>, String> {
+            let files = self.files.clone();
+            Ok(Box::new(MemFile { path: p.to_path_buf(), buf: Vec::new(), files }))
+        }
+    }
+
+    #[test]
+    fn files_stream_through_the_sftp_protocol() {
+        let (client, agent) = UnixStream::pair().unwrap();
+        let served = std::thread::spawn(move || {
+            let mut reader = BufReader::new(agent.try_clone().unwrap());
+            let mut writer = agent;
+            serve_sftp_ops(&MemSftp::default(), &mut reader, &mut writer)
+        });
+        let mut conn = Conn { reader: BufReader::new(client.try_clone().unwrap()), writer: client };
+        let path = PathBuf::from("/srv/a.bin");
+        let data: Vec<u8> = (0..600_000u32).map(|i| i as u8).collect();
+
+        conn.call(&SftpOp::Write { path: path.clone() }).unwrap();
+        for chunk in data.chunks(64 * 1024) {
+            write_frame(&mut conn.writer, chunk).unwrap();
+        }
+        write_frame(&mut conn.writer, &[]).unwrap();
+        assert_eq!(
+            recv_line::<Reply>(&mut conn.reader).unwrap().unwrap().into_result(),
+            Ok(serde_json::Value::Null)
+        );
+        assert_eq!(conn.call(&SftpOp::StatIsFile { path: path.clone() }), Ok(true.into()));
+        assert_eq!(
+            conn.call(&SftpOp::Mkdir { path: path.clone(), mode: 0o755 }),
+            Err("permission denied".to_string())
+        );
+        assert!(conn.call(&SftpOp::Read { path: PathBuf::from("/missing") }).is_err());
+
+        conn.call(&SftpOp::Read { path }).unwrap();
+        let mut r = FrameReader { conn, remaining: 0, done: false };
+        let mut back = Vec::new();
+        r.read_to_end(&mut back).unwrap();
+        assert_eq!(back, data);
+        drop(r);
+        served.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn requests_are_tagged_by_op() {
+        let line = serde_json::to_string(&SftpOp::Rename {
+            from: PathBuf::from("/a"),
+            to: PathBuf::from("/b"),
+        })
+        .unwrap();
+        assert_eq!(line, r#"{"op":"rename","from":"/a","to":"/b"}"#);
+        let req: Request = serde_json::from_str(r#"{"op":"sftp","alias":"web"}"#).unwrap();
+        assert!(matches!(req, Request::Sftp { alias } if alias == "web"));
+        let reply = Reply::from_result::<()>(Err("denied".to_string()));
+        assert_eq!(reply.into_result(), Err("denied".to_string()));
+    }
+
+    #[test]
+    fn stop_is_refused_while_a_session_is_in_use() {
+        let agent = Agent {
+            server_file: PathBuf::new(),
+            socket: PathBuf::new(),
+            pool: SessionPool::default(),
+            started: Instant::now(),
+            idle_timeout: Duration::from_secs(60),
+            requests: AtomicU64::new(0),
+            in_use: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+        };
+        let held = agent.enter().unwrap();
+        assert!(agent.try_stop().unwrap_err().contains("1 个会话"));
+        // the refused stop does not block further work
+        drop(agent.enter().unwrap());
+        drop(held);
+        agent.try_stop().unwrap();
+        assert!(agent.enter().is_err());
+        assert_eq!(agent.in_use.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn socket_directory_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_agent_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        prepare_socket_dir(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
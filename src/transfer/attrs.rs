@@ -0,0 +1,235 @@
+// File attribute handling for `-p/--preserve`, `--chmod` and `--umask`.
+//
+// Upload: after the remote write completes, `Sftp::setstat` applies mode and times.
+// Download: mode and times are applied to the temp file before the atomic rename.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Attributes carried from the source to the destination. Times are Unix seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FileAttrs {
+    pub(crate) mode: Option<u32>,
+    pub(crate) atime: Option<u64>,
+    pub(crate) mtime: Option<u64>,
+}
+
+impl FileAttrs {
+    pub(crate) fn from_stat(st: &ssh2::FileStat) -> Self {
+        FileAttrs { mode: st.perm.map(|p| p & 0o7777), atime: st.atime, mtime: st.mtime }
+    }
+
+    pub(crate) fn from_metadata(md: &std::fs::Metadata) -> Self {
+        let secs = |t: std::io::Result<SystemTime>| {
+            t.ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs())
+        };
+        FileAttrs { mode: local_mode(md), atime: secs(md.accessed()), mtime: secs(md.modified()) }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.atime.is_none() && self.mtime.is_none()
+    }
+}
+
+#[cfg(unix)]
+fn local_mode(md: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt as _;
+    Some(md.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn local_mode(_md: &std::fs::Metadata) -> Option<u32> {
+    // Windows has no Unix mode bits; only overrides (--chmod/--umask) apply
+    None
+}
+
+/// How destination attributes are derived from the source.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct AttrPolicy {
+    pub(crate) preserve: bool,
+    chmod_file: Option<u32>,
+    chmod_dir: Option<u32>,
+    umask: Option<u32>,
+}
+
+impl AttrPolicy {
+    /// Build from CLI values. `chmod` accepts `644` or `D755,F644`; `umask` is octal.
+    pub(crate) fn from_cli(
+        preserve: bool,
+        chmod: Option<&str>,
+        umask: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut policy = AttrPolicy { preserve, ..Default::default() };
+        if let Some(spec) = chmod {
+            for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let (dir, file, digits) = match part.as_bytes()[0] {
+                    b'D' | b'd' => (true, false, &part[1..]),
+                    b'F' | b'f' => (false, true, &part[1..]),
+                    _ => (true, true, part),
+                };
+                let mode = parse_octal(digits).ok_or_else(|| -> anyhow::Error {
+                    crate::TransferError::InvalidArgument(format!("无效的 --chmod: {}", spec))
+                        .into()
+                })?;
+                if dir {
+                    policy.chmod_dir = Some(mode);
+                }
+                if file {
+                    policy.chmod_file = Some(mode);
+                }
+            }
+        }
+        if let Some(u) = umask {
+            policy.umask = Some(parse_octal(u).ok_or_else(|| -> anyhow::Error {
+                crate::TransferError::InvalidArgument(format!("无效的 --umask: {}", u)).into()
+            })?);
+        }
+        Ok(policy)
+    }
+
+    /// True when any attribute work is needed after a transfer.
+    pub(crate) fn is_active(&self) -> bool {
+        self.preserve
+            || self.chmod_file.is_some()
+            || self.chmod_dir.is_some()
+            || self.umask.is_some()
+    }
+
+    /// Attributes to apply at the destination given the source attributes.
+    pub(crate) fn resolve(&self, src: FileAttrs, is_dir: bool) -> FileAttrs {
+        let chmod = if is_dir { self.chmod_dir } else { self.chmod_file };
+        let mode = match chmod {
+            Some(m) => Some(m),
+            None => {
+                let default = if is_dir { 0o777 } else { 0o666 };
+                let base = if self.preserve { src.mode } else { self.umask.map(|_| default) };
+                base.map(|m| m & !self.umask.unwrap_or(0))
+            }
+        };
+        // directory times change as children are written, so only files carry times
+        let (atime, mtime) =
+            if self.preserve && !is_dir { (src.atime, src.mtime) } else { (None, None) };
+        FileAttrs { mode, atime, mtime }
+    }
+}
+
+fn parse_octal(s: &str) -> Option<u32> {
+    let s = s.trim();
+    if s.is_empty() || s.len() > 5 {
+        return None;
+    }
+    u32::from_str_radix(s, 8).ok().filter(|m| *m <= 0o7777)
+}
+
+/// Apply attributes to a local path (download side, before the atomic rename).
+pub(crate) fn apply_local(path: &std::path::Path, attrs: FileAttrs) -> std::io::Result<()> {
+    if attrs.atime.is_some() || attrs.mtime.is_some() {
+        let f = std::fs::OpenOptions::new().write(true).open(path)?;
+        let mut times = std::fs::FileTimes::new();
+        if let Some(a) = attrs.atime {
+            times = times.set_accessed(UNIX_EPOCH + Duration::from_secs(a));
+        }
+        if let Some(m) = attrs.mtime {
+            times = times.set_modified(UNIX_EPOCH + Duration::from_secs(m));
+        }
+        f.set_times(times)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = attrs.mode {
+        use std::os::unix::fs::PermissionsExt as _;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Convert to an `ssh2::FileStat` carrying only the attributes to change.
+pub(crate) fn to_stat(attrs: FileAttrs) -> ssh2::FileStat {
+    ssh2::FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: attrs.mode,
+        atime: attrs.atime.or(attrs.mtime),
+        mtime: attrs.mtime.or(attrs.atime),
+    }
+}
+
+// Directory attributes are applied once the transfer finished, deepest first, so a
+// restrictive preserved mode cannot block writing the directory's children.
+fn deepest_first<P: AsRef<std::path::Path>>(dirs: &mut [(P, FileAttrs)]) {
+    dirs.sort_by_key(|(p, _)| std::cmp::Reverse(p.as_ref().components().count()));
+}
+
+/// Apply deferred directory attributes on the remote side (upload).
+pub(crate) fn apply_dir_attrs_remote(
+    sftp: &ssh2::Sftp,
+    mut dirs: Vec<(String, FileAttrs)>,
+    failure_tx: &crossbeam_channel::Sender<crate::TransferError>,
+) {
+    deepest_first(&mut dirs);
+    for (path, attrs) in dirs {
+        if let Err(e) = sftp.setstat(std::path::Path::new(&path), to_stat(attrs)) {
+            super::workers::report_failure(
+                failure_tx,
+                crate::TransferError::PreserveFailed(path, e.to_string()),
+            );
+        }
+    }
+}
+
+/// Apply deferred directory attributes on the local side (download).
+pub(crate) fn apply_dir_attrs_local(
+    mut dirs: Vec<(std::path::PathBuf, FileAttrs)>,
+    failure_tx: &crossbeam_channel::Sender<crate::TransferError>,
+) {
+    deepest_first(&mut dirs);
+    for (path, attrs) in dirs {
+        if let Err(e) = apply_local(&path, attrs) {
+            super::workers::report_failure(
+                failure_tx,
+                crate::TransferError::PreserveFailed(path.display().to_string(), e.to_string()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chmod_and_umask_parsing() {
+        let p = AttrPolicy::from_cli(false, Some("D755,F644"), None).unwrap();
+        assert_eq!(p.resolve(FileAttrs::default(), true).mode, Some(0o755));
+        assert_eq!(p.resolve(FileAttrs::default(), false).mode, Some(0o644));
+        let p = AttrPolicy::from_cli(false, None, Some("027")).unwrap();
+        assert_eq!(p.resolve(FileAttrs::default(), false).mode, Some(0o640));
+        assert_eq!(p.resolve(FileAttrs::default(), true).mode, Some(0o750));
+        assert!(AttrPolicy::from_cli(false, Some("9x"), None).is_err());
+        assert!(AttrPolicy::from_cli(false, None, Some("77777")).is_err());
+        assert!(!AttrPolicy::default().is_active());
+    }
+
+    #[test]
+    fn preserve_carries_mode_and_file_times() {
+        let src = FileAttrs { mode: Some(0o755), atime: Some(10), mtime: Some(20) };
+        let p = AttrPolicy::from_cli(true, None, Some("022")).unwrap();
+        assert_eq!(p.resolve(src, false), src);
+        let d = p.resolve(src, true);
+        assert_eq!((d.mode, d.mtime), (Some(0o755), None));
+        let p = AttrPolicy::from_cli(true, Some("F600"), None).unwrap();
+        assert_eq!(p.resolve(src, false).mode, Some(0o600));
+    }
+
+    #[test]
+    fn apply_local_sets_mtime() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("hp_attrs_{}", std::process::id()));
+        std::fs::write(&path, b"x").unwrap();
+        let attrs = FileAttrs { mode: Some(0o640), atime: Some(1_000_000), mtime: Some(1_234_567) };
+        apply_local(&path, attrs).unwrap();
+        let got = FileAttrs::from_metadata(&std::fs::metadata(&path).unwrap());
+        assert_eq!(got.mtime, Some(1_234_567));
+        #[cfg(unix)]
+        assert_eq!(got.mode, Some(0o640));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,113 @@
+// Direct remote-to-remote mode (`--direct`): the source host pushes the data to the
+// destination itself over its own `ssh`, so the bytes never pass through this machine.
+// Requires key-based (BatchMode) access from the source host to the destination alias;
+// when the probe fails the caller falls back to the local relay.
+use super::helpers::shell_quote;
+use super::session::exec_remote;
+
+/// Result of a direct attempt.
+pub(super) enum DirectOutcome {
+    Done,
+    // the source host cannot log into the destination; message from the probe
+    Unreachable(String),
+}
+
+// `ssh` invocation run on the source host, non-interactive so a missing key fails fast.
+fn ssh_prefix(dst: &crate::server::Server) -> String {
+    format!(
+        "ssh -o BatchMode=yes -o ConnectTimeout=10 -p {} {}",
+        dst.port,
+        shell_quote(&format!("{}@{}", dst.username, dst.address))
+    )
+}
+
+/// Shell command executed on the source host. Directories send their contents (same
+/// semantics as the relay), files are written to `dest` or into it when it is a directory.
+pub(super) fn direct_command(
+    dst: &crate::server::Server,
+    src_root: &str,
+    src_is_dir: bool,
+    dest: &str,
+    target_is_dir: bool,
+) -> String {
+    let ssh = ssh_prefix(dst);
+    if src_is_dir {
+        let remote = format!("tar -C {} -xf -", shell_quote(dest));
+        return format!(
+            "tar -C {} -cf - . | {} {}",
+            shell_quote(src_root),
+            ssh,
+            shell_quote(&remote)
+        );
+    }
+    let dest_file = if target_is_dir {
+        let name = std::path::Path::new(src_root)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        format!("{}/{}", dest.trim_end_matches('/'), name)
+    } else {
+        dest.to_string()
+    };
+    let remote = format!("cat > {}", shell_quote(&dest_file));
+    format!("{} {} < {}", ssh, shell_quote(&remote), shell_quote(src_root))
+}
+
+/// Probe reachability from the source host, then run the copy there.
+pub(super) fn run_direct(
+    src_sess: &ssh2::Session,
+    dst: &crate::server::Server,
+    command: &str,
+) -> anyhow::Result<DirectOutcome> {
+    let (status, out) = exec_remote(src_sess, &format!("{} true", ssh_prefix(dst)))?;
+    if status != 0 {
+        return Ok(DirectOutcome::Unreachable(out.trim().to_string()));
+    }
+    tracing::debug!("[ts][direct] {}", command);
+    let (status, out) = exec_remote(src_sess, command)?;
+    if status != 0 {
+        return Err(crate::TransferError::OperationFailed(format!(
+            "直连传输失败（退出码 {}）: {}",
+            status,
+            out.trim()
+        ))
+        .into());
+    }
+    Ok(DirectOutcome::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dst() -> crate::server::Server {
+        crate::server::Server {
+            id: None,
+            alias: Some("db2".into()),
+            username: "ops".into(),
+            address: "10.0.0.2".into(),
+            port: 2222,
+            last_connect: None,
+            limit_rate: None,
+            tags: Vec::new(),
+            max_handshakes: None,
+        }
+    }
+
+    #[test]
+    fn commands_per_source_kind() {
+        let ssh = "ssh -o BatchMode=yes -o ConnectTimeout=10 -p 2222 'ops@10.0.0.2'";
+        assert_eq!(
+            direct_command(&dst(), "/var/dump", true, "/data/in", true),
+            format!("tar -C '/var/dump' -cf - . | {} 'tar -C '\\''/data/in'\\'' -xf -'", ssh)
+        );
+        assert_eq!(
+            direct_command(&dst(), "/var/db.sql", false, "/data/in/", true),
+            format!("{} 'cat > '\\''/data/in/db.sql'\\''' < '/var/db.sql'", ssh)
+        );
+        assert_eq!(
+            direct_command(&dst(), "/var/db.sql", false, "/data/copy.sql", false),
+            format!("{} 'cat > '\\''/data/copy.sql'\\''' < '/var/db.sql'", ssh)
+        );
+    }
+}
@@ -2,117 +2,292 @@ use anyhow::Result;
 use std::collections::VecDeque;
 use walkdir::WalkDir;
 
-use super::wildcard_match;
+use super::filter::PathFilter;
+use super::helpers::{GlobPattern, is_local_glob, is_remote_glob, normalize_path};
+use super::links::{LinkPolicy, is_link_loop};
+use super::session::SessionPool;
 use super::{EntryKind, FileEntry};
 
-// enumerate local sources per rules (R3/R4/R9)
-pub(super) fn enumerate_local_sources(sources: &[String]) -> Result<(Vec<FileEntry>, u64)> {
+/// Result of local source enumeration.
+#[derive(Default)]
+pub(super) struct LocalEnumeration {
+    pub(super) entries: Vec<FileEntry>,
+    pub(super) total_size: u64,
+    // paths that could not be read while walking; mirror deletion is skipped when non-empty
+    pub(super) unreadable: Vec<String>,
+    // symlinks left out (`--links skip`, broken or looping links under `follow`)
+    pub(super) skipped_links: Vec<String>,
+}
+
+/// Outcome of a remote walk besides the entries handed to `push`.
+#[derive(Debug, Default)]
+pub(super) struct RemoteWalk {
+    // directories whose readdir failed; mirror deletion is skipped when non-empty
+    pub(super) unreadable: Vec<String>,
+    pub(super) skipped_links: Vec<String>,
+}
+
+/// Outcome of a streamed local walk besides the entries handed to `push`.
+#[derive(Debug, Default)]
+pub(super) struct LocalStream {
+    // entries handed to `push`, directories included
+    pub(super) entries: u64,
+    // non-directory entries among them
+    pub(super) files: u64,
+    pub(super) total_size: u64,
+    pub(super) unreadable: Vec<String>,
+    pub(super) skipped_links: Vec<String>,
+}
+
+// Shared state for one local walk.
+struct LocalWalk<'a> {
+    filter: &'a PathFilter,
+    links: LinkPolicy,
+    push: &'a mut dyn FnMut(FileEntry),
+    out: &'a mut LocalStream,
+}
+
+impl LocalWalk<'_> {
+    // Record a walkdir error: looping/broken links (under `follow`) are skipped links,
+    // anything else makes the enumeration incomplete.
+    fn record_error(&mut self, root: &std::path::Path, e: walkdir::Error) {
+        let p = e.path().unwrap_or(root).to_path_buf();
+        let is_link =
+            std::fs::symlink_metadata(&p).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+        if e.loop_ancestor().is_some() || is_link {
+            tracing::warn!("[ts][enum] 跳过符号链接（循环或目标不存在）: {}", p.display());
+            let rel = p.strip_prefix(root).unwrap_or(&p).to_string_lossy().to_string();
+            self.out.skipped_links.push(normalize_path(&rel, false));
+        } else {
+            tracing::warn!("[ts][enum] 本地遍历失败: {} — {}", p.display(), e);
+            self.out.unreadable.push(p.display().to_string());
+        }
+    }
+
+    // Handle an unfollowed symlink entry according to the policy.
+    fn push_link(&mut self, path: &std::path::Path, rel: &str) {
+        match self.links {
+            LinkPolicy::Copy => self.emit(make_local_entry(EntryKind::Symlink, path, rel, None)),
+            _ => self.out.skipped_links.push(normalize_path(rel, false)),
+        }
+    }
+
+    fn emit(&mut self, entry: FileEntry) {
+        self.out.entries += 1;
+        if entry.kind != EntryKind::Dir {
+            self.out.files += 1;
+        }
+        self.out.total_size += entry.size.unwrap_or(0);
+        (self.push)(entry);
+    }
+}
+
+// enumerate local sources per rules (R3/R4/R9); directory walks honor `filter`
+// plus the `.hpignore` at each walked root
+pub(super) fn enumerate_local_sources(
+    sources: &[String],
+    filter: &PathFilter,
+    links: LinkPolicy,
+) -> Result<LocalEnumeration> {
     let mut entries: Vec<FileEntry> = Vec::new();
-    let mut total_size: u64 = 0;
+    let walk = enumerate_local_and_push(sources, filter, links, &mut |e| entries.push(e))?;
+    Ok(LocalEnumeration {
+        entries,
+        total_size: walk.total_size,
+        unreadable: walk.unreadable,
+        skipped_links: walk.skipped_links,
+    })
+}
+
+/// Source checks done before the destination is touched: existence, trailing `/`, and
+/// for globs the base directory plus at least one match that the filters keep.
+pub(super) fn check_local_source(src: &str, filter: &PathFilter, links: LinkPolicy) -> Result<()> {
+    let src_norm = normalize_path(src, false);
+    if is_local_glob(&src_norm) {
+        let glob = GlobPattern::parse(&src_norm);
+        let base = std::path::Path::new(&glob.base);
+        if !base.is_dir() {
+            return Err(crate::TransferError::WorkerIo(format!(
+                "无法读取目录: {}",
+                base.display()
+            ))
+            .into());
+        }
+        let filter = filter.for_local_root(base);
+        if !glob_has_match(&glob, &filter, links == LinkPolicy::Follow) {
+            return Err(crate::TransferError::GlobNoMatches(src.to_string()).into());
+        }
+        return Ok(());
+    }
+    let p = std::path::Path::new(&src_norm);
+    if src_norm.ends_with('/') && !p.is_dir() {
+        return Err(crate::TransferError::WorkerIo(format!(
+            "源以 '/' 结尾但不是目录: {} (本地)",
+            src
+        ))
+        .into());
+    }
+    if !p.exists() {
+        return Err(crate::TransferError::WorkerIo(format!("源不存在: {} (本地)", src)).into());
+    }
+    Ok(())
+}
+
+/// Walk local sources and hand every entry to `push` as it is found (streamed uploads).
+pub(super) fn enumerate_local_and_push(
+    sources: &[String],
+    filter: &PathFilter,
+    links: LinkPolicy,
+    push: &mut dyn FnMut(FileEntry),
+) -> Result<LocalStream> {
+    let mut out = LocalStream::default();
     for src in sources {
-        let src_norm = crate::transfer::helpers::normalize_path(src, false);
-        let has_glob = src_norm.contains('*') || src_norm.contains('?');
-        let ends_slash = src_norm.ends_with('/');
-        if has_glob {
-            // R3: only expand within the parent dir, non-recursive
-            let p = std::path::Path::new(&src_norm);
-            let parent = p.parent().unwrap_or_else(|| std::path::Path::new("."));
-            let pat = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
-            if let Ok(rd) = std::fs::read_dir(parent) {
-                let mut matched = 0usize;
-                for ent in rd.flatten() {
-                    let name = ent.file_name();
-                    let name = name.to_string_lossy().to_string();
-                    if wildcard_match(pat, &name) {
-                        matched += 1;
-                        let full = parent.join(&name);
-                        let md = match std::fs::metadata(&full) {
-                            Ok(m) => m,
-                            Err(e) => {
-                                return Err(crate::TransferError::WorkerIo(format!(
-                                    "本地 stat 失败: {} — {}",
-                                    full.display(),
-                                    e
-                                ))
-                                .into());
-                            }
-                        };
-                        if md.is_file() {
-                            total_size += md.len();
-                            entries.push(make_local_entry(
-                                EntryKind::File,
-                                &full,
-                                &name,
-                                Some(md.len()),
-                            ));
-                        } else {
-                            entries.push(make_local_entry(EntryKind::Dir, &full, &name, None));
-                        }
-                    }
-                }
-                if matched == 0 {
-                    return Err(crate::TransferError::GlobNoMatches(src.clone()).into());
-                }
-            } else {
-                return Err(crate::TransferError::WorkerIo(format!(
-                    "无法读取目录: {}",
-                    parent.display()
-                ))
-                .into());
-            }
+        check_local_source(src, filter, links)?;
+        let src_norm = normalize_path(src, false);
+        let p = std::path::Path::new(&src_norm);
+        if is_local_glob(&src_norm) {
+            // R3: expand below the literal base dir; multi-segment and `**` patterns recurse
+            let glob = GlobPattern::parse(&src_norm);
+            let filter = filter.for_local_root(std::path::Path::new(&glob.base));
+            let mut walk = LocalWalk { filter: &filter, links, push: &mut *push, out: &mut out };
+            collect_glob_entries(&glob, &mut walk)?;
+        } else if p.is_dir() {
+            // 目录无论是否带 '/'，均复制“目录内容”（不含容器），递归
+            let filter = filter.for_local_root(p);
+            let mut walk = LocalWalk { filter: &filter, links, push: &mut *push, out: &mut out };
+            collect_dir_entries(p, &mut walk);
         } else {
-            let p = std::path::Path::new(&src_norm);
-            if ends_slash {
-                if !p.exists() || !p.is_dir() {
-                    return Err(crate::TransferError::WorkerIo(format!(
-                        "源以 '/' 结尾但不是目录: {} (本地)",
-                        src
-                    ))
-                    .into());
-                }
-                collect_dir_entries(p, &mut entries, &mut total_size);
-            } else {
-                if !p.exists() {
-                    return Err(crate::TransferError::WorkerIo(format!(
-                        "源不存在: {} (本地)",
-                        src
-                    ))
-                    .into());
-                }
-                if p.is_dir() {
-                    // 目录无论是否带 '/'，均复制“目录内容”（不含容器），递归
-                    collect_dir_entries(p, &mut entries, &mut total_size);
-                } else {
-                    let md = std::fs::metadata(p).unwrap();
-                    total_size += md.len();
-                    let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
-                    entries.push(make_local_entry(EntryKind::File, p, &name, Some(md.len())));
-                }
-            }
+            let md = std::fs::metadata(p).map_err(|e| -> anyhow::Error {
+                crate::TransferError::WorkerIo(format!("本地 stat 失败: {} — {}", src, e)).into()
+            })?;
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+            let mut walk = LocalWalk { filter, links, push: &mut *push, out: &mut out };
+            walk.emit(make_local_entry(EntryKind::File, p, &name, Some(md.len())));
         }
     }
-    Ok((entries, total_size))
+    Ok(out)
 }
 
-fn collect_dir_entries(root: &std::path::Path, entries: &mut Vec<FileEntry>, total_size: &mut u64) {
-    for entry in WalkDir::new(root).into_iter().flatten() {
+fn collect_dir_entries(root: &std::path::Path, walk: &mut LocalWalk<'_>) {
+    let follow = walk.links == LinkPolicy::Follow;
+    let mut it = WalkDir::new(root).follow_links(follow).into_iter();
+    while let Some(entry) = it.next() {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                walk.record_error(root, e);
+                continue;
+            }
+        };
         let path = entry.path();
-        if entry.file_type().is_dir() {
-            let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
+        let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
+        let is_dir = entry.file_type().is_dir();
+        if !rel.is_empty() && walk.filter.is_excluded(&normalize_path(&rel, false), is_dir) {
+            // prune excluded trees so they are never walked
+            if is_dir {
+                it.skip_current_dir();
+            }
+            continue;
+        }
+        if is_dir {
             if rel.is_empty() {
                 continue;
             }
             let abs = path.to_path_buf();
-            entries.push(make_local_entry(EntryKind::Dir, &abs, &rel, None));
+            walk.emit(make_local_entry(EntryKind::Dir, &abs, &rel, None));
         } else if entry.file_type().is_file() {
-            let md = std::fs::metadata(path).unwrap();
-            *total_size += md.len();
-            let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
-            entries.push(make_local_entry(EntryKind::File, path, &rel, Some(md.len())));
+            match std::fs::metadata(path) {
+                Ok(md) => walk.emit(make_local_entry(EntryKind::File, path, &rel, Some(md.len()))),
+                Err(e) => {
+                    tracing::warn!("[ts][enum] 本地 stat 失败: {} — {}", path.display(), e);
+                    walk.out.unreadable.push(path.display().to_string());
+                }
+            }
+        } else if entry.file_type().is_symlink() {
+            walk.push_link(path, &rel);
         }
     }
 }
 
+// Whether anything below the glob's base matches and is kept by `filter`; stops at the
+// first match, pruning like `collect_glob_entries`.
+fn glob_has_match(glob: &GlobPattern, filter: &PathFilter, follow: bool) -> bool {
+    let base = std::path::Path::new(&glob.base);
+    let mut it = WalkDir::new(base).min_depth(1).follow_links(follow).into_iter();
+    while let Some(entry) = it.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let rel = entry.path().strip_prefix(base).unwrap_or(entry.path()).to_string_lossy();
+        let rel = normalize_path(&rel, false);
+        let is_dir = entry.file_type().is_dir();
+        if filter.is_excluded(&rel, is_dir) {
+            if is_dir {
+                it.skip_current_dir();
+            }
+            continue;
+        }
+        if glob.matches(&rel) {
+            return true;
+        }
+        if is_dir && !glob.may_match_below(&rel) {
+            it.skip_current_dir();
+        }
+    }
+    false
+}
+
+// Walk the glob's base dir, pruning subtrees that cannot match.
+// Matched directories are transferred as directories only (their contents are not implied).
+fn collect_glob_entries(glob: &GlobPattern, walk: &mut LocalWalk<'_>) -> Result<()> {
+    let base = std::path::Path::new(&glob.base);
+    let follow = walk.links == LinkPolicy::Follow;
+    let mut it =
+        WalkDir::new(base).min_depth(1).follow_links(follow).sort_by_file_name().into_iter();
+    while let Some(entry) = it.next() {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                walk.record_error(base, e);
+                continue;
+            }
+        };
+        let path = entry.path();
+        let rel = path.strip_prefix(base).unwrap_or(path).to_string_lossy().to_string();
+        let rel = normalize_path(&rel, false);
+        let is_dir = entry.file_type().is_dir();
+        if walk.filter.is_excluded(&rel, is_dir) {
+            if is_dir {
+                it.skip_current_dir();
+            }
+            continue;
+        }
+        if glob.matches(&rel) {
+            if entry.file_type().is_symlink() {
+                walk.push_link(path, &rel);
+                continue;
+            }
+            let md = std::fs::metadata(path).map_err(|e| -> anyhow::Error {
+                crate::TransferError::WorkerIo(format!(
+                    "本地 stat 失败: {} — {}",
+                    path.display(),
+                    e
+                ))
+                .into()
+            })?;
+            if md.is_file() {
+                walk.emit(make_local_entry(EntryKind::File, path, &rel, Some(md.len())));
+            } else {
+                walk.emit(make_local_entry(EntryKind::Dir, path, &rel, None));
+            }
+        }
+        if is_dir && !glob.may_match_below(&rel) {
+            it.skip_current_dir();
+        }
+    }
+    Ok(())
+}
+
 fn make_local_entry(
     kind: EntryKind,
     full_path: &std::path::Path,
@@ -125,49 +300,28 @@ fn make_local_entry(
         size,
         kind,
         local_full: Some(full_path.to_string_lossy().to_string()),
+        origin: 0,
     }
 }
 
-// enumerate remote entries and push into a bounded channel (streaming)
+// enumerate remote entries and push into a bounded channel (streaming).
+// Returns the remote directories that could not be listed and the skipped links.
 pub(super) fn enumerate_remote_and_push(
     sftp: &ssh2::Sftp,
     remote_root: &str,
-    explicit_dir_suffix: bool,
-    src_has_glob: bool,
+    filter: &PathFilter,
+    links: LinkPolicy,
+    listers: RemoteListers<'_>,
     push: &dyn Fn(String, String, Option<u64>, EntryKind),
-) {
-    let is_glob = src_has_glob;
-    if explicit_dir_suffix && !is_glob {
-        if let Ok(st) = sftp.stat(std::path::Path::new(remote_root))
-            && st.is_file()
-        {
-            // handled in the generic branch below (no-op here)
-        }
-        walk_remote_dir(sftp, remote_root, push);
+) -> RemoteWalk {
+    let is_glob = is_remote_glob(sftp, remote_root);
+    if remote_root.ends_with('/') && !is_glob {
+        let filter = filter.for_remote_root(sftp, remote_root);
+        walk_remote_dir(sftp, remote_root, &filter, links, listers, push)
     } else if is_glob {
-        use std::path::Path;
-        let p = Path::new(remote_root);
-        let parent =
-            p.parent().map(|x| x.to_string_lossy().to_string()).unwrap_or_else(|| "/".to_string());
-        let pattern = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
-        if let Ok(entries) = sftp.readdir(Path::new(&parent)) {
-            for (pathbuf, stat) in entries {
-                if let Some(name) = pathbuf.file_name().and_then(|n| n.to_str()) {
-                    if name == "." || name == ".." {
-                        continue;
-                    }
-                    if wildcard_match(pattern, name) {
-                        let full = format!("{}/{}", parent.trim_end_matches('/'), name);
-                        if stat.is_file() {
-                            push(full, name.to_string(), stat.size, EntryKind::File);
-                        } else {
-                            // Matched a directory; do not recurse when using glob
-                            push(full, name.to_string(), None, EntryKind::Dir);
-                        }
-                    }
-                }
-            }
-        }
+        let glob = GlobPattern::parse(remote_root);
+        let filter = filter.for_remote_root(sftp, &glob.base);
+        walk_remote_glob(sftp, &glob, &filter, links, listers, push)
     } else if let Ok(m) = sftp.stat(std::path::Path::new(remote_root)) {
         if m.is_file() {
             let fname = std::path::Path::new(remote_root)
@@ -176,43 +330,557 @@ pub(super) fn enumerate_remote_and_push(
                 .unwrap_or(remote_root)
                 .to_string();
             push(remote_root.to_string(), fname, m.size, EntryKind::File);
-        } else if explicit_dir_suffix {
-            walk_remote_dir(sftp, remote_root, push);
+            RemoteWalk::default()
         } else {
             // 目录无论是否带 '/'，均复制“目录内容”（不含容器），递归
-            walk_remote_dir(sftp, remote_root, push);
+            let filter = filter.for_remote_root(sftp, remote_root);
+            walk_remote_dir(sftp, remote_root, &filter, links, listers, push)
         }
+    } else {
+        RemoteWalk::default()
     }
 }
 
-fn walk_remote_dir(
+// How one readdir entry is treated under the link policy.
+enum RemoteKind {
+    File(Option<u64>),
+    // resolved path of the directory (used for loop detection when following links)
+    Dir(String),
+    Link,
+    SkippedLink,
+    // fifo/socket/device: never read
+    Special,
+}
+
+// readdir returns lstat-style attributes, so links show up as links here.
+// `cur_real` is the resolved path of the directory being listed.
+fn classify_remote(
     sftp: &ssh2::Sftp,
-    root: &str,
-    push: &dyn Fn(String, String, Option<u64>, EntryKind),
-) {
-    let mut q: VecDeque<(String, String)> = VecDeque::new();
-    q.push_back((root.to_string(), String::new()));
-    while let Some((cur, rel_prefix)) = q.pop_front() {
-        if let Ok(entries) = sftp.readdir(std::path::Path::new(&cur)) {
-            for (pathbuf, stat) in entries {
-                if let Some(name) = pathbuf.file_name().and_then(|n| n.to_str()) {
-                    if matches!(name, "." | "..") {
-                        continue;
-                    }
-                    let full = format!("{}/{}", cur.trim_end_matches('/'), name);
-                    let rel = if rel_prefix.is_empty() {
-                        name.to_string()
-                    } else {
-                        format!("{}/{}", rel_prefix, name)
-                    };
-                    if stat.is_file() {
-                        push(full, rel, stat.size, EntryKind::File);
+    full: &str,
+    name: &str,
+    stat: &ssh2::FileStat,
+    links: LinkPolicy,
+    cur_real: &str,
+) -> RemoteKind {
+    if stat.file_type().is_symlink() {
+        return match links {
+            LinkPolicy::Copy => RemoteKind::Link,
+            LinkPolicy::Skip => RemoteKind::SkippedLink,
+            LinkPolicy::Follow => match sftp.stat(std::path::Path::new(full)) {
+                Ok(st) if st.is_dir() => {
+                    let real = sftp
+                        .realpath(std::path::Path::new(full))
+                        .map(|p| normalize_path(&p.to_string_lossy(), false))
+                        .unwrap_or_else(|_| full.to_string());
+                    if is_link_loop(cur_real, &real) {
+                        tracing::warn!("[ts][enum] 跳过循环符号链接: {} -> {}", full, real);
+                        RemoteKind::SkippedLink
                     } else {
+                        RemoteKind::Dir(real)
+                    }
+                }
+                Ok(st) => RemoteKind::File(st.size),
+                Err(e) => {
+                    tracing::warn!("[ts][enum] 跳过失效符号链接: {} — {}", full, e);
+                    RemoteKind::SkippedLink
+                }
+            },
+        };
+    }
+    if stat.is_file() {
+        RemoteKind::File(stat.size)
+    } else if stat.is_dir() || stat.perm.is_none() {
+        // servers that omit permissions: assume a directory (readdir fails otherwise)
+        RemoteKind::Dir(format!("{}/{}", cur_real.trim_end_matches('/'), name))
+    } else {
+        RemoteKind::Special
+    }
+}
+
+// Resolved root for loop detection; only needed (and only paid for) when following links.
+fn remote_root_real(sftp: &ssh2::Sftp, root: &str, links: LinkPolicy) -> String {
+    if links == LinkPolicy::Follow
+        && let Ok(p) = sftp.realpath(std::path::Path::new(root))
+    {
+        return normalize_path(&p.to_string_lossy(), false);
+    }
+    normalize_path(root, false)
+}
+
+// Sessions a remote walk opens for listing directories; the walking thread keeps its own.
+const REMOTE_LISTERS: usize = 4;
+// directories handed out per lister before the walking thread has consumed them
+const LISTER_WINDOW: usize = 8;
+
+/// Extra SFTP sessions used to list remote directories in parallel.
+/// `RemoteListers::default()` lists on the walking handle, one readdir at a time.
+#[derive(Clone, Copy, Default)]
+pub(super) struct RemoteListers<'a> {
+    server: Option<(&'a SessionPool, &'a crate::server::Server)>,
+    sessions: usize,
+}
+
+impl<'a> RemoteListers<'a> {
+    pub(super) fn new(pool: &'a SessionPool, server: &'a crate::server::Server) -> Self {
+        Self { server: Some((pool, server)), sessions: REMOTE_LISTERS }
+    }
+}
+
+type Listing = Result<Vec<(std::path::PathBuf, ssh2::FileStat)>, ssh2::Error>;
+
+// A directory waiting to be listed: full path, path relative to the root, resolved path.
+struct QueuedDir {
+    full: String,
+    rel: String,
+    real: String,
+}
+
+// Consumes listings in discovery order; everything that touches `push` or the
+// filter runs on the walking thread, so entries come out in the same order no
+// matter which lister finished first.
+struct RemoteWalker<'a> {
+    sftp: &'a ssh2::Sftp,
+    filter: &'a PathFilter,
+    links: LinkPolicy,
+    // glob walks descend only into dirs that may still match and push matches only
+    glob: Option<&'a GlobPattern>,
+    push: &'a dyn Fn(String, String, Option<u64>, EntryKind),
+    out: RemoteWalk,
+}
+
+impl RemoteWalker<'_> {
+    // Push the entries of one listing and return its subdirectories to walk next.
+    fn visit(&mut self, dir: &QueuedDir, listing: Listing) -> Vec<QueuedDir> {
+        let mut listing = match listing {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::warn!("[ts][enum] 远端 readdir 失败: {} — {}", dir.full, e);
+                self.out.unreadable.push(dir.full.clone());
+                return Vec::new();
+            }
+        };
+        listing.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut subdirs = Vec::new();
+        for (pathbuf, stat) in listing {
+            let Some(name) = pathbuf.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if matches!(name, "." | "..") {
+                continue;
+            }
+            let full = format!("{}/{}", dir.full.trim_end_matches('/'), name);
+            let rel =
+                if dir.rel.is_empty() { name.to_string() } else { format!("{}/{}", dir.rel, name) };
+            let kind = classify_remote(self.sftp, &full, name, &stat, self.links, &dir.real);
+            if self.filter.is_excluded(&rel, matches!(kind, RemoteKind::Dir(_))) {
+                continue;
+            }
+            let matched = self.glob.is_none_or(|g| g.matches(&rel));
+            let push = self.push;
+            match kind {
+                RemoteKind::File(size) if matched => push(full, rel, size, EntryKind::File),
+                RemoteKind::Dir(real) => {
+                    if matched {
                         push(full.clone(), rel.clone(), None, EntryKind::Dir);
-                        q.push_back((full, rel));
                     }
+                    if self.glob.is_none_or(|g| g.may_match_below(&rel)) {
+                        subdirs.push(QueuedDir { full, rel, real });
+                    }
+                }
+                RemoteKind::Link if matched => push(full, rel, None, EntryKind::Symlink),
+                RemoteKind::SkippedLink if matched => self.out.skipped_links.push(rel),
+                RemoteKind::Special => {
+                    tracing::debug!("[ts][enum] 跳过特殊文件: {}", full);
+                }
+                _ => {}
+            }
+        }
+        subdirs
+    }
+}
+
+// BFS over a remote tree. Directories are visited in discovery order; with listers
+// their readdir round trips overlap, but the pushed order stays the same.
+fn walk_remote_tree(
+    walker: &mut RemoteWalker<'_>,
+    root: &str,
+    listers: RemoteListers<'_>,
+) -> RemoteWalk {
+    let real = remote_root_real(walker.sftp, root, walker.links);
+    let root = QueuedDir { full: root.to_string(), rel: String::new(), real };
+    // the root is listed right away while no lister session exists yet; a flat
+    // directory never opens one
+    let listing = walker.sftp.readdir(std::path::Path::new(&root.full));
+    let mut q: VecDeque<QueuedDir> = walker.visit(&root, listing).into();
+    let (pool, server) = match listers.server {
+        Some(pooled) if listers.sessions > 0 && !q.is_empty() => pooled,
+        _ => {
+            while let Some(dir) = q.pop_front() {
+                let listing = walker.sftp.readdir(std::path::Path::new(&dir.full));
+                q.extend(walker.visit(&dir, listing));
+            }
+            return std::mem::take(&mut walker.out);
+        }
+    };
+
+    std::thread::scope(|s| {
+        // shared work queue: each lister takes the next directory any other lister has not
+        let (job_tx, job_rx) = crossbeam_channel::unbounded::<(usize, String)>();
+        let (res_tx, res_rx) = crossbeam_channel::unbounded::<(usize, Listing)>();
+        for _ in 0..listers.sessions {
+            let (job_rx, res_tx) = (job_rx.clone(), res_tx.clone());
+            s.spawn(move || {
+                let (sess, sftp) = match pool.checkout_sftp(server) {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        tracing::debug!("[ts][enum] 列目录会话建立失败: {}", e);
+                        return;
+                    }
+                };
+                for (id, path) in job_rx {
+                    if res_tx.send((id, sftp.readdir(std::path::Path::new(&path)))).is_err() {
+                        break;
+                    }
+                }
+                // warm for the workers once the walk is over
+                drop(sftp);
+                pool.checkin(server, sess);
+            });
+        }
+        drop(res_tx);
+
+        let sftp = walker.sftp;
+        visit_in_order(
+            &mut q,
+            listers.sessions * LISTER_WINDOW,
+            |id, dir| {
+                let _ = job_tx.send((id, dir.full.clone()));
+            },
+            || res_rx.recv().ok(),
+            |dir| sftp.readdir(std::path::Path::new(&dir.full)),
+            |dir, listing| walker.visit(dir, listing),
+        );
+        drop(job_tx);
+    });
+    std::mem::take(&mut walker.out)
+}
+
+// Visit the queued directories strictly in queue order while their listings finish in
+// any order: up to `window` directories past the front are handed to `submit`, and
+// listings that arrive early wait in `ready` for their turn. Once `recv` reports that
+// no lister is left, the remaining directories are listed with `list_inline`.
+fn visit_in_order<D, L>(
+    q: &mut VecDeque<D>,
+    window: usize,
+    mut submit: impl FnMut(usize, &D),
+    mut recv: impl FnMut() -> Option<(usize, L)>,
+    mut list_inline: impl FnMut(&D) -> L,
+    mut visit: impl FnMut(&D, L) -> Vec<D>,
+) {
+    let mut ready: std::collections::HashMap<usize, L> = Default::default();
+    // `next` is the id of the queue front; ids below `submitted` were handed out
+    let (mut next, mut submitted) = (0usize, 0usize);
+    let mut inline = false;
+    loop {
+        while !inline && submitted < next + q.len() && submitted - next < window {
+            submit(submitted, &q[submitted - next]);
+            submitted += 1;
+        }
+        let Some(dir) = q.pop_front() else {
+            break;
+        };
+        let listing = loop {
+            if let Some(l) = ready.remove(&next) {
+                break l;
+            }
+            if inline {
+                break list_inline(&dir);
+            }
+            match recv() {
+                Some((id, l)) => {
+                    ready.insert(id, l);
                 }
+                None => inline = true,
             }
+        };
+        next += 1;
+        q.extend(visit(&dir, listing));
+    }
+}
+
+// BFS over a remote directory tree.
+// Entries excluded by `filter` are skipped and excluded directories are not descended.
+pub(super) fn walk_remote_dir(
+    sftp: &ssh2::Sftp,
+    root: &str,
+    filter: &PathFilter,
+    links: LinkPolicy,
+    listers: RemoteListers<'_>,
+    push: &dyn Fn(String, String, Option<u64>, EntryKind),
+) -> RemoteWalk {
+    let mut walker =
+        RemoteWalker { sftp, filter, links, glob: None, push, out: RemoteWalk::default() };
+    walk_remote_tree(&mut walker, root, listers)
+}
+
+// BFS below the glob's literal base, descending only into dirs that may still match.
+fn walk_remote_glob(
+    sftp: &ssh2::Sftp,
+    glob: &GlobPattern,
+    filter: &PathFilter,
+    links: LinkPolicy,
+    listers: RemoteListers<'_>,
+    push: &dyn Fn(String, String, Option<u64>, EntryKind),
+) -> RemoteWalk {
+    let mut walker =
+        RemoteWalker { sftp, filter, links, glob: Some(glob), push, out: RemoteWalk::default() };
+    walk_remote_tree(&mut walker, &glob.base, listers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A tree given as (dir, children) pairs; listings are the children's names.
+    fn tree() -> std::collections::HashMap<&'static str, Vec<&'static str>> {
+        [
+            ("", vec!["a", "b", "c"]),
+            ("a", vec!["a/x", "a/y"]),
+            ("b", vec![]),
+            ("c", vec!["c/z"]),
+            ("a/x", vec!["a/x/deep"]),
+            ("a/y", vec![]),
+            ("c/z", vec![]),
+            ("a/x/deep", vec![]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    // Walk `tree()` BFS through `visit_in_order`; listings come back newest job first,
+    // and after `answers` of them the listers are gone.
+    fn walk_out_of_order(window: usize, answers: usize) -> (Vec<String>, usize) {
+        let tree = tree();
+        let pending = std::cell::RefCell::new(Vec::<(usize, String)>::new());
+        let mut answered = 0;
+        let mut inline = 0;
+        let mut visited = Vec::new();
+        let mut q: VecDeque<String> = tree[""].iter().map(|d| d.to_string()).collect();
+        visit_in_order(
+            &mut q,
+            window,
+            |id, dir| pending.borrow_mut().push((id, dir.clone())),
+            || {
+                if answered == answers {
+                    return None;
+                }
+                answered += 1;
+                let (id, dir) = pending.borrow_mut().pop()?;
+                Some((id, (dir.clone(), tree[dir.as_str()].clone())))
+            },
+            |dir| {
+                inline += 1;
+                (dir.clone(), tree[dir.as_str()].clone())
+            },
+            |dir, (listed, children)| {
+                assert_eq!(dir, &listed, "listing handed to the wrong directory");
+                visited.push(dir.clone());
+                children.iter().map(|c| c.to_string()).collect()
+            },
+        );
+        (visited, inline)
+    }
+
+    #[test]
+    fn out_of_order_listings_are_visited_once_in_discovery_order() {
+        let bfs = ["a", "b", "c", "a/x", "a/y", "c/z", "a/x/deep"];
+        for window in [1, 2, 3, 16] {
+            let (visited, inline) = walk_out_of_order(window, usize::MAX);
+            assert_eq!(visited, bfs, "window {}", window);
+            assert_eq!(inline, 0);
         }
+        // listers vanish midway: the rest is listed inline, still once and in order
+        let (visited, inline) = walk_out_of_order(4, 3);
+        assert_eq!(visited, bfs);
+        assert!(inline > 0);
+    }
+
+    #[test]
+    fn local_recursive_glob_preserves_relative_paths() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_glob_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("logs/2024/01")).unwrap();
+        std::fs::create_dir_all(dir.join("logs/skip")).unwrap();
+        std::fs::write(dir.join("logs/top.gz"), b"a").unwrap();
+        std::fs::write(dir.join("logs/2024/01/app-1.gz"), b"bb").unwrap();
+        std::fs::write(dir.join("logs/2024/01/app-x.gz"), b"c").unwrap();
+        std::fs::write(dir.join("logs/skip/app-2.txt"), b"d").unwrap();
+
+        let pat = format!("{}/logs/**/{{top,app-[0-9]}}.gz", dir.display());
+        let res = enumerate_local_sources(&[pat], &PathFilter::default(), LinkPolicy::Skip)
+            .expect("enumerate");
+        let mut rels: Vec<String> = res.entries.iter().map(|e| e.rel.clone()).collect();
+        rels.sort();
+        assert_eq!(rels, vec!["2024/01/app-1.gz", "top.gz"]);
+        assert_eq!(res.total_size, 3);
+
+        let none = format!("{}/logs/**/*.zst", dir.display());
+        assert!(
+            enumerate_local_sources(&[none], &PathFilter::default(), LinkPolicy::Skip).is_err()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn existing_name_with_glob_chars_is_taken_literally() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_literal_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file[1].txt"), b"abc").unwrap();
+        std::fs::write(dir.join("file1.txt"), b"x").unwrap();
+
+        let src = format!("{}/file[1].txt", dir.display());
+        check_local_source(&src, &PathFilter::default(), LinkPolicy::Skip).expect("literal");
+        let res = enumerate_local_sources(&[src], &PathFilter::default(), LinkPolicy::Skip)
+            .expect("enumerate");
+        let rels: Vec<&str> = res.entries.iter().map(|e| e.rel.as_str()).collect();
+        assert_eq!(rels, ["file[1].txt"]);
+        assert_eq!(res.total_size, 3);
+
+        // without such a file the same syntax is still a pattern
+        let pat = format!("{}/file[0-9].txt", dir.display());
+        let res = enumerate_local_sources(&[pat], &PathFilter::default(), LinkPolicy::Skip)
+            .expect("enumerate");
+        let rels: Vec<&str> = res.entries.iter().map(|e| e.rel.as_str()).collect();
+        assert_eq!(rels, ["file1.txt"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unmatched_glob_fails_the_source_check_before_any_push() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_nomatch_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        std::fs::write(dir.join("logs/app.log"), b"l").unwrap();
+        std::fs::write(dir.join("logs/app.gz"), b"g").unwrap();
+
+        let is_no_match = |r: Result<()>| {
+            matches!(
+                r.unwrap_err().downcast_ref::<crate::TransferError>(),
+                Some(crate::TransferError::GlobNoMatches(_))
+            )
+        };
+        let none = format!("{}/logs/*.zst", dir.display());
+        assert!(is_no_match(check_local_source(&none, &PathFilter::default(), LinkPolicy::Skip)));
+        // the only match is excluded
+        let gz = format!("{}/logs/*.gz", dir.display());
+        let filter = PathFilter::from_cli(&["*.gz".into()], &[], None).unwrap();
+        assert!(is_no_match(check_local_source(&gz, &filter, LinkPolicy::Skip)));
+        check_local_source(&gz, &PathFilter::default(), LinkPolicy::Skip).expect("match");
+
+        let mut pushed = 0;
+        let res = enumerate_local_and_push(
+            &[gz, none],
+            &PathFilter::default(),
+            LinkPolicy::Skip,
+            &mut |_| pushed += 1,
+        );
+        assert!(res.is_err());
+        assert_eq!(pushed, 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn local_dir_walk_honors_hpignore_and_cli_filters() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_filter_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("node_modules/pkg")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join(".hpignore"), "node_modules/\n*.log\n").unwrap();
+        std::fs::write(dir.join("node_modules/pkg/index.js"), b"x").unwrap();
+        std::fs::write(dir.join("src/main.rs"), b"fn main() {}").unwrap();
+        std::fs::write(dir.join("src/debug.log"), b"l").unwrap();
+        std::fs::write(dir.join("src/keep.log"), b"k").unwrap();
+        std::fs::write(dir.join("src/out.bin"), b"b").unwrap();
+
+        let filter = PathFilter::from_cli(&["*.bin".into()], &["keep.log".into()], None).unwrap();
+        let src = dir.to_string_lossy().to_string();
+        let res = enumerate_local_sources(&[src], &filter, LinkPolicy::Skip).expect("enumerate");
+        let mut rels: Vec<String> =
+            res.entries.iter().map(|e| normalize_path(&e.rel, false)).collect();
+        rels.sort();
+        assert_eq!(rels, vec![".hpignore", "src", "src/keep.log", "src/main.rs"]);
+        assert!(res.unreadable.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn streamed_walk_counts_files_apart_from_directories() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_files_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::write(dir.join("a/one.txt"), b"1").unwrap();
+        std::fs::write(dir.join("a/b/two.txt"), b"22").unwrap();
+        let src = dir.to_string_lossy().to_string();
+        let mut pushed = Vec::new();
+        let walk =
+            enumerate_local_and_push(&[src], &PathFilter::default(), LinkPolicy::Skip, &mut |e| {
+                pushed.push(e)
+            })
+            .expect("enumerate");
+        assert_eq!(walk.entries, pushed.len() as u64);
+        assert_eq!(walk.files, 2);
+        assert!(walk.entries > walk.files);
+
+        // every file failed: the directories must not make the run look partial
+        let failures: Vec<crate::TransferError> = pushed
+            .iter()
+            .filter(|e| e.kind != EntryKind::Dir)
+            .map(|e| crate::TransferError::WorkerIo("x".into()).for_entry("web", &e.rel, "web:/"))
+            .collect();
+        let outcome = crate::TransferError::for_run(&failures, walk.files).unwrap();
+        assert_eq!(outcome.exit_code(), crate::error::exit_code::ALL_FAILED);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_link_policies() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_enum_links_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        std::fs::write(dir.join("real/data.txt"), b"abc").unwrap();
+        std::os::unix::fs::symlink("real/data.txt", dir.join("file-link")).unwrap();
+        std::os::unix::fs::symlink("real", dir.join("dir-link")).unwrap();
+        // points back at an ancestor: following it would never end
+        std::os::unix::fs::symlink("..", dir.join("real/up")).unwrap();
+        let src = dir.to_string_lossy().to_string();
+        let run = |links| {
+            let res =
+                enumerate_local_sources(std::slice::from_ref(&src), &PathFilter::default(), links)
+                    .expect("enumerate");
+            let mut rels: Vec<(String, EntryKind)> =
+                res.entries.iter().map(|e| (normalize_path(&e.rel, false), e.kind)).collect();
+            rels.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut skipped = res.skipped_links;
+            skipped.sort();
+            (rels, skipped)
+        };
+
+        let (rels, skipped) = run(LinkPolicy::Skip);
+        assert_eq!(rels.len(), 2);
+        assert_eq!(skipped, vec!["dir-link", "file-link", "real/up"]);
+
+        let (rels, skipped) = run(LinkPolicy::Copy);
+        assert!(skipped.is_empty());
+        assert!(rels.contains(&("dir-link".to_string(), EntryKind::Symlink)));
+        assert!(rels.contains(&("real/up".to_string(), EntryKind::Symlink)));
+
+        let (rels, skipped) = run(LinkPolicy::Follow);
+        assert!(rels.contains(&("dir-link/data.txt".to_string(), EntryKind::File)));
+        assert!(rels.contains(&("file-link".to_string(), EntryKind::File)));
+        assert_eq!(skipped.len(), 2, "{:?}", skipped);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
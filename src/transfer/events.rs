@@ -0,0 +1,319 @@
+// `hp ts --events ndjson`: one JSON object per line describing the run while it happens.
+//
+// The stream is process-wide (like the retry attempt log) so that the places which drive
+// the progress bars and the failure/metrics channels can report without extra plumbing.
+// Every line carries `event` and `ts`; per-entry events also carry `alias`, `worker`,
+// `path` and `size` of the entry the emitting worker thread is moving.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+// 同一条目两次 progress 事件的最小间隔 — minimum spacing of progress events of one entry
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+static SINK: OnceLock<EventSink> = OnceLock::new();
+
+struct EventSink {
+    out: Mutex<Box<dyn Write + Send>>,
+    stdout: bool,
+    // set after the first failed write (reader went away); later events are dropped
+    broken: AtomicBool,
+}
+
+/// Destination of the event stream (`--events-to`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventsTarget {
+    Stdout,
+    // an inherited file descriptor, e.g. `--events-to fd:3` with `3>events.ndjson`
+    Fd(u32),
+    File(PathBuf),
+}
+
+impl EventsTarget {
+    /// `-`/`stdout`, `fd:N`, or a file path (truncated).
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s {
+            "" | "-" | "stdout" => Ok(EventsTarget::Stdout),
+            _ => match s.strip_prefix("fd:") {
+                Some(n) => n.parse::<u32>().map(EventsTarget::Fd).map_err(|_| {
+                    crate::TransferError::InvalidArgument(format!("无效的文件描述符 '{}'", s))
+                        .into()
+                }),
+                None => Ok(EventsTarget::File(PathBuf::from(s))),
+            },
+        }
+    }
+
+    fn open(&self) -> Result<Box<dyn Write + Send>> {
+        match self {
+            EventsTarget::Stdout => Ok(Box::new(std::io::stdout())),
+            #[cfg(unix)]
+            EventsTarget::Fd(fd) => {
+                let path = format!("/dev/fd/{}", fd);
+                let file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("无法打开事件输出 fd:{}", fd))?;
+                Ok(Box::new(file))
+            }
+            #[cfg(not(unix))]
+            EventsTarget::Fd(_) => Err(crate::TransferError::OperationFailed(
+                "--events-to fd:N 仅支持 Unix 平台".to_string(),
+            )
+            .into()),
+            EventsTarget::File(path) => {
+                let file = std::fs::File::create(path)
+                    .with_context(|| format!("无法创建事件文件 {}", path.display()))?;
+                Ok(Box::new(std::io::LineWriter::new(file)))
+            }
+        }
+    }
+}
+
+/// Start the event stream of this process (`--events ndjson [--events-to DEST]`).
+pub fn init_events(target: Option<&str>) -> Result<()> {
+    let target = EventsTarget::parse(target.unwrap_or("-"))?;
+    let sink = EventSink {
+        out: Mutex::new(target.open()?),
+        stdout: target == EventsTarget::Stdout,
+        broken: AtomicBool::new(false),
+    };
+    let _ = SINK.set(sink);
+    Ok(())
+}
+
+pub(crate) fn enabled() -> bool {
+    SINK.get().is_some()
+}
+
+/// Events share stdout: human output and `--json` are left out so the stream stays parseable.
+pub(crate) fn on_stdout() -> bool {
+    SINK.get().is_some_and(|s| s.stdout)
+}
+
+/// Write one event line; `fields` (an object) is merged after `event` and `ts`.
+pub(crate) fn emit(event: &str, fields: serde_json::Value) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    if sink.broken.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut obj = serde_json::json!({ "event": event, "ts": chrono::Utc::now().to_rfc3339() });
+    if let (Some(map), serde_json::Value::Object(extra)) = (obj.as_object_mut(), fields) {
+        map.extend(extra);
+    }
+    let Ok(line) = serde_json::to_string(&obj) else {
+        return;
+    };
+    let Ok(mut out) = sink.out.lock() else {
+        return;
+    };
+    if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+        sink.broken.store(true, Ordering::Relaxed);
+        tracing::warn!("[ts][events] event stream closed, later events are dropped: {}", e);
+    }
+}
+
+/// The entry a worker thread is moving. Retries, session rebuilds and failures reported
+/// further down the call stack are attributed to it; chunk range threads adopt it.
+pub(crate) struct EntryState {
+    alias: String,
+    worker: usize,
+    path: String,
+    size: u64,
+    done: AtomicU64,
+    failed: AtomicBool,
+    started: Instant,
+    last_progress: Mutex<Instant>,
+}
+
+impl EntryState {
+    fn fields(&self) -> serde_json::Value {
+        serde_json::json!({
+            "alias": self.alias,
+            "worker": self.worker,
+            "path": self.path,
+            "size": self.size,
+        })
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<EntryState>>> = const { RefCell::new(None) };
+}
+
+fn with_current<T>(f: impl FnOnce(Option<&Arc<EntryState>>) -> T) -> T {
+    CURRENT.with(|c| f(c.borrow().as_ref()))
+}
+
+// entry fields of this thread (if any) merged with `extra`
+fn entry_event(event: &str, extra: serde_json::Value) {
+    let mut fields =
+        with_current(|e| e.map(|e| e.fields())).unwrap_or_else(|| serde_json::json!({}));
+    if let (Some(map), serde_json::Value::Object(extra)) = (fields.as_object_mut(), extra) {
+        map.extend(extra);
+    }
+    emit(event, fields);
+}
+
+/// A file or link was handed to the workers.
+pub(crate) fn queued(alias: Option<&str>, path: &str, size: Option<u64>) {
+    if enabled() {
+        emit("queued", serde_json::json!({ "alias": alias, "path": path, "size": size }));
+    }
+}
+
+/// The worker starts moving `path`; a retry of the same entry only restarts its byte count.
+pub(crate) fn started(alias: &str, worker: usize, path: &str, size: u64) {
+    if !enabled() {
+        return;
+    }
+    let again = with_current(|e| {
+        e.filter(|e| e.path == path && e.alias == alias)
+            .map(|e| e.done.store(0, Ordering::Relaxed))
+            .is_some()
+    });
+    if again {
+        return;
+    }
+    let now = Instant::now();
+    let entry = Arc::new(EntryState {
+        alias: alias.to_string(),
+        worker,
+        path: path.to_string(),
+        size,
+        done: AtomicU64::new(0),
+        failed: AtomicBool::new(false),
+        started: now,
+        last_progress: Mutex::new(now),
+    });
+    emit("started", entry.fields());
+    CURRENT.with(|c| *c.borrow_mut() = Some(entry));
+}
+
+/// Bytes of the current entry counted by a throttler flush.
+pub(crate) fn progress(n: u64) {
+    let Some(entry) = current() else {
+        return;
+    };
+    let done = entry.done.fetch_add(n, Ordering::Relaxed) + n;
+    let due = match entry.last_progress.lock() {
+        Ok(mut last) if last.elapsed() >= PROGRESS_INTERVAL => {
+            *last = Instant::now();
+            true
+        }
+        _ => false,
+    };
+    if due {
+        let mut fields = entry.fields();
+        fields["bytes"] = serde_json::json!(done);
+        emit("progress", fields);
+    }
+}
+
+/// A failed chunk attempt takes its bytes back.
+pub(crate) fn rewind(n: u64) {
+    if let Some(entry) = current() {
+        let _ = entry
+            .done
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| Some(d.saturating_sub(n)));
+    }
+}
+
+/// The entry's progress bar is released: `completed` unless a failure was reported for it.
+pub(crate) fn finished() {
+    let Some(entry) = CURRENT.with(|c| c.borrow_mut().take()) else {
+        return;
+    };
+    if !entry.failed.load(Ordering::Relaxed) {
+        let mut fields = entry.fields();
+        fields["bytes"] = serde_json::json!(entry.done.load(Ordering::Relaxed));
+        fields["elapsed_ms"] = serde_json::json!(entry.started.elapsed().as_millis() as u64);
+        emit("completed", fields);
+    }
+}
+
+/// A failure record was sent to the run's failure channel.
+pub(crate) fn failed(err: &crate::TransferError) {
+    if !enabled() {
+        return;
+    }
+    with_current(|e| {
+        if let Some(e) = e {
+            e.failed.store(true, Ordering::Relaxed);
+        }
+    });
+    entry_event("failed", serde_json::json!({ "error": crate::util::transfer_error_to_json(err) }));
+}
+
+/// A failed attempt is retried after `backoff_ms`.
+pub(crate) fn retrying(
+    phase: &str,
+    attempt: usize,
+    max_retries: usize,
+    backoff_ms: u64,
+    err: &str,
+) {
+    if enabled() {
+        entry_event(
+            "retrying",
+            serde_json::json!({
+                "phase": phase,
+                "attempt": attempt,
+                "max_retries": max_retries,
+                "backoff_ms": backoff_ms,
+                "error": err,
+            }),
+        );
+    }
+}
+
+/// A worker opened a new SSH session and/or SFTP channel.
+pub(crate) fn session_rebuild(alias: Option<&str>, session: bool, sftp: bool) {
+    if enabled() {
+        let mut extra = serde_json::json!({ "session": session, "sftp": sftp });
+        if with_current(|e| e.is_none()) {
+            extra["alias"] = serde_json::json!(alias);
+        }
+        entry_event("session_rebuild", extra);
+    }
+}
+
+/// The entry of this thread, handed to helper threads with `adopt`.
+pub(crate) fn current() -> Option<Arc<EntryState>> {
+    if !enabled() {
+        return None;
+    }
+    with_current(|e| e.cloned())
+}
+
+pub(crate) fn adopt(entry: Option<Arc<EntryState>>) {
+    if entry.is_some() {
+        CURRENT.with(|c| *c.borrow_mut() = entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_target_parses_stdout_fd_and_path() {
+        assert_eq!(EventsTarget::parse("-").unwrap(), EventsTarget::Stdout);
+        assert_eq!(EventsTarget::parse("stdout").unwrap(), EventsTarget::Stdout);
+        assert_eq!(EventsTarget::parse("fd:3").unwrap(), EventsTarget::Fd(3));
+        assert!(EventsTarget::parse("fd:x").is_err());
+        assert_eq!(
+            EventsTarget::parse("run.ndjson").unwrap(),
+            EventsTarget::File(PathBuf::from("run.ndjson"))
+        );
+    }
+}
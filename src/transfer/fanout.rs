@@ -0,0 +1,440 @@
+// Tag fan-out uploads (`hp ts ./dist @web:/srv/app/`): the local sources are enumerated
+// once and uploaded to every alias carrying the tag, a few hosts at a time. Each host
+// runs its own worker pool; the pools share the global worker ceiling.
+use super::ratelimit::{self, RateLimiter};
+use super::{HistoryTarget, SharedProgress, UploadRun, resolve_remote_endpoint, upload_to_host};
+use crate::config::Config;
+use crate::server::ServerCollection;
+use anyhow::{Context, Result};
+use cli_table::{Cell, CellStruct, Style, Table, format::Justify, print_stdout};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const DEFAULT_PARALLEL_HOSTS: usize = 4;
+
+pub(super) struct FanoutOptions {
+    pub(super) tag: String,
+    pub(super) aliases: Vec<String>,
+    // as given on the command line; `~` is expanded per host
+    pub(super) remote_path: String,
+    pub(super) parallel_hosts: Option<usize>,
+    // `--fail-fast`: hosts not yet started are skipped after the first failure
+    pub(super) fail_fast: bool,
+}
+
+/// Aliases carrying `tag`; an unknown or empty tag is reported like a missing alias.
+pub(super) fn resolve_tag(config: &Config, tag: &str) -> Result<Vec<String>> {
+    let collection = ServerCollection::read_from_storage(&config.server_file_path)?;
+    let members = collection.members_of(tag);
+    if members.is_empty() {
+        return Err(crate::TransferError::AliasNotFound(format!("@{}", tag)).into());
+    }
+    Ok(members)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HostStatus {
+    Ok,
+    // the transfer ran but some entries failed
+    Failed,
+    // the host could not be prepared (alias, connect, target checks)
+    Error,
+    // not started because of `--fail-fast`
+    Skipped,
+    // `--dry-run`: the plan was printed
+    Planned,
+}
+
+impl HostStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            HostStatus::Ok => "ok",
+            HostStatus::Failed => "failed",
+            HostStatus::Error => "error",
+            HostStatus::Skipped => "skipped",
+            HostStatus::Planned => "dry-run",
+        }
+    }
+
+    fn is_failure(self) -> bool {
+        matches!(self, HostStatus::Failed | HostStatus::Error)
+    }
+}
+
+struct HostResult {
+    alias: String,
+    status: HostStatus,
+    files: u64,
+    bytes: u64,
+    failures: Vec<crate::TransferError>,
+    elapsed: f64,
+    // preparation error, or the first entry failure
+    error: Option<String>,
+    // (skipped existing, backed up) under --no-clobber/--update/--backup
+    overwrite: Option<(usize, usize)>,
+    deleted: Option<usize>,
+}
+
+impl HostResult {
+    fn new(alias: &str, status: HostStatus) -> Self {
+        HostResult {
+            alias: alias.to_string(),
+            status,
+            files: 0,
+            bytes: 0,
+            failures: Vec::new(),
+            elapsed: 0.0,
+            error: None,
+            overwrite: None,
+            deleted: None,
+        }
+    }
+
+    // A host whose transfer ran; `files` counts only the files that were written.
+    fn finished(
+        alias: &str,
+        found: u64,
+        skipped: u64,
+        bytes: u64,
+        failures: Vec<crate::TransferError>,
+    ) -> Self {
+        let status = if failures.is_empty() { HostStatus::Ok } else { HostStatus::Failed };
+        let mut res = HostResult::new(alias, status);
+        let failed = crate::TransferError::failed_files(&failures);
+        res.files = found.saturating_sub(failed + skipped);
+        res.bytes = bytes;
+        res.error = failures.first().map(|e| e.to_string());
+        res.failures = failures;
+        res
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut obj = serde_json::json!({
+            "alias": self.alias,
+            "status": self.status.as_str(),
+            "files": self.files,
+            "bytes": self.bytes,
+            "failures": self.failures.len(),
+            "elapsed_secs": self.elapsed,
+            "error": self.error,
+        });
+        if let Some((skipped, backed_up)) = self.overwrite {
+            obj["skipped_existing"] = serde_json::json!(skipped);
+            obj["backed_up"] = serde_json::json!(backed_up);
+        }
+        if let Some(deleted) = self.deleted {
+            obj["deleted"] = serde_json::json!(deleted);
+        }
+        obj
+    }
+}
+
+/// Host concurrency and per-host worker ceiling so that hosts × workers stays within
+/// the global limit.
+fn plan_parallelism(requested: Option<usize>, hosts: usize, max_workers: usize) -> (usize, usize) {
+    let ceiling = hosts.min(max_workers).max(1);
+    let parallel = requested.unwrap_or(DEFAULT_PARALLEL_HOSTS).clamp(1, ceiling);
+    (parallel, (max_workers / parallel).max(1))
+}
+
+// Rate limiter per host: `--limit-rate` is one bucket for the whole fan-out, alias
+// defaults stay per host. Aliases missing from server.db get none; they fail on connect.
+fn host_limiters(
+    limit_rate: Option<&str>,
+    servers: &[Option<crate::server::Server>],
+) -> Result<Vec<Option<Arc<RateLimiter>>>> {
+    let known: Vec<&crate::server::Server> = servers.iter().flatten().collect();
+    let mut limiters = ratelimit::limiters_for(limit_rate, &known)?.into_iter();
+    Ok(servers.iter().map(|s| s.as_ref().and_then(|_| limiters.next().flatten())).collect())
+}
+
+fn upload_one(
+    config: &Config,
+    run: &UploadRun,
+    alias: &str,
+    remote_path: &str,
+    limiter: Option<Arc<RateLimiter>>,
+    mp: &Arc<indicatif::MultiProgress>,
+    style: &indicatif::ProgressStyle,
+) -> HostResult {
+    let started = Instant::now();
+    let outcome = resolve_remote_endpoint(config, run.pool, alias, remote_path).and_then(
+        |(server, addr, base)| {
+            let shared = SharedProgress { mp, style, alias };
+            upload_to_host(run, &server, &addr, &base, limiter, Some(shared))
+        },
+    );
+    let mut res = match outcome {
+        Ok(Some(out)) => {
+            out.total_pb.finish_and_clear();
+            let overwrite = run.overwrite.is_active().then(|| out.overwrite.counts());
+            let skipped = overwrite.map_or(0, |(skipped, _)| skipped as u64);
+            let mut res =
+                HostResult::finished(alias, out.files, skipped, out.metrics.bytes, out.failures);
+            res.overwrite = overwrite;
+            res.deleted = out.mirror.as_ref().map(|m| m.deleted);
+            super::remember_tuning(&run.history.db, Some(alias), out.tuning.as_ref());
+            res
+        }
+        Ok(None) => HostResult::new(alias, HostStatus::Planned),
+        Err(e) => {
+            let mut res = HostResult::new(alias, HostStatus::Error);
+            res.error = Some(format!("{:#}", e));
+            res
+        }
+    };
+    res.elapsed = started.elapsed().as_secs_f64();
+    res
+}
+
+/// Upload the enumerated sources to every host of the tag, then print the per-host
+/// matrix. Returns an error when at least one host failed.
+pub(super) fn fanout_upload(config: &Config, run: &UploadRun, opts: FanoutOptions) -> Result<()> {
+    let FanoutOptions { tag, aliases, remote_path, parallel_hosts, fail_fast } = opts;
+    // dry-run plans are printed one host after another
+    let requested = if run.dry_run { Some(1) } else { parallel_hosts };
+    let (parallel, host_workers) = plan_parallelism(requested, aliases.len(), run.max_workers);
+    let host_run = UploadRun { max_workers: host_workers, ..*run };
+    let collection = ServerCollection::read_from_storage(&config.server_file_path)?;
+    let servers: Vec<_> = aliases.iter().map(|a| collection.get(a).cloned()).collect();
+    let limiters = host_limiters(run.limit_rate, &servers)?;
+    let style = indicatif::ProgressStyle::with_template(
+        "{prefix:>12} {spinner:.green} [{elapsed_precise}] [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+    )
+    .with_context(|| "无效的进度条模板")?
+    .progress_chars("=> ");
+    let mp = Arc::new(if run.verbose {
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::stdout())
+    } else {
+        indicatif::MultiProgress::new()
+    });
+    tracing::debug!(
+        "[ts][fanout] @{} hosts={} parallel={} workers/host={}",
+        tag,
+        aliases.len(),
+        parallel,
+        host_workers
+    );
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let slots: Mutex<Vec<Option<HostResult>>> = Mutex::new(aliases.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..parallel {
+            scope.spawn(|| {
+                loop {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(alias) = aliases.get(i) else {
+                        break;
+                    };
+                    let limiter = limiters[i].clone();
+                    let res =
+                        upload_one(config, &host_run, alias, &remote_path, limiter, &mp, &style);
+                    if fail_fast && res.status.is_failure() {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                    if let Ok(mut slots) = slots.lock() {
+                        slots[i] = Some(res);
+                    }
+                }
+            });
+        }
+    });
+    let _ = mp.clear();
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let results: Vec<HostResult> = slots
+        .into_inner()
+        .unwrap_or_default()
+        .into_iter()
+        .zip(aliases.iter())
+        .map(|(r, alias)| r.unwrap_or_else(|| HostResult::new(alias, HostStatus::Skipped)))
+        .collect();
+    let (failures, failed_hosts, outcome) = aggregate(&tag, &results);
+
+    for r in results.iter().filter(|r| !matches!(r.status, HostStatus::Skipped)) {
+        let failures = r.failures.len().max(usize::from(r.status.is_failure()));
+        HistoryTarget { target: format!("{}:{}", r.alias, remote_path), ..run.history.clone() }
+            .record(crate::history::TransferRecord {
+                direction: "upload".to_string(),
+                alias: Some(r.alias.clone()),
+                bytes: r.bytes,
+                files: r.files,
+                elapsed_secs: r.elapsed,
+                failures: failures as u64,
+                job_id: run.journal.map(|j| j.id()),
+                ..Default::default()
+            });
+    }
+    if !run.quiet {
+        print_matrix(&tag, &results, elapsed);
+    }
+    let mut failures_path: Option<std::path::PathBuf> = None;
+    if !failures.is_empty() {
+        // one batch per host so that records without a file still name their alias
+        for r in results.iter().filter(|r| !r.failures.is_empty()) {
+            let record = crate::util::FailureRun {
+                run_id: run.journal.map(|j| j.id()),
+                direction: Some("upload".to_string()),
+                alias: Some(r.alias.clone()),
+            };
+            let written = crate::util::write_failures_jsonl(None, &record, &r.failures);
+            failures_path = failures_path.or(written);
+        }
+        if !run.quiet
+            && let Some(ref p) = failures_path
+        {
+            println!("失败清单已写入: {}", p.display());
+        }
+    }
+    if run.json || super::events::enabled() {
+        let obj = serde_json::json!({
+            "fanout": tag,
+            "elapsed_secs": elapsed,
+            "hosts": results.iter().map(HostResult::to_json).collect::<Vec<_>>(),
+            "failed_hosts": failed_hosts,
+            "failures": failures.len(),
+            "failures_path": failures_path.as_ref().map(|p| p.to_string_lossy().to_string()),
+            "skipped_links": run.skipped_links,
+        });
+        if run.json
+            && let Ok(line) = serde_json::to_string(&obj)
+        {
+            println!("{}", line);
+        }
+        super::events::emit("summary", obj);
+    }
+    match outcome {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// Entry failures of every host, the number of failed hosts and the error of the whole
+// run when at least one host failed.
+fn aggregate(
+    tag: &str,
+    results: &[HostResult],
+) -> (Vec<crate::TransferError>, usize, Option<crate::TransferError>) {
+    let failures: Vec<crate::TransferError> =
+        results.iter().flat_map(|r| r.failures.iter().cloned()).collect();
+    let failed_hosts = results.iter().filter(|r| r.status.is_failure()).count();
+    if failed_hosts == 0 {
+        return (failures, 0, None);
+    }
+    let msg = format!("标签 @{} 中 {}/{} 台主机上传失败", tag, failed_hosts, results.len());
+    // hosts that ran a transfer (even with failed entries) count as partial success
+    let ran = results.iter().any(|r| matches!(r.status, HostStatus::Ok | HostStatus::Failed));
+    let err = if ran {
+        crate::TransferError::PartialFailure(msg)
+    } else {
+        crate::TransferError::AllFailed(msg)
+    };
+    (failures, failed_hosts, Some(err))
+}
+
+// Per-host success/failure matrix.
+fn print_matrix(tag: &str, results: &[HostResult], elapsed: f64) {
+    println!("标签 @{} 分发结果（{} 台主机，用时 {:.1}s）:", tag, results.len(), elapsed);
+    let title = vec![
+        "Host".cell().bold(true),
+        "Status".cell().bold(true),
+        "Files".cell().bold(true),
+        "Bytes".cell().bold(true),
+        "Failures".cell().bold(true),
+        "Elapsed".cell().bold(true),
+        "Error".cell().bold(true),
+    ];
+    let rows: Vec<Vec<CellStruct>> = results
+        .iter()
+        .map(|r| {
+            vec![
+                r.alias.clone().cell(),
+                r.status.as_str().cell(),
+                r.files.cell().justify(Justify::Right),
+                crate::util::human_bytes(r.bytes).cell().justify(Justify::Right),
+                r.failures.len().cell().justify(Justify::Right),
+                format!("{:.1}s", r.elapsed).cell().justify(Justify::Right),
+                r.error.clone().unwrap_or_default().cell(),
+            ]
+        })
+        .collect();
+    if let Err(e) = print_stdout(rows.table().title(title)) {
+        eprintln!("⚠️ 无法渲染表格: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallelism_respects_global_worker_ceiling() {
+        assert_eq!(plan_parallelism(None, 10, 32), (4, 8));
+        assert_eq!(plan_parallelism(None, 2, 32), (2, 16));
+        assert_eq!(plan_parallelism(Some(64), 40, 32), (32, 1));
+        assert_eq!(plan_parallelism(Some(0), 3, 32), (1, 32));
+    }
+
+    #[test]
+    fn limit_rate_is_one_bucket_across_hosts() {
+        let server = |alias: &str, limit: Option<&str>| crate::server::Server {
+            id: None,
+            alias: Some(alias.into()),
+            username: "ops".into(),
+            address: "10.0.0.1".into(),
+            port: 22,
+            last_connect: None,
+            limit_rate: limit.map(str::to_string),
+            tags: vec!["web".into()],
+            max_handshakes: None,
+        };
+        let servers = vec![Some(server("web1", Some("1M"))), None, Some(server("web3", None))];
+        let shared = host_limiters(Some("10M"), &servers).unwrap();
+        assert!(shared[1].is_none());
+        assert!(Arc::ptr_eq(shared[0].as_ref().unwrap(), shared[2].as_ref().unwrap()));
+
+        // without --limit-rate each alias keeps its own default
+        let per_host = host_limiters(None, &servers).unwrap();
+        assert!(per_host[0].is_some());
+        assert!(per_host[1].is_none() && per_host[2].is_none());
+    }
+
+    fn entry_failure(alias: &str, rel: &str) -> crate::TransferError {
+        crate::TransferError::WorkerIo("disk full".into()).for_entry(alias, rel, "/srv/app/")
+    }
+
+    #[test]
+    fn host_results_count_written_files_and_aggregate_failures() {
+        // 5 files found: 1 failed, 1 skipped by --no-clobber
+        let web1 = HostResult::finished("web1", 5, 1, 300, vec![entry_failure("web1", "a")]);
+        assert_eq!((web1.status, web1.files), (HostStatus::Failed, 3));
+        assert!(web1.error.is_some());
+        let web2 = HostResult::finished("web2", 5, 0, 500, Vec::new());
+        assert_eq!((web2.status, web2.files), (HostStatus::Ok, 5));
+        let mut web3 = HostResult::new("web3", HostStatus::Error);
+        web3.error = Some("connection refused".into());
+        let web4 = HostResult::new("web4", HostStatus::Skipped);
+
+        let (failures, failed_hosts, outcome) = aggregate("web", &[web1, web2, web3, web4]);
+        assert_eq!((failures.len(), failed_hosts), (1, 2));
+        let outcome = outcome.expect("failed hosts fail the run");
+        assert_eq!(outcome.exit_code(), crate::error::exit_code::PARTIAL);
+        assert!(outcome.to_string().contains("2/4"));
+
+        // no host got to transfer anything
+        let down = HostResult::new("web1", HostStatus::Error);
+        let (_, _, outcome) = aggregate("web", &[down, HostResult::new("web2", HostStatus::Error)]);
+        assert_eq!(outcome.unwrap().exit_code(), crate::error::exit_code::ALL_FAILED);
+
+        let (_, failed_hosts, outcome) =
+            aggregate("web", &[HostResult::finished("web1", 2, 0, 10, Vec::new())]);
+        assert_eq!(failed_hosts, 0);
+        assert!(outcome.is_none());
+    }
+}
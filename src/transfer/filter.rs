@@ -0,0 +1,167 @@
+// Include/exclude filtering for transfers: `--exclude`, `--include`, `--exclude-from`
+// and a `.hpignore` file at the root of each source directory (gitignore syntax).
+//
+// Rules are evaluated last-match-wins. Precedence (lowest first): `.hpignore`,
+// `--exclude-from`, `--exclude`, `--include`. Excluded directories are pruned and
+// never walked, so (as with gitignore) a path cannot be re-included below them.
+use anyhow::{Context, Result};
+
+use super::helpers::path_glob_match;
+
+/// Name of the per-source ignore file honored automatically.
+pub(super) const IGNORE_FILE: &str = ".hpignore";
+
+#[derive(Clone, Debug)]
+struct Rule {
+    // pattern matched against the full relative path (unanchored rules get a `**/` prefix)
+    pattern: String,
+    dir_only: bool,
+    // `!pattern` / `--include`: re-include instead of exclude
+    negated: bool,
+}
+
+impl Rule {
+    // Parse one gitignore-style line; None for blanks and comments.
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, body) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let body = body.replace('\\', "/");
+        let dir_only = body.ends_with('/');
+        let body = body.trim_end_matches('/');
+        if body.is_empty() {
+            return None;
+        }
+        // a slash at the start or in the middle anchors the pattern to the root
+        let anchored = body.contains('/');
+        let body = body.trim_start_matches('/');
+        let pattern = if anchored { body.to_string() } else { format!("**/{}", body) };
+        Some(Rule { pattern, dir_only, negated })
+    }
+
+    fn matches(&self, rel: &str, is_dir: bool) -> bool {
+        (!self.dir_only || is_dir) && path_glob_match(&self.pattern, rel)
+    }
+}
+
+/// Compiled filter rules for one source root.
+#[derive(Clone, Debug, Default)]
+pub(super) struct PathFilter {
+    rules: Vec<Rule>,
+    // file name suffixes always treated as excluded (backups kept by `--delete`)
+    protected_suffixes: Vec<String>,
+}
+
+impl PathFilter {
+    /// Build the CLI-level filter shared by every source.
+    pub(super) fn from_cli(
+        excludes: &[String],
+        includes: &[String],
+        exclude_from: Option<&std::path::Path>,
+    ) -> Result<Self> {
+        let mut rules = Vec::new();
+        if let Some(path) = exclude_from {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("读取排除规则文件失败: {}", path.display()))?;
+            rules.extend(text.lines().filter_map(Rule::parse));
+        }
+        rules.extend(excludes.iter().filter_map(|p| Rule::parse(p)));
+        for p in includes {
+            if let Some(mut r) = Rule::parse(p) {
+                r.negated = true;
+                rules.push(r);
+            }
+        }
+        Ok(PathFilter { rules, protected_suffixes: Vec::new() })
+    }
+
+    /// Copy of this filter with ignore-file rules prepended (lowest precedence).
+    pub(super) fn with_ignore_text(&self, text: &str) -> Self {
+        let mut rules: Vec<Rule> = text.lines().filter_map(Rule::parse).collect();
+        rules.extend(self.rules.iter().cloned());
+        PathFilter { rules, protected_suffixes: self.protected_suffixes.clone() }
+    }
+
+    /// Treat files ending in `suffix` as excluded regardless of the rules.
+    pub(super) fn protect_suffix(&mut self, suffix: &str) {
+        self.protected_suffixes.push(suffix.to_string());
+    }
+
+    /// Add the `.hpignore` found directly under a local root, if any.
+    pub(super) fn for_local_root(&self, root: &std::path::Path) -> Self {
+        match std::fs::read_to_string(root.join(IGNORE_FILE)) {
+            Ok(text) => self.with_ignore_text(&text),
+            Err(_) => self.clone(),
+        }
+    }
+
+    /// Add the `.hpignore` found directly under a remote root, if any.
+    pub(super) fn for_remote_root(&self, sftp: &ssh2::Sftp, root: &str) -> Self {
+        use std::io::Read as _;
+        let path = format!("{}/{}", root.trim_end_matches('/'), IGNORE_FILE);
+        let mut text = String::new();
+        let read = sftp
+            .open(std::path::Path::new(&path))
+            .map(|mut f| f.read_to_string(&mut text).is_ok())
+            .unwrap_or(false);
+        if read { self.with_ignore_text(&text) } else { self.clone() }
+    }
+
+    /// Is `rel` (relative to the source root, `/`-separated) excluded?
+    /// Callers prune excluded directories, so ancestors are not re-checked here.
+    pub(super) fn is_excluded(&self, rel: &str, is_dir: bool) -> bool {
+        if !is_dir && self.protected_suffixes.iter().any(|s| rel.ends_with(s.as_str())) {
+            return true;
+        }
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.matches(rel, is_dir))
+            .map(|r| !r.negated)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_style_rules() {
+        let f = PathFilter::default().with_ignore_text(
+            "# deps\nnode_modules/\n/target\n*.log\n!keep.log\ndocs/**/*.tmp\n\n",
+        );
+        assert!(f.is_excluded("node_modules", true));
+        assert!(f.is_excluded("web/node_modules", true));
+        // dir-only rule does not match a file of the same name
+        assert!(!f.is_excluded("node_modules", false));
+        assert!(f.is_excluded("target", true));
+        // anchored: only at the root
+        assert!(!f.is_excluded("crates/target", true));
+        assert!(f.is_excluded("a/b/c.log", false));
+        assert!(!f.is_excluded("a/keep.log", false));
+        assert!(f.is_excluded("docs/x/y/z.tmp", false));
+        assert!(!f.is_excluded("src/main.rs", false));
+    }
+
+    #[test]
+    fn cli_precedence_over_ignore_file() {
+        let cli = PathFilter::from_cli(
+            &["*.bin".to_string(), ".git".to_string()],
+            &["important.bin".to_string()],
+            None,
+        )
+        .unwrap();
+        let f = cli.with_ignore_text("!*.bin\ndist/\n");
+        assert!(f.is_excluded("out/a.bin", false));
+        assert!(!f.is_excluded("out/important.bin", false));
+        assert!(f.is_excluded(".git", true));
+        assert!(f.is_excluded("dist", true));
+    }
+}
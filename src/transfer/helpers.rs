@@ -1,27 +1,189 @@
-// Simple glob-style matcher supporting '*' and '?'. Not full-featured but
-// sufficient for our use (matching file/directory names).
+// Glob-style matcher for a single path segment (file/directory name).
+// Supports '*', '?', character classes ('[0-9]', '[!a-z]') and braces ('{a,b}').
 pub fn wildcard_match(pat: &str, text: &str) -> bool {
-    let p: Vec<char> = pat.chars().collect();
     let t: Vec<char> = text.chars().collect();
-    fn helper(p: &[char], t: &[char]) -> bool {
-        if p.is_empty() {
-            return t.is_empty();
+    expand_braces(pat).iter().any(|alt| {
+        let p: Vec<char> = alt.chars().collect();
+        segment_match(&p, &t)
+    })
+}
+
+fn segment_match(p: &[char], t: &[char]) -> bool {
+    if p.is_empty() {
+        return t.is_empty();
+    }
+    match p[0] {
+        // Try to match '*' with any number of chars
+        '*' => segment_match(&p[1..], t) || (!t.is_empty() && segment_match(p, &t[1..])),
+        '?' => !t.is_empty() && segment_match(&p[1..], &t[1..]),
+        '[' => match class_match(p, t.first().copied()) {
+            Some((true, used)) => segment_match(&p[used..], &t[1..]),
+            Some((false, _)) => false,
+            // unterminated class: treat '[' literally
+            None => !t.is_empty() && t[0] == '[' && segment_match(&p[1..], &t[1..]),
+        },
+        c => !t.is_empty() && c == t[0] && segment_match(&p[1..], &t[1..]),
+    }
+}
+
+// Evaluate a bracket expression at the start of `p` against `c`.
+// Returns (matched, chars consumed from p), or None when the class is unterminated.
+fn class_match(p: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(p.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut hit = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            let matched = c.is_some() && hit != negate;
+            return Some((matched, i + 1));
         }
-        if p[0] == '*' {
-            // Try to match '*' with any number of chars
-            if helper(&p[1..], t) {
-                return true;
+        first = false;
+        if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            if let Some(c) = c
+                && p[i] <= c
+                && c <= p[i + 2]
+            {
+                hit = true;
             }
-            if !t.is_empty() && helper(p, &t[1..]) {
-                return true;
+            i += 3;
+        } else {
+            if c == Some(p[i]) {
+                hit = true;
             }
-            return false;
-        } else if !t.is_empty() && (p[0] == '?' || p[0] == t[0]) {
-            return helper(&p[1..], &t[1..]);
+            i += 1;
         }
-        false
     }
-    helper(&p, &t)
+    None
+}
+
+/// Expand `{a,b}` alternatives (nested braces allowed). Unbalanced braces are kept literally.
+pub(crate) fn expand_braces(pat: &str) -> Vec<String> {
+    let chars: Vec<char> = pat.chars().collect();
+    let Some(open) = chars.iter().position(|&c| c == '{') else {
+        return vec![pat.to_string()];
+    };
+    let mut depth = 0usize;
+    let mut close = None;
+    let mut commas = Vec::new();
+    for (i, &c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    let Some(close) = close else {
+        return vec![pat.to_string()];
+    };
+    let prefix: String = chars[..open].iter().collect();
+    let suffix: String = chars[close + 1..].iter().collect();
+    let mut bounds = vec![open];
+    bounds.extend(commas);
+    bounds.push(close);
+    let mut out = Vec::new();
+    for w in bounds.windows(2) {
+        let alt: String = chars[w[0] + 1..w[1]].iter().collect();
+        out.extend(expand_braces(&format!("{}{}{}", prefix, alt, suffix)));
+    }
+    out
+}
+
+/// Whether a path-like string contains glob syntax.
+pub(crate) fn has_glob_chars(s: &str) -> bool {
+    s.contains(['*', '?', '[', '{'])
+}
+
+/// Whether a local source exists under its literal name, e.g. a file called `file[1].txt`.
+pub(crate) fn local_literal(s: &str) -> bool {
+    std::fs::symlink_metadata(normalize_path(s, false)).is_ok()
+}
+
+/// A local source is a pattern when it has glob syntax and is not an existing path.
+pub(crate) fn is_local_glob(s: &str) -> bool {
+    has_glob_chars(s) && !local_literal(s)
+}
+
+/// Remote counterpart of `is_local_glob`.
+pub(crate) fn is_remote_glob(sftp: &ssh2::Sftp, s: &str) -> bool {
+    has_glob_chars(s) && sftp.lstat(std::path::Path::new(s)).is_err()
+}
+
+/// A (possibly multi-segment / recursive) source pattern split into a literal base
+/// directory and a relative pattern matched against paths below that base.
+///
+/// Examples: `logs/**/*.gz` -> base `logs`, pattern `**/*.gz`;
+/// `releases/v1.*/bin/*` -> base `releases`, pattern `v1.*/bin/*`.
+pub(crate) struct GlobPattern {
+    pub(crate) base: String,
+    // brace-expanded alternatives, each split into segments
+    alts: Vec<Vec<String>>,
+}
+
+impl GlobPattern {
+    pub(crate) fn parse(pattern: &str) -> Self {
+        let norm = normalize_path(pattern, false);
+        let segs: Vec<&str> = norm.split('/').collect();
+        let split = segs.iter().position(|s| has_glob_chars(s)).unwrap_or(segs.len());
+        let base = match segs[..split].join("/") {
+            b if b.is_empty() && norm.starts_with('/') => "/".to_string(),
+            b if b.is_empty() => ".".to_string(),
+            b => b,
+        };
+        let rest = segs[split..].join("/");
+        let alts = expand_braces(&rest)
+            .into_iter()
+            .map(|a| a.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect())
+            .collect();
+        GlobPattern { base, alts }
+    }
+
+    /// Does the relative path (below `base`) match the pattern?
+    pub(crate) fn matches(&self, rel: &str) -> bool {
+        let t: Vec<&str> = rel.split('/').filter(|s| !s.is_empty()).collect();
+        self.alts.iter().any(|p| segs_match(p, &t, false))
+    }
+
+    /// Could something strictly below the relative directory `rel` match? Used to prune walks.
+    pub(crate) fn may_match_below(&self, rel: &str) -> bool {
+        let t: Vec<&str> = rel.split('/').filter(|s| !s.is_empty()).collect();
+        self.alts.iter().any(|p| segs_match(p, &t, true))
+    }
+}
+
+/// Match a slash-separated relative path against a pattern whose segments may use any
+/// wildcard syntax, including whole-segment `**`.
+pub(crate) fn path_glob_match(pattern: &str, rel: &str) -> bool {
+    let t: Vec<&str> = rel.split('/').filter(|s| !s.is_empty()).collect();
+    expand_braces(pattern).iter().any(|alt| {
+        let p: Vec<String> = alt.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        segs_match(&p, &t, false)
+    })
+}
+
+// Segment-wise match where a whole-segment `**` matches zero or more segments.
+// With `partial`, succeed when `t` is exhausted but pattern segments remain.
+fn segs_match(p: &[String], t: &[&str], partial: bool) -> bool {
+    if t.is_empty() {
+        return if partial { !p.is_empty() } else { p.iter().all(|s| s == "**") };
+    }
+    let Some(head) = p.first() else {
+        return false;
+    };
+    if head == "**" {
+        return segs_match(&p[1..], t, partial) || segs_match(p, &t[1..], partial);
+    }
+    wildcard_match(head, t[0]) && segs_match(&p[1..], &t[1..], partial)
 }
 
 fn is_windows_drive(s: &str) -> bool {
@@ -33,6 +195,11 @@ fn is_windows_drive(s: &str) -> bool {
     false
 }
 
+/// Single-quote `s` for a POSIX shell (`it's` -> `'it'\''s'`).
+pub(crate) fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
 pub fn is_remote_spec(s: &str) -> bool {
     if is_windows_drive(s) {
         return false;
@@ -47,20 +214,30 @@ pub fn is_remote_spec(s: &str) -> bool {
     false
 }
 
+/// Reject malformed glob syntax: `**` mixed with other characters in a segment,
+/// or unbalanced `[`/`{`.
 pub fn is_disallowed_glob(s: &str) -> bool {
-    if s.contains("**") {
+    let norm = normalize_path(s, false);
+    if norm.split('/').any(|seg| seg.contains("**") && seg != "**") {
         return true;
     }
-    let parts: Vec<&str> = s.split('/').collect();
-    if parts.len() <= 1 {
-        return false;
-    }
-    for seg in &parts[..parts.len() - 1] {
-        if seg.contains('*') || seg.contains('?') {
-            return true;
+    let mut braces = 0i32;
+    let mut in_class = false;
+    for c in norm.chars() {
+        match c {
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            '{' if !in_class => braces += 1,
+            '}' if !in_class => {
+                braces -= 1;
+                if braces < 0 {
+                    return true;
+                }
+            }
+            _ => {}
         }
     }
-    false
+    in_class || braces != 0
 }
 
 // Lightweight path display wrapper that renders with forward slashes.
@@ -105,10 +282,54 @@ pub fn normalize_path(p: &str, preserve_trailing_slash: bool) -> String {
     s
 }
 
+/// Render the per-host directory of a fan-in download (`--host-dir`): `{alias}`,
+/// `{host}` (address) and `{date}` (local `YYYY-MM-DD`) are substituted. Substituted
+/// values cannot add path segments; the result must stay relative to the target.
+pub(crate) fn render_host_dir(
+    template: &str,
+    alias: &str,
+    host: &str,
+    date: &str,
+) -> anyhow::Result<String> {
+    let safe = |v: &str| v.replace(['/', '\\', ':'], "_");
+    let rendered = template
+        .replace("{alias}", &safe(alias))
+        .replace("{host}", &safe(host))
+        .replace("{date}", &safe(date));
+    let rendered = normalize_path(&rendered, false);
+    let escapes = rendered.split('/').any(|seg| seg == "..");
+    if rendered.is_empty() || rendered.starts_with('/') || is_windows_drive(&rendered) || escapes {
+        return Err(crate::TransferError::InvalidArgument(format!(
+            "无效的主机目录模板: {}（须为目标目录下的相对路径）",
+            template
+        ))
+        .into());
+    }
+    Ok(rendered.trim_start_matches("./").to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn host_dir_template_renders_and_stays_relative() {
+        assert_eq!(render_host_dir("{alias}", "web1", "10.0.0.1", "2026-10-18").unwrap(), "web1");
+        assert_eq!(
+            render_host_dir("{date}/{alias}-{host}", "web1", "fe80::1", "2026-10-18").unwrap(),
+            "2026-10-18/web1-fe80__1"
+        );
+        assert_eq!(render_host_dir("{alias}", "a/../b", "h", "d").unwrap(), "a_.._b");
+        assert!(render_host_dir("/tmp/{alias}", "web1", "h", "d").is_err());
+        assert!(render_host_dir("../{alias}", "web1", "h", "d").is_err());
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("/srv/a b"), "'/srv/a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
     #[test]
     fn normalize_empty() {
         assert_eq!(normalize_path("", true), "");
@@ -160,10 +381,50 @@ mod tests {
         assert!(!is_remote_spec("C:\\path\\to\\file"));
         assert!(!is_remote_spec("just-a-name"));
 
-        // disallowed glob
-        assert!(is_disallowed_glob("a/**/b"));
-        assert!(is_disallowed_glob("a/*/b/c"));
+        // recursive and multi-segment globs are allowed; only malformed syntax is rejected
+        assert!(!is_disallowed_glob("a/**/b"));
+        assert!(!is_disallowed_glob("a/*/b/c"));
         assert!(!is_disallowed_glob("a/b/*.txt"));
+        assert!(!is_disallowed_glob("a/{x,y}/[0-9]*.log"));
+        assert!(is_disallowed_glob("a/x**/b"));
+        assert!(is_disallowed_glob("a/[0-9.log"));
+        assert!(is_disallowed_glob("a/{x,y.log"));
+    }
+
+    #[test]
+    fn wildcard_classes_and_braces() {
+        assert!(wildcard_match("*.txt", "file.txt"));
+        assert!(wildcard_match("data-??.bin", "data-01.bin"));
+        assert!(!wildcard_match("a*b", "ac"));
+        assert!(wildcard_match("app-[0-9].log", "app-7.log"));
+        assert!(!wildcard_match("app-[0-9].log", "app-x.log"));
+        assert!(wildcard_match("app-[!0-9].log", "app-x.log"));
+        assert!(wildcard_match("*.{gz,zst}", "a.zst"));
+        assert!(!wildcard_match("*.{gz,zst}", "a.bz2"));
+        assert_eq!(expand_braces("a{b,c{d,e}}f"), vec!["abf", "acdf", "acef"]);
+    }
+
+    #[test]
+    fn glob_pattern_recursive_and_multi_segment() {
+        let g = GlobPattern::parse("logs/**/*.gz");
+        assert_eq!(g.base, "logs");
+        assert!(g.matches("a.gz"));
+        assert!(g.matches("2024/01/a.gz"));
+        assert!(!g.matches("2024/01/a.txt"));
+        assert!(g.may_match_below("2024"));
+
+        let g = GlobPattern::parse("/srv/releases/v1.*/bin/*");
+        assert_eq!(g.base, "/srv/releases");
+        assert!(g.matches("v1.2/bin/hp"));
+        assert!(!g.matches("v2.0/bin/hp"));
+        assert!(g.may_match_below("v1.2"));
+        assert!(!g.may_match_below("v2.0"));
+        assert!(g.may_match_below("v1.2/bin"));
+        assert!(!g.may_match_below("v1.2/bin/hp"));
+
+        let g = GlobPattern::parse("*.txt");
+        assert_eq!(g.base, ".");
+        assert!(g.matches("a.txt"));
     }
 
     #[test]
@@ -0,0 +1,518 @@
+// Transfer journal (`jobs.db` next to server.db): every `ts` run is a job holding its
+// arguments and the state of each file the workers picked up, so `hp ts --resume <id>`
+// can rerun it and skip what an earlier attempt finished. Workers hand updates to one
+// writer thread that commits them in batches.
+use super::HandleTsArgs;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use cli_table::{Cell, CellStruct, Style, Table, format::Justify, print_stdout};
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const JOURNAL_FILE: &str = "jobs.db";
+const FLUSH_EVERY: Duration = Duration::from_millis(500);
+const FLUSH_BATCH: usize = 512;
+
+pub(crate) fn journal_path(config: &Config) -> PathBuf {
+    config.server_file_path.with_file_name(JOURNAL_FILE)
+}
+
+fn open_journal(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path).with_context(|| "Failed to open job journal")?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = WAL;
+         PRAGMA foreign_keys = ON;
+         CREATE TABLE IF NOT EXISTS jobs (
+             id INTEGER PRIMARY KEY AUTOINCREMENT,
+             created_at INTEGER NOT NULL,
+             updated_at INTEGER NOT NULL,
+             status TEXT NOT NULL,
+             cwd TEXT NOT NULL,
+             sources TEXT NOT NULL,
+             target TEXT NOT NULL,
+             options TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS job_entries (
+             job_id INTEGER NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+             key TEXT NOT NULL,
+             state TEXT NOT NULL,
+             bytes INTEGER NOT NULL DEFAULT 0,
+             error TEXT,
+             PRIMARY KEY (job_id, key)
+         );",
+    )
+    .with_context(|| "Failed to create job journal tables")?;
+    Ok(conn)
+}
+
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
+
+fn job_not_found(id: i64) -> anyhow::Error {
+    crate::TransferError::InvalidArgument(format!("任务 #{} 不存在", id)).into()
+}
+
+// Stored arguments and working directory of a job.
+fn load_job(conn: &Connection, id: i64) -> Result<(HandleTsArgs, PathBuf)> {
+    let row: Option<(String, String)> = conn
+        .query_row("SELECT options, cwd FROM jobs WHERE id = ?1", [id], |r| {
+            Ok((r.get(0)?, r.get(1)?))
+        })
+        .optional()?;
+    let Some((options, cwd)) = row else {
+        return Err(job_not_found(id));
+    };
+    let args: HandleTsArgs =
+        serde_json::from_str(&options).with_context(|| format!("任务 #{} 的参数无法解析", id))?;
+    Ok((args, PathBuf::from(cwd)))
+}
+
+/// Arguments and working directory a job was started with (`hp ts --retry-failed`).
+pub(crate) fn job_args(path: &Path, id: i64) -> Result<(HandleTsArgs, PathBuf)> {
+    load_job(&open_journal(path)?, id)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryState {
+    // picked up by a worker, not finished yet
+    Pending,
+    Done,
+    Failed,
+}
+
+impl EntryState {
+    fn as_str(self) -> &'static str {
+        match self {
+            EntryState::Pending => "pending",
+            EntryState::Done => "done",
+            EntryState::Failed => "failed",
+        }
+    }
+}
+
+enum Update {
+    Entry { key: String, state: EntryState, bytes: u64, error: Option<String> },
+    Close,
+}
+
+/// The job of one `ts` run.
+pub(crate) struct Journal {
+    id: i64,
+    path: PathBuf,
+    // keys finished by earlier attempts of the job
+    done: HashSet<String>,
+    tx: Sender<Update>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// A file a worker is processing; `finish` records how it ended.
+pub(crate) struct Tracked<'a> {
+    journal: &'a Journal,
+    key: String,
+}
+
+impl Tracked<'_> {
+    pub(crate) fn finish(self, res: &anyhow::Result<()>, bytes: u64) {
+        let (state, error) = match res {
+            Ok(()) => (EntryState::Done, None),
+            Err(e) => {
+                self.journal.failed.fetch_add(1, Ordering::Relaxed);
+                (EntryState::Failed, Some(format!("{:#}", e)))
+            }
+        };
+        self.journal.send(self.key, state, bytes, error);
+    }
+}
+
+impl Journal {
+    /// Record a new job for `args`, run from the current directory.
+    pub(crate) fn create(path: &Path, args: &HandleTsArgs) -> Result<Self> {
+        let conn = open_journal(path)?;
+        let cwd = std::env::current_dir().with_context(|| "无法获取当前目录")?;
+        let ts = now();
+        conn.execute(
+            "INSERT INTO jobs (created_at, updated_at, status, cwd, sources, target, options)
+             VALUES (?1, ?1, 'running', ?2, ?3, ?4, ?5)",
+            params![
+                ts,
+                cwd.to_string_lossy(),
+                serde_json::to_string(&args.sources)?,
+                args.target,
+                serde_json::to_string(args)?,
+            ],
+        )
+        .with_context(|| "Failed to insert job")?;
+        Ok(Self::start(path, conn.last_insert_rowid(), HashSet::new()))
+    }
+
+    /// Reopen job `id`: returns its journal, the stored arguments and the directory it
+    /// was started from.
+    pub(crate) fn resume(path: &Path, id: i64) -> Result<(Self, HandleTsArgs, PathBuf)> {
+        let conn = open_journal(path)?;
+        let (args, cwd) = load_job(&conn, id)?;
+        let mut stmt =
+            conn.prepare("SELECT key FROM job_entries WHERE job_id = ?1 AND state = 'done'")?;
+        let done = stmt.query_map([id], |r| r.get::<_, String>(0))?.collect::<Result<_, _>>()?;
+        conn.execute(
+            "UPDATE jobs SET status = 'running', updated_at = ?2 WHERE id = ?1",
+            params![id, now()],
+        )?;
+        Ok((Self::start(path, id, done), args, cwd))
+    }
+
+    fn start(path: &Path, id: i64, done: HashSet<String>) -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded::<Update>();
+        let writer_path = path.to_path_buf();
+        let writer = std::thread::spawn(move || {
+            let mut conn = match open_journal(&writer_path) {
+                Ok(c) => c,
+                Err(e) => {
+                    tracing::warn!("[ts][journal] 无法打开任务日志: {:#}", e);
+                    return;
+                }
+            };
+            let mut batch: Vec<Update> = Vec::new();
+            let mut last_flush = Instant::now();
+            loop {
+                let closing = match rx.recv_timeout(FLUSH_EVERY) {
+                    Ok(Update::Close) | Err(RecvTimeoutError::Disconnected) => true,
+                    Ok(update) => {
+                        batch.push(update);
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                };
+                if closing || batch.len() >= FLUSH_BATCH || last_flush.elapsed() >= FLUSH_EVERY {
+                    if let Err(e) = flush_entries(&mut conn, id, &mut batch) {
+                        tracing::warn!("[ts][journal] 写入任务日志失败: {:#}", e);
+                    }
+                    last_flush = Instant::now();
+                }
+                if closing {
+                    break;
+                }
+            }
+        });
+        Journal {
+            id,
+            path: path.to_path_buf(),
+            done,
+            tx,
+            writer: Mutex::new(Some(writer)),
+            failed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Entries skipped because an earlier attempt finished them.
+    pub(crate) fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// `None` when an earlier attempt of the job finished `key` and the worker skips it;
+    /// otherwise the entry is recorded as pending.
+    pub(crate) fn begin(&self, key: String) -> Option<Tracked<'_>> {
+        if self.done.contains(&key) {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.send(key.clone(), EntryState::Pending, 0, None);
+        Some(Tracked { journal: self, key })
+    }
+
+    fn send(&self, key: String, state: EntryState, bytes: u64, error: Option<String>) {
+        let _ = self.tx.send(Update::Entry { key, state, bytes, error });
+    }
+
+    /// Write outstanding updates and the final status: `done` when the run succeeded
+    /// without failed entries, `failed` otherwise.
+    pub(crate) fn finish(&self, ok: bool) -> Result<()> {
+        let _ = self.tx.send(Update::Close);
+        if let Some(writer) = self.writer.lock().ok().and_then(|mut w| w.take()) {
+            let _ = writer.join();
+        }
+        let status = if ok && self.failed.load(Ordering::Relaxed) == 0 { "done" } else { "failed" };
+        let conn = open_journal(&self.path)?;
+        conn.execute(
+            "UPDATE jobs SET status = ?2, updated_at = ?3 WHERE id = ?1",
+            params![self.id, status, now()],
+        )?;
+        Ok(())
+    }
+}
+
+fn flush_entries(conn: &mut Connection, id: i64, batch: &mut Vec<Update>) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO job_entries (job_id, key, state, bytes, error) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(job_id, key) DO UPDATE
+             SET state = excluded.state, bytes = excluded.bytes, error = excluded.error",
+        )?;
+        for update in batch.drain(..) {
+            if let Update::Entry { key, state, bytes, error } = update {
+                stmt.execute(params![id, key, state.as_str(), bytes as i64, error])?;
+            }
+        }
+    }
+    tx.execute("UPDATE jobs SET updated_at = ?2 WHERE id = ?1", params![id, now()])?;
+    tx.commit()?;
+    Ok(())
+}
+
+struct JobRow {
+    id: i64,
+    created_at: i64,
+    updated_at: i64,
+    status: String,
+    cwd: String,
+    sources: Vec<String>,
+    target: String,
+    done: u64,
+    failed: u64,
+    pending: u64,
+    bytes: u64,
+}
+
+const JOB_QUERY: &str =
+    "SELECT j.id, j.created_at, j.updated_at, j.status, j.cwd, j.sources, j.target,
+        COUNT(CASE WHEN e.state = 'done' THEN 1 END),
+        COUNT(CASE WHEN e.state = 'failed' THEN 1 END),
+        COUNT(CASE WHEN e.state = 'pending' THEN 1 END),
+        COALESCE(SUM(CASE WHEN e.state = 'done' THEN e.bytes END), 0)
+     FROM jobs j LEFT JOIN job_entries e ON e.job_id = j.id";
+
+fn job_row(r: &rusqlite::Row) -> rusqlite::Result<JobRow> {
+    let sources: String = r.get(5)?;
+    Ok(JobRow {
+        id: r.get(0)?,
+        created_at: r.get(1)?,
+        updated_at: r.get(2)?,
+        status: r.get(3)?,
+        cwd: r.get(4)?,
+        sources: serde_json::from_str(&sources).unwrap_or_default(),
+        target: r.get(6)?,
+        done: r.get::<_, i64>(7)? as u64,
+        failed: r.get::<_, i64>(8)? as u64,
+        pending: r.get::<_, i64>(9)? as u64,
+        bytes: r.get::<_, i64>(10)? as u64,
+    })
+}
+
+fn fmt_time(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+/// `hp jobs ls`
+pub fn handle_jobs_ls(config: &Config) -> Result<()> {
+    let conn = open_journal(&journal_path(config))?;
+    let mut stmt = conn.prepare(&format!("{} GROUP BY j.id ORDER BY j.id DESC", JOB_QUERY))?;
+    let jobs = stmt.query_map([], job_row)?.collect::<Result<Vec<_>, _>>()?;
+    if jobs.is_empty() {
+        println!("暂无传输任务");
+        return Ok(());
+    }
+    let title = vec![
+        "Job".cell().bold(true),
+        "Status".cell().bold(true),
+        "Updated".cell().bold(true),
+        "Done".cell().bold(true),
+        "Failed".cell().bold(true),
+        "Pending".cell().bold(true),
+        "Bytes".cell().bold(true),
+        "Transfer".cell().bold(true),
+    ];
+    let rows: Vec<Vec<CellStruct>> = jobs
+        .iter()
+        .map(|j| {
+            vec![
+                j.id.cell().justify(Justify::Right),
+                j.status.clone().cell(),
+                fmt_time(j.updated_at).cell(),
+                j.done.cell().justify(Justify::Right),
+                j.failed.cell().justify(Justify::Right),
+                j.pending.cell().justify(Justify::Right),
+                crate::util::human_bytes(j.bytes).cell().justify(Justify::Right),
+                format!("{} -> {}", j.sources.join(" "), j.target).cell(),
+            ]
+        })
+        .collect();
+    if let Err(e) = print_stdout(rows.table().title(title)) {
+        eprintln!("⚠️ 无法渲染表格: {}", e);
+    }
+    Ok(())
+}
+
+/// `hp jobs show <id>`: the job, its options and every entry that is not done
+/// (`--all` lists finished entries too).
+pub fn handle_jobs_show(config: &Config, id: i64, all: bool) -> Result<()> {
+    let conn = open_journal(&journal_path(config))?;
+    let job = conn
+        .query_row(&format!("{} WHERE j.id = ?1 GROUP BY j.id", JOB_QUERY), [id], job_row)
+        .optional()?
+        .ok_or_else(|| job_not_found(id))?;
+    let options: String =
+        conn.query_row("SELECT options FROM jobs WHERE id = ?1", [id], |r| r.get(0))?;
+    println!("任务 #{} [{}]", job.id, job.status);
+    println!("  创建: {} | 更新: {}", fmt_time(job.created_at), fmt_time(job.updated_at));
+    println!("  目录: {}", job.cwd);
+    println!("  源: {}", job.sources.join(" "));
+    println!("  目标: {}", job.target);
+    println!("  参数: {}", options);
+    println!(
+        "  完成: {} ({}) | 失败: {} | 未完成: {}",
+        job.done,
+        crate::util::human_bytes(job.bytes),
+        job.failed,
+        job.pending
+    );
+    let filter = if all { "" } else { " AND state <> 'done'" };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT key, state, bytes, error FROM job_entries WHERE job_id = ?1{} ORDER BY key",
+        filter
+    ))?;
+    let entries = stmt.query_map([id], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, String>(1)?,
+            r.get::<_, i64>(2)?,
+            r.get::<_, Option<String>>(3)?,
+        ))
+    })?;
+    for entry in entries {
+        let (key, state, bytes, error) = entry?;
+        match error {
+            Some(err) => println!("  {:<8} {} — {}", state, key, err),
+            None if state == EntryState::Done.as_str() => {
+                println!("  {:<8} {} ({})", state, key, crate::util::human_bytes(bytes as u64))
+            }
+            None => println!("  {:<8} {}", state, key),
+        }
+    }
+    if job.status != "done" {
+        println!("续传: hp ts --resume {}", job.id);
+    }
+    Ok(())
+}
+
+/// `hp jobs rm <id>...`, or every finished job with `--done`.
+pub fn handle_jobs_rm(config: &Config, ids: &[i64], done: bool) -> Result<()> {
+    if ids.is_empty() && !done {
+        return Err(crate::TransferError::InvalidArgument(
+            "请指定要删除的任务 ID，或使用 --done 删除所有已完成任务".to_string(),
+        )
+        .into());
+    }
+    let conn = open_journal(&journal_path(config))?;
+    for &id in ids {
+        if conn.execute("DELETE FROM jobs WHERE id = ?1", [id])? == 0 {
+            return Err(job_not_found(id));
+        }
+        println!("✅ 已删除任务 #{}", id);
+    }
+    if done {
+        let n = conn.execute("DELETE FROM jobs WHERE status = 'done'", [])?;
+        println!("✅ 已删除 {} 个已完成任务", n);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> HandleTsArgs {
+        serde_json::from_value(serde_json::json!({
+            "sources": ["./dist/"],
+            "target": "web:/srv/app/",
+            "verbose": false, "json": false, "quiet": true, "concurrency": null,
+            "max_retries": 3, "buf_size": 1048576, "delete": false, "delete_dry_run": false,
+            "max_delete": null, "dry_run": false, "excludes": [], "includes": [],
+            "exclude_from": null, "preserve": false, "chmod": null, "umask": null,
+            "links": null, "no_clobber": false, "update": false, "backup": null,
+            "limit_rate": null, "direct": false, "parallel_hosts": null, "fail_fast": false,
+            "host_dir": null, "tar": false, "chunk_threshold": null, "prescan": false
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn resume_skips_entries_finished_earlier() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_journal_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(JOURNAL_FILE);
+        let journal = Journal::create(&path, &args()).unwrap();
+        let a = journal.begin("web:/srv/app/a".to_string()).unwrap();
+        let b = journal.begin("web:/srv/app/b".to_string()).unwrap();
+        let _c = journal.begin("web:/srv/app/c".to_string()).unwrap();
+        a.finish(&Ok(()), 10);
+        b.finish(&Err(anyhow::anyhow!("disk full")), 0);
+        journal.finish(true).unwrap();
+
+        let (resumed, stored, _) = Journal::resume(&path, journal.id()).unwrap();
+        assert_eq!(stored.target, "web:/srv/app/");
+        assert!(resumed.begin("web:/srv/app/a".to_string()).is_none());
+        for key in ["web:/srv/app/b", "web:/srv/app/c"] {
+            resumed.begin(key.to_string()).unwrap().finish(&Ok(()), 5);
+        }
+        assert_eq!(resumed.skipped(), 1);
+        resumed.finish(true).unwrap();
+
+        let conn = open_journal(&path).unwrap();
+        let status: String = conn
+            .query_row("SELECT status FROM jobs WHERE id = ?1", [journal.id()], |r| r.get(0))
+            .unwrap();
+        assert_eq!(status, "done");
+        // an unknown job id is a usage error (exit 2), like other bad arguments
+        let missing = Journal::resume(&path, journal.id() + 1).err().unwrap();
+        assert_eq!(crate::error::exit_code_of(&missing), crate::error::exit_code::USAGE);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn job_stored_by_older_version_still_loads() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_journal_old_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(JOURNAL_FILE);
+        let conn = open_journal(&path).unwrap();
+        // options written before --prescan / --chunk-threshold / --tar existed
+        let options = serde_json::json!({
+            "sources": ["./dist/"], "target": "web:/srv/app/", "quiet": true, "delete": true
+        });
+        conn.execute(
+            "INSERT INTO jobs (created_at, updated_at, status, cwd, sources, target, options)
+             VALUES (0, 0, 'running', '/tmp', './dist/', 'web:/srv/app/', ?1)",
+            [options.to_string()],
+        )
+        .unwrap();
+
+        let (args, cwd) = job_args(&path, conn.last_insert_rowid()).unwrap();
+        assert_eq!(args.target, "web:/srv/app/");
+        assert!(args.delete && args.quiet);
+        assert!(!args.prescan && !args.tar && args.chunk_threshold.is_none());
+        assert_eq!((args.max_retries, args.buf_size), (3, 1024 * 1024));
+        assert_eq!(cwd, PathBuf::from("/tmp"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,110 @@
+// Symbolic link handling for `ts` (`--links copy|follow|skip`).
+//
+// copy:   recreate the link itself at the destination (`Sftp::symlink` / local symlink call)
+// follow: transfer what the link points to; directory loops are detected and skipped
+// skip:   leave links out (default) and list them in the summary
+//
+// Explicitly named sources (`hp ts ./link host:/dst`) are always dereferenced.
+
+/// How symbolic links met while walking a source directory are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LinkPolicy {
+    Copy,
+    Follow,
+    #[default]
+    Skip,
+}
+
+impl LinkPolicy {
+    /// Parse the `--links` value; `None` selects the default (`skip`).
+    pub(crate) fn from_cli(value: Option<&str>) -> anyhow::Result<Self> {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("skip") => Ok(LinkPolicy::Skip),
+            Some("copy") => Ok(LinkPolicy::Copy),
+            Some("follow") => Ok(LinkPolicy::Follow),
+            Some(other) => Err(crate::TransferError::InvalidArgument(format!(
+                "无效的 --links: {}（可选 copy|follow|skip）",
+                other
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Following a link whose resolved target is the directory being listed, or one of its
+/// ancestors, would recurse forever. Both paths are resolved (`realpath`) remote paths.
+pub(super) fn is_link_loop(cur_real: &str, target_real: &str) -> bool {
+    let cur = cur_real.trim_end_matches('/');
+    let target = target_real.trim_end_matches('/');
+    target.is_empty() || cur == target || cur.starts_with(&format!("{}/", target))
+}
+
+/// Link target as sent to the remote side (forward slashes).
+pub(super) fn remote_link_target(target: &std::path::Path) -> String {
+    let s = target.to_string_lossy();
+    if cfg!(windows) { s.replace('\\', "/") } else { s.to_string() }
+}
+
+/// Create (or replace) a local symlink at `link` pointing to `target`.
+/// An existing directory at `link` is never removed.
+pub(super) fn create_local_symlink(
+    target: &std::path::Path,
+    link: &std::path::Path,
+) -> std::io::Result<()> {
+    if let Ok(md) = std::fs::symlink_metadata(link)
+        && !md.is_dir()
+    {
+        std::fs::remove_file(link)?;
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+    #[cfg(windows)]
+    {
+        // Windows distinguishes file and directory links; decide by what the target is now
+        let resolved = link.parent().map(|p| p.join(target)).unwrap_or_else(|| target.into());
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = target;
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "symlinks not supported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy_and_detect_loops() {
+        assert_eq!(LinkPolicy::from_cli(None).unwrap(), LinkPolicy::Skip);
+        assert_eq!(LinkPolicy::from_cli(Some("Follow")).unwrap(), LinkPolicy::Follow);
+        assert_eq!(LinkPolicy::from_cli(Some("copy")).unwrap(), LinkPolicy::Copy);
+        assert!(LinkPolicy::from_cli(Some("hard")).is_err());
+
+        assert!(is_link_loop("/srv/app/sub", "/srv/app"));
+        assert!(is_link_loop("/srv/app", "/srv/app/"));
+        assert!(is_link_loop("/srv/app", "/"));
+        assert!(!is_link_loop("/srv/app", "/srv/application"));
+        assert!(!is_link_loop("/srv/app", "/srv/app/sub"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_local_symlink_replaces_file() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_links_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("current");
+        std::fs::write(&link, b"stale").unwrap();
+        create_local_symlink(std::path::Path::new("releases/v2"), &link).unwrap();
+        assert_eq!(std::fs::read_link(&link).unwrap(), std::path::Path::new("releases/v2"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
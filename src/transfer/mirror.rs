@@ -0,0 +1,313 @@
+// Mirror mode (`--delete`): after a sync-style transfer, remove destination entries
+// that have no counterpart at the source so the target matches the source exactly.
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crossbeam_channel::Sender;
+use walkdir::WalkDir;
+
+use super::EntryKind;
+use super::enumeration::{RemoteListers, walk_remote_dir};
+use super::filter::PathFilter;
+use super::helpers::normalize_path;
+use super::links::LinkPolicy;
+
+/// Options controlling mirror deletion.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct MirrorOptions {
+    pub(super) dry_run: bool,
+    pub(super) max_delete: Option<usize>,
+}
+
+/// Outcome of the mirror step, surfaced in the human and JSON summaries.
+#[derive(Debug, Default)]
+pub(super) struct MirrorReport {
+    pub(super) dry_run: bool,
+    // extraneous destination paths (relative to the target), in deletion order
+    pub(super) planned: Vec<String>,
+    pub(super) deleted: usize,
+    // true when deletion was skipped entirely (threshold exceeded or incomplete source)
+    pub(super) aborted: bool,
+}
+
+/// Build the set of relative paths that must be kept at the destination: every
+/// source entry plus all of its ancestor directories.
+pub(super) fn build_keep_set<'a, I>(rels: I) -> HashSet<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut keep = HashSet::new();
+    for rel in rels {
+        let norm = normalize_path(rel, false);
+        let norm = norm.trim_start_matches("./").trim_start_matches('/');
+        if norm.is_empty() {
+            continue;
+        }
+        let mut cur = String::new();
+        for seg in norm.split('/') {
+            if !cur.is_empty() {
+                cur.push('/');
+            }
+            cur.push_str(seg);
+            keep.insert(cur.clone());
+        }
+    }
+    keep
+}
+
+/// Select destination entries missing from `keep` and order them so that
+/// children are removed before their parent directories.
+pub(super) fn plan_deletions(
+    dest: Vec<(String, EntryKind)>,
+    keep: &HashSet<String>,
+) -> Vec<(String, EntryKind)> {
+    let mut plan: Vec<(String, EntryKind)> = dest
+        .into_iter()
+        .map(|(rel, kind)| (normalize_path(&rel, false), kind))
+        .filter(|(rel, _)| !rel.is_empty() && !keep.contains(rel))
+        .collect();
+    plan.sort_by(|a, b| {
+        let da = a.0.matches('/').count();
+        let db = b.0.matches('/').count();
+        db.cmp(&da)
+            .then_with(|| (a.1 == EntryKind::Dir).cmp(&(b.1 == EntryKind::Dir)))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    plan
+}
+
+// Shared driver: applies threshold/dry-run policy and calls `remove` per entry.
+fn apply_plan<F>(
+    plan: Vec<(String, EntryKind)>,
+    opts: MirrorOptions,
+    failure_tx: &Sender<crate::TransferError>,
+    mut remove: F,
+) -> MirrorReport
+where
+    F: FnMut(&str, EntryKind) -> Result<(), String>,
+{
+    let mut report = MirrorReport {
+        dry_run: opts.dry_run,
+        planned: plan.iter().map(|(rel, _)| rel.clone()).collect(),
+        ..Default::default()
+    };
+    if let Some(max) = opts.max_delete
+        && plan.len() > max
+    {
+        super::workers::report_failure(
+            failure_tx,
+            crate::TransferError::DeleteLimitExceeded(plan.len(), max),
+        );
+        report.aborted = true;
+        return report;
+    }
+    if opts.dry_run {
+        return report;
+    }
+    for (rel, kind) in plan {
+        match remove(&rel, kind) {
+            Ok(()) => report.deleted += 1,
+            Err(e) => {
+                super::workers::report_failure(
+                    failure_tx,
+                    crate::TransferError::DeleteFailed(rel, e),
+                );
+            }
+        }
+    }
+    report
+}
+
+/// Destination of a mirror step: the local download target or a remote target.
+pub(super) enum MirrorTarget<'a> {
+    Local(&'a std::path::Path),
+    Remote(&'a ssh2::Sftp, &'a str),
+}
+
+/// Mirror step after the source walk: keep every entry the walk produced plus the links
+/// it skipped and delete the rest. An incomplete walk deletes nothing.
+pub(super) fn mirror_after_walk<'a>(
+    target: MirrorTarget,
+    rels: impl IntoIterator<Item = &'a str>,
+    skipped_links: &'a [String],
+    unreadable: &[String],
+    filter: &PathFilter,
+    opts: MirrorOptions,
+    failure_tx: &Sender<crate::TransferError>,
+) -> MirrorReport {
+    if !unreadable.is_empty() {
+        return skipped_report(opts, unreadable, failure_tx);
+    }
+    let keep = build_keep_set(rels.into_iter().chain(skipped_links.iter().map(String::as_str)));
+    match target {
+        MirrorTarget::Local(base) => mirror_local(base, &keep, filter, opts, failure_tx),
+        MirrorTarget::Remote(sftp, base) => {
+            mirror_remote(sftp, base, &keep, filter, opts, failure_tx)
+        }
+    }
+}
+
+/// `mirror_after_walk` for `--dry-run` plans: lists what would be deleted.
+pub(super) fn preview_after_walk<'a>(
+    target: MirrorTarget,
+    rels: impl IntoIterator<Item = &'a str>,
+    skipped_links: &'a [String],
+    unreadable: &[String],
+    filter: &PathFilter,
+    opts: MirrorOptions,
+) -> MirrorReport {
+    let (tx, _rx) = crossbeam_channel::unbounded();
+    let opts = MirrorOptions { dry_run: true, ..opts };
+    mirror_after_walk(target, rels, skipped_links, unreadable, filter, opts, &tx)
+}
+
+/// Report returned when the source listing was incomplete; nothing is deleted.
+pub(super) fn skipped_report(
+    opts: MirrorOptions,
+    unreadable: &[String],
+    failure_tx: &Sender<crate::TransferError>,
+) -> MirrorReport {
+    super::workers::report_failure(
+        failure_tx,
+        crate::TransferError::SourceIncomplete(unreadable.join(", ")),
+    );
+    MirrorReport { dry_run: opts.dry_run, aborted: true, ..Default::default() }
+}
+
+/// Mirror an upload: list the remote target and remove entries not in `keep`.
+/// Paths excluded by `filter` (or the target's `.hpignore`) are never deleted.
+pub(super) fn mirror_remote(
+    sftp: &ssh2::Sftp,
+    base: &str,
+    keep: &HashSet<String>,
+    filter: &PathFilter,
+    opts: MirrorOptions,
+    failure_tx: &Sender<crate::TransferError>,
+) -> MirrorReport {
+    let dest: RefCell<Vec<(String, EntryKind)>> = RefCell::new(Vec::new());
+    let push = |_full: String, rel: String, _size: Option<u64>, kind: EntryKind| {
+        dest.borrow_mut().push((rel, kind));
+    };
+    let filter = filter.for_remote_root(sftp, base);
+    // links at the destination are listed (and unlinked), never followed
+    let _ = walk_remote_dir(sftp, base, &filter, LinkPolicy::Copy, RemoteListers::default(), &push);
+    let plan = plan_deletions(dest.into_inner(), keep);
+    let root = base.trim_end_matches('/').to_string();
+    apply_plan(plan, opts, failure_tx, |rel, kind| {
+        let full = format!("{}/{}", root, rel);
+        let p = std::path::Path::new(&full);
+        let res = if kind == EntryKind::Dir { sftp.rmdir(p) } else { sftp.unlink(p) };
+        res.map_err(|e| e.to_string())
+    })
+}
+
+/// Mirror a download: walk the local target and remove entries not in `keep`.
+/// Paths excluded by `filter` (or the target's `.hpignore`) are never deleted.
+pub(super) fn mirror_local(
+    base: &std::path::Path,
+    keep: &HashSet<String>,
+    filter: &PathFilter,
+    opts: MirrorOptions,
+    failure_tx: &Sender<crate::TransferError>,
+) -> MirrorReport {
+    let filter = filter.for_local_root(base);
+    let mut dest: Vec<(String, EntryKind)> = Vec::new();
+    let mut it = WalkDir::new(base).min_depth(1).into_iter();
+    while let Some(entry) = it.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let rel = entry.path().strip_prefix(base).unwrap_or(entry.path());
+        let rel = normalize_path(&rel.to_string_lossy(), false);
+        let is_dir = entry.file_type().is_dir();
+        if filter.is_excluded(&rel, is_dir) {
+            if is_dir {
+                it.skip_current_dir();
+            }
+            continue;
+        }
+        let kind = if is_dir { EntryKind::Dir } else { EntryKind::File };
+        dest.push((rel, kind));
+    }
+    let plan = plan_deletions(dest, keep);
+    apply_plan(plan, opts, failure_tx, |rel, kind| {
+        let p = base.join(rel);
+        let res =
+            if kind == EntryKind::Dir { std::fs::remove_dir(&p) } else { std::fs::remove_file(&p) };
+        res.map_err(|e| e.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tmp_dir() -> std::path::PathBuf {
+        let mut base = std::env::temp_dir();
+        let uniq = format!(
+            "hp_mirror_test_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()
+        );
+        base.push(uniq);
+        std::fs::create_dir(&base).expect("create tmp dir");
+        base
+    }
+
+    #[test]
+    fn keep_set_includes_ancestors() {
+        let keep = build_keep_set(["a/b/c.txt", "d\\e.txt"]);
+        for k in ["a", "a/b", "a/b/c.txt", "d", "d/e.txt"] {
+            assert!(keep.contains(k), "missing {}", k);
+        }
+    }
+
+    #[test]
+    fn plan_orders_children_before_parents() {
+        let keep = build_keep_set(["keep.txt"]);
+        let dest = vec![
+            ("old".to_string(), EntryKind::Dir),
+            ("old/sub".to_string(), EntryKind::Dir),
+            ("old/sub/x.bin".to_string(), EntryKind::File),
+            ("keep.txt".to_string(), EntryKind::File),
+            ("stale.txt".to_string(), EntryKind::File),
+        ];
+        let plan: Vec<String> = plan_deletions(dest, &keep).into_iter().map(|(r, _)| r).collect();
+        assert_eq!(plan, vec!["old/sub/x.bin", "old/sub", "stale.txt", "old"]);
+    }
+
+    #[test]
+    fn mirror_local_removes_extraneous_and_honors_threshold() {
+        let dir = make_tmp_dir();
+        std::fs::create_dir_all(dir.join("keep/nested")).unwrap();
+        std::fs::create_dir_all(dir.join("gone")).unwrap();
+        std::fs::write(dir.join("keep/nested/a.txt"), b"a").unwrap();
+        std::fs::write(dir.join("keep/extra.txt"), b"x").unwrap();
+        std::fs::write(dir.join("gone/b.txt"), b"b").unwrap();
+        let keep = build_keep_set(["keep/nested/a.txt"]);
+        let none = PathFilter::default();
+        let (tx, rx) = crossbeam_channel::unbounded();
+
+        // threshold below the planned count: nothing is removed
+        let opts = MirrorOptions { dry_run: false, max_delete: Some(1) };
+        let report = mirror_local(&dir, &keep, &none, opts, &tx);
+        assert!(report.aborted);
+        assert_eq!(report.planned.len(), 3);
+        assert!(dir.join("gone/b.txt").exists());
+        assert!(matches!(rx.try_recv(), Ok(crate::TransferError::DeleteLimitExceeded(3, 1))));
+
+        // dry-run reports without touching the tree
+        let opts = MirrorOptions { dry_run: true, max_delete: None };
+        let report = mirror_local(&dir, &keep, &none, opts, &tx);
+        assert_eq!(report.deleted, 0);
+        assert!(dir.join("keep/extra.txt").exists());
+
+        let report = mirror_local(&dir, &keep, &none, MirrorOptions::default(), &tx);
+        assert_eq!(report.deleted, 3);
+        assert!(dir.join("keep/nested/a.txt").exists());
+        assert!(!dir.join("keep/extra.txt").exists());
+        assert!(!dir.join("gone").exists());
+        assert!(rx.try_recv().is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
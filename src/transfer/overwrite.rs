@@ -0,0 +1,187 @@
+// Overwrite policies for existing destination files:
+// `--no-clobber` (skip), `--update` (only when the source is newer) and
+// `--backup[=suffix]` (rename the existing file aside before writing).
+//
+// The decision is taken once per file, before the retry loop, so a partially written
+// destination from a failed attempt never turns into a "newer" file that gets skipped.
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OverwritePolicy {
+    pub(crate) no_clobber: bool,
+    pub(crate) update: bool,
+    pub(crate) backup_suffix: Option<String>,
+}
+
+/// What to do with one file whose destination may already exist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum OverwriteDecision {
+    Write,
+    Skip,
+    // rename the existing destination to this path first
+    Backup(String),
+}
+
+impl OverwritePolicy {
+    pub(crate) fn from_cli(
+        no_clobber: bool,
+        update: bool,
+        backup: Option<String>,
+    ) -> anyhow::Result<Self> {
+        if let Some(ref s) = backup
+            && (s.is_empty() || s.contains('/') || s.contains('\\'))
+        {
+            return Err(crate::TransferError::InvalidArgument(format!(
+                "无效的 --backup 后缀: '{}'",
+                s
+            ))
+            .into());
+        }
+        Ok(OverwritePolicy { no_clobber, update, backup_suffix: backup })
+    }
+
+    /// False when files are overwritten unconditionally (no destination probe needed).
+    pub(crate) fn is_active(&self) -> bool {
+        self.no_clobber || self.update || self.backup_suffix.is_some()
+    }
+
+    /// `dest_mtime` is `None` when the destination does not exist, `Some(None)` when it
+    /// exists without a known mtime. Times are Unix seconds.
+    pub(crate) fn decide(
+        &self,
+        dest: &str,
+        src_mtime: Option<u64>,
+        dest_mtime: Option<Option<u64>>,
+    ) -> OverwriteDecision {
+        let Some(dest_mtime) = dest_mtime else {
+            return OverwriteDecision::Write;
+        };
+        if self.no_clobber {
+            return OverwriteDecision::Skip;
+        }
+        if self.update
+            && let (Some(src), Some(dst)) = (src_mtime, dest_mtime)
+            && src <= dst
+        {
+            return OverwriteDecision::Skip;
+        }
+        match self.backup_suffix {
+            Some(ref suffix) => OverwriteDecision::Backup(format!("{}{}", dest, suffix)),
+            None => OverwriteDecision::Write,
+        }
+    }
+
+    /// Filter used by `--delete` so earlier backups at the destination are kept.
+    pub(crate) fn protect_backups(
+        &self,
+        filter: &super::filter::PathFilter,
+    ) -> super::filter::PathFilter {
+        match self.backup_suffix {
+            Some(ref suffix) => {
+                let mut f = filter.clone();
+                f.protect_suffix(suffix);
+                f
+            }
+            None => filter.clone(),
+        }
+    }
+}
+
+/// Files skipped or backed up during one run; shared by all workers.
+#[derive(Debug, Default)]
+pub(crate) struct OverwriteReport {
+    pub(crate) skipped: Mutex<Vec<String>>,
+    // (destination, backup path)
+    pub(crate) backed_up: Mutex<Vec<(String, String)>>,
+}
+
+impl OverwriteReport {
+    pub(crate) fn record_skip(&self, path: &str) {
+        if let Ok(mut v) = self.skipped.lock() {
+            v.push(path.to_string());
+        }
+    }
+
+    pub(crate) fn record_backup(&self, path: &str, backup: &str) {
+        if let Ok(mut v) = self.backed_up.lock() {
+            v.push((path.to_string(), backup.to_string()));
+        }
+    }
+
+    pub(crate) fn counts(&self) -> (usize, usize) {
+        let skipped = self.skipped.lock().map(|v| v.len()).unwrap_or(0);
+        let backed_up = self.backed_up.lock().map(|v| v.len()).unwrap_or(0);
+        (skipped, backed_up)
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let skipped = self.skipped.lock().map(|v| v.clone()).unwrap_or_default();
+        let backups: Vec<serde_json::Value> = self
+            .backed_up
+            .lock()
+            .map(|v| v.iter().map(|(p, b)| serde_json::json!({ "path": p, "backup": b })).collect())
+            .unwrap_or_default();
+        serde_json::json!({ "skipped_existing": skipped, "backups": backups })
+    }
+}
+
+/// Move an existing local file aside (download side).
+pub(crate) fn backup_local(
+    path: &std::path::Path,
+    backup: &std::path::Path,
+) -> std::io::Result<()> {
+    // rename does not replace an existing file on Windows; an older backup is dropped
+    if backup.exists() {
+        std::fs::remove_file(backup)?;
+    }
+    std::fs::rename(path, backup)
+}
+
+/// mtime of an existing local file; `None` when it does not exist (or is not a file).
+pub(crate) fn local_dest_mtime(path: &std::path::Path) -> Option<Option<u64>> {
+    let md = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+    Some(super::attrs::FileAttrs::from_metadata(&md).mtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decisions_per_policy() {
+        let plain = OverwritePolicy::default();
+        assert!(!plain.is_active());
+        assert_eq!(plain.decide("/a", Some(5), Some(Some(9))), OverwriteDecision::Write);
+
+        let nc = OverwritePolicy::from_cli(true, false, None).unwrap();
+        assert_eq!(nc.decide("/a", Some(5), None), OverwriteDecision::Write);
+        assert_eq!(nc.decide("/a", Some(5), Some(None)), OverwriteDecision::Skip);
+
+        let up = OverwritePolicy::from_cli(false, true, Some("~".into())).unwrap();
+        assert_eq!(up.decide("/a", Some(5), Some(Some(9))), OverwriteDecision::Skip);
+        assert_eq!(up.decide("/a", Some(9), Some(Some(9))), OverwriteDecision::Skip);
+        assert_eq!(
+            up.decide("/a", Some(10), Some(Some(9))),
+            OverwriteDecision::Backup("/a~".to_string())
+        );
+        // unknown times never block the update
+        assert_eq!(up.decide("/a", None, Some(Some(9))), OverwriteDecision::Backup("/a~".into()));
+
+        assert!(OverwritePolicy::from_cli(false, false, Some("x/y".into())).is_err());
+    }
+
+    #[test]
+    fn backup_local_replaces_older_backup() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("hp_overwrite_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (f, b) = (dir.join("app.conf"), dir.join("app.conf.bak"));
+        std::fs::write(&f, b"new").unwrap();
+        std::fs::write(&b, b"old").unwrap();
+        backup_local(&f, &b).unwrap();
+        assert!(!f.exists());
+        assert_eq!(std::fs::read(&b).unwrap(), b"new");
+        assert_eq!(local_dest_mtime(&f), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
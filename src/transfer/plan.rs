@@ -0,0 +1,258 @@
+// Dry-run planning (`--dry-run`): classify every enumerated entry against the
+// destination as seen by a single probe session, without opening worker sessions.
+use super::EntryKind;
+use super::mirror::MirrorReport;
+
+/// Planned operation for one destination path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum PlanAction {
+    Mkdir,
+    Create,
+    Overwrite,
+    Skip,
+    Symlink,
+}
+
+impl PlanAction {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            PlanAction::Mkdir => "mkdir",
+            PlanAction::Create => "create",
+            PlanAction::Overwrite => "overwrite",
+            PlanAction::Skip => "skip",
+            PlanAction::Symlink => "symlink",
+        }
+    }
+}
+
+/// Destination state observed by a stat probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum DestState {
+    Missing,
+    Dir,
+    File(u64),
+}
+
+#[derive(Debug)]
+pub(super) struct PlannedOp {
+    pub(super) action: PlanAction,
+    pub(super) path: String,
+    pub(super) size: Option<u64>,
+    pub(super) existing_size: Option<u64>,
+}
+
+/// Decide what the transfer would do for a source entry given the destination state.
+pub(super) fn classify(kind: EntryKind, dest: DestState) -> PlanAction {
+    match (kind, dest) {
+        (EntryKind::Dir, DestState::Dir) => PlanAction::Skip,
+        (EntryKind::Dir, _) => PlanAction::Mkdir,
+        (EntryKind::File, DestState::Missing) => PlanAction::Create,
+        (EntryKind::File, _) => PlanAction::Overwrite,
+        (EntryKind::Symlink, _) => PlanAction::Symlink,
+    }
+}
+
+pub(super) fn remote_state(sftp: &ssh2::Sftp, path: &str) -> DestState {
+    match sftp.stat(std::path::Path::new(path)) {
+        Ok(st) if st.is_dir() => DestState::Dir,
+        Ok(st) => DestState::File(st.size.unwrap_or(0)),
+        Err(_) => DestState::Missing,
+    }
+}
+
+pub(super) fn local_state(path: &std::path::Path) -> DestState {
+    match std::fs::metadata(path) {
+        Ok(md) if md.is_dir() => DestState::Dir,
+        Ok(md) => DestState::File(md.len()),
+        Err(_) => DestState::Missing,
+    }
+}
+
+/// The full dry-run plan for one `hp ts` invocation.
+pub(super) struct TransferPlan {
+    direction: &'static str,
+    target: String,
+    ops: Vec<PlannedOp>,
+    pub(super) mirror: Option<MirrorReport>,
+    pub(super) skipped_links: Vec<String>,
+    // `--no-clobber`: existing files are planned as `skip`
+    pub(super) no_clobber: bool,
+    // destination alias, set for tag fan-out where one plan is emitted per host
+    pub(super) host: Option<String>,
+}
+
+impl TransferPlan {
+    pub(super) fn new(direction: &'static str, target: &str) -> Self {
+        TransferPlan {
+            direction,
+            target: target.to_string(),
+            ops: Vec::new(),
+            mirror: None,
+            skipped_links: Vec::new(),
+            no_clobber: false,
+            host: None,
+        }
+    }
+
+    /// Record the target directory itself when `prepare_*_target` would create it.
+    pub(super) fn push_target(&mut self, state: DestState, target_is_dir: bool) {
+        if target_is_dir && state == DestState::Missing {
+            let path = self.target.clone();
+            self.ops.push(PlannedOp {
+                action: PlanAction::Mkdir,
+                path,
+                size: None,
+                existing_size: None,
+            });
+        }
+    }
+
+    pub(super) fn push(
+        &mut self,
+        kind: EntryKind,
+        path: String,
+        size: Option<u64>,
+        dest: DestState,
+    ) {
+        let existing_size = match dest {
+            DestState::File(s) => Some(s),
+            _ => None,
+        };
+        let size = if kind == EntryKind::File { size } else { None };
+        let action = match classify(kind, dest) {
+            PlanAction::Overwrite if self.no_clobber => PlanAction::Skip,
+            a => a,
+        };
+        self.ops.push(PlannedOp { action, path, size, existing_size });
+    }
+
+    fn count(&self, action: PlanAction) -> usize {
+        self.ops.iter().filter(|op| op.action == action).count()
+    }
+
+    // bytes that would actually be sent (create + overwrite)
+    fn transfer_bytes(&self) -> u64 {
+        self.ops
+            .iter()
+            .filter(|op| matches!(op.action, PlanAction::Create | PlanAction::Overwrite))
+            .filter_map(|op| op.size)
+            .sum()
+    }
+
+    pub(super) fn print_human(&self) {
+        match self.host {
+            Some(ref h) => {
+                println!("预演（不会修改任何文件）: {} -> {}:{}", self.direction, h, self.target)
+            }
+            None => println!("预演（不会修改任何文件）: {} -> {}", self.direction, self.target),
+        }
+        for op in &self.ops {
+            match (op.size, op.existing_size) {
+                (Some(s), Some(old)) => println!(
+                    "  {:<9} {} ({}, 现有 {})",
+                    op.action.as_str(),
+                    op.path,
+                    crate::util::human_bytes(s),
+                    crate::util::human_bytes(old)
+                ),
+                (Some(s), None) => println!(
+                    "  {:<9} {} ({})",
+                    op.action.as_str(),
+                    op.path,
+                    crate::util::human_bytes(s)
+                ),
+                _ => println!("  {:<9} {}", op.action.as_str(), op.path),
+            }
+        }
+        println!(
+            "计划: 新建目录 {}, 新建文件 {}, 覆盖 {}, 跳过 {}, 符号链接 {}, 共需传输 {}",
+            self.count(PlanAction::Mkdir),
+            self.count(PlanAction::Create),
+            self.count(PlanAction::Overwrite),
+            self.count(PlanAction::Skip),
+            self.count(PlanAction::Symlink),
+            crate::util::human_bytes(self.transfer_bytes())
+        );
+        if let Some(ref m) = self.mirror {
+            super::print_mirror_summary(m);
+        }
+        super::print_skipped_links(&self.skipped_links);
+    }
+
+    pub(super) fn to_json(&self) -> serde_json::Value {
+        let ops: Vec<serde_json::Value> = self
+            .ops
+            .iter()
+            .map(|op| {
+                serde_json::json!({
+                    "action": op.action.as_str(),
+                    "path": op.path,
+                    "size": op.size,
+                    "existing_size": op.existing_size,
+                })
+            })
+            .collect();
+        let mut obj = serde_json::json!({
+            "dry_run": true,
+            "direction": self.direction,
+            "target": self.target,
+            "operations": ops,
+            "mkdir": self.count(PlanAction::Mkdir),
+            "create": self.count(PlanAction::Create),
+            "overwrite": self.count(PlanAction::Overwrite),
+            "skip": self.count(PlanAction::Skip),
+            "symlink": self.count(PlanAction::Symlink),
+            "skipped_links": self.skipped_links,
+            "total_bytes": self.transfer_bytes(),
+        });
+        if let Some(ref h) = self.host {
+            obj["host"] = serde_json::json!(h);
+        }
+        if let Some(ref m) = self.mirror {
+            obj["deletions"] = serde_json::json!(m.planned);
+            obj["delete_aborted"] = serde_json::json!(m.aborted);
+        }
+        obj
+    }
+
+    /// Print the plan: human listing unless quiet, single-line JSON when requested.
+    pub(super) fn emit(&self, json_mode: bool, quiet_mode: bool) {
+        if !quiet_mode {
+            self.print_human();
+        }
+        if json_mode && let Ok(line) = serde_json::to_string(&self.to_json()) {
+            println!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_matches_destination_state() {
+        assert_eq!(classify(EntryKind::Dir, DestState::Dir), PlanAction::Skip);
+        assert_eq!(classify(EntryKind::Dir, DestState::Missing), PlanAction::Mkdir);
+        assert_eq!(classify(EntryKind::File, DestState::Missing), PlanAction::Create);
+        assert_eq!(classify(EntryKind::File, DestState::File(3)), PlanAction::Overwrite);
+        assert_eq!(classify(EntryKind::Symlink, DestState::Missing), PlanAction::Symlink);
+    }
+
+    #[test]
+    fn plan_json_counts_and_bytes() {
+        let mut plan = TransferPlan::new("upload", "/srv/app");
+        plan.push_target(DestState::Missing, true);
+        plan.push(EntryKind::Dir, "/srv/app/sub".into(), None, DestState::Missing);
+        plan.push(EntryKind::File, "/srv/app/a".into(), Some(10), DestState::Missing);
+        plan.push(EntryKind::File, "/srv/app/b".into(), Some(5), DestState::File(2));
+        let v = plan.to_json();
+        assert_eq!(v["mkdir"], 2);
+        assert_eq!(v["create"], 1);
+        assert_eq!(v["overwrite"], 1);
+        assert_eq!(v["skip"], 0);
+        assert_eq!(v["total_bytes"], 15);
+        assert_eq!(v["operations"][3]["existing_size"], 2);
+        assert!(v.get("deletions").is_none());
+    }
+}
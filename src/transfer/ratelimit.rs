@@ -0,0 +1,238 @@
+// Global bandwidth limiting (`--limit-rate`): one token bucket shared by every worker,
+// charged from `Throttler::tick` as bytes are written.
+//
+// Spec syntax (CLI and per-alias default, see `hp limit`):
+//   10M                        constant rate (K/M/G are 1024-based; `off`/`0` = unlimited)
+//   09:00-18:00=2M,20M         2 MiB/s during office hours, 20 MiB/s otherwise
+//   22:00-06:00=off,1M         windows may wrap midnight; first matching window wins
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Parse a rate such as `512K`, `10M`, `1.5G` or `2048` (bytes/s). `None` = unlimited.
+pub(crate) fn parse_rate(s: &str) -> Option<Option<u64>> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+    if matches!(lower.as_str(), "off" | "none" | "unlimited") {
+        return Some(None);
+    }
+    let body = lower.strip_suffix("/s").unwrap_or(&lower);
+    let body = body.strip_suffix('b').unwrap_or(body);
+    let (num, mult) = match body.chars().last()? {
+        'k' => (&body[..body.len() - 1], 1024f64),
+        'm' => (&body[..body.len() - 1], 1024f64 * 1024.0),
+        'g' => (&body[..body.len() - 1], 1024f64 * 1024.0 * 1024.0),
+        _ => (body, 1f64),
+    };
+    let v: f64 = num.trim().parse().ok()?;
+    if !v.is_finite() || v < 0.0 {
+        return None;
+    }
+    let bytes = (v * mult) as u64;
+    Some(if bytes == 0 { None } else { Some(bytes) })
+}
+
+fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+/// Rate per time of day; minutes are counted from local midnight.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RateSchedule {
+    // (start, end, rate); end is exclusive and may be < start (wraps midnight)
+    windows: Vec<(u32, u32, Option<u64>)>,
+    fallback: Option<u64>,
+}
+
+impl RateSchedule {
+    pub(crate) fn parse(spec: &str) -> anyhow::Result<Self> {
+        let invalid = || -> anyhow::Error {
+            crate::TransferError::InvalidArgument(format!("无效的限速设置: {}", spec)).into()
+        };
+        let mut sched = RateSchedule::default();
+        let mut has_fallback = false;
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item.split_once('=') {
+                Some((range, rate)) => {
+                    let (a, b) = range.split_once('-').ok_or_else(invalid)?;
+                    let (a, b) =
+                        (parse_hhmm(a).ok_or_else(invalid)?, parse_hhmm(b).ok_or_else(invalid)?);
+                    sched.windows.push((a, b, parse_rate(rate).ok_or_else(invalid)?));
+                }
+                None if !has_fallback => {
+                    sched.fallback = parse_rate(item).ok_or_else(invalid)?;
+                    has_fallback = true;
+                }
+                None => return Err(invalid()),
+            }
+        }
+        Ok(sched)
+    }
+
+    /// True when no byte is ever limited (nothing to enforce).
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.fallback.is_none() && self.windows.iter().all(|w| w.2.is_none())
+    }
+
+    pub(crate) fn rate_at(&self, minute: u32) -> Option<u64> {
+        for &(start, end, rate) in &self.windows {
+            let inside = if start <= end {
+                minute >= start && minute < end
+            } else {
+                minute >= start || minute < end
+            };
+            if inside {
+                return rate;
+            }
+        }
+        self.fallback
+    }
+
+    fn rate_now(&self) -> Option<u64> {
+        if self.windows.is_empty() {
+            return self.fallback;
+        }
+        use chrono::Timelike as _;
+        let now = chrono::Local::now();
+        self.rate_at(now.hour() * 60 + now.minute())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Token bucket shared by all workers of one run. The bucket holds at most one second
+/// worth of tokens and may go into debt, so a large chunk simply waits longer.
+pub(crate) struct RateLimiter {
+    schedule: RateSchedule,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(schedule: RateSchedule) -> Self {
+        RateLimiter { schedule, bucket: Mutex::new(Bucket { tokens: 0.0, last: Instant::now() }) }
+    }
+
+    /// Charge `n` bytes and return how long the caller must wait before continuing.
+    fn charge(&self, n: u64, rate: u64) -> Duration {
+        let rate = rate as f64;
+        let Ok(mut b) = self.bucket.lock() else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(b.last).as_secs_f64() * rate;
+        b.tokens = (b.tokens + refill).min(rate);
+        b.last = now;
+        b.tokens -= n as f64;
+        if b.tokens < 0.0 { Duration::from_secs_f64(-b.tokens / rate) } else { Duration::ZERO }
+    }
+
+    /// Block until `n` more bytes fit within the current rate.
+    pub(crate) fn acquire(&self, n: u64) {
+        if let Some(rate) = self.schedule.rate_now() {
+            let wait = self.charge(n, rate);
+            if !wait.is_zero() {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Effective limiter for one run: the CLI value wins over the alias default
+/// (`--limit-rate off` disables an alias default).
+pub(crate) fn limiter_for(
+    cli: Option<&str>,
+    server: &crate::server::Server,
+) -> anyhow::Result<Option<std::sync::Arc<RateLimiter>>> {
+    let Some(spec) = cli.or(server.limit_rate.as_deref()) else {
+        return Ok(None);
+    };
+    let schedule = RateSchedule::parse(spec)?;
+    Ok((!schedule.is_unlimited()).then(|| std::sync::Arc::new(RateLimiter::new(schedule))))
+}
+
+/// Limiters of a run reading from several aliases, one per server: each alias default
+/// applies to the bytes of its own alias, while a CLI value caps the whole run with one
+/// shared bucket.
+pub(crate) fn limiters_for(
+    cli: Option<&str>,
+    servers: &[&crate::server::Server],
+) -> anyhow::Result<Vec<Option<std::sync::Arc<RateLimiter>>>> {
+    if cli.is_some() {
+        let shared = match servers.first() {
+            Some(server) => limiter_for(cli, server)?,
+            None => None,
+        };
+        return Ok(vec![shared; servers.len()]);
+    }
+    servers.iter().map(|server| limiter_for(None, server)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rates_and_schedules() {
+        assert_eq!(parse_rate("10M"), Some(Some(10 * 1024 * 1024)));
+        assert_eq!(parse_rate("512k"), Some(Some(512 * 1024)));
+        assert_eq!(parse_rate("1.5KB/s"), Some(Some(1536)));
+        assert_eq!(parse_rate("off"), Some(None));
+        assert_eq!(parse_rate("0"), Some(None));
+        assert_eq!(parse_rate("fast"), None);
+
+        let s = RateSchedule::parse("09:00-18:00=2M, 22:00-06:00=off, 20M").unwrap();
+        assert_eq!(s.rate_at(9 * 60), Some(2 * 1024 * 1024));
+        assert_eq!(s.rate_at(18 * 60), Some(20 * 1024 * 1024));
+        assert_eq!(s.rate_at(23 * 60), None);
+        assert_eq!(s.rate_at(5 * 60 + 59), None);
+        assert!(!s.is_unlimited());
+        assert!(RateSchedule::parse("off").unwrap().is_unlimited());
+        assert!(RateSchedule::parse("25:00-06:00=1M").is_err());
+        assert!(RateSchedule::parse("1M,2M").is_err());
+    }
+
+    #[test]
+    fn bucket_charges_debt_as_wait() {
+        let l = RateLimiter::new(RateSchedule::parse("1M").unwrap());
+        // empty bucket: one second worth of bytes costs about one second
+        let wait = l.charge(1024 * 1024, 1024 * 1024);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1), "{:?}", wait);
+        // the debt carries over to the next caller
+        let wait = l.charge(512 * 1024, 1024 * 1024);
+        assert!(wait > Duration::from_millis(1400), "{:?}", wait);
+    }
+
+    #[test]
+    fn each_alias_keeps_its_own_default() {
+        let server = |limit: Option<&str>| crate::server::Server {
+            id: None,
+            alias: Some("web".into()),
+            username: "ops".into(),
+            address: "10.0.0.1".into(),
+            port: 22,
+            last_connect: None,
+            limit_rate: limit.map(str::to_string),
+            tags: Vec::new(),
+            max_handshakes: None,
+        };
+        let (slow, fast, open) = (server(Some("1M")), server(Some("20M")), server(None));
+        let servers = [&slow, &fast, &open];
+
+        let per_alias = limiters_for(None, &servers).unwrap();
+        let rate =
+            |l: &Option<std::sync::Arc<RateLimiter>>| l.as_ref().unwrap().schedule.rate_at(0);
+        assert_eq!(rate(&per_alias[0]), Some(1024 * 1024));
+        assert_eq!(rate(&per_alias[1]), Some(20 * 1024 * 1024));
+        assert!(per_alias[2].is_none());
+
+        // --limit-rate: one bucket for the whole run
+        let shared = limiters_for(Some("5M"), &servers).unwrap();
+        assert_eq!(rate(&shared[2]), Some(5 * 1024 * 1024));
+        assert!(std::sync::Arc::ptr_eq(shared[0].as_ref().unwrap(), shared[2].as_ref().unwrap()));
+        assert!(limiters_for(Some("off"), &servers).unwrap().iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,171 @@
+// `hp ts --retry-failed [run-id]`: replay the files of one run that failed for good.
+// Per-file records in failures.jsonl carry the run id (the journaled job) with the
+// source and destination in ts argument form; the job supplies the original options.
+use super::{HandleTsArgs, journal};
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+const FAILURES_FILE: &str = "failures.jsonl";
+
+// The fields of a failure record the replay needs; everything else is ignored.
+#[derive(Deserialize)]
+struct FailureLine {
+    run_id: Option<i64>,
+    direction: Option<String>,
+    source: Option<String>,
+    destination: Option<String>,
+}
+
+struct FailedFile {
+    source: String,
+    destination: String,
+    relay: bool,
+}
+
+// "alias:/srv/app/a.txt" -> ("alias:/srv/app/", "a.txt"); None without a parent directory.
+fn split_parent(spec: &str) -> Option<(&str, &str)> {
+    let cut = spec.rfind(['/', '\\'])?;
+    let (dir, name) = spec.split_at(cut + 1);
+    (!name.is_empty()).then_some((dir, name))
+}
+
+fn file_name(spec: &str) -> &str {
+    let path = if super::is_remote_spec(spec) {
+        spec.split_once(':').map(|(_, p)| p).unwrap_or(spec)
+    } else {
+        spec
+    };
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+// Turn the failed files into ts invocations (sources, target). Files landing in the same
+// directory under their own name share one run with the directory as target; a renamed
+// destination is replayed on its own, as is every relay (one remote source per run).
+fn plan_replay(files: &[FailedFile]) -> Vec<(Vec<String>, String)> {
+    let mut runs: Vec<(Vec<String>, String)> = Vec::new();
+    for f in files {
+        let dir = split_parent(&f.destination)
+            .filter(|(_, name)| *name == file_name(&f.source))
+            .map(|(dir, _)| dir);
+        match dir {
+            Some(dir) if !f.relay => match runs.iter_mut().find(|(_, t)| t == dir) {
+                Some((sources, _)) => sources.push(f.source.clone()),
+                None => runs.push((vec![f.source.clone()], dir.to_string())),
+            },
+            Some(dir) => runs.push((vec![f.source.clone()], dir.to_string())),
+            None => runs.push((vec![f.source.clone()], f.destination.clone())),
+        }
+    }
+    runs
+}
+
+pub(crate) fn retry_failed(config: &Config, run_id: Option<i64>) -> Result<()> {
+    let path = crate::util::logs_dir().join(FAILURES_FILE);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("无法读取失败清单: {}", path.display()))?;
+    // only per-file records of journaled runs can be replayed
+    let records: Vec<FailureLine> = content
+        .lines()
+        .filter_map(|l| serde_json::from_str::<FailureLine>(l).ok())
+        .filter(|r| r.run_id.is_some() && r.source.is_some() && r.destination.is_some())
+        .collect();
+    let id = match run_id.or_else(|| records.last().and_then(|r| r.run_id)) {
+        Some(id) => id,
+        None => {
+            return Err(crate::TransferError::OperationFailed(
+                "失败清单中没有可重试的文件记录".to_string(),
+            )
+            .into());
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let files: Vec<FailedFile> = records
+        .into_iter()
+        .filter(|r| r.run_id == Some(id))
+        .filter_map(|r| {
+            let relay = r.direction.as_deref() == Some("relay");
+            Some(FailedFile { source: r.source?, destination: r.destination?, relay })
+        })
+        .filter(|f| seen.insert((f.source.clone(), f.destination.clone())))
+        .collect();
+    if files.is_empty() {
+        return Err(crate::TransferError::OperationFailed(format!(
+            "运行 #{} 没有可重试的失败文件",
+            id
+        ))
+        .into());
+    }
+
+    let (stored, cwd) = journal::job_args(&journal::journal_path(config), id)?;
+    // relative local paths of the records refer to the directory the run was started from
+    std::env::set_current_dir(&cwd)
+        .with_context(|| format!("无法进入任务 #{} 的工作目录 {}", id, cwd.display()))?;
+    let runs = plan_replay(&files);
+    if !stored.quiet {
+        println!("重试运行 #{} 的 {} 个失败文件（{} 组）", id, files.len(), runs.len());
+    }
+    let total = runs.len();
+    let mut failed = 0usize;
+    for (sources, target) in runs {
+        // the original options minus those that only make sense for whole trees
+        let args = HandleTsArgs {
+            sources,
+            target,
+            delete: false,
+            delete_dry_run: false,
+            max_delete: None,
+            tar: false,
+            host_dir: None,
+            resume: None,
+            retry_failed: None,
+            ..stored.clone()
+        };
+        if let Err(e) = super::handle_ts(config, args) {
+            eprintln!("❌ {:#}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        let msg = format!("重试后仍有 {}/{} 组传输失败", failed, total);
+        return Err(if failed == total {
+            crate::TransferError::AllFailed(msg)
+        } else {
+            crate::TransferError::PartialFailure(msg)
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(source: &str, destination: &str, relay: bool) -> FailedFile {
+        FailedFile { source: source.to_string(), destination: destination.to_string(), relay }
+    }
+
+    #[test]
+    fn replay_groups_files_by_destination_directory() {
+        let files = [
+            file("build/a.js", "web:/srv/app/a.js", false),
+            file("build/css/b.css", "web:/srv/app/css/b.css", false),
+            file("build/c.js", "web:/srv/app/c.js", false),
+            file("notes.txt", "web:/tmp/renamed.txt", false),
+            file("db:/var/dump/x.sql", "bak:/data/x.sql", true),
+            file("db:/var/dump/y.sql", "bak:/data/y.sql", true),
+        ];
+        let runs = plan_replay(&files);
+        assert_eq!(
+            runs[0],
+            (vec!["build/a.js".into(), "build/c.js".into()], "web:/srv/app/".into())
+        );
+        assert_eq!(runs[1], (vec!["build/css/b.css".into()], "web:/srv/app/css/".into()));
+        assert_eq!(runs[2], (vec!["notes.txt".into()], "web:/tmp/renamed.txt".into()));
+        assert_eq!(runs[3], (vec!["db:/var/dump/x.sql".into()], "bak:/data/".into()));
+        assert_eq!(runs.len(), 5);
+    }
+}
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::io::Read;
 use std::net::{TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub fn expand_remote_tilde(sess: &ssh2::Session, path: &str) -> anyhow::Result<String> {
     let mut channel = sess.channel_session()?;
@@ -16,6 +19,18 @@ pub fn expand_remote_tilde(sess: &ssh2::Session, path: &str) -> anyhow::Result<S
     Ok(expanded)
 }
 
+/// Run `cmd` through the remote shell; returns the exit status and its output
+/// (stderr merged into stdout so neither stream can stall the other).
+pub fn exec_remote(sess: &ssh2::Session, cmd: &str) -> anyhow::Result<(i32, String)> {
+    let mut channel = sess.channel_session()?;
+    channel.handle_extended_data(ssh2::ExtendedData::Merge)?;
+    channel.exec(cmd)?;
+    let mut out = String::new();
+    channel.read_to_string(&mut out).ok();
+    channel.wait_close().ok();
+    Ok((channel.exit_status().unwrap_or(-1), out))
+}
+
 /// SSH 密钥认证的通用逻辑
 fn try_key_authentication(sess: &mut ssh2::Session, username: &str) -> bool {
     if sess.authenticated() {
@@ -47,15 +62,18 @@ fn create_tcp_connection(addr: &str) -> anyhow::Result<TcpStream> {
     Ok(tcp)
 }
 
-pub fn connect_session(server: &crate::server::Server) -> anyhow::Result<ssh2::Session> {
+// Only `SessionPool::connect` opens sessions, under the host's handshake limiter.
+fn connect_session(server: &crate::server::Server) -> anyhow::Result<ssh2::Session> {
     let addr = format!("{}:{}", server.address, server.port);
     let tcp = create_tcp_connection(&addr)?;
     let mut sess = ssh2::Session::new().map_err(|_| -> anyhow::Error {
         crate::TransferError::SshSessionCreateFailed(addr.clone()).into()
     })?;
     sess.set_tcp_stream(tcp);
-    sess.handshake().map_err(|_| -> anyhow::Error {
-        crate::TransferError::SshHandshakeFailed(addr.clone()).into()
+    // keep the libssh2 error underneath so the pool can tell a dropped connection
+    // from a real handshake failure
+    sess.handshake().map_err(|e| {
+        anyhow::Error::new(e).context(crate::TransferError::SshHandshakeFailed(addr.clone()))
     })?;
 
     if try_key_authentication(&mut sess, &server.username) {
@@ -65,51 +83,337 @@ pub fn connect_session(server: &crate::server::Server) -> anyhow::Result<ssh2::S
     }
 }
 
-pub fn ensure_worker_session(
-    maybe_sess: &mut Option<ssh2::Session>,
-    server: &crate::server::Server,
-    addr: &str,
-) -> anyhow::Result<()> {
-    if maybe_sess.is_some() {
-        return Ok(());
+/// Session counters of one run, reported in the summary next to `session_rebuilds`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub handshakes: u64,
+    pub reuses: u64,
+    // refused handshakes that were waited out instead of failing the caller
+    pub handshake_backoffs: u64,
+}
+
+/// 未配置 `hp handshakes` 时每台主机同时进行的握手上限 — default concurrent handshakes per host
+pub const DEFAULT_MAX_HANDSHAKES: u32 = 4;
+// 握手被拒绝后的重试次数与初始退避 — attempts and first backoff after a refused handshake
+const HANDSHAKE_ATTEMPTS: u32 = 5;
+const HANDSHAKE_BACKOFF: Duration = Duration::from_millis(500);
+
+/// 每台主机共享的握手限流器：sshd 的 MaxStartups 会在未认证连接过多时直接断开，
+/// 表现为握手中途连接被断开的 `SshHandshakeFailed`。此类拒绝时上限减半并退避，连续成功后逐步恢复到配置值。
+/// Shared by every worker, chunk range and probe of one host, in both directions.
+#[derive(Debug)]
+pub struct HandshakeLimiter {
+    gate: crate::auto_concurrency::ConcurrencyGate,
+    // (limit, configured max, successes since the last change)
+    state: Mutex<(u32, u32, u32)>,
+}
+
+impl HandshakeLimiter {
+    pub fn new(max: u32) -> Self {
+        let max = max.max(1);
+        Self {
+            gate: crate::auto_concurrency::ConcurrencyGate::new(max as usize),
+            state: Mutex::new((max, max, 0)),
+        }
+    }
+
+    #[cfg(test)]
+    fn limit(&self) -> u32 {
+        self.state.lock().map(|s| s.0).unwrap_or(1)
+    }
+
+    // additive recovery: one more slot after `limit` handshakes in a row succeeded
+    fn succeeded(&self) {
+        let Ok(mut st) = self.state.lock() else {
+            return;
+        };
+        st.2 += 1;
+        if st.0 < st.1 && st.2 >= st.0 {
+            st.0 += 1;
+            st.2 = 0;
+            self.gate.set_limit(st.0 as usize);
+        }
+    }
+
+    // multiplicative decrease; returns the new limit
+    fn refused(&self) -> u32 {
+        let Ok(mut st) = self.state.lock() else {
+            return 1;
+        };
+        st.0 = (st.0 / 2).max(1);
+        st.2 = 0;
+        self.gate.set_limit(st.0 as usize);
+        st.0
+    }
+}
+
+/// Authenticated sessions shared by one `ts` run, keyed by user@host:port.
+/// The session that expanded `~` and probed the target goes to the first worker, and
+/// sessions a worker or chunk range is done with stay open for the next one.
+/// `hp agent` keeps one pool for its whole lifetime.
+#[derive(Default)]
+pub struct SessionPool {
+    // idle sessions with the time they were checked in
+    idle: Mutex<HashMap<String, Vec<(ssh2::Session, Instant)>>>,
+    handshakes: AtomicU64,
+    reuses: AtomicU64,
+    handshake_backoffs: AtomicU64,
+    // one handshake limiter per user@host:port
+    limiters: Mutex<HashMap<String, Arc<HandshakeLimiter>>>,
+    // running `hp agent` that worker SFTP channels are delegated to
+    #[cfg(unix)]
+    agent: Option<super::agent::AgentHandle>,
+}
+
+fn pool_key(server: &crate::server::Server) -> String {
+    format!("{}@{}:{}", server.username, server.address, server.port)
+}
+
+impl SessionPool {
+    /// A pool that delegates worker SFTP channels to `hp agent` when one answers.
+    pub fn with_agent() -> Self {
+        SessionPool {
+            #[cfg(unix)]
+            agent: super::agent::AgentHandle::probe(),
+            ..SessionPool::default()
+        }
     }
 
-    let server_addr = format!("{}:{}", server.address, server.port);
-    match create_tcp_connection(&server_addr) {
-        Ok(tcp) => {
-            if let Ok(mut sess) = ssh2::Session::new() {
-                sess.set_tcp_stream(tcp);
-                if sess.handshake().is_err() {
-                    return Err(crate::TransferError::SshHandshakeFailed(addr.to_string()).into());
+    #[cfg(unix)]
+    pub(super) fn agent(&self) -> Option<&super::agent::AgentHandle> {
+        self.agent.as_ref()
+    }
+
+    /// An idle session to `server`, or a new one when none is left.
+    pub fn checkout(&self, server: &crate::server::Server) -> anyhow::Result<ssh2::Session> {
+        if let Some(sess) = self.take_idle(server) {
+            self.reuses.fetch_add(1, Ordering::Relaxed);
+            return Ok(sess);
+        }
+        self.connect(server)
+    }
+
+    /// Like `checkout` plus an SFTP channel; idle sessions that went stale are dropped.
+    pub fn checkout_sftp(
+        &self,
+        server: &crate::server::Server,
+    ) -> anyhow::Result<(ssh2::Session, ssh2::Sftp)> {
+        while let Some(sess) = self.take_idle(server) {
+            match sess.sftp() {
+                Ok(sftp) => {
+                    self.reuses.fetch_add(1, Ordering::Relaxed);
+                    return Ok((sess, sftp));
                 }
+                Err(e) => tracing::debug!("[ts][pool] 丢弃失效的空闲会话: {}", e),
+            }
+        }
+        let sess = self.connect(server)?;
+        let sftp = sess.sftp().map_err(|e| -> anyhow::Error {
+            crate::TransferError::SftpCreateFailed(e.to_string()).into()
+        })?;
+        Ok((sess, sftp))
+    }
+
+    /// Keep `sess` for the next checkout; drop any SFTP channel on it first.
+    pub fn checkin(&self, server: &crate::server::Server, sess: ssh2::Session) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.entry(pool_key(server)).or_default().push((sess, Instant::now()));
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            handshakes: self.handshakes.load(Ordering::Relaxed),
+            reuses: self.reuses.load(Ordering::Relaxed),
+            handshake_backoffs: self.handshake_backoffs.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Close sessions idle for longer than `max_idle` and send a keepalive on the rest
+    /// so NAT and server timeouts do not drop them; returns how many were closed.
+    pub fn maintain(&self, max_idle: Duration) -> usize {
+        let Ok(mut idle) = self.idle.lock() else {
+            return 0;
+        };
+        let mut closed = 0;
+        for sessions in idle.values_mut() {
+            let before = sessions.len();
+            sessions.retain(|(sess, since)| {
+                if since.elapsed() > max_idle {
+                    return false;
+                }
+                sess.set_keepalive(false, max_idle.as_secs().clamp(1, u32::MAX as u64) as u32);
+                sess.keepalive_send().is_ok()
+            });
+            closed += before - sessions.len();
+        }
+        idle.retain(|_, sessions| !sessions.is_empty());
+        closed
+    }
 
-                if try_key_authentication(&mut sess, &server.username) {
-                    *maybe_sess = Some(sess);
-                    Ok(())
-                } else {
-                    Err(crate::TransferError::WorkerBuildSessionFailed(server_addr).into())
+    /// Idle sessions per user@host:port with the age of the oldest one.
+    pub fn idle_summary(&self) -> Vec<(String, usize, Duration)> {
+        let Ok(idle) = self.idle.lock() else {
+            return Vec::new();
+        };
+        let mut out: Vec<(String, usize, Duration)> = idle
+            .iter()
+            .map(|(key, sessions)| {
+                let oldest = sessions.iter().map(|(_, since)| since.elapsed()).max();
+                (key.clone(), sessions.len(), oldest.unwrap_or_default())
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
+    fn take_idle(&self, server: &crate::server::Server) -> Option<ssh2::Session> {
+        self.idle.lock().ok()?.get_mut(&pool_key(server))?.pop().map(|(sess, _)| sess)
+    }
+
+    /// The handshake limiter of `server`, created with its `hp handshakes` value on first use.
+    pub fn handshake_limiter(&self, server: &crate::server::Server) -> Arc<HandshakeLimiter> {
+        let max = server.max_handshakes.unwrap_or(DEFAULT_MAX_HANDSHAKES);
+        match self.limiters.lock() {
+            Ok(mut limiters) => limiters
+                .entry(pool_key(server))
+                .or_insert_with(|| Arc::new(HandshakeLimiter::new(max)))
+                .clone(),
+            Err(_) => Arc::new(HandshakeLimiter::new(max)),
+        }
+    }
+
+    // New session under the host's handshake limit. A refused handshake (MaxStartups)
+    // lowers the limit and is retried here after a backoff, so it does not use up the
+    // caller's per-file retries; authentication and address errors return at once.
+    fn connect(&self, server: &crate::server::Server) -> anyhow::Result<ssh2::Session> {
+        let limiter = self.handshake_limiter(server);
+        let mut backoff = HANDSHAKE_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let res = {
+                let _turn = limiter.gate.acquire();
+                connect_session(server)
+            };
+            match res {
+                Ok(sess) => {
+                    limiter.succeeded();
+                    self.handshakes.fetch_add(1, Ordering::Relaxed);
+                    return Ok(sess);
                 }
-            } else {
-                Err(crate::TransferError::WorkerBuildSessionFailed(server_addr).into())
+                Err(e) if attempt < HANDSHAKE_ATTEMPTS && is_refused_handshake(&e) => {
+                    let limit = limiter.refused();
+                    self.handshake_backoffs.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        "[ts][pool] {} 握手被拒绝（第 {} 次），握手上限降为 {}，{}ms 后重试",
+                        pool_key(server),
+                        attempt,
+                        limit,
+                        backoff.as_millis()
+                    );
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
             }
         }
-        Err(_) => Err(crate::TransferError::WorkerBuildSessionFailed(server_addr).into()),
     }
 }
 
+// libssh2 codes of a connection the server dropped mid-handshake — what sshd's
+// MaxStartups does to surplus unauthenticated connections: banner not received,
+// socket send/recv failure, disconnect
+const REFUSAL_CODES: [i32; 4] = [-2, -7, -13, -43];
+
+// Only a handshake the server cut off (or a reset connection) is a refusal worth a
+// backoff; algorithm mismatches, timeouts and the like fail the same way on retry.
+fn is_refused_handshake(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+            return matches!(e.code(), ssh2::ErrorCode::Session(c) if REFUSAL_CODES.contains(&c));
+        }
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted
+            )
+        })
+    })
+}
+
+// Worker-facing error for a failed checkout: handshake failures keep the alias address,
+// everything but SFTP creation is reported as a worker build failure.
+fn worker_session_error(
+    err: anyhow::Error,
+    server: &crate::server::Server,
+    addr: &str,
+) -> anyhow::Error {
+    match err.downcast_ref::<crate::TransferError>() {
+        Some(crate::TransferError::SshHandshakeFailed(_)) => {
+            crate::TransferError::SshHandshakeFailed(addr.to_string()).into()
+        }
+        Some(crate::TransferError::SftpCreateFailed(_)) => err,
+        _ => crate::TransferError::WorkerBuildSessionFailed(format!(
+            "{}:{}",
+            server.address, server.port
+        ))
+        .into(),
+    }
+}
+
+pub fn ensure_worker_session(
+    maybe_sess: &mut Option<ssh2::Session>,
+    pool: &SessionPool,
+    server: &crate::server::Server,
+    addr: &str,
+) -> anyhow::Result<()> {
+    if maybe_sess.is_some() {
+        return Ok(());
+    }
+    let sess = pool.checkout(server).map_err(|e| worker_session_error(e, server, addr))?;
+    *maybe_sess = Some(sess);
+    Ok(())
+}
+
 /// 统一的会话+SFTP准备函数，减少upload/download重复逻辑
 pub fn ensure_session_and_sftp(
     maybe_sess: &mut Option<ssh2::Session>,
     maybe_sftp: &mut Option<Box<dyn crate::transfer::sftp_like::SftpLike>>,
+    pool: &SessionPool,
     server: &crate::server::Server,
     addr: &str,
     session_rebuilds: &mut u32,
     sftp_rebuilds: &mut u32,
 ) -> anyhow::Result<()> {
-    // Ensure session first
+    // with `hp agent` running the channel lives in the agent and no local session is kept
+    #[cfg(unix)]
+    if let (Some(agent), Some(alias)) = (pool.agent(), server.alias.as_deref()) {
+        if maybe_sftp.is_none() {
+            let sftp = agent.sftp(alias).map_err(|e| -> anyhow::Error {
+                crate::TransferError::SftpCreateFailed(format!("{:#}", e)).into()
+            })?;
+            *maybe_sftp = Some(Box::new(sftp));
+            *sftp_rebuilds += 1;
+            crate::transfer::events::session_rebuild(Some(alias), false, true);
+        }
+        return Ok(());
+    }
+    // Ensure session first; a pooled session is only taken once SFTP opens on it
+    if maybe_sess.is_none() && maybe_sftp.is_none() {
+        let (sess, sftp) =
+            pool.checkout_sftp(server).map_err(|e| worker_session_error(e, server, addr))?;
+        *maybe_sess = Some(sess);
+        *maybe_sftp = Some(Box::new(crate::transfer::sftp_like::Ssh2Adapter(sftp)));
+        *session_rebuilds += 1;
+        *sftp_rebuilds += 1;
+        crate::transfer::events::session_rebuild(server.alias.as_deref(), true, true);
+        return Ok(());
+    }
     if maybe_sess.is_none() {
-        ensure_worker_session(maybe_sess, server, addr)?;
+        ensure_worker_session(maybe_sess, pool, server, addr)?;
         *session_rebuilds += 1;
+        crate::transfer::events::session_rebuild(server.alias.as_deref(), true, false);
     }
 
     let sess = maybe_sess.as_mut().ok_or_else(|| -> anyhow::Error {
@@ -124,6 +428,7 @@ pub fn ensure_session_and_sftp(
         match sess.sftp() {
             Ok(s) => {
                 *sftp_rebuilds += 1;
+                crate::transfer::events::session_rebuild(server.alias.as_deref(), false, true);
                 *maybe_sftp = Some(Box::new(crate::transfer::sftp_like::Ssh2Adapter(s)));
             }
             Err(e) => {
@@ -134,3 +439,93 @@ pub fn ensure_session_and_sftp(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(address: &str) -> crate::server::Server {
+        crate::server::Server {
+            id: None,
+            alias: None,
+            username: "deploy".to_string(),
+            address: address.to_string(),
+            port: 22,
+            last_connect: None,
+            limit_rate: None,
+            tags: Vec::new(),
+            max_handshakes: None,
+        }
+    }
+
+    #[test]
+    fn pool_hands_back_sessions_per_server() {
+        let pool = SessionPool::default();
+        let (web, db) = (server("10.0.0.1"), server("10.0.0.2"));
+        pool.checkin(&web, ssh2::Session::new().unwrap());
+        assert!(pool.take_idle(&db).is_none());
+        assert!(pool.checkout(&web).is_ok());
+        assert!(pool.take_idle(&web).is_none());
+        assert_eq!(pool.stats(), PoolStats { handshakes: 0, reuses: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn maintain_closes_sessions_past_idle_timeout() {
+        let pool = SessionPool::default();
+        let web = server("10.0.0.1");
+        pool.checkin(&web, ssh2::Session::new().unwrap());
+        pool.checkin(&web, ssh2::Session::new().unwrap());
+        let summary = pool.idle_summary();
+        assert_eq!(summary.len(), 1);
+        assert_eq!((summary[0].0.as_str(), summary[0].1), ("deploy@10.0.0.1:22", 2));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(pool.maintain(Duration::ZERO), 2);
+        assert!(pool.idle_summary().is_empty());
+    }
+
+    #[test]
+    fn handshake_limit_halves_on_refusal_and_recovers_on_success() {
+        let pool = SessionPool::default();
+        let web = server("10.0.0.1");
+        let limiter = pool.handshake_limiter(&web);
+        assert!(Arc::ptr_eq(&limiter, &pool.handshake_limiter(&web)));
+        assert_eq!(limiter.limit(), DEFAULT_MAX_HANDSHAKES);
+        assert_eq!(limiter.refused(), 2);
+        assert_eq!(limiter.refused(), 1);
+        assert_eq!(limiter.refused(), 1);
+        limiter.succeeded();
+        assert_eq!(limiter.limit(), 2);
+        limiter.succeeded();
+        limiter.succeeded();
+        assert_eq!(limiter.limit(), 3);
+
+        let db = crate::server::Server { max_handshakes: Some(10), ..server("10.0.0.2") };
+        assert_eq!(pool.handshake_limiter(&db).limit(), 10);
+    }
+
+    #[test]
+    fn only_dropped_connections_count_as_refused_handshakes() {
+        let failed = |code: i32| {
+            anyhow::Error::new(ssh2::Error::new(ssh2::ErrorCode::Session(code), "handshake"))
+                .context(crate::TransferError::SshHandshakeFailed("10.0.0.1:22".to_string()))
+        };
+        // LIBSSH2_ERROR_SOCKET_DISCONNECT / BANNER_RECV: MaxStartups closed the connection
+        assert!(is_refused_handshake(&failed(-13)));
+        assert!(is_refused_handshake(&failed(-2)));
+        // LIBSSH2_ERROR_KEX_FAILURE / TIMEOUT fail again on every retry
+        assert!(!is_refused_handshake(&failed(-5)));
+        assert!(!is_refused_handshake(&failed(-9)));
+        assert!(matches!(
+            failed(-13).downcast_ref::<crate::TransferError>(),
+            Some(crate::TransferError::SshHandshakeFailed(_))
+        ));
+
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_refused_handshake(&anyhow::Error::new(reset)));
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(!is_refused_handshake(&anyhow::Error::new(refused)));
+        assert!(!is_refused_handshake(
+            &crate::TransferError::SshAuthFailed("10.0.0.1:22".to_string()).into()
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use std::path::Path;
 
+use super::attrs::FileAttrs;
+
 /// Trait abstracting SFTP operations used by workers. Return boxed readers/writers
 /// so tests can inject mock file-like objects. Implementors must be Send so they
 /// can be stored in worker threads as trait objects.
@@ -8,6 +10,27 @@ pub trait SftpLike: Send {
     fn mkdir(&self, p: &Path, mode: i32) -> Result<(), String>;
     fn open_read(&self, p: &Path) -> Result<Box<dyn std::io::Read + Send>, String>;
     fn create_write(&self, p: &Path) -> Result<Box<dyn std::io::Write + Send>, String>;
+    /// Mode and times of a remote path (used by `--preserve` on download).
+    fn stat_attrs(&self, _p: &Path) -> Result<FileAttrs, String> {
+        Err("stat_attrs not supported".to_string())
+    }
+    /// Apply mode and/or times to a remote path (`setstat`, used on upload).
+    fn set_attrs(&self, _p: &Path, _attrs: FileAttrs) -> Result<(), String> {
+        Err("set_attrs not supported".to_string())
+    }
+    /// Target of a remote symlink (`--links copy` on download).
+    fn read_link(&self, _p: &Path) -> Result<std::path::PathBuf, String> {
+        Err("read_link not supported".to_string())
+    }
+    /// Create a remote symlink at `link` pointing to `target`, replacing a file or link
+    /// already there (`--links copy` on upload).
+    fn symlink(&self, _target: &str, _link: &Path) -> Result<(), String> {
+        Err("symlink not supported".to_string())
+    }
+    /// Move an existing remote file aside (`--backup`); an older file at `to` is replaced.
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<(), String> {
+        Err("rename not supported".to_string())
+    }
 }
 
 /// Adapter that owns an `ssh2::Sftp` and implements `SftpLike` so it can be
@@ -39,4 +62,35 @@ impl SftpLike for Ssh2Adapter {
             Err(e) => Err(e.to_string()),
         }
     }
+
+    fn stat_attrs(&self, p: &Path) -> Result<FileAttrs, String> {
+        self.0.stat(p).map(|st| FileAttrs::from_stat(&st)).map_err(|e| e.to_string())
+    }
+
+    fn set_attrs(&self, p: &Path, attrs: FileAttrs) -> Result<(), String> {
+        self.0.setstat(p, super::attrs::to_stat(attrs)).map_err(|e| e.to_string())
+    }
+
+    fn read_link(&self, p: &Path) -> Result<std::path::PathBuf, String> {
+        self.0.readlink(p).map_err(|e| e.to_string())
+    }
+
+    fn symlink(&self, target: &str, link: &Path) -> Result<(), String> {
+        if let Ok(st) = self.0.lstat(link)
+            && !st.is_dir()
+        {
+            self.0.unlink(link).map_err(|e| e.to_string())?;
+        }
+        // ssh2's argument order follows OpenSSH's (swapped) SSH_FXP_SYMLINK:
+        // `symlink(path, target)` creates a link at `target` pointing to `path`
+        self.0.symlink(Path::new(target), link).map_err(|e| e.to_string())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String> {
+        // SFTPv3 servers (OpenSSH) refuse to rename onto an existing path
+        if self.0.lstat(to).is_ok() {
+            self.0.unlink(to).map_err(|e| e.to_string())?;
+        }
+        self.0.rename(from, to, None).map_err(|e| e.to_string())
+    }
 }
@@ -0,0 +1,386 @@
+// Tar-stream bulk mode (`--tar`) for directory trees with many small files: one exec
+// channel per host carries a tar stream instead of one SFTP round trip per file.
+//
+// upload:   entries from the local walk are archived on the fly into `tar -x` on the host
+// download: the remote walk's file list is uploaded and fed to `tar -c -T`; the stream is
+//           extracted locally, accepting only the paths that were requested
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crossbeam_channel::Sender;
+use indicatif::ProgressBar;
+
+use super::attrs::{FileAttrs, apply_dir_attrs_local, apply_local};
+use super::helpers::shell_quote;
+use super::links::create_local_symlink;
+use super::normalize_path;
+use super::ratelimit::RateLimiter;
+use super::workers::{Throttler, WorkerMetrics};
+use super::{EntryKind, FileEntry};
+
+// Charges the limiter and advances the total bar as file content is read.
+struct ProgressReader<'a, R> {
+    inner: R,
+    throttler: &'a mut Throttler,
+    total_pb: &'a ProgressBar,
+    bytes: &'a mut u64,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttler.tick(n as u64, None, self.total_pb, None);
+        *self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+/// Shared state of one tar stream.
+pub(super) struct TarStream<'a> {
+    pub(super) sess: &'a ssh2::Session,
+    pub(super) preserve: bool,
+    pub(super) total_pb: &'a ProgressBar,
+    pub(super) limiter: Option<Arc<RateLimiter>>,
+    pub(super) failure_tx: &'a Sender<crate::TransferError>,
+}
+
+/// Archive `entries` into `tar -x` running in `dest_dir` on the host. Unreadable files
+/// are reported and left out; a broken stream aborts the rest of the archive.
+pub(super) fn upload(ctx: &TarStream, entries: &[FileEntry], dest_dir: &str) -> WorkerMetrics {
+    let mut metrics = WorkerMetrics::default();
+    // without --preserve extracted files get the current time, like normal mode
+    let flags = if ctx.preserve { "xpf" } else { "xmf" };
+    let cmd = format!("tar -C {} -{} -", shell_quote(dest_dir), flags);
+    let fail = |msg: String| {
+        super::workers::report_failure(ctx.failure_tx, crate::TransferError::WorkerIo(msg));
+    };
+    let mut channel = match ctx.sess.channel_session().and_then(|mut c| {
+        c.handle_extended_data(ssh2::ExtendedData::Merge)?;
+        c.exec(&cmd)?;
+        Ok(c)
+    }) {
+        Ok(c) => c,
+        Err(e) => {
+            fail(format!("tar 通道建立失败: {}", e));
+            return metrics;
+        }
+    };
+    let mut throttler = Throttler::new(ctx.limiter.clone());
+    let mut builder = tar::Builder::new(&mut channel);
+    builder.follow_symlinks(false);
+    for e in entries {
+        let Some(local) = e.local_full.as_deref() else {
+            continue;
+        };
+        let name = normalize_path(&e.rel, false);
+        let res = match e.kind {
+            EntryKind::Dir => builder.append_dir(&name, local),
+            EntryKind::Symlink => match std::fs::read_link(local) {
+                Ok(target) => {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    header.set_mode(0o777);
+                    builder.append_link(&mut header, &name, target)
+                }
+                Err(err) => {
+                    fail(format!("upload failed: {} — {}", local, err));
+                    continue;
+                }
+            },
+            EntryKind::File => {
+                let opened = std::fs::File::open(local).and_then(|f| Ok((f.metadata()?, f)));
+                let (md, file) = match opened {
+                    Ok(v) => v,
+                    Err(err) => {
+                        fail(format!("upload failed: {} — {}", local, err));
+                        continue;
+                    }
+                };
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&md);
+                header.set_size(md.len());
+                let reader = ProgressReader {
+                    inner: file.take(md.len()),
+                    throttler: &mut throttler,
+                    total_pb: ctx.total_pb,
+                    bytes: &mut metrics.bytes,
+                };
+                builder.append_data(&mut header, &name, reader)
+            }
+        };
+        if let Err(err) = res {
+            // the archive cannot be resumed after a partial entry
+            fail(format!("tar 流中断于 {}: {}", local, err));
+            break;
+        }
+    }
+    throttler.flush(None, ctx.total_pb, None);
+    // writes the end-of-archive blocks; a broken channel is reported by the exit status
+    let _ = builder.into_inner().and_then(|c| c.flush());
+    let _ = channel.send_eof();
+    let mut out = String::new();
+    let _ = channel.read_to_string(&mut out);
+    let _ = channel.wait_close();
+    let status = channel.exit_status().unwrap_or(-1);
+    if status != 0 {
+        fail(format!("远端 tar 解包失败（退出码 {}）: {}", status, out.trim()));
+    }
+    metrics
+}
+
+/// One remote entry requested from a host's `tar -c`.
+pub(super) struct TarItem {
+    pub(super) full: String,
+    // destination relative to the local target
+    pub(super) rel: String,
+    pub(super) kind: EntryKind,
+}
+
+/// Reject archive member names that could leave the target (absolute, `..`, drive prefix).
+pub(super) fn sanitize_member(path: &Path) -> Option<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => parts.push(p.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+// Member name as written by the remote tar: leading `/` removed, `./` collapsed.
+fn member_key(full: &str) -> String {
+    normalize_path(full, false).trim_start_matches('/').trim_start_matches("./").to_string()
+}
+
+// Unpredictable, private (0600) file for the list of paths fed to `tar -c -T`.
+const MKTEMP: &str = "mktemp \"${TMPDIR:-/tmp}/.hp-tar.XXXXXXXX\"";
+
+// Path printed by a successful `mktemp`.
+fn mktemp_path(status: i32, out: &str) -> Option<String> {
+    let path = out.trim();
+    (status == 0 && path.starts_with('/') && !path.contains('\n')).then(|| path.to_string())
+}
+
+// Removes the remote file list on every exit path; the remote shell may already have
+// removed it after tar exited, so a failed unlink is ignored.
+struct RemoteTemp<'a> {
+    sftp: &'a ssh2::Sftp,
+    path: String,
+}
+
+impl Drop for RemoteTemp<'_> {
+    fn drop(&mut self) {
+        let _ = self.sftp.unlink(Path::new(&self.path));
+    }
+}
+
+/// Stream `items` from the host with `tar -c` and extract them below `target`.
+/// `follow` dereferences symlinks (`--links follow`). Returns (files, bytes) extracted.
+pub(super) fn download(
+    ctx: &TarStream,
+    sftp: &ssh2::Sftp,
+    items: &[TarItem],
+    target: &Path,
+    follow: bool,
+) -> (u64, u64) {
+    let fail = |msg: String| {
+        super::workers::report_failure(ctx.failure_tx, crate::TransferError::WorkerIo(msg));
+    };
+    let mut wanted: HashMap<String, &TarItem> = HashMap::new();
+    let mut files_list: Vec<u8> = Vec::new();
+    for item in items {
+        wanted.insert(member_key(&item.full), item);
+        files_list.extend_from_slice(item.full.as_bytes());
+        files_list.push(0);
+    }
+    // the list goes through a file: feeding it on stdin while reading stdout could stall
+    let list = match super::session::exec_remote(ctx.sess, MKTEMP) {
+        Ok((status, out)) => match mktemp_path(status, &out) {
+            Some(path) => RemoteTemp { sftp, path },
+            None => {
+                fail(format!("远端临时文件创建失败（退出码 {}）: {}", status, out.trim()));
+                return (0, 0);
+            }
+        },
+        Err(e) => {
+            fail(format!("远端临时文件创建失败: {}", e));
+            return (0, 0);
+        }
+    };
+    let written = sftp
+        .open_mode(
+            Path::new(&list.path),
+            ssh2::OpenFlags::WRITE | ssh2::OpenFlags::TRUNCATE,
+            0o600,
+            ssh2::OpenType::File,
+        )
+        .map_err(std::io::Error::from)
+        .and_then(|mut f| f.write_all(&files_list));
+    if let Err(e) = written {
+        fail(format!("tar 文件清单上传失败: {}: {}", list.path, e));
+        return (0, 0);
+    }
+    // the shell removes the list too, in case this process dies before the guard runs
+    let cmd = format!(
+        "tar --no-recursion --null -T {list} -c{h}f -; s=$?; rm -f {list}; exit $s",
+        list = shell_quote(&list.path),
+        h = if follow { "h" } else { "" }
+    );
+    let mut channel = match ctx.sess.channel_session().and_then(|mut c| {
+        c.exec(&cmd)?;
+        Ok(c)
+    }) {
+        Ok(c) => c,
+        Err(e) => {
+            fail(format!("tar 通道建立失败: {}", e));
+            return (0, 0);
+        }
+    };
+
+    let mut throttler = Throttler::new(ctx.limiter.clone());
+    let (mut files, mut bytes) = (0u64, 0u64);
+    // member key -> local path, for hard link entries
+    let mut extracted: HashMap<String, PathBuf> = HashMap::new();
+    let mut dirs: Vec<(PathBuf, FileAttrs)> = Vec::new();
+    let mut archive = tar::Archive::new(&mut channel);
+    match archive.entries() {
+        Ok(entries) => {
+            for entry in entries {
+                let mut entry = match entry {
+                    Ok(e) => e,
+                    Err(e) => {
+                        fail(format!("tar 流读取失败: {}", e));
+                        break;
+                    }
+                };
+                let Some(key) = entry.path().ok().and_then(|p| sanitize_member(&p)) else {
+                    fail(format!(
+                        "已拒绝不安全的 tar 条目: {}",
+                        String::from_utf8_lossy(&entry.path_bytes())
+                    ));
+                    continue;
+                };
+                let Some(item) = wanted.remove(&key) else {
+                    fail(format!("已忽略未请求的 tar 条目: {}", key));
+                    continue;
+                };
+                let dest = target.join(&item.rel);
+                let header = entry.header();
+                let attrs = FileAttrs {
+                    mode: header.mode().ok().filter(|_| ctx.preserve),
+                    atime: None,
+                    mtime: header.mtime().ok().filter(|_| ctx.preserve),
+                };
+                // parents may be filtered out of the walk
+                if header.entry_type() != tar::EntryType::Directory
+                    && let Some(parent) = dest.parent()
+                {
+                    let _ = std::fs::create_dir_all(parent);
+                }
+                let res: std::io::Result<()> = match header.entry_type() {
+                    tar::EntryType::Directory => {
+                        dirs.push((dest.clone(), attrs));
+                        std::fs::create_dir_all(&dest)
+                    }
+                    tar::EntryType::Symlink => match entry.link_name() {
+                        Ok(Some(link)) => create_local_symlink(&link, &dest),
+                        _ => Err(std::io::Error::other("符号链接缺少目标")),
+                    },
+                    tar::EntryType::Link => {
+                        // hard link to a member extracted earlier
+                        let src = entry
+                            .link_name()
+                            .ok()
+                            .flatten()
+                            .and_then(|l| sanitize_member(&l))
+                            .and_then(|k| extracted.get(&k).cloned());
+                        match src {
+                            Some(src) => std::fs::copy(src, &dest).map(|_| ()),
+                            None => Err(std::io::Error::other("硬链接目标不在本次传输中")),
+                        }
+                    }
+                    _ => {
+                        let tmp = PathBuf::from(format!(
+                            "{}.hp.part.{}",
+                            dest.display(),
+                            std::process::id()
+                        ));
+                        let mut reader = ProgressReader {
+                            inner: &mut entry,
+                            throttler: &mut throttler,
+                            total_pb: ctx.total_pb,
+                            bytes: &mut bytes,
+                        };
+                        let res = std::fs::File::create(&tmp)
+                            .and_then(|mut f| {
+                                std::io::copy(&mut reader, &mut f)?;
+                                f.sync_all()
+                            })
+                            .and_then(|_| apply_local(&tmp, attrs))
+                            .and_then(|_| std::fs::rename(&tmp, &dest));
+                        if res.is_err() {
+                            let _ = std::fs::remove_file(&tmp);
+                        } else {
+                            files += 1;
+                        }
+                        res
+                    }
+                };
+                match res {
+                    Ok(()) => {
+                        extracted.insert(key, dest);
+                    }
+                    Err(e) => fail(format!("download failed: {} — {}", item.full, e)),
+                }
+            }
+        }
+        Err(e) => fail(format!("tar 流读取失败: {}", e)),
+    }
+    throttler.flush(None, ctx.total_pb, None);
+    let mut err_out = String::new();
+    let _ = channel.stderr().read_to_string(&mut err_out);
+    let _ = channel.wait_close();
+    let status = channel.exit_status().unwrap_or(-1);
+    if status != 0 {
+        fail(format!("远端 tar 打包失败（退出码 {}）: {}", status, err_out.trim()));
+    }
+    for item in wanted.values().filter(|i| i.kind != EntryKind::Dir) {
+        fail(format!("download failed: {} — 未包含在 tar 流中", item.full));
+    }
+    // directory times last, after their contents were written
+    dirs.retain(|(_, attrs)| !attrs.is_empty());
+    apply_dir_attrs_local(dirs, ctx.failure_tx);
+    (files, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_outside_the_target_are_rejected() {
+        assert_eq!(sanitize_member(Path::new("logs/app.log")).as_deref(), Some("logs/app.log"));
+        assert_eq!(sanitize_member(Path::new("./a/./b")).as_deref(), Some("a/b"));
+        assert_eq!(sanitize_member(Path::new("../etc/passwd")), None);
+        assert_eq!(sanitize_member(Path::new("a/../../b")), None);
+        assert_eq!(sanitize_member(Path::new("/etc/passwd")), None);
+        assert_eq!(member_key("/var/log/app/"), "var/log/app");
+        assert_eq!(member_key("./logs//a.log"), "logs/a.log");
+    }
+
+    #[test]
+    fn only_a_clean_mktemp_result_names_the_list_file() {
+        assert_eq!(
+            mktemp_path(0, "/tmp/.hp-tar.Ab3dEf9h\n").as_deref(),
+            Some("/tmp/.hp-tar.Ab3dEf9h")
+        );
+        assert_eq!(mktemp_path(1, "mktemp: failed to create file"), None);
+        assert_eq!(mktemp_path(0, ""), None);
+        assert_eq!(mktemp_path(0, "warning\n/tmp/.hp-tar.Ab3dEf9h"), None);
+    }
+}
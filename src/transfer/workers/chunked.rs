@@ -0,0 +1,382 @@
+// Chunked transfer of large files (`--chunk-threshold`): one file is split into byte
+// ranges that several SFTP sessions move at the same time, so a single 50 GB file no
+// longer leaves all but one worker idle.
+//
+// - remote side: every range seeks its own SFTP handle to the range offset
+// - local side:  positional reads/writes on one shared handle (no shared cursor)
+// - both directions write into a temp file that is renamed into place at the end
+// - each range is retried on its own; the file fails only when a range runs out of retries
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use indicatif::ProgressBar;
+
+use super::Throttler;
+use crate::transfer::ratelimit::RateLimiter;
+use crate::transfer::session::SessionPool;
+use crate::transfer::sftp_like::{SftpLike, Ssh2Adapter};
+
+const MIN_CHUNK: u64 = 8 * 1024 * 1024;
+const MAX_CHUNK: u64 = 256 * 1024 * 1024;
+// several ranges per stream so one slow stream does not hold up the tail of the file
+const CHUNKS_PER_STREAM: u64 = 4;
+const MAX_STREAMS: usize = 4;
+// sessions opened for chunks across all workers stay around this many
+const STREAM_BUDGET: usize = 8;
+const IO_BUF: usize = 1024 * 1024;
+
+/// When and how wide large files are split.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ChunkPolicy {
+    pub(crate) threshold: u64,
+    // parallel sessions per file
+    pub(crate) streams: usize,
+}
+
+impl ChunkPolicy {
+    /// Parse `--chunk-threshold` (`512M`, `2G`, `off`; same units as `--limit-rate`).
+    /// Splitting is opt-in: `None` (flag not given) and `off` keep one stream per file.
+    pub(crate) fn from_cli(spec: Option<&str>) -> anyhow::Result<Option<Self>> {
+        let threshold = match spec.map(crate::transfer::ratelimit::parse_rate) {
+            None | Some(Some(None)) => return Ok(None),
+            Some(Some(Some(t))) if t >= 2 * MIN_CHUNK => t,
+            Some(_) => {
+                return Err(crate::TransferError::InvalidArgument(format!(
+                    "无效的 --chunk-threshold: {}（不小于 16M，示例: 512M、2G、off）",
+                    spec.unwrap_or_default()
+                ))
+                .into());
+            }
+        };
+        Ok(Some(ChunkPolicy { threshold, streams: MAX_STREAMS }))
+    }
+
+    /// Narrow the per-file stream count when many workers may split files at once.
+    pub(crate) fn for_workers(self, workers: usize) -> Self {
+        ChunkPolicy { streams: (STREAM_BUDGET / workers.max(1)).clamp(2, MAX_STREAMS), ..self }
+    }
+
+    pub(crate) fn applies(&self, size: u64) -> bool {
+        self.streams > 1 && size >= self.threshold
+    }
+}
+
+/// Byte ranges `(offset, len)` covering `size`.
+pub(crate) fn plan_chunks(size: u64, streams: usize) -> Vec<(u64, u64)> {
+    let per = (size / (streams.max(1) as u64 * CHUNKS_PER_STREAM)).clamp(MIN_CHUNK, MAX_CHUNK);
+    let mut ranges = Vec::new();
+    let mut offset = 0u64;
+    while offset < size {
+        let len = per.min(size - offset);
+        ranges.push((offset, len));
+        offset += len;
+    }
+    ranges
+}
+
+/// Per-file context handed in by the upload/download worker.
+pub(crate) struct ChunkCtx<'a> {
+    pub(crate) policy: ChunkPolicy,
+    pub(crate) server: &'a crate::server::Server,
+    // range sessions come from and go back to the run's pool
+    pub(crate) pool: &'a SessionPool,
+    pub(crate) max_retries: usize,
+    pub(crate) limiter: Option<Arc<RateLimiter>>,
+    pub(crate) meter: Option<Arc<AtomicU64>>,
+    pub(crate) worker_pb: Option<&'a ProgressBar>,
+    pub(crate) total_pb: &'a ProgressBar,
+    pub(crate) bytes_transferred: Option<&'a AtomicU64>,
+    // retry log context of the file
+    pub(crate) label: &'a str,
+}
+
+impl ChunkCtx<'_> {
+    // a failed attempt restarts its range, so its bytes leave the progress again
+    fn rewind(&self, n: u64) {
+        if n == 0 {
+            return;
+        }
+        self.total_pb.dec(n);
+        if let Some(pb) = self.worker_pb {
+            pb.dec(n);
+        }
+        if let Some(bytes) = self.bytes_transferred {
+            bytes.fetch_sub(n, Ordering::SeqCst);
+        }
+        crate::transfer::events::rewind(n);
+    }
+}
+
+type CopyRange<'f> =
+    dyn Fn(&ssh2::Sftp, u64, u64, &mut dyn FnMut(u64)) -> anyhow::Result<()> + Sync + 'f;
+
+// Run `copy(sftp, offset, len, progress)` for every range on `policy.streams` sessions.
+fn run_ranges(ctx: &ChunkCtx, size: u64, copy: &CopyRange) -> anyhow::Result<()> {
+    let ranges = plan_chunks(size, ctx.policy.streams);
+    let next = AtomicUsize::new(0);
+    let failed: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+    // range threads report progress and retries for the worker's entry
+    let entry = crate::transfer::events::current();
+    std::thread::scope(|scope| {
+        for stream in 0..ctx.policy.streams.min(ranges.len()) {
+            let (ranges, next, failed) = (&ranges, &next, &failed);
+            let entry = entry.clone();
+            scope.spawn(move || {
+                crate::transfer::events::adopt(entry);
+                let mut conn: Option<(ssh2::Session, ssh2::Sftp)> = None;
+                let mut throttler = Throttler::new(ctx.limiter.clone()).metered(ctx.meter.clone());
+                loop {
+                    if failed.lock().map(|f| f.is_some()).unwrap_or(true) {
+                        break;
+                    }
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(&(offset, len)) = ranges.get(i) else {
+                        break;
+                    };
+                    let retry_ctx =
+                        format!("{} chunk={}/{} stream={}", ctx.label, i + 1, ranges.len(), stream);
+                    let res = crate::util::retry_operation_with_ctx(
+                        ctx.max_retries,
+                        || -> anyhow::Result<()> {
+                            if conn.is_none() {
+                                conn = Some(ctx.pool.checkout_sftp(ctx.server)?);
+                            }
+                            let Some((_, sftp)) = conn.as_ref() else {
+                                return Err(crate::TransferError::WorkerNoSftp(
+                                    ctx.label.to_string(),
+                                )
+                                .into());
+                            };
+                            let mut moved = 0u64;
+                            let res = copy(sftp, offset, len, &mut |n| {
+                                throttler.tick(
+                                    n,
+                                    ctx.worker_pb,
+                                    ctx.total_pb,
+                                    ctx.bytes_transferred,
+                                );
+                                moved += n;
+                            });
+                            throttler.flush(ctx.worker_pb, ctx.total_pb, ctx.bytes_transferred);
+                            if res.is_err() {
+                                ctx.rewind(moved);
+                                // the next attempt reconnects
+                                conn = None;
+                            }
+                            res
+                        },
+                        crate::util::RetryPhase::DuringTransfer,
+                        &retry_ctx,
+                    );
+                    if let Err(e) = res {
+                        if let Ok(mut f) = failed.lock() {
+                            f.get_or_insert(e.context(format!("分块 {}/{}", i + 1, ranges.len())));
+                        }
+                        break;
+                    }
+                }
+                // warm for the next range or file
+                if let Some((sess, sftp)) = conn {
+                    drop(sftp);
+                    ctx.pool.checkin(ctx.server, sess);
+                }
+            });
+        }
+    });
+    match failed.into_inner().ok().flatten() {
+        // ranges were retried already; the worker must not restart the whole file
+        Some(e) => {
+            Err(crate::TransferError::OperationFailed(format!("分块传输失败: {:#}", e)).into())
+        }
+        None => Ok(()),
+    }
+}
+
+// Run `f` on an SFTP channel of a pooled session, then hand the session back.
+fn with_pooled_sftp<T>(
+    ctx: &ChunkCtx,
+    f: impl FnOnce(&Ssh2Adapter) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let (sess, sftp) = ctx.pool.checkout_sftp(ctx.server)?;
+    let sftp = Ssh2Adapter(sftp);
+    let res = f(&sftp);
+    drop(sftp);
+    ctx.pool.checkin(ctx.server, sess);
+    res
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.hp.part.{}", path.display(), std::process::id()))
+}
+
+fn remote_err(what: &str, path: &Path, e: impl std::fmt::Display) -> anyhow::Error {
+    crate::TransferError::WorkerIo(format!("{}: {} — {}", what, path.display(), e)).into()
+}
+
+/// Upload `local` to `remote` in parallel ranges through `<remote>.hp.part.<pid>`.
+/// Returns the bytes moved.
+pub(crate) fn upload_file(
+    ctx: &ChunkCtx,
+    local: &Path,
+    remote: &Path,
+    size: u64,
+) -> anyhow::Result<u64> {
+    let local_f = File::open(local).map_err(|e| remote_err("本地打开失败", local, e))?;
+    let tmp = part_path(remote);
+    // the session that creates the temp file goes straight back for the ranges to reuse
+    with_pooled_sftp(ctx, |sftp| {
+        sftp.0.create(&tmp).map(drop).map_err(|e| remote_err("远端创建文件失败", &tmp, e))
+    })?;
+    let copy = |sftp: &ssh2::Sftp, offset: u64, len: u64, progress: &mut dyn FnMut(u64)| {
+        let mut f = sftp
+            .open_mode(&tmp, ssh2::OpenFlags::WRITE, 0o644, ssh2::OpenType::File)
+            .map_err(|e| remote_err("远端打开失败", &tmp, e))?;
+        f.seek(SeekFrom::Start(offset)).map_err(|e| remote_err("远端定位失败", &tmp, e))?;
+        let mut buf = vec![0u8; IO_BUF.min(len as usize)];
+        let (mut pos, end) = (offset, offset + len);
+        while pos < end {
+            let n = buf.len().min((end - pos) as usize);
+            read_exact_at(&local_f, &mut buf[..n], pos)
+                .map_err(|e| remote_err("本地读取失败", local, e))?;
+            f.write_all(&buf[..n]).map_err(|e| remote_err("远端写入失败", &tmp, e))?;
+            pos += n as u64;
+            progress(n as u64);
+        }
+        Ok(())
+    };
+    let moved = run_ranges(ctx, size, &copy).and_then(|_| {
+        with_pooled_sftp(ctx, |sftp| {
+            sftp.rename(&tmp, remote).map_err(|e| remote_err("远端重命名失败", remote, e))
+        })
+    });
+    if let Err(e) = moved {
+        let _ = with_pooled_sftp(ctx, |sftp| Ok(sftp.0.unlink(&tmp)));
+        return Err(e);
+    }
+    Ok(size)
+}
+
+/// Download `remote` in parallel ranges into the local temp file `tmp` (created and
+/// sized here; the caller renames it). Returns the bytes moved.
+pub(crate) fn download_file(
+    ctx: &ChunkCtx,
+    remote: &Path,
+    tmp: &Path,
+    size: u64,
+) -> anyhow::Result<u64> {
+    let local_f = File::create(tmp)
+        .and_then(|f| f.set_len(size).map(|_| f))
+        .map_err(|e| remote_err("本地创建文件失败", tmp, e))?;
+    let copy = |sftp: &ssh2::Sftp, offset: u64, len: u64, progress: &mut dyn FnMut(u64)| {
+        let mut f = sftp.open(remote).map_err(|e| remote_err("远端打开失败", remote, e))?;
+        f.seek(SeekFrom::Start(offset)).map_err(|e| remote_err("远端定位失败", remote, e))?;
+        let mut buf = vec![0u8; IO_BUF.min(len as usize)];
+        let (mut pos, end) = (offset, offset + len);
+        while pos < end {
+            let want = buf.len().min((end - pos) as usize);
+            let n = f.read(&mut buf[..want]).map_err(|e| remote_err("远端读取失败", remote, e))?;
+            if n == 0 {
+                return Err(remote_err("远端读取失败", remote, "文件在传输中变短"));
+            }
+            write_all_at(&local_f, &buf[..n], pos)
+                .map_err(|e| remote_err("本地写入失败", tmp, e))?;
+            pos += n as u64;
+            progress(n as u64);
+        }
+        Ok(())
+    };
+    run_ranges(ctx, size, &copy)?;
+    local_f.sync_all().map_err(|e| remote_err("本地同步失败", tmp, e))?;
+    Ok(size)
+}
+
+#[cfg(unix)]
+fn read_exact_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(f, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(f: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(f, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(f: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(f: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_write(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_cover_the_file_without_gaps() {
+        let size = 50 * 1024 * 1024 * 1024 + 7;
+        let ranges = plan_chunks(size, 4);
+        assert_eq!(ranges.iter().map(|r| r.1).sum::<u64>(), size);
+        assert!(ranges.windows(2).all(|w| w[0].0 + w[0].1 == w[1].0));
+        assert!(ranges.iter().all(|r| r.1 <= MAX_CHUNK));
+        // small files still get ranges of at least MIN_CHUNK
+        assert_eq!(plan_chunks(20 * 1024 * 1024, 4).len(), 3);
+    }
+
+    #[test]
+    fn threshold_parses_like_rates() {
+        let p = ChunkPolicy::from_cli(Some("1G")).unwrap().unwrap();
+        assert_eq!(p.threshold, 1024 * 1024 * 1024);
+        assert!(ChunkPolicy::from_cli(Some("off")).unwrap().is_none());
+        assert!(ChunkPolicy::from_cli(Some("1M")).is_err());
+        // opt-in only: without the flag files are not split
+        assert!(ChunkPolicy::from_cli(None).unwrap().is_none());
+        assert_eq!(p.for_workers(1).streams, 4);
+        assert_eq!(p.for_workers(16).streams, 2);
+    }
+
+    #[test]
+    fn positional_writes_reassemble_out_of_order() {
+        let dir = std::env::temp_dir().join(format!("hp_chunk_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("part");
+        let f = File::create(&path).unwrap();
+        f.set_len(8).unwrap();
+        write_all_at(&f, b"5678", 4).unwrap();
+        write_all_at(&f, b"1234", 0).unwrap();
+        let r = File::open(&path).unwrap();
+        let mut buf = [0u8; 4];
+        read_exact_at(&r, &mut buf, 2).unwrap();
+        assert_eq!(&buf, b"3456");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,26 +1,44 @@
 use std::fs::File;
 use std::io::{Read, Write};
 use std::sync::Arc;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
 use crossbeam_channel::Receiver;
 use indicatif::ProgressBar;
 
+use super::chunked::{self, ChunkCtx};
 use super::{
     Throttler, WorkerCommonCtx, WorkerMetrics, finalize_worker_metrics, finish_and_release_pb,
-    prepare_file_progress, report_failure_and_finish_pb,
+    prepare_file_progress, report_failure, report_failure_and_finish_pb, spawn_workers,
 };
+use crate::auto_concurrency::AutoTuner;
 use crate::transfer::workers::pipeline::{
     PipelineConfig, ReadMsg, adapt_buf_size, spawn_file_reader,
 };
 // display_path not needed in this module; keep helpers minimal
 
+use crate::transfer::attrs::{FileAttrs, apply_local};
+use crate::transfer::links::create_local_symlink;
+use crate::transfer::overwrite::{OverwriteDecision, backup_local, local_dest_mtime};
 use crate::transfer::{EntryKind, FileEntry};
 // classifier-aware retry helper is used via crate::util::retry_operation_with_classifier
 
+/// Per-alias counters of one download, indexed like `origins` (fan-in breakdown).
+#[derive(Default)]
+pub(crate) struct OriginStats {
+    pub(crate) files: AtomicU64,
+    pub(crate) bytes: AtomicU64,
+}
+
 pub(crate) struct DownloadWorkersCtx {
     pub(crate) common: WorkerCommonCtx,
+    // (server, addr) per source alias, indexed by `FileEntry::origin`; the first one is
+    // also `common.server`
+    pub(crate) origins: Arc<Vec<(Arc<crate::server::Server>, String)>>,
+    // rate limiter per source alias, indexed like `origins` (`common.rate_limiter` is unused)
+    pub(crate) origin_limiters: Arc<Vec<Option<Arc<crate::transfer::ratelimit::RateLimiter>>>>,
+    pub(crate) origin_stats: Arc<Vec<OriginStats>>,
     pub(crate) file_rx: Receiver<FileEntry>,
     pub(crate) target: String,
     pub(crate) bytes_transferred: Arc<AtomicU64>,
@@ -34,6 +52,9 @@ pub(crate) struct DownloadWorkersCtx {
 pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::JoinHandle<()>> {
     let DownloadWorkersCtx {
         common,
+        origins,
+        origin_limiters,
+        origin_stats,
         file_rx,
         target,
         bytes_transferred,
@@ -47,41 +68,60 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
         mp,
         total_pb,
         file_style,
-        server,
-        addr,
+        server: _,
+        addr: _,
+        pool,
         max_retries,
         target_is_dir_final,
         failure_tx,
         buf_size,
+        attr_policy,
+        overwrite,
+        overwrite_report,
+        rate_limiter: _,
+        chunking,
+        journal,
+        tuner,
     } = common;
-    let mut handles = Vec::new();
-    for worker_id in 0..workers {
+    let admit = tuner.clone();
+    let spawn = move |worker_id: usize| {
         let file_rx = file_rx.clone();
         let mp = mp.clone();
         let total_pb = total_pb.clone();
         let file_style = file_style.clone();
-        let server = server.clone();
+        let origins = origins.clone();
+        let origin_stats = origin_stats.clone();
         let target = target.clone();
         let failure_tx = failure_tx.clone();
         let bytes_transferred = bytes_transferred.clone();
-        let addr = addr.clone();
         let metrics_tx_thread = metrics_tx.clone();
         let pb_slot_rx = pb_slot_rx.clone();
         let pb_slot_tx = pb_slot_tx.clone();
-        let handle = std::thread::spawn(move || {
-            let server_alias = server.alias.as_deref().unwrap_or("<unknown>");
+        let overwrite = overwrite.clone();
+        let overwrite_report = overwrite_report.clone();
+        let origin_limiters = origin_limiters.clone();
+        let pool = pool.clone();
+        let journal = journal.clone();
+        let tuner = tuner.clone();
+        let meter = tuner.as_ref().map(|t| t.meter());
+        std::thread::spawn(move || {
             let mut worker_pb: Option<ProgressBar> = None;
             let mut buf = vec![0u8; buf_size];
             // per-worker adaptive buffer size (bytes). Persists across files.
             let mut current_buf_size = buf_size;
-            let mut maybe_sess: Option<ssh2::Session> = None;
-            let mut maybe_sftp: Option<Box<dyn crate::transfer::sftp_like::SftpLike>> = None;
+            // one session/SFTP slot per source alias, opened on first use
+            let mut sess_slots: Vec<Option<ssh2::Session>> =
+                std::iter::repeat_with(|| None).take(origins.len()).collect();
+            let mut sftp_slots: Vec<Option<Box<dyn crate::transfer::sftp_like::SftpLike>>> =
+                std::iter::repeat_with(|| None).take(origins.len()).collect();
             let mut session_rebuilds: u32 = 0;
             let mut sftp_rebuilds: u32 = 0;
             let worker_start = Instant::now();
             let mut worker_bytes: u64 = 0;
             let mut has_pb_slot = false;
             while let Ok(entry) = file_rx.recv() {
+                // adaptive concurrency: wait for a free slot before working on the entry
+                let _turn = tuner.as_deref().map(AutoTuner::acquire);
                 tracing::debug!(
                     "[ts][download] worker_id={} received entry {}",
                     worker_id,
@@ -89,6 +129,11 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                 );
                 let remote_full = entry.remote_full;
                 let rel = entry.rel;
+                let (server, addr) = &origins[entry.origin];
+                let rate_limiter = &origin_limiters[entry.origin];
+                let server_alias = server.alias.as_deref().unwrap_or("<unknown>");
+                let maybe_sess = &mut sess_slots[entry.origin];
+                let maybe_sftp = &mut sftp_slots[entry.origin];
                 let file_name = std::path::Path::new(&rel)
                     .file_name()
                     .and_then(|n| n.to_str())
@@ -98,6 +143,17 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                 } else {
                     std::path::Path::new(&target).to_path_buf()
                 };
+                // --resume: files an earlier attempt of the job finished are skipped
+                let tracked = match journal.as_deref().filter(|_| entry.kind != EntryKind::Dir) {
+                    Some(j) => match j.begin(local_target.to_string_lossy().to_string()) {
+                        Some(t) => Some(t),
+                        None => {
+                            total_pb.inc(entry.size.unwrap_or(0));
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
                 if let Err(e) = ensure_local_parent(&local_target) {
                     report_failure_and_finish_pb(
                         &failure_tx,
@@ -112,9 +168,12 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                 if entry.kind == EntryKind::Dir {
                     if local_target.exists() {
                         if !local_target.is_dir() {
-                            let _ = failure_tx.send(crate::TransferError::LocalTargetMustBeDir(
-                                local_target.display().to_string(),
-                            ));
+                            report_failure(
+                                &failure_tx,
+                                crate::TransferError::LocalTargetMustBeDir(
+                                    local_target.display().to_string(),
+                                ),
+                            );
                         }
                     } else if let Err(e) = ensure_local_parent(&local_target) {
                         report_failure_and_finish_pb(
@@ -139,6 +198,13 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                     finish_and_release_pb(&mut worker_pb, Some(&pb_slot_tx), &mut has_pb_slot);
                     continue;
                 }
+                let bytes_before = worker_bytes;
+                crate::transfer::events::started(
+                    server_alias,
+                    worker_id,
+                    &rel,
+                    entry.size.unwrap_or(0),
+                );
                 prepare_file_progress(
                     &mut worker_pb,
                     &mp,
@@ -155,10 +221,11 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                     max_retries,
                     || -> anyhow::Result<()> {
                         crate::transfer::session::ensure_session_and_sftp(
-                            &mut maybe_sess,
-                            &mut maybe_sftp,
-                            &server,
-                            &addr,
+                            maybe_sess,
+                            maybe_sftp,
+                            &pool,
+                            server,
+                            addr,
                             &mut session_rebuilds,
                             &mut sftp_rebuilds,
                         )?;
@@ -172,17 +239,92 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                     &pre_ctx,
                 ) {
                     tracing::debug!("[ts][download] pre-transfer failed for {}: {}", rel, e);
-                    let _ = failure_tx.send(crate::TransferError::WorkerIo(format!(
+                    let err = crate::TransferError::WorkerIo(format!(
                         "pre-transfer failed: {} — {}",
                         remote_full, e
-                    )));
+                    ));
+                    report_failure(
+                        &failure_tx,
+                        err.for_entry(
+                            server_alias,
+                            format!("{}:{}", server_alias, remote_full),
+                            local_target.display().to_string(),
+                        ),
+                    );
                     // reset state for next file
-                    maybe_sftp = None;
-                    maybe_sess = None;
+                    *maybe_sftp = None;
+                    *maybe_sess = None;
                     finish_and_release_pb(&mut worker_pb, Some(&pb_slot_tx), &mut has_pb_slot);
                     continue;
                 }
 
+                // 符号链接（--links copy）：在本地重建链接本身
+                if entry.kind == EntryKind::Symlink {
+                    let res = maybe_sftp
+                        .as_ref()
+                        .ok_or_else(|| "no sftp".to_string())
+                        .and_then(|sftp| sftp.read_link(std::path::Path::new(&remote_full)))
+                        .and_then(|target| {
+                            create_local_symlink(&target, &local_target).map_err(|e| e.to_string())
+                        });
+                    if let Err(e) = res {
+                        report_failure(
+                            &failure_tx,
+                            crate::TransferError::WorkerIo(format!(
+                                "符号链接复制失败: {} — {}",
+                                remote_full, e
+                            )),
+                        );
+                    }
+                    finish_and_release_pb(&mut worker_pb, Some(&pb_slot_tx), &mut has_pb_slot);
+                    continue;
+                }
+
+                // --no-clobber/--update/--backup against the existing local file
+                if overwrite.is_active()
+                    && let Some(dest_mtime) = local_dest_mtime(&local_target)
+                {
+                    let src_mtime = if overwrite.update {
+                        maybe_sftp.as_ref().and_then(|s| {
+                            s.stat_attrs(std::path::Path::new(&remote_full)).ok()?.mtime
+                        })
+                    } else {
+                        None
+                    };
+                    let dest = local_target.display().to_string();
+                    match overwrite.decide(&dest, src_mtime, Some(dest_mtime)) {
+                        OverwriteDecision::Skip => {
+                            overwrite_report.record_skip(&dest);
+                            total_pb.inc(entry.size.unwrap_or(0));
+                            finish_and_release_pb(
+                                &mut worker_pb,
+                                Some(&pb_slot_tx),
+                                &mut has_pb_slot,
+                            );
+                            continue;
+                        }
+                        OverwriteDecision::Backup(backup) => {
+                            if let Err(e) =
+                                backup_local(&local_target, std::path::Path::new(&backup))
+                            {
+                                report_failure_and_finish_pb(
+                                    &failure_tx,
+                                    crate::TransferError::WorkerIo(format!(
+                                        "本地备份失败: {} — {}",
+                                        dest, e
+                                    )),
+                                    &mut worker_pb,
+                                    Some(&pb_slot_tx),
+                                    &mut has_pb_slot,
+                                );
+                                continue;
+                            }
+                            overwrite_report.record_backup(&dest, &backup);
+                        }
+                        OverwriteDecision::Write => {}
+                    }
+                }
+
                 // Streaming transfer with DuringTransfer policy
                 let transfer_res = crate::util::retry_operation_with_ctx(
                     max_retries,
@@ -197,6 +339,39 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                                 crate::TransferError::WorkerIo(format!("remote open failed: {}", e))
                                     .into()
                             })?;
+                        let dest_attrs = if attr_policy.is_active() {
+                            let src = if attr_policy.preserve {
+                                sftp.stat_attrs(remote_path).unwrap_or_else(|e| {
+                                    report_failure(
+                                        &failure_tx,
+                                        crate::TransferError::PreserveFailed(
+                                            remote_full.clone(),
+                                            e,
+                                        ),
+                                    );
+                                    FileAttrs::default()
+                                })
+                            } else {
+                                FileAttrs::default()
+                            };
+                            attr_policy.resolve(src, false)
+                        } else {
+                            FileAttrs::default()
+                        };
+                        // applied to the temp file so the final path appears with its attributes
+                        let apply_attrs = |tmp: &std::path::Path| {
+                            if !dest_attrs.is_empty()
+                                && let Err(e) = apply_local(tmp, dest_attrs)
+                            {
+                                report_failure(
+                                    &failure_tx,
+                                    crate::TransferError::PreserveFailed(
+                                        local_target.display().to_string(),
+                                        e.to_string(),
+                                    ),
+                                );
+                            }
+                        };
 
                         let parent =
                             local_target.parent().unwrap_or_else(|| std::path::Path::new("."));
@@ -215,6 +390,43 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                         let tmp_name = format!("{}.{:x}", tmp_name, ts);
                         let tmp_path = parent.join(tmp_name);
 
+                        let file_size = entry.size.unwrap_or(0);
+                        if let Some(policy) = chunking.filter(|c| c.applies(file_size)) {
+                            drop(remote_f);
+                            let chunk_ctx = ChunkCtx {
+                                policy,
+                                server,
+                                pool: &pool,
+                                max_retries,
+                                limiter: rate_limiter.clone(),
+                                meter: meter.clone(),
+                                worker_pb: worker_pb.as_ref(),
+                                total_pb: &total_pb,
+                                bytes_transferred: Some(&bytes_transferred),
+                                label: &rel,
+                            };
+                            match chunked::download_file(
+                                &chunk_ctx,
+                                remote_path,
+                                &tmp_path,
+                                file_size,
+                            ) {
+                                Ok(n) => worker_bytes += n,
+                                Err(e) => {
+                                    let _ = std::fs::remove_file(&tmp_path);
+                                    return Err(e);
+                                }
+                            }
+                            apply_attrs(&tmp_path);
+                            if let Err(e) = atomic_rename_with_retries(&tmp_path, &local_target) {
+                                return cleanup_tmp_and_err(
+                                    &tmp_path,
+                                    crate::TransferError::WorkerIo(format!("rename failed: {}", e)),
+                                );
+                            }
+                            return Ok(());
+                        }
+
                         // Decide whether to use pipeline: only enable for files larger than threshold
                         let cfg = PipelineConfig::current();
                         if entry.size.unwrap_or(0) >= cfg.enable_min {
@@ -231,7 +443,8 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                                     ))
                                     .into()
                                 })?;
-                            let mut throttler = Throttler::new();
+                            let mut throttler =
+                                Throttler::new(rate_limiter.clone()).metered(meter.clone());
                             let mut file_write_bytes: u64 = 0;
                             let mut total_write_time = Duration::from_secs(0);
                             loop {
@@ -312,6 +525,7 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                                 current_buf_size = new_size;
                             }
 
+                            apply_attrs(&tmp_path);
                             if let Err(e) = atomic_rename_with_retries(&tmp_path, &local_target) {
                                 return cleanup_tmp_and_err(
                                     &tmp_path,
@@ -328,7 +542,8 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                                     ))
                                     .into()
                                 })?;
-                            let mut throttler = Throttler::new();
+                            let mut throttler =
+                                Throttler::new(rate_limiter.clone()).metered(meter.clone());
                             loop {
                                 match remote_f.read(&mut buf) {
                                     Ok(0) => break,
@@ -376,6 +591,7 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                                 );
                             }
                             drop(local_f);
+                            apply_attrs(&tmp_path);
                             if let Err(e) = atomic_rename_with_retries(&tmp_path, &local_target) {
                                 return cleanup_tmp_and_err(
                                     &tmp_path,
@@ -389,18 +605,33 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                     &format!("download stream worker={} file={}", worker_id, rel),
                 );
 
+                if let Some(t) = tracked {
+                    t.finish(&transfer_res, entry.size.unwrap_or(0));
+                }
                 if let Err(e) = transfer_res {
                     tracing::debug!(
                         "[ts][download] transfer failed for {}: {}; reset SFTP for next try",
                         rel,
                         e
                     );
-                    let _ = failure_tx.send(crate::TransferError::WorkerIo(format!(
+                    let err = crate::TransferError::WorkerIo(format!(
                         "download failed: {} — {}",
                         remote_full, e
-                    )));
+                    ));
+                    report_failure(
+                        &failure_tx,
+                        err.for_entry(
+                            server_alias,
+                            format!("{}:{}", server_alias, remote_full),
+                            local_target.display().to_string(),
+                        ),
+                    );
                     // Drop SFTP to force recreation on next attempt/file
-                    maybe_sftp = None;
+                    *maybe_sftp = None;
+                } else {
+                    let stats = &origin_stats[entry.origin];
+                    stats.files.fetch_add(1, Ordering::SeqCst);
+                    stats.bytes.fetch_add(worker_bytes - bytes_before, Ordering::SeqCst);
                 }
 
                 finish_and_release_pb(&mut worker_pb, Some(&pb_slot_tx), &mut has_pb_slot);
@@ -408,6 +639,13 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                     tracing::debug!("[ts][download] finished {}", file_name);
                 }
             }
+            // keep the sessions warm for directory attributes and the mirror pass
+            drop(sftp_slots);
+            for (sess, (server, _)) in sess_slots.into_iter().zip(origins.iter()) {
+                if let Some(sess) = sess {
+                    pool.checkin(server, sess);
+                }
+            }
             finalize_worker_metrics(
                 "download",
                 worker_bytes,
@@ -416,10 +654,9 @@ pub(crate) fn run_download_workers(ctx: DownloadWorkersCtx) -> Vec<std::thread::
                 sftp_rebuilds,
                 &metrics_tx_thread,
             );
-        });
-        handles.push(handle);
-    }
-    handles
+        })
+    };
+    spawn_workers(workers, admit, spawn)
 }
 
 fn ensure_local_parent(path: &std::path::Path) -> Result<(), crate::TransferError> {
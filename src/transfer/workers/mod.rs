@@ -1,7 +1,9 @@
+pub(super) mod chunked;
 pub(super) mod download;
 #[cfg(test)]
 pub(super) mod mock_io;
 pub(super) mod pipeline;
+pub(super) mod relay;
 pub(super) mod upload;
 
 use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
@@ -17,10 +19,25 @@ pub(super) struct WorkerCommonCtx {
     pub(super) file_style: ProgressStyle,
     pub(super) server: Arc<crate::server::Server>,
     pub(super) addr: String,
+    // sessions shared with the probe, enumeration and chunk ranges of the run
+    pub(super) pool: Arc<crate::transfer::session::SessionPool>,
     pub(super) max_retries: usize,
     pub(super) target_is_dir_final: bool,
     pub(super) failure_tx: Sender<crate::TransferError>,
     pub(super) buf_size: usize,
+    // -p/--preserve, --chmod, --umask; directory attributes are applied after the run
+    pub(super) attr_policy: crate::transfer::attrs::AttrPolicy,
+    // --no-clobber / --update / --backup, decided per file before it is written
+    pub(super) overwrite: crate::transfer::overwrite::OverwritePolicy,
+    pub(super) overwrite_report: Arc<crate::transfer::overwrite::OverwriteReport>,
+    // --limit-rate / alias default; one bucket shared by every worker
+    pub(super) rate_limiter: Option<Arc<crate::transfer::ratelimit::RateLimiter>>,
+    // --chunk-threshold: files at or above it are split across several sessions
+    pub(super) chunking: Option<chunked::ChunkPolicy>,
+    // transfer journal of the run; absent for dry runs
+    pub(super) journal: Option<Arc<crate::transfer::journal::Journal>>,
+    // adaptive concurrency (no -c): admits `limit` of `workers` at a time
+    pub(super) tuner: Option<Arc<crate::auto_concurrency::AutoTuner>>,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -30,6 +47,43 @@ pub(super) struct WorkerMetrics {
     pub(super) sftp_rebuilds: u32,
 }
 
+// Start `workers` threads via `spawn(worker_id)`. With adaptive concurrency only as many as
+// the tuner admits are started, more as its limit grows: every worker holds its own sessions,
+// so starting the whole cap up front would open sessions that stay idle behind the gate.
+// The returned handles finish once every started worker has returned.
+pub(super) fn spawn_workers<F>(
+    workers: usize,
+    tuner: Option<Arc<crate::auto_concurrency::AutoTuner>>,
+    mut spawn: F,
+) -> Vec<std::thread::JoinHandle<()>>
+where
+    F: FnMut(usize) -> std::thread::JoinHandle<()> + Send + 'static,
+{
+    let Some(tuner) = tuner else {
+        return (0..workers).map(spawn).collect();
+    };
+    let grower = std::thread::spawn(move || {
+        let mut handles = Vec::new();
+        loop {
+            let want = tuner.limit().min(workers).max(1);
+            while handles.len() < want {
+                handles.push(spawn(handles.len()));
+            }
+            // all started or the queue already drained (every worker returned)
+            if handles.len() >= workers || handles.iter().all(|h| h.is_finished()) {
+                break;
+            }
+            std::thread::sleep(GROW_POLL);
+        }
+        for h in handles {
+            let _ = h.join();
+        }
+    });
+    vec![grower]
+}
+
+const GROW_POLL: Duration = Duration::from_millis(200);
+
 // Helpers shared by upload/download workers
 
 pub(super) fn try_acquire_pb_slot(slot_rx: &Receiver<()>, has_slot: &mut bool) {
@@ -79,6 +133,7 @@ pub(super) fn finish_and_release_pb(
     if let Some(pb) = worker_pb.take() {
         pb.finish_and_clear();
     }
+    crate::transfer::events::finished();
     if let (Some(tx), true) = (slot_tx, *has_slot) {
         let _ = tx.send(());
         *has_slot = false;
@@ -88,11 +143,20 @@ pub(super) fn finish_and_release_pb(
 pub(super) struct Throttler {
     pending: u64,
     last_flush: Instant,
+    limiter: Option<Arc<crate::transfer::ratelimit::RateLimiter>>,
+    // bytes moved by every worker of the run, sampled by the adaptive concurrency
+    meter: Option<Arc<AtomicU64>>,
 }
 
 impl Throttler {
-    pub(super) fn new() -> Self {
-        Self { pending: 0, last_flush: Instant::now() }
+    /// `limiter` is charged for every byte before progress is counted.
+    pub(super) fn new(limiter: Option<Arc<crate::transfer::ratelimit::RateLimiter>>) -> Self {
+        Self { pending: 0, last_flush: Instant::now(), limiter, meter: None }
+    }
+
+    /// Also count the bytes into `meter` (throughput samples of `AutoTuner`).
+    pub(super) fn metered(self, meter: Option<Arc<AtomicU64>>) -> Self {
+        Self { meter, ..self }
     }
 
     #[inline]
@@ -103,6 +167,9 @@ impl Throttler {
         total_pb: &ProgressBar,
         bytes_transferred: Option<&AtomicU64>,
     ) {
+        if let Some(ref limiter) = self.limiter {
+            limiter.acquire(n);
+        }
         self.pending += n;
         if self.pending >= 64 * 1024 || self.last_flush.elapsed() >= Duration::from_millis(50) {
             if let Some(pb) = worker_pb {
@@ -112,6 +179,10 @@ impl Throttler {
             if let Some(bytes) = bytes_transferred {
                 bytes.fetch_add(self.pending, Ordering::SeqCst);
             }
+            if let Some(ref meter) = self.meter {
+                meter.fetch_add(self.pending, Ordering::Relaxed);
+            }
+            crate::transfer::events::progress(self.pending);
             self.pending = 0;
             self.last_flush = Instant::now();
         }
@@ -132,6 +203,10 @@ impl Throttler {
             if let Some(bytes) = bytes_transferred {
                 bytes.fetch_add(self.pending, Ordering::SeqCst);
             }
+            if let Some(ref meter) = self.meter {
+                meter.fetch_add(self.pending, Ordering::Relaxed);
+            }
+            crate::transfer::events::progress(self.pending);
             self.pending = 0;
             self.last_flush = Instant::now();
         }
@@ -192,6 +267,15 @@ pub(super) fn finalize_worker_metrics(
     let _ = metrics_tx.send(WorkerMetrics { bytes: worker_bytes, session_rebuilds, sftp_rebuilds });
 }
 
+/// Send a failure record of the run; it also goes out as a `failed` event (`--events`).
+pub(super) fn report_failure(
+    failure_tx: &Sender<crate::TransferError>,
+    error: crate::TransferError,
+) {
+    crate::transfer::events::failed(&error);
+    let _ = failure_tx.send(error);
+}
+
 pub(super) fn report_failure_and_finish_pb(
     failure_tx: &Sender<crate::TransferError>,
     error: crate::TransferError,
@@ -199,6 +283,45 @@ pub(super) fn report_failure_and_finish_pb(
     pb_slot_tx: Option<&Sender<()>>,
     has_pb_slot: &mut bool,
 ) {
-    let _ = failure_tx.send(error);
+    report_failure(failure_tx, error);
     finish_and_release_pb(worker_pb, pb_slot_tx, has_pb_slot);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auto_concurrency::AutoTuner;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn adaptive_run_starts_only_admitted_workers() {
+        let tuner = AutoTuner::spawn(2, 8);
+        let (tx, rx) = unbounded::<()>();
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let handles = spawn_workers(8, Some(tuner.clone()), move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let rx = rx.clone();
+            std::thread::spawn(move || while rx.recv().is_ok() {})
+        });
+        std::thread::sleep(GROW_POLL * 2);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        tuner.set_limit_for_test(5);
+        std::thread::sleep(GROW_POLL * 2);
+        assert_eq!(started.load(Ordering::SeqCst), 5);
+
+        drop(tx);
+        for h in handles {
+            h.join().unwrap();
+        }
+        tuner.finish();
+        assert_eq!(started.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn fixed_run_starts_every_worker() {
+        let handles = spawn_workers(3, None, |_| std::thread::spawn(|| {}));
+        assert_eq!(handles.len(), 3);
+    }
+}
@@ -0,0 +1,479 @@
+// Remote-to-remote relay: every worker holds one SFTP session to the source alias and
+// one to the destination alias and streams each file reader -> writer through memory.
+// Nothing touches the local disk.
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+use indicatif::ProgressBar;
+
+use super::upload::sftp_mkdir_p;
+use super::{
+    Throttler, WorkerCommonCtx, WorkerMetrics, finalize_worker_metrics, finish_and_release_pb,
+    prepare_file_progress, report_failure, report_failure_and_finish_pb, spawn_workers,
+};
+use crate::auto_concurrency::AutoTuner;
+use crate::transfer::attrs::FileAttrs;
+use crate::transfer::helpers::{display_path, normalize_path};
+use crate::transfer::overwrite::OverwriteDecision;
+use crate::transfer::session::SessionPool;
+use crate::transfer::sftp_like::SftpLike;
+use crate::transfer::workers::pipeline::{
+    PipelineConfig, ReadMsg, adapt_buf_size, spawn_file_reader,
+};
+use crate::transfer::{EntryKind, FileEntry};
+
+pub(crate) struct RelayWorkersCtx {
+    // `common.server`/`common.addr` describe the destination alias
+    pub(crate) common: WorkerCommonCtx,
+    pub(crate) src_server: Arc<crate::server::Server>,
+    pub(crate) src_addr: String,
+    pub(crate) file_rx: Receiver<FileEntry>,
+    pub(crate) dest_base: String,
+    pub(crate) bytes_transferred: Arc<AtomicU64>,
+    pub(crate) metrics_tx: Sender<WorkerMetrics>,
+    // 最多仅允许 8 个可见文件进度条（通过槽位令牌实现）；不影响传输并发
+    pub(crate) pb_slot_rx: Receiver<()>,
+    pub(crate) pb_slot_tx: Sender<()>,
+}
+
+// Session + SFTP of one side, rebuilt lazily after failures.
+#[derive(Default)]
+struct Side {
+    sess: Option<ssh2::Session>,
+    sftp: Option<Box<dyn SftpLike>>,
+}
+
+impl Side {
+    fn ensure(
+        &mut self,
+        pool: &SessionPool,
+        server: &crate::server::Server,
+        addr: &str,
+        session_rebuilds: &mut u32,
+        sftp_rebuilds: &mut u32,
+    ) -> anyhow::Result<()> {
+        crate::transfer::session::ensure_session_and_sftp(
+            &mut self.sess,
+            &mut self.sftp,
+            pool,
+            server,
+            addr,
+            session_rebuilds,
+            sftp_rebuilds,
+        )
+    }
+
+    // hand a healthy session back to the pool once the worker is done
+    fn release(self, pool: &SessionPool, server: &crate::server::Server) {
+        drop(self.sftp);
+        if let Some(sess) = self.sess {
+            pool.checkin(server, sess);
+        }
+    }
+
+    fn sftp(&self, alias: &str) -> anyhow::Result<&dyn SftpLike> {
+        self.sftp
+            .as_deref()
+            .ok_or_else(|| crate::TransferError::WorkerNoSftp(alias.to_string()).into())
+    }
+}
+
+pub(crate) fn run_relay_workers(ctx: RelayWorkersCtx) -> Vec<std::thread::JoinHandle<()>> {
+    let RelayWorkersCtx {
+        common,
+        src_server,
+        src_addr,
+        file_rx,
+        dest_base,
+        bytes_transferred,
+        metrics_tx,
+        pb_slot_rx,
+        pb_slot_tx,
+    } = ctx;
+    let WorkerCommonCtx {
+        workers,
+        mp,
+        total_pb,
+        file_style,
+        server,
+        addr,
+        pool,
+        max_retries,
+        target_is_dir_final,
+        failure_tx,
+        buf_size,
+        attr_policy,
+        overwrite,
+        overwrite_report,
+        rate_limiter,
+        chunking: _,
+        journal,
+        tuner,
+    } = common;
+    let admit = tuner.clone();
+    let spawn = move |worker_id: usize| {
+        let file_rx = file_rx.clone();
+        let mp = mp.clone();
+        let total_pb = total_pb.clone();
+        let file_style = file_style.clone();
+        let (src_server, src_addr) = (src_server.clone(), src_addr.clone());
+        let (dst_server, dst_addr) = (server.clone(), addr.clone());
+        let dest_base = dest_base.clone();
+        let failure_tx = failure_tx.clone();
+        let bytes_transferred = bytes_transferred.clone();
+        let metrics_tx_thread = metrics_tx.clone();
+        let pb_slot_rx = pb_slot_rx.clone();
+        let pb_slot_tx = pb_slot_tx.clone();
+        let overwrite = overwrite.clone();
+        let overwrite_report = overwrite_report.clone();
+        let rate_limiter = rate_limiter.clone();
+        let pool = pool.clone();
+        let journal = journal.clone();
+        let tuner = tuner.clone();
+        let meter = tuner.as_ref().map(|t| t.meter());
+        std::thread::spawn(move || {
+            let src_alias = src_server.alias.as_deref().unwrap_or("<unknown>");
+            let dst_alias = dst_server.alias.as_deref().unwrap_or("<unknown>");
+            let mut worker_pb: Option<ProgressBar> = None;
+            let mut current_buf_size = buf_size;
+            let mut created_dirs: HashSet<String> = HashSet::new();
+            let mut src = Side::default();
+            let mut dst = Side::default();
+            let mut session_rebuilds: u32 = 0;
+            let mut sftp_rebuilds: u32 = 0;
+            let worker_start = Instant::now();
+            let mut worker_bytes: u64 = 0;
+            let mut has_pb_slot = false;
+            while let Ok(entry) = file_rx.recv() {
+                // adaptive concurrency: wait for a free slot before working on the entry
+                let _turn = tuner.as_deref().map(AutoTuner::acquire);
+                let FileEntry { remote_full, rel, size, kind, .. } = entry;
+                let dest_path_str = if target_is_dir_final {
+                    format!("{}/{}", dest_base.trim_end_matches('/'), normalize_path(&rel, true))
+                } else {
+                    dest_base.clone()
+                };
+                let dest_path = std::path::Path::new(&dest_path_str);
+                let src_path = std::path::Path::new(&remote_full);
+                // --resume: files an earlier attempt of the job finished are skipped
+                let tracked = match journal.as_deref().filter(|_| kind != EntryKind::Dir) {
+                    Some(j) => match j.begin(format!("{}:{}", dst_alias, dest_path_str)) {
+                        Some(t) => Some(t),
+                        None => {
+                            total_pb.inc(size.unwrap_or(0));
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                // Pre-transfer: both sides must be connected
+                let pre_ctx = format!("relay pre-transfer worker={} file={}", worker_id, rel);
+                if let Err(e) = crate::util::retry_operation_with_ctx(
+                    max_retries,
+                    || -> anyhow::Result<()> {
+                        src.ensure(
+                            &pool,
+                            &src_server,
+                            &src_addr,
+                            &mut session_rebuilds,
+                            &mut sftp_rebuilds,
+                        )?;
+                        dst.ensure(
+                            &pool,
+                            &dst_server,
+                            &dst_addr,
+                            &mut session_rebuilds,
+                            &mut sftp_rebuilds,
+                        )
+                    },
+                    crate::util::RetryPhase::PreTransfer,
+                    &pre_ctx,
+                ) {
+                    let err = crate::TransferError::WorkerIo(format!(
+                        "pre-transfer failed: {} — {}",
+                        remote_full, e
+                    ));
+                    if kind == EntryKind::Dir {
+                        report_failure(&failure_tx, err);
+                    } else {
+                        report_failure(
+                            &failure_tx,
+                            err.for_entry(
+                                src_alias,
+                                format!("{}:{}", src_alias, remote_full),
+                                format!("{}:{}", dst_alias, dest_path_str),
+                            ),
+                        );
+                    }
+                    src = Side::default();
+                    dst = Side::default();
+                    continue;
+                }
+
+                let res = (|| -> anyhow::Result<()> {
+                    let ssftp = src.sftp(src_alias)?;
+                    let dsftp = dst.sftp(dst_alias)?;
+                    let mkdir = |p: &std::path::Path,
+                                 created: &mut HashSet<String>|
+                     -> anyhow::Result<()> {
+                        let s = p.to_string_lossy().to_string();
+                        if !created.contains(&s) {
+                            sftp_mkdir_p(dsftp, p).map_err(|e| -> anyhow::Error {
+                                crate::TransferError::CreateRemoteDirFailed(
+                                    s.clone(),
+                                    e.to_string(),
+                                )
+                                .into()
+                            })?;
+                            created.insert(s);
+                        }
+                        Ok(())
+                    };
+                    if kind == EntryKind::Dir {
+                        return mkdir(dest_path, &mut created_dirs);
+                    }
+                    if let Some(parent) = dest_path.parent() {
+                        mkdir(parent, &mut created_dirs)?;
+                    }
+                    if kind == EntryKind::Symlink {
+                        let target = ssftp.read_link(src_path).map_err(|e| -> anyhow::Error {
+                            crate::TransferError::WorkerIo(format!(
+                                "读取源端符号链接失败: {} — {}",
+                                remote_full, e
+                            ))
+                            .into()
+                        })?;
+                        let target = crate::transfer::links::remote_link_target(&target);
+                        return dsftp.symlink(&target, dest_path).map_err(|e| {
+                            crate::TransferError::WorkerIo(format!(
+                                "创建目标端符号链接失败: {} — {}",
+                                dest_path_str, e
+                            ))
+                            .into()
+                        });
+                    }
+                    Ok(())
+                })();
+                if let Err(e) = res {
+                    report_failure(
+                        &failure_tx,
+                        e.downcast::<crate::TransferError>()
+                            .unwrap_or_else(|e| crate::TransferError::WorkerIo(e.to_string())),
+                    );
+                    continue;
+                }
+                if kind != EntryKind::File {
+                    continue;
+                }
+
+                // source attributes serve --update and --preserve alike
+                let src_attrs = if overwrite.update || attr_policy.preserve {
+                    src.sftp.as_ref().and_then(|s| s.stat_attrs(src_path).ok()).unwrap_or_default()
+                } else {
+                    FileAttrs::default()
+                };
+                if overwrite.is_active()
+                    && let Some(d) = dst.sftp.as_deref()
+                    && let Ok(true) = d.stat_is_file(dest_path)
+                {
+                    let dest_mtime = d.stat_attrs(dest_path).ok().and_then(|a| a.mtime);
+                    match overwrite.decide(&dest_path_str, src_attrs.mtime, Some(dest_mtime)) {
+                        OverwriteDecision::Skip => {
+                            overwrite_report.record_skip(&dest_path_str);
+                            total_pb.inc(size.unwrap_or(0));
+                            continue;
+                        }
+                        OverwriteDecision::Backup(backup) => {
+                            if let Err(e) = d.rename(dest_path, std::path::Path::new(&backup)) {
+                                report_failure(
+                                    &failure_tx,
+                                    crate::TransferError::WorkerIo(format!(
+                                        "远端备份失败: {} — {}",
+                                        dest_path_str, e
+                                    )),
+                                );
+                                continue;
+                            }
+                            overwrite_report.record_backup(&dest_path_str, &backup);
+                        }
+                        OverwriteDecision::Write => {}
+                    }
+                }
+
+                crate::transfer::events::started(dst_alias, worker_id, &rel, size.unwrap_or(0));
+                prepare_file_progress(
+                    &mut worker_pb,
+                    &mp,
+                    &file_style,
+                    &pb_slot_rx,
+                    &mut has_pb_slot,
+                    size.unwrap_or(0),
+                    &rel,
+                );
+                let transfer_res = crate::util::retry_operation_with_ctx(
+                    max_retries,
+                    || -> anyhow::Result<()> {
+                        // a failed attempt may have dropped either side
+                        src.ensure(
+                            &pool,
+                            &src_server,
+                            &src_addr,
+                            &mut session_rebuilds,
+                            &mut sftp_rebuilds,
+                        )?;
+                        dst.ensure(
+                            &pool,
+                            &dst_server,
+                            &dst_addr,
+                            &mut session_rebuilds,
+                            &mut sftp_rebuilds,
+                        )?;
+                        let ssftp = src.sftp(src_alias)?;
+                        let dsftp = dst.sftp(dst_alias)?;
+                        let reader = ssftp.open_read(src_path).map_err(|e| -> anyhow::Error {
+                            crate::TransferError::WorkerIo(format!("remote open failed: {}", e))
+                                .into()
+                        })?;
+                        let mut writer =
+                            dsftp.create_write(dest_path).map_err(|e| -> anyhow::Error {
+                                crate::TransferError::WorkerIo(format!(
+                                    "远端创建文件失败: {} — {}",
+                                    display_path(dest_path),
+                                    e
+                                ))
+                                .into()
+                            })?;
+                        // reads from the source overlap with writes to the destination
+                        let cfg = PipelineConfig::current();
+                        let (chunk_tx, chunk_rx) = crossbeam_channel::bounded::<ReadMsg>(cfg.depth);
+                        let reader_handle = spawn_file_reader(reader, chunk_tx, current_buf_size);
+                        let mut throttler =
+                            Throttler::new(rate_limiter.clone()).metered(meter.clone());
+                        let mut file_write_bytes: u64 = 0;
+                        let mut total_write_time = Duration::from_secs(0);
+                        let streamed = loop {
+                            match chunk_rx.recv() {
+                                Ok(ReadMsg::Data(chunk)) => {
+                                    let write_start = Instant::now();
+                                    if let Err(e) = writer.write_all(&chunk) {
+                                        break Err(crate::TransferError::WorkerIo(format!(
+                                            "远端写入失败: {} — {}",
+                                            display_path(dest_path),
+                                            e
+                                        )));
+                                    }
+                                    total_write_time += write_start.elapsed();
+                                    let n = chunk.len() as u64;
+                                    file_write_bytes += n;
+                                    worker_bytes += n;
+                                    throttler.tick(
+                                        n,
+                                        worker_pb.as_ref(),
+                                        &total_pb,
+                                        Some(&bytes_transferred),
+                                    );
+                                }
+                                Ok(ReadMsg::Err(msg)) => {
+                                    break Err(crate::TransferError::WorkerIo(msg));
+                                }
+                                Ok(ReadMsg::Eof) => break Ok(()),
+                                Err(_) => {
+                                    break Err(crate::TransferError::WorkerIo(
+                                        "读取通道断开".to_string(),
+                                    ));
+                                }
+                            }
+                        };
+                        // unblock and reap the reader before bailing out
+                        drop(chunk_rx);
+                        let _ = reader_handle.join();
+                        throttler.flush(worker_pb.as_ref(), &total_pb, Some(&bytes_transferred));
+                        let closed = writer.flush();
+                        drop(writer);
+                        streamed?;
+                        closed.map_err(|e| {
+                            crate::TransferError::WorkerIo(format!(
+                                "远端写入失败: {} — {}",
+                                display_path(dest_path),
+                                e
+                            ))
+                        })?;
+
+                        let attrs = attr_policy.resolve(src_attrs, false);
+                        if attr_policy.is_active()
+                            && !attrs.is_empty()
+                            && let Err(e) = dsftp.set_attrs(dest_path, attrs)
+                        {
+                            report_failure(
+                                &failure_tx,
+                                crate::TransferError::PreserveFailed(dest_path_str.clone(), e),
+                            );
+                        }
+                        let new_size = adapt_buf_size(
+                            current_buf_size,
+                            file_write_bytes,
+                            total_write_time,
+                            cfg.target_ms,
+                            cfg.min_size,
+                            cfg.max_size,
+                        );
+                        if new_size != current_buf_size {
+                            tracing::debug!(
+                                "[ts][relay] worker_id={} adapt buf {} -> {}",
+                                worker_id,
+                                current_buf_size,
+                                new_size
+                            );
+                            current_buf_size = new_size;
+                        }
+                        Ok(())
+                    },
+                    crate::util::RetryPhase::DuringTransfer,
+                    &format!("relay stream worker={} file={}", worker_id, rel),
+                );
+                if let Some(t) = tracked {
+                    t.finish(&transfer_res, size.unwrap_or(0));
+                }
+                if let Err(e) = transfer_res {
+                    tracing::debug!("[ts][relay] transfer failed for {}: {}", rel, e);
+                    let err = crate::TransferError::WorkerIo(format!(
+                        "中转失败: {} -> {} — {}",
+                        remote_full, dest_path_str, e
+                    ));
+                    report_failure_and_finish_pb(
+                        &failure_tx,
+                        err.for_entry(
+                            src_alias,
+                            format!("{}:{}", src_alias, remote_full),
+                            format!("{}:{}", dst_alias, dest_path_str),
+                        ),
+                        &mut worker_pb,
+                        Some(&pb_slot_tx),
+                        &mut has_pb_slot,
+                    );
+                    // either side may be broken; rebuild both SFTP channels for the next file
+                    src.sftp = None;
+                    dst.sftp = None;
+                    continue;
+                }
+                finish_and_release_pb(&mut worker_pb, Some(&pb_slot_tx), &mut has_pb_slot);
+            }
+            src.release(&pool, &src_server);
+            dst.release(&pool, &dst_server);
+            finalize_worker_metrics(
+                "relay",
+                worker_bytes,
+                worker_start,
+                session_rebuilds,
+                sftp_rebuilds,
+                &metrics_tx_thread,
+            );
+        })
+    };
+    spawn_workers(workers, admit, spawn)
+}
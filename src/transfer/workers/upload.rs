@@ -9,12 +9,16 @@ use indicatif::ProgressBar;
 
 // use classifier-aware retry helper from util; explicit import not required here
 
+use super::chunked::{self, ChunkCtx};
 use super::{
     Throttler, WorkerCommonCtx, WorkerMetrics, finalize_worker_metrics, finish_and_release_pb,
-    prepare_file_progress, report_failure_and_finish_pb,
+    prepare_file_progress, report_failure, report_failure_and_finish_pb, spawn_workers,
 };
 use crate::MkdirError;
+use crate::auto_concurrency::AutoTuner;
+use crate::transfer::attrs::FileAttrs;
 use crate::transfer::helpers::display_path;
+use crate::transfer::overwrite::OverwriteDecision;
 
 use crate::transfer::sftp_like::SftpLike;
 use crate::transfer::workers::pipeline::{
@@ -24,32 +28,6 @@ use crate::transfer::{EntryKind, FileEntry};
 
 // ...existing code...
 
-// RAII guard for connection token. Holds a Sender and returns the token on Drop
-// or when release() is called. Defined at module scope so tests can access it.
-struct ConnTokenGuard {
-    tx: Option<Sender<()>>,
-}
-
-impl ConnTokenGuard {
-    fn new(tx: Sender<()>) -> Self {
-        Self { tx: Some(tx) }
-    }
-
-    fn release(&mut self) {
-        if let Some(tx) = self.tx.take() {
-            let _ = tx.send(());
-        }
-    }
-}
-
-impl Drop for ConnTokenGuard {
-    fn drop(&mut self) {
-        if let Some(tx) = self.tx.take() {
-            let _ = tx.send(());
-        }
-    }
-}
-
 fn ensure_remote_dir_all_generic(
     sftp: &dyn SftpLike,
     dir_path: &std::path::Path,
@@ -196,8 +174,6 @@ pub(crate) struct UploadWorkersCtx {
     pub(crate) common: WorkerCommonCtx,
     pub(crate) rx: Receiver<FileEntry>,
     pub(crate) expanded_remote_base: String,
-    pub(crate) conn_token_rx: Receiver<()>,
-    pub(crate) conn_token_tx: Sender<()>,
     pub(crate) metrics_tx: Sender<WorkerMetrics>,
     // 最多仅允许 8 个可见文件进度条（通过槽位令牌实现）；不影响传输并发
     pub(crate) pb_slot_rx: Receiver<()>,
@@ -205,16 +181,8 @@ pub(crate) struct UploadWorkersCtx {
 }
 
 pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
-    let UploadWorkersCtx {
-        common,
-        rx,
-        expanded_remote_base,
-        conn_token_rx,
-        conn_token_tx,
-        metrics_tx,
-        pb_slot_rx,
-        pb_slot_tx,
-    } = ctx;
+    let UploadWorkersCtx { common, rx, expanded_remote_base, metrics_tx, pb_slot_rx, pb_slot_tx } =
+        ctx;
     let WorkerCommonCtx {
         workers,
         mp,
@@ -222,13 +190,21 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
         file_style,
         server,
         addr,
+        pool,
         max_retries,
         target_is_dir_final,
         failure_tx,
         buf_size,
+        attr_policy,
+        overwrite,
+        overwrite_report,
+        rate_limiter,
+        chunking,
+        journal,
+        tuner,
     } = common;
-    let mut handles = Vec::new();
-    for worker_id in 0..workers {
+    let admit = tuner.clone();
+    let spawn = move |worker_id: usize| {
         let rx = rx.clone();
         let pb = total_pb.clone();
         let mp = mp.clone();
@@ -236,13 +212,18 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
         let server = server.clone();
         let expanded_remote_base = expanded_remote_base.clone();
         let failure_tx = failure_tx.clone();
-        let conn_token_rx = conn_token_rx.clone();
-        let conn_token_tx = conn_token_tx.clone();
         let addr = addr.clone();
         let metrics_tx_thread = metrics_tx.clone();
         let pb_slot_rx = pb_slot_rx.clone();
         let pb_slot_tx = pb_slot_tx.clone();
-        let handle = std::thread::spawn(move || {
+        let overwrite = overwrite.clone();
+        let overwrite_report = overwrite_report.clone();
+        let rate_limiter = rate_limiter.clone();
+        let pool = pool.clone();
+        let journal = journal.clone();
+        let tuner = tuner.clone();
+        let meter = tuner.as_ref().map(|t| t.meter());
+        std::thread::spawn(move || {
             let server_alias = server.alias.as_deref().unwrap_or("<unknown>");
             let mut worker_pb: Option<ProgressBar> = None;
             // small stack buffer is fine for small sizes; use Vec only when buf_size > 8192
@@ -255,13 +236,14 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
             let mut worker_bytes: u64 = 0;
             let mut maybe_sess: Option<ssh2::Session> = None;
             let mut maybe_sftp: Option<Box<dyn SftpLike>> = None;
-            let mut token_guard: Option<ConnTokenGuard> = None;
             let mut session_rebuilds: u32 = 0;
             let mut sftp_rebuilds: u32 = 0;
             // Count consecutive connection-level errors; reset session only after threshold
             let mut connection_error_streak: u8 = 0;
             let mut has_pb_slot = false;
             while let Ok(entry) = rx.recv() {
+                // adaptive concurrency: wait for a free slot before working on the entry
+                let _turn = tuner.as_deref().map(AutoTuner::acquire);
                 let FileEntry { rel, size, kind, local_full, .. } = entry;
                 let remote_path_str = if expanded_remote_base.ends_with('/') || target_is_dir_final
                 {
@@ -273,21 +255,29 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                     expanded_remote_base.clone()
                 };
                 let remote_path = std::path::Path::new(&remote_path_str);
+                // --resume: files an earlier attempt of the job finished are skipped
+                let tracked = match journal.as_deref().filter(|_| kind != EntryKind::Dir) {
+                    Some(j) => match j.begin(format!("{}:{}", server_alias, remote_path_str)) {
+                        Some(t) => Some(t),
+                        None => {
+                            pb.inc(size.unwrap_or(0));
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                // --no-clobber/--update/--backup: decided on the first attempt only
+                let mut decision: Option<OverwriteDecision> = None;
 
                 let retry_ctx = format!("upload stream worker={} file={}", worker_id, rel);
                 let transfer_res = crate::util::retry_operation_with_ctx(
                     max_retries,
                     || -> anyhow::Result<()> {
-                        // Ensure connection token for handshake
-                        if maybe_sess.is_none() && token_guard.is_none() {
-                            let _ = conn_token_rx.recv();
-                            token_guard = Some(ConnTokenGuard::new(conn_token_tx.clone()));
-                        }
-
-                        // Use unified session+SFTP preparation
+                        // Use unified session+SFTP preparation; handshakes are limited per host by the pool
                         if let Err(e) = crate::transfer::session::ensure_session_and_sftp(
                             &mut maybe_sess,
                             &mut maybe_sftp,
+                            &pool,
                             &server,
                             &addr,
                             &mut session_rebuilds,
@@ -298,20 +288,9 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                                 worker_id,
                                 e
                             );
-                            if let Some(mut g) = token_guard.take() {
-                                g.release();
-                            }
                             return Err(e);
                         }
 
-                        // Release token after successful handshake
-                        if let Some(mut g) = token_guard.take() {
-                            g.release();
-                            tracing::debug!(
-                                "[ts][upload] worker_id={} session/SFTP ready",
-                                worker_id
-                            );
-                        }
                         let sftp_box = maybe_sftp.as_ref().ok_or_else(|| -> anyhow::Error {
                             crate::TransferError::WorkerNoSftp(server_alias.to_string()).into()
                         })?;
@@ -326,7 +305,8 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                                         created_dirs.insert(rstr);
                                     }
                                     Err(e) => {
-                                        let _ = failure_tx.send(
+                                        report_failure(
+                                            &failure_tx,
                                             crate::TransferError::CreateRemoteDirFailed(
                                                 rstr.clone(),
                                                 e.to_string(),
@@ -357,6 +337,70 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                             }
                         }
 
+                        // 符号链接（--links copy）：在远端重建链接本身
+                        if kind == EntryKind::Symlink {
+                            let local_link = local_full.as_deref().unwrap_or(rel.as_str());
+                            let target = std::fs::read_link(local_link).map_err(|e| {
+                                anyhow::Error::from(crate::TransferError::WorkerIo(format!(
+                                    "读取本地符号链接失败: {} — {}",
+                                    local_link, e
+                                )))
+                            })?;
+                            let target = crate::transfer::links::remote_link_target(&target);
+                            return sftp.symlink(&target, remote_path).map_err(|e| {
+                                crate::TransferError::WorkerIo(format!(
+                                    "创建远端符号链接失败: {} — {}",
+                                    display_path(remote_path),
+                                    e
+                                ))
+                                .into()
+                            });
+                        }
+
+                        if overwrite.is_active() && decision.is_none() {
+                            let src_mtime =
+                                std::fs::metadata(local_full.as_deref().unwrap_or(&rel))
+                                    .ok()
+                                    .and_then(|m| FileAttrs::from_metadata(&m).mtime);
+                            let dest_mtime = match sftp.stat_is_file(remote_path) {
+                                Ok(true) => {
+                                    Some(sftp.stat_attrs(remote_path).ok().and_then(|a| a.mtime))
+                                }
+                                _ => None,
+                            };
+                            decision =
+                                Some(overwrite.decide(&remote_path_str, src_mtime, dest_mtime));
+                        }
+                        match decision {
+                            Some(OverwriteDecision::Skip) => {
+                                overwrite_report.record_skip(&remote_path_str);
+                                pb.inc(size.unwrap_or(0));
+                                return Ok(());
+                            }
+                            Some(OverwriteDecision::Backup(ref backup)) => {
+                                sftp.rename(remote_path, std::path::Path::new(backup)).map_err(
+                                    |e| -> anyhow::Error {
+                                        crate::TransferError::WorkerIo(format!(
+                                            "远端备份失败: {} — {}",
+                                            display_path(remote_path),
+                                            e
+                                        ))
+                                        .into()
+                                    },
+                                )?;
+                                overwrite_report.record_backup(&remote_path_str, backup);
+                                // the original is aside now; a retry must not back up a partial file
+                                decision = Some(OverwriteDecision::Write);
+                            }
+                            _ => {}
+                        }
+
+                        crate::transfer::events::started(
+                            server_alias,
+                            worker_id,
+                            &rel,
+                            size.unwrap_or(0),
+                        );
                         prepare_file_progress(
                             &mut worker_pb,
                             &mp,
@@ -372,6 +416,52 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                         } else {
                             std::path::PathBuf::from(&rel)
                         };
+                        if let Some(policy) = chunking.filter(|c| c.applies(size.unwrap_or(0))) {
+                            let src_attrs = if attr_policy.is_active() {
+                                std::fs::metadata(&local_full)
+                                    .map(|m| FileAttrs::from_metadata(&m))
+                                    .unwrap_or_default()
+                            } else {
+                                FileAttrs::default()
+                            };
+                            let chunk_ctx = ChunkCtx {
+                                policy,
+                                server: &server,
+                                pool: &pool,
+                                max_retries,
+                                limiter: rate_limiter.clone(),
+                                meter: meter.clone(),
+                                worker_pb: worker_pb.as_ref(),
+                                total_pb: &pb,
+                                bytes_transferred: None,
+                                label: &retry_ctx,
+                            };
+                            worker_bytes += chunked::upload_file(
+                                &chunk_ctx,
+                                &local_full,
+                                remote_path,
+                                size.unwrap_or(0),
+                            )?;
+                            let attrs = attr_policy.resolve(src_attrs, false);
+                            if attr_policy.is_active()
+                                && !attrs.is_empty()
+                                && let Err(e) = sftp.set_attrs(remote_path, attrs)
+                            {
+                                report_failure(
+                                    &failure_tx,
+                                    crate::TransferError::PreserveFailed(
+                                        display_path(remote_path).to_string(),
+                                        e,
+                                    ),
+                                );
+                            }
+                            finish_and_release_pb(
+                                &mut worker_pb,
+                                Some(&pb_slot_tx),
+                                &mut has_pb_slot,
+                            );
+                            return Ok(());
+                        }
                         let local_file = File::open(&local_full).map_err(|e| -> anyhow::Error {
                             crate::TransferError::WorkerIo(format!(
                                 "本地打开失败: {} — {}",
@@ -380,6 +470,14 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                             ))
                             .into()
                         })?;
+                        let src_attrs = if attr_policy.is_active() {
+                            local_file
+                                .metadata()
+                                .map(|m| FileAttrs::from_metadata(&m))
+                                .unwrap_or_default()
+                        } else {
+                            FileAttrs::default()
+                        };
                         let mut remote_f =
                             sftp.create_write(remote_path).map_err(|e| -> anyhow::Error {
                                 crate::TransferError::WorkerIo(format!(
@@ -401,7 +499,8 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                             spawn_file_reader(local_file, chunk_tx.clone(), current_buf_size);
 
                         // Throttled progress updates
-                        let mut throttler = Throttler::new();
+                        let mut throttler =
+                            Throttler::new(rate_limiter.clone()).metered(meter.clone());
                         // Track bytes and total write duration to adapt buffer size
                         let mut file_write_bytes: u64 = 0;
                         let mut total_write_time = Duration::from_secs(0);
@@ -444,6 +543,31 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                         // Flush remaining pending progress
                         throttler.flush(worker_pb.as_ref(), &pb, None);
 
+                        // delegated writers report late write errors on flush
+                        remote_f.flush().map_err(|e| -> anyhow::Error {
+                            crate::TransferError::WorkerIo(format!(
+                                "远端写入失败: {} — {}",
+                                display_path(remote_path),
+                                e
+                            ))
+                            .into()
+                        })?;
+                        // setstat after the write completes so the close cannot bump mtime
+                        drop(remote_f);
+                        let attrs = attr_policy.resolve(src_attrs, false);
+                        if attr_policy.is_active()
+                            && !attrs.is_empty()
+                            && let Err(e) = sftp.set_attrs(remote_path, attrs)
+                        {
+                            report_failure(
+                                &failure_tx,
+                                crate::TransferError::PreserveFailed(
+                                    display_path(remote_path).to_string(),
+                                    e,
+                                ),
+                            );
+                        }
+
                         // Adjust buffer size for next file using a simple throughput-based heuristic.
                         // We only adapt when we observed measurable write time.
                         if total_write_time.as_secs_f64() > 0.01 && file_write_bytes > 0 {
@@ -473,19 +597,27 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                     &retry_ctx,
                 );
 
+                if let Some(t) = tracked {
+                    t.finish(&transfer_res, size.unwrap_or(0));
+                }
                 if let Err(e) = transfer_res {
                     tracing::debug!(
                         "[ts][upload] transfer failed for {}: {}; reset SFTP for next try",
                         rel,
                         e
                     );
+                    let err = crate::TransferError::WorkerIo(format!(
+                        "上传失败: {} — {}",
+                        display_path(remote_path),
+                        e
+                    ));
                     report_failure_and_finish_pb(
                         &failure_tx,
-                        crate::TransferError::WorkerIo(format!(
-                            "上传失败: {} — {}",
-                            display_path(remote_path),
-                            e
-                        )),
+                        err.for_entry(
+                            server_alias,
+                            local_full.as_deref().unwrap_or(rel.as_str()),
+                            format!("{}:{}", server_alias, remote_path_str),
+                        ),
                         &mut worker_pb,
                         Some(&pb_slot_tx),
                         &mut has_pb_slot,
@@ -503,11 +635,11 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                         connection_error_streak = 0;
                     }
                 }
-
-                // Ensure token is never held beyond handshake scope
-                if let Some(mut g) = token_guard.take() {
-                    g.release();
-                }
+            }
+            // keep the session warm for directory attributes and the mirror pass
+            drop(maybe_sftp);
+            if let Some(sess) = maybe_sess {
+                pool.checkin(&server, sess);
             }
             finalize_worker_metrics(
                 "upload",
@@ -517,10 +649,9 @@ pub(crate) fn run_upload_workers(ctx: UploadWorkersCtx) {
                 sftp_rebuilds,
                 &metrics_tx_thread,
             );
-        });
-        handles.push(handle);
-    }
-    for h in handles {
+        })
+    };
+    for h in spawn_workers(workers, admit, spawn) {
         let _ = h.join();
     }
 }
@@ -622,29 +753,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn conn_token_guard_drop_and_release() {
-        use crossbeam_channel::bounded;
-        // create a short-lived channel to observe token return
-        let (tx, rx) = bounded::<()>(1);
-        {
-            // create guard and drop it to send token
-            let g = ConnTokenGuard::new(tx.clone());
-            drop(g);
-        }
-        // we should be able to recv the returned token
-        assert!(rx.try_recv().is_ok());
-
-        // test release() doesn't panic and is idempotent
-        let (tx2, rx2) = bounded::<()>(1);
-        let mut g2 = ConnTokenGuard::new(tx2.clone());
-        g2.release();
-        // release() already sent token
-        assert!(rx2.try_recv().is_ok());
-        // subsequent drop should be no-op
-        drop(g2);
-    }
-
     #[test]
     fn ensure_remote_dir_all_generic_creates_dirs() {
         let mock = MockSftp::new();
@@ -65,6 +65,8 @@ pub struct TuiApp {
     add_choice: bool,
     error_message: String,
     show_help: bool,
+    // 主机详情弹窗：别名与传输统计 — Host detail popup: alias and transfer stats
+    detail: Option<(String, crate::history::HostStats)>,
     quick_connect_focused: bool,
 }
 
@@ -96,6 +98,7 @@ impl TuiApp {
             add_choice: false,
             error_message: String::new(),
             show_help: false,
+            detail: None,
             quick_connect_focused: false,
         }
     }
@@ -126,6 +129,10 @@ impl TuiApp {
                                 && let Some(old_alias) = self.collection.hosts().keys().nth(idx)
                             {
                                 let old_alias = old_alias.clone();
+                                let old = self.collection.get(&old_alias);
+                                let limit_rate = old.and_then(|s| s.limit_rate.clone());
+                                let tags = old.map(|s| s.tags.clone()).unwrap_or_default();
+                                let max_handshakes = old.and_then(|s| s.max_handshakes);
                                 let new_server = Server {
                                     id: None,
                                     alias: Some(self.edit_alias.clone()),
@@ -133,13 +140,19 @@ impl TuiApp {
                                     address: self.edit_address.clone(),
                                     port,
                                     last_connect: None,
+                                    limit_rate,
+                                    tags,
+                                    max_handshakes,
                                 };
                                 self.collection.remove(old_alias.as_str());
                                 self.collection.insert(self.edit_alias.as_str(), new_server);
                                 if let Err(e) =
                                     self.collection.save_to_storage(&self.config.server_file_path)
                                 {
-                                    eprintln!("⚠️ 保存 server 集合失败: {}", e);
+                                    // 留在编辑模式显示错误 — stay in edit mode and show the error
+                                    self.error_message =
+                                        format!("⚠️ 保存 server 集合失败: {:#}", e);
+                                    continue;
                                 }
                             }
                             self.editing = None;
@@ -152,11 +165,7 @@ impl TuiApp {
                             0 => self.edit_alias.push(c),
                             1 => self.edit_username.push(c),
                             2 => self.edit_address.push(c),
-                            3 => {
-                                if c.is_ascii_digit() {
-                                    self.edit_port.push(c)
-                                }
-                            }
+                            3 if c.is_ascii_digit() => self.edit_port.push(c),
                             _ => {}
                         },
                         KeyCode::Backspace => match self.current_field {
@@ -233,13 +242,18 @@ impl TuiApp {
                                         address: self.add_address.clone(),
                                         port,
                                         last_connect: None,
+                                        limit_rate: None,
+                                        tags: Vec::new(),
+                                        max_handshakes: None,
                                     };
                                     self.collection.insert(self.add_alias.as_str(), server);
                                     if let Err(e) = self
                                         .collection
                                         .save_to_storage(&self.config.server_file_path)
                                     {
-                                        eprintln!("⚠️ 保存 server 集合失败: {}", e);
+                                        self.error_message =
+                                            format!("⚠️ 保存 server 集合失败: {:#}", e);
+                                        continue;
                                     }
                                     // 更新选择到新服务器 — Update selection to new server
                                     if let Some(pos) = self
@@ -280,11 +294,7 @@ impl TuiApp {
                                 0 => self.add_alias.push(c),
                                 1 => self.add_username.push(c),
                                 2 => self.add_address.push(c),
-                                3 => {
-                                    if c.is_ascii_digit() {
-                                        self.add_port.push(c)
-                                    }
-                                }
+                                3 if c.is_ascii_digit() => self.add_port.push(c),
                                 _ => {}
                             },
                             KeyCode::Backspace => match self.add_current_field {
@@ -307,7 +317,18 @@ impl TuiApp {
                     }
                 } else {
                     // 正常模式 — Normal mode
-                    if self.show_help {
+                    if self.detail.is_some() {
+                        // 详情弹窗打开时 i/q/Esc 关闭 — i/q/Esc close the detail popup
+                        if matches!(
+                            key.code,
+                            KeyCode::Char('i')
+                                | KeyCode::Char('I')
+                                | KeyCode::Char('q')
+                                | KeyCode::Esc
+                        ) {
+                            self.detail = None;
+                        }
+                    } else if self.show_help {
                         // 帮助对话已打开 —— 仅允许 h 或 esc 关闭它 — Help dialog is open - only allow h or esc to close it
                         match key.code {
                             KeyCode::Char('h')
@@ -337,23 +358,18 @@ impl TuiApp {
                         // 如果 Quick Connect 被聚焦，则只处理与输入相关的按键 — If Quick Connect is focused, only handle input-related keys
                         if self.quick_connect_focused {
                             match key.code {
-                                KeyCode::Enter => {
-                                    if !self.input.is_empty() {
-                                        // 使用输入的别名尝试连接服务器（Quick Connect） — Try to connect to the server with the entered alias (Quick Connect)
-                                        if let Some(alias) = self
-                                            .collection
-                                            .hosts()
-                                            .keys()
-                                            .find(|k| k == &&self.input)
-                                        {
-                                            self.connect(terminal, &alias.clone())?;
-                                        } else {
-                                            self.error_message =
-                                                format!("Server '{}' not found", self.input);
-                                        }
-                                        self.input.clear();
-                                        self.quick_connect_focused = false;
+                                KeyCode::Enter if !self.input.is_empty() => {
+                                    // 使用输入的别名尝试连接服务器（Quick Connect） — Try to connect to the server with the entered alias (Quick Connect)
+                                    if let Some(alias) =
+                                        self.collection.hosts().keys().find(|k| k == &&self.input)
+                                    {
+                                        self.connect(terminal, &alias.clone())?;
+                                    } else {
+                                        self.error_message =
+                                            format!("Server '{}' not found", self.input);
                                     }
+                                    self.input.clear();
+                                    self.quick_connect_focused = false;
                                 }
                                 KeyCode::Esc => {
                                     self.input.clear();
@@ -421,6 +437,24 @@ impl TuiApp {
                                 KeyCode::Char('h') | KeyCode::Char('H') => {
                                     self.show_help = true;
                                 }
+                                KeyCode::Char('i') | KeyCode::Char('I') => {
+                                    if let Some(alias) =
+                                        self.collection.hosts().keys().nth(self.selected)
+                                    {
+                                        let alias = alias.clone();
+                                        match crate::history::host_stats(
+                                            &self.config.server_file_path,
+                                            &alias,
+                                            8,
+                                        ) {
+                                            Ok(stats) => self.detail = Some((alias, stats)),
+                                            Err(e) => {
+                                                self.error_message =
+                                                    format!("⚠️ 无法读取传输记录: {}", e)
+                                            }
+                                        }
+                                    }
+                                }
                                 // 不要在任意按键时自动聚焦 Quick Connect。 — Do not auto-focus Quick Connect on arbitrary keys.
                                 // Quick Connect 仅通过 Ctrl+F 切换；此处忽略其他 Char/Backspace。 — Quick Connect is only toggled via Ctrl+F; ignore stray Char/Backspace here.
                                 _ => {}
@@ -795,6 +829,8 @@ impl TuiApp {
                     Span::styled("-Add | ", Style::default().fg(Color::Gray)),
                     Span::styled("Del", Style::default().fg(Color::Red)),
                     Span::styled("-Delete | ", Style::default().fg(Color::Gray)),
+                    Span::styled("i", Style::default().fg(Color::Cyan)),
+                    Span::styled("-Info | ", Style::default().fg(Color::Gray)),
                     Span::styled("h", Style::default().fg(Color::Magenta)),
                     Span::styled("-Help | ", Style::default().fg(Color::Gray)),
                     Span::styled("q", Style::default().fg(Color::Magenta)),
@@ -949,6 +985,16 @@ impl TuiApp {
                             Style::default().fg(Color::White),
                         ),
                     ]),
+                    Line::from(vec![
+                        Span::styled(
+                            "  i",
+                            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                        ),
+                        Span::styled(
+                            " - Show host details and recent transfers",
+                            Style::default().fg(Color::White),
+                        ),
+                    ]),
                     Line::from(vec![
                         Span::styled(
                             "  h",
@@ -996,6 +1042,110 @@ impl TuiApp {
                 f.render_widget(WidgetClear, area);
                 f.render_widget(para, area);
             }
+
+            // 主机详情弹窗 — Host detail popup
+            if let Some((alias, stats)) = self.detail.as_ref() {
+                let area = centered_rect(90, 40, size);
+                let block = Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(ratatui::widgets::BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Cyan))
+                    .title(format!("🖥  {}", alias))
+                    .title_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+                let label = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+                let value = Style::default().fg(Color::White);
+
+                let mut text = Vec::new();
+                if let Some(server) = self.collection.get(alias) {
+                    text.push(Line::from(vec![
+                        Span::styled("Host: ", label),
+                        Span::styled(
+                            format!("{}@{}:{}", server.username, server.address, server.port),
+                            value,
+                        ),
+                    ]));
+                    text.push(Line::from(vec![
+                        Span::styled("Tags: ", label),
+                        Span::styled(
+                            if server.tags.is_empty() {
+                                "-".to_string()
+                            } else {
+                                server.tags.join(", ")
+                            },
+                            value,
+                        ),
+                        Span::styled("  Limit: ", label),
+                        Span::styled(
+                            server.limit_rate.clone().unwrap_or_else(|| "-".to_string()),
+                            value,
+                        ),
+                        Span::styled("  Last connect: ", label),
+                        Span::styled(
+                            server.last_connect.clone().unwrap_or_else(|| "-".to_string()),
+                            value,
+                        ),
+                    ]));
+                }
+                text.push(Line::from(vec![
+                    Span::styled("Transfers: ", label),
+                    Span::styled(stats.runs.to_string(), value),
+                    Span::styled("  Total: ", label),
+                    Span::styled(crate::util::human_bytes(stats.total_bytes), value),
+                    Span::styled("  Avg throughput: ", label),
+                    Span::styled(crate::history::fmt_rate(stats.avg_bytes_per_sec), value),
+                ]));
+                text.push(Line::from(""));
+                if stats.recent.is_empty() {
+                    text.push(Line::from(Span::styled(
+                        "  暂无传输记录",
+                        Style::default().fg(Color::Gray),
+                    )));
+                }
+                for r in stats.recent.iter() {
+                    let failed = if r.failures > 0 {
+                        Span::styled(
+                            format!("  {} failed", r.failures),
+                            Style::default().fg(Color::Red),
+                        )
+                    } else {
+                        Span::raw("")
+                    };
+                    text.push(Line::from(vec![
+                        Span::styled(
+                            format!("  {}  ", crate::history::fmt_time(r.finished_at)),
+                            Style::default().fg(Color::Gray),
+                        ),
+                        Span::styled(
+                            format!("{:<8}", r.direction),
+                            Style::default().fg(Color::Green),
+                        ),
+                        Span::styled(
+                            if r.measured() {
+                                format!(
+                                    "{} files  {}  {:.1}s  {}",
+                                    r.files,
+                                    crate::util::human_bytes(r.bytes),
+                                    r.elapsed_secs,
+                                    crate::history::fmt_rate(r.bytes_per_sec())
+                                )
+                            } else {
+                                format!("source-pushed  {:.1}s", r.elapsed_secs)
+                            },
+                            value,
+                        ),
+                        failed,
+                    ]));
+                }
+                text.push(Line::from(""));
+                text.push(Line::from(Span::styled(
+                    "Press i or Esc to close",
+                    Style::default().fg(Color::Cyan).add_modifier(Modifier::ITALIC),
+                )));
+
+                let para = Paragraph::new(text).block(block).alignment(Alignment::Left);
+                f.render_widget(WidgetClear, area);
+                f.render_widget(para, area);
+            }
         }
     }
 
@@ -1044,6 +1194,9 @@ impl TuiApp {
                 address: address.clone(),
                 port,
                 last_connect: server.last_connect.clone(),
+                limit_rate: server.limit_rate.clone(),
+                tags: server.tags.clone(),
+                max_handshakes: server.max_handshakes,
             };
             updated_server.set_last_connect_now();
 
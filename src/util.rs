@@ -12,7 +12,7 @@ use std::time::Duration;
 use std::time::SystemTime;
 
 /// Convert a TransferError to a structured JSON object for JSONL output
-fn transfer_error_to_json(err: &crate::TransferError) -> serde_json::Value {
+pub(crate) fn transfer_error_to_json(err: &crate::TransferError) -> serde_json::Value {
     match err {
         crate::TransferError::InvalidDirection => {
             serde_json::json!({"variant":"InvalidDirection","message":err.to_string()})
@@ -68,6 +68,12 @@ fn transfer_error_to_json(err: &crate::TransferError) -> serde_json::Value {
         crate::TransferError::WorkerBuildSessionFailed(a) => {
             serde_json::json!({"variant":"WorkerBuildSessionFailed","addr":a,"message":err.to_string()})
         }
+        crate::TransferError::InvalidArgument(s) => {
+            serde_json::json!({"variant":"InvalidArgument","message":err.to_string(),"detail":s})
+        }
+        crate::TransferError::AliasExists(a) => {
+            serde_json::json!({"variant":"AliasExists","alias":a,"message":err.to_string()})
+        }
         crate::TransferError::MissingLocalSource(s) => {
             serde_json::json!({"variant":"MissingLocalSource","message":err.to_string(),"detail":s})
         }
@@ -80,9 +86,64 @@ fn transfer_error_to_json(err: &crate::TransferError) -> serde_json::Value {
         crate::TransferError::WorkerIo(s) => {
             serde_json::json!({"variant":"WorkerIo","message":s})
         }
+        crate::TransferError::MirrorUnsupported(s) => {
+            serde_json::json!({"variant":"MirrorUnsupported","message":err.to_string(),"detail":s})
+        }
+        crate::TransferError::SourceIncomplete(p) => {
+            serde_json::json!({"variant":"SourceIncomplete","path":p,"message":err.to_string()})
+        }
+        crate::TransferError::DeleteLimitExceeded(planned, max) => {
+            serde_json::json!({"variant":"DeleteLimitExceeded","planned":planned,"max":max,"message":err.to_string()})
+        }
+        crate::TransferError::DeleteFailed(p, m) => {
+            serde_json::json!({"variant":"DeleteFailed","path":p,"error":m,"message":err.to_string()})
+        }
+        crate::TransferError::PreserveFailed(p, m) => {
+            serde_json::json!({"variant":"PreserveFailed","path":p,"error":m,"message":err.to_string()})
+        }
+        crate::TransferError::EntryFailed(entry, cause) => {
+            let mut obj = transfer_error_to_json(cause);
+            obj["alias"] = serde_json::json!(entry.alias);
+            obj["source"] = serde_json::json!(entry.source);
+            obj["destination"] = serde_json::json!(entry.destination);
+            obj
+        }
+        crate::TransferError::PartialFailure(s) => {
+            serde_json::json!({"variant":"PartialFailure","message":err.to_string(),"detail":s})
+        }
+        crate::TransferError::AllFailed(s) => {
+            serde_json::json!({"variant":"AllFailed","message":err.to_string(),"detail":s})
+        }
     }
 }
 
+/// Run-level fields stamped on every failure record so that `hp ts --retry-failed`
+/// can find the records of one run and replay them with its options.
+#[derive(Debug, Clone, Default)]
+pub struct FailureRun {
+    // id of the journaled job (`hp jobs`); None when the run was not journaled
+    pub run_id: Option<i64>,
+    // upload / download / relay
+    pub direction: Option<String>,
+    // alias of the run; records of a single file carry the file's own alias instead
+    pub alias: Option<String>,
+}
+
+// One failures.jsonl line: the error fields plus run id, direction, alias, source and
+// destination (null when the failure is not tied to one file).
+fn failure_record(run: &FailureRun, err: &crate::TransferError) -> serde_json::Value {
+    let mut obj = transfer_error_to_json(err);
+    if let Some(map) = obj.as_object_mut() {
+        map.insert("run_id".to_string(), serde_json::json!(run.run_id));
+        map.insert("direction".to_string(), serde_json::json!(run.direction));
+        // per-file records (and variants naming an alias) already carry their own
+        map.entry("alias").or_insert_with(|| serde_json::json!(run.alias));
+        map.entry("source").or_insert(serde_json::Value::Null);
+        map.entry("destination").or_insert(serde_json::Value::Null);
+    }
+    obj
+}
+
 /// Try to enable ANSI escape sequence support on Windows consoles.
 /// Returns true if enabling succeeded (or platform likely already supports ANSI), false otherwise.
 #[cfg(windows)]
@@ -169,17 +230,21 @@ pub fn print_summary(
     files: u64,
     session_rebuilds: u64,
     sftp_rebuilds: u64,
+    handshakes: u64,
+    reuses: u64,
 ) {
     if elapsed_secs > 0.0 {
         let mb = total_bytes as f64 / 1024.0 / 1024.0;
         println!(
-            "平均速率: {:.2} MB/s (传输 {} 字节, 耗时 {:.2} 秒, {} 文件) | 会话重建: {} | SFTP重建: {}",
+            "平均速率: {:.2} MB/s (传输 {} 字节, 耗时 {:.2} 秒, {} 文件) | 会话重建: {} | SFTP重建: {} | 握手: {} | 会话复用: {}",
             mb / elapsed_secs,
             total_bytes,
             elapsed_secs,
             files,
             session_rebuilds,
-            sftp_rebuilds
+            sftp_rebuilds,
+            handshakes,
+            reuses
         );
     } else {
         println!("平均速率: 0.00 MB/s (0 文件)");
@@ -189,6 +254,22 @@ pub fn print_summary(
 // Write failures to a file with a UTC timestamped header (append mode).
 // removed plain-text & structured failure writers; use JSONL-only writer
 
+/// Canonical logs directory `~/.{pkgname}/logs` holding failures.jsonl and the
+/// retry attempt log.
+pub fn logs_dir() -> PathBuf {
+    // Compute logs_dir from ~/.{pkgname}/ (ops::ensure_hostpilot_dir)
+    match dirs::home_dir().and_then(|home_dir| match crate::ops::ensure_hostpilot_dir(&home_dir) {
+        Ok(p) => Some(p.join("logs")),
+        Err(_) => None,
+    }) {
+        Some(d) => d,
+        None => {
+            let home = dirs::home_dir().unwrap_or_else(|| std::path::PathBuf::from("."));
+            home.join(".".to_string() + env!("CARGO_PKG_NAME")).join("logs")
+        }
+    }
+}
+
 /// Write failures in both plain-text and JSON Lines form using a single call.
 /// This is a convenience wrapper used by higher-level helpers to produce both
 /// human and machine-readable failure outputs alongside each other.
@@ -196,9 +277,12 @@ pub fn print_summary(
 /// combined writer that also wrote plain-text; JSONL is easier for CI to
 /// parse and therefore preferred.
 ///
+/// Every record is stamped with the run's id, direction and alias (see [`FailureRun`]).
+///
 /// Returns the final JSONL file path if writing occurred (Some), otherwise None.
 pub fn write_failures_jsonl(
     path: Option<PathBuf>,
+    run: &FailureRun,
     failures: &[crate::TransferError],
 ) -> Option<PathBuf> {
     // Always write failures JSONL into the canonical logs directory located at
@@ -206,20 +290,7 @@ pub fn write_failures_jsonl(
     // Do not accept external paths for the log location. If a filename was
     // provided via `path`, use its basename; otherwise generate a default
     // timestamped name.
-    // Compute logs_dir from ~/.{pkgname}/ (ops::ensure_hostpilot_dir)
-    let logs_dir =
-        match dirs::home_dir().and_then(|home_dir| {
-            match crate::ops::ensure_hostpilot_dir(&home_dir) {
-                Ok(p) => Some(p.join("logs")),
-                Err(_) => None,
-            }
-        }) {
-            Some(d) => d,
-            None => {
-                let home = dirs::home_dir().unwrap_or_else(|| std::path::PathBuf::from("."));
-                home.join(".".to_string() + env!("CARGO_PKG_NAME")).join("logs")
-            }
-        };
+    let logs_dir = logs_dir();
     // Try to create the logs directory and capture any error so we can emit a
     // structured JSON warning to stderr if it fails.
     let create_dir_res = std::fs::create_dir_all(&logs_dir);
@@ -276,7 +347,7 @@ pub fn write_failures_jsonl(
         Ok(mut f) => {
             let mut wrote_any = false;
             for err in failures {
-                let obj = transfer_error_to_json(err);
+                let obj = failure_record(run, err);
                 if let Ok(line) = serde_json::to_string(&obj) {
                     match writeln!(f, "{}", line) {
                         Ok(_) => {
@@ -460,6 +531,13 @@ where
                         error = %e,
                         "retrying"
                     );
+                    crate::transfer::events::retrying(
+                        &format!("{:?}", phase),
+                        attempt_no,
+                        max_retries,
+                        wait,
+                        &e.to_string(),
+                    );
                     last_err = Some(e);
                     std::thread::sleep(Duration::from_millis(wait));
                     continue;
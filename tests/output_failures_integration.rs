@@ -1,6 +1,6 @@
 use hostpilot::ops;
 use hostpilot::server::{Server, ServerCollection};
-use hostpilot::util::write_failures_jsonl;
+use hostpilot::util::{FailureRun, write_failures_jsonl};
 use std::fs;
 use std::io::Read;
 use std::path::PathBuf;
@@ -39,6 +39,9 @@ fn test_output_failures_via_cli_writes_to_specified_file() {
         address: "127.0.0.1".to_string(),
         port: 22,
         last_connect: None,
+        limit_rate: None,
+        tags: Vec::new(),
+        max_handshakes: None,
     };
     coll.insert("nonexistent", s);
     let _ = coll.save_to_storage(&db_path);
@@ -54,7 +57,7 @@ fn test_output_failures_via_cli_writes_to_specified_file() {
         version: Some(2),
         mode: 1,
     };
-    cfg.save_to_storage();
+    cfg.save_to_storage().expect("write test config");
 
     let hp = find_hp_binary();
 
@@ -76,7 +79,7 @@ fn test_output_failures_via_cli_writes_to_specified_file() {
         let failures_struct = vec![hostpilot::TransferError::OperationFailed(
             "simulated: local open failed".to_string(),
         )];
-        write_failures_jsonl(Some(temp.clone()), &failures_struct);
+        write_failures_jsonl(Some(temp.clone()), &FailureRun::default(), &failures_struct);
         // Fallback expectation: the failures JSONL is written into the hostpilot
         // config logs directory (~/.hostpilot/logs) and the filename will have the
         // original basename's stem with a .jsonl extension.
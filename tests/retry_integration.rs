@@ -25,6 +25,9 @@ fn retry_option_triggers_and_returns_err() {
         address: "127.0.0.1".to_string(),
         port: 65000u16,
         last_connect: None,
+        limit_rate: None,
+        tags: Vec::new(),
+        max_handshakes: None,
     };
     coll.insert("fakehost", server);
     let _ = coll.save_to_storage(&db_path);
@@ -59,6 +62,30 @@ fn retry_option_triggers_and_returns_err() {
         concurrency: Some(1),
         max_retries: 2,
         buf_size: 1024 * 1024,
+        delete: false,
+        delete_dry_run: false,
+        max_delete: None,
+        dry_run: false,
+        excludes: Vec::new(),
+        includes: Vec::new(),
+        exclude_from: None,
+        preserve: false,
+        chmod: None,
+        umask: None,
+        links: None,
+        no_clobber: false,
+        update: false,
+        backup: None,
+        limit_rate: None,
+        direct: false,
+        parallel_hosts: None,
+        fail_fast: false,
+        host_dir: None,
+        tar: false,
+        chunk_threshold: None,
+        prescan: false,
+        resume: None,
+        retry_failed: None,
     };
     let res = hostpilot::transfer::handle_ts(&cfg, args);
 